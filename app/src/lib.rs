@@ -1,5 +1,5 @@
-#[cfg(test)]
-mod tests {
-    #[test]
-    fn it_works() {}
-}
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn it_works() {}
+}
@@ -1,112 +1,559 @@
-extern crate corrosion;
-extern crate stopwatch;
-extern crate config;
-
-
-use config::{Config, File};
-
-use corrosion::{Emulator, EmulatorBuilder, Settings};
-use corrosion::cart::Cart;
-use corrosion::sdl2::EventPump;
-use corrosion::sdl2::event::Event;
-use std::cell::RefCell;
-use std::env;
-use std::path::Path;
-
-use std::rc::Rc;
-use stopwatch::Stopwatch;
-
-fn main() {
-    let args = env::args();
-    let file_name = args.skip(1).next().expect("No ROM file provided.");
-    let path = Path::new(&file_name);
-    let cart = Cart::read(&path).expect("Failed to read ROM File");
-    let config = load_config();
-    start_emulator(cart, config);
-}
-
-fn load_config() -> Config {
-    let mut s = Config::new();
-    s.merge(File::with_name("config/default").required(false))
-        .expect("Failed to read config file");
-    s
-}
-
-fn get_bool(config: &Config, key: &str, default: bool) -> bool {
-    config.get_bool(key).unwrap_or(default)
-}
-
-fn make_emulator_settings(config: &Config) -> Settings {
-    let defaults: Settings = Default::default();
-    Settings {
-        jit: get_bool(&config, "jit", defaults.jit),
-        graphics_enabled: get_bool(&config, "graphics_enabled", defaults.graphics_enabled),
-        sound_enabled: get_bool(&config, "sound_enabled", defaults.sound_enabled),
-
-        trace_cpu: get_bool(&config, "debug.trace_cpu", defaults.trace_cpu),
-        disassemble_functions: get_bool(&config, "debug.disassemble_functions", defaults.disassemble_functions),
-    }
-}
-
-#[cfg(feature = "debug_features")]
-fn mouse_pick(event_pump: &Rc<RefCell<EventPump>>, emulator: &Emulator) {
-    let mouse_state = event_pump.borrow().mouse_state();
-    // Should get this from the screen, but eh.
-    let size_factor = 3;
-    let (px_x, px_y) = (mouse_state.x() / size_factor, mouse_state.y() / size_factor);
-    emulator.mouse_pick(px_x, px_y);
-}
-
-#[cfg(not(feature = "debug_features"))]
-fn mouse_pick(_: &Rc<RefCell<EventPump>>, _: &Emulator) {}
-
-fn pump_events(pump: &Rc<RefCell<EventPump>>) -> bool {
-    for event in pump.borrow_mut().poll_iter() {
-        if let Event::Quit { .. } = event {
-            return true;
-        }
-    }
-    false
-}
-
-fn get_movie_file() -> Option<String> {
-    std::env::args()
-        .skip_while(|arg| arg != "--movie")
-        .skip(1)
-        .next()
-}
-
-fn start_emulator(cart: Cart, config: Config) {
-    let sdl = corrosion::sdl2::init().unwrap();
-    let event_pump = Rc::new(RefCell::new(sdl.event_pump().unwrap()));
-
-    let mut builder =
-        EmulatorBuilder::new_sdl(cart, make_emulator_settings(&config), &sdl, &event_pump);
-
-    if let Some(file) = get_movie_file() {
-        let fm2io = corrosion::io::fm2::FM2IO::read(file).unwrap();
-        builder.io = Box::new(fm2io)
-    }
-
-    let mut emulator = builder.build();
-
-    let mut stopwatch = Stopwatch::start_new();
-    let smoothing = 0.9;
-    let mut avg_frame_time = 0.0f64;
-    let mousepick_enabled = config.get_bool("debug.mousepick").unwrap_or(false);
-    loop {
-        if pump_events(&event_pump) || emulator.halted() {
-            break;
-        }
-        emulator.run_frame();
-        let current = stopwatch.elapsed().num_nanoseconds().unwrap() as f64;
-        avg_frame_time = (avg_frame_time * smoothing) + (current * (1.0 - smoothing));
-
-        if mousepick_enabled {
-            mouse_pick(&event_pump, &emulator);
-        }
-
-        // println!("Frames per second:{:.*}", 2, 1000000000.0 / avg_frame_time);
-        stopwatch.restart();
-    }
-}
+extern crate corrosion;
+extern crate stopwatch;
+extern crate config;
+
+
+use config::{Config, File};
+
+use corrosion::{Emulator, EmulatorBuilder, Region, Settings};
+use corrosion::apu::{ALL_CHANNELS, ChannelMix, MixerMode};
+use corrosion::audio;
+use corrosion::audio::DummyAudioOut;
+use corrosion::cart::Cart;
+use corrosion::events::EventViewer;
+use corrosion::io::DummyIO;
+use corrosion::io::ports::PortLayout;
+use corrosion::io::sdl::{ALL_BUTTONS, Binding, InputConfig, SdlEvents, SdlIO};
+use corrosion::nsf::Nsf;
+use corrosion::ppu::debug;
+use corrosion::recording::{Recorder, Sink, StemWriter};
+use corrosion::screen::{DummyScreen, Palette};
+use corrosion::screen::palette::NtscParams;
+use corrosion::screen::screenshot::{PngScreen, ScreenshotRequest};
+use corrosion::screen::sdl::DebugWindow;
+use corrosion::sdl2::{EventPump, Sdl};
+use corrosion::sdl2::event::Event;
+use corrosion::sdl2::keyboard::Keycode;
+#[cfg(feature = "debug_features")]
+use corrosion::screen::sdl::window_to_screen;
+use std::cell::RefCell;
+use std::env;
+use std::mem;
+use std::path::{Path, PathBuf};
+
+use std::rc::Rc;
+use stopwatch::Stopwatch;
+
+fn main() {
+    let args = env::args();
+    let file_name = args.skip(1).next().expect("No ROM file provided.");
+    let path = Path::new(&file_name);
+    let config = load_config();
+    if is_nsf(&path) {
+        let nsf = Nsf::read(&path).expect("Failed to read NSF file");
+        play_nsf(nsf, config);
+    } else {
+        let cart = Cart::read(&path).expect("Failed to read ROM File");
+        start_emulator(cart, config);
+    }
+}
+
+fn is_nsf(path: &Path) -> bool {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) => ["nsf", "nsfe"].contains(&&*ext.to_lowercase()),
+        None => false,
+    }
+}
+
+fn load_config() -> Config {
+    let mut s = Config::new();
+    s.merge(File::with_name("config/default").required(false))
+        .expect("Failed to read config file");
+    s
+}
+
+fn get_bool(config: &Config, key: &str, default: bool) -> bool {
+    config.get_bool(key).unwrap_or(default)
+}
+
+fn get_u32(config: &Config, key: &str, default: u32) -> u32 {
+    config.get_int(key).map(|x| x as u32).unwrap_or(default)
+}
+
+fn get_float(config: &Config, key: &str, default: f64) -> f64 {
+    config.get_float(key).unwrap_or(default)
+}
+
+fn make_palette(config: &Config) -> Palette {
+    if let Ok(file) = config.get_str("palette.file") {
+        return Palette::load(Path::new(&file)).expect("Failed to read palette file");
+    }
+    if get_bool(config, "palette.ntsc", false) {
+        let defaults: NtscParams = Default::default();
+        let params = NtscParams {
+            hue: get_float(config, "palette.hue", defaults.hue),
+            saturation: get_float(config, "palette.saturation", defaults.saturation),
+            contrast: get_float(config, "palette.contrast", defaults.contrast),
+            brightness: get_float(config, "palette.brightness", defaults.brightness),
+            gamma: get_float(config, "palette.gamma", defaults.gamma),
+        };
+        return Palette::ntsc(&params);
+    }
+    Default::default()
+}
+
+fn get_port_layout(config: &Config) -> PortLayout {
+    match config.get_str("controllers") {
+        Ok(ref layout) if layout == "four_score" => PortLayout::FourScore,
+        Ok(ref layout) if layout == "famicom_four_player" => PortLayout::FamicomFourPlayer,
+        _ => PortLayout::Standard,
+    }
+}
+
+/// Reads the bindings from the [input] section. Any buttons which aren't
+/// mentioned keep their default bindings.
+fn make_input_config(config: &Config) -> InputConfig {
+    let mut input: InputConfig = Default::default();
+    if let Ok(deadzone) = config.get_int("input.deadzone") {
+        input.deadzone = deadzone as i16;
+    }
+    if let Ok(period) = config.get_int("input.turbo_period") {
+        input.turbo_period = period as u32;
+    }
+    for (idx, player) in input.players.iter_mut().enumerate() {
+        for &button in ALL_BUTTONS.iter() {
+            let key = format!("input.player{}.{}", idx + 1, button.name());
+            if let Ok(names) = config.get_str(&key) {
+                let bindings = Binding::parse_list(&names)
+                    .unwrap_or_else(|name| panic!("Unknown input binding: {}", name));
+                player.set(button, bindings);
+            }
+        }
+    }
+    input
+}
+
+fn get_region(config: &Config) -> Option<Region> {
+    match config.get_str("region") {
+        Ok(ref region) if region == "ntsc" => Some(Region::NTSC),
+        Ok(ref region) if region == "pal" => Some(Region::PAL),
+        Ok(ref region) if region == "dendy" => Some(Region::Dendy),
+        _ => None,
+    }
+}
+
+fn get_mixer_mode(config: &Config, default: MixerMode) -> MixerMode {
+    match config.get_str("audio_mixer") {
+        Ok(ref mode) if mode == "accurate" => MixerMode::Accurate,
+        Ok(ref mode) if mode == "clean" => MixerMode::Clean,
+        _ => default,
+    }
+}
+
+fn get_sample_rate(config: &Config, default: u32) -> u32 {
+    let sample_rate = get_u32(config, "sample_rate", default);
+    if sample_rate < audio::MIN_SAMPLE_RATE || sample_rate > audio::MAX_SAMPLE_RATE {
+        panic!(
+            "sample_rate must be between {} and {}",
+            audio::MIN_SAMPLE_RATE,
+            audio::MAX_SAMPLE_RATE
+        );
+    }
+    sample_rate
+}
+
+fn get_audio_channels(config: &Config, default: usize) -> usize {
+    match config.get_str("audio_channels") {
+        Ok(ref channels) if channels == "mono" => 1,
+        Ok(ref channels) if channels == "stereo" => 2,
+        _ => default,
+    }
+}
+
+fn make_emulator_settings(config: &Config) -> Settings {
+    let defaults: Settings = Default::default();
+    Settings {
+        jit: get_bool(&config, "jit", defaults.jit),
+        graphics_enabled: get_bool(&config, "graphics_enabled", defaults.graphics_enabled),
+        sound_enabled: get_bool(&config, "sound_enabled", defaults.sound_enabled),
+        run_ahead: get_u32(&config, "run_ahead", defaults.run_ahead),
+        no_sprite_limit: get_bool(&config, "no_sprite_limit", defaults.no_sprite_limit),
+        dot_accurate_ppu: get_bool(&config, "dot_accurate_ppu", defaults.dot_accurate_ppu),
+        region: get_region(&config),
+        mixer_mode: get_mixer_mode(&config, defaults.mixer_mode),
+        dynamic_rate_control: get_bool(&config, "dynamic_rate_control", defaults.dynamic_rate_control),
+        sample_rate: get_sample_rate(&config, defaults.sample_rate),
+        audio_channels: get_audio_channels(&config, defaults.audio_channels),
+        audio_latency_ms: get_u32(&config, "audio_latency_ms", defaults.audio_latency_ms),
+
+        trace_cpu: get_bool(&config, "debug.trace_cpu", defaults.trace_cpu),
+        disassemble_functions: get_bool(&config, "debug.disassemble_functions", defaults.disassemble_functions),
+    }
+}
+
+/// Applies the volume, panning, mute and solo settings from the [audio]
+/// section.
+fn set_channel_mixes(config: &Config, emulator: &mut Emulator) {
+    for &channel in ALL_CHANNELS.iter() {
+        let key = |setting: &str| format!("audio.{}.{}", channel.name(), setting);
+        let defaults = ChannelMix::default();
+        let mix = ChannelMix {
+            volume: get_float(config, &key("volume"), defaults.volume as f64) as f32,
+            pan: get_float(config, &key("pan"), defaults.pan as f64) as f32,
+            muted: get_bool(config, &key("mute"), defaults.muted),
+            solo: get_bool(config, &key("solo"), defaults.solo),
+        };
+        emulator.set_channel_mix(channel, mix);
+    }
+}
+
+#[cfg(feature = "debug_features")]
+fn mouse_pick(event_pump: &Rc<RefCell<EventPump>>, emulator: &Emulator) {
+    let mouse_state = event_pump.borrow().mouse_state();
+    let (px_x, px_y) = window_to_screen(mouse_state.x(), mouse_state.y());
+    emulator.mouse_pick(px_x, px_y);
+}
+
+#[cfg(not(feature = "debug_features"))]
+fn mouse_pick(_: &Rc<RefCell<EventPump>>, _: &Emulator) {}
+
+/// The optional windows showing the PPU's nametables, pattern tables,
+/// sprites, palette and register writes.
+struct DebugViewers<'a> {
+    palette: Palette,
+    nametables: Option<DebugWindow<'a>>,
+    pattern_tables: Option<DebugWindow<'a>>,
+    pattern_palette: u8,
+    sprites: Option<DebugWindow<'a>>,
+    palette_entries: Option<DebugWindow<'a>>,
+    events: Option<(EventViewer, DebugWindow<'a>)>,
+}
+
+impl<'a> DebugViewers<'a> {
+    fn new(
+        sdl: &Sdl,
+        config: &Config,
+        palette: &Palette,
+        emulator: &mut Emulator,
+    ) -> DebugViewers<'a> {
+        let video = sdl.video().unwrap();
+        let window = |key: &str, title: &str, width: usize, height: usize, scale: usize| {
+            if get_bool(config, key, false) {
+                Some(DebugWindow::new(&video, title, width, height, scale))
+            } else {
+                None
+            }
+        };
+        DebugViewers {
+            palette: palette.clone(),
+            nametables: window(
+                "viewers.nametables",
+                "Nametables",
+                debug::NAMETABLES_WIDTH,
+                debug::NAMETABLES_HEIGHT,
+                1,
+            ),
+            pattern_tables: window(
+                "viewers.pattern_tables",
+                "Pattern Tables",
+                debug::PATTERN_TABLES_WIDTH,
+                debug::PATTERN_TABLES_HEIGHT,
+                3,
+            ),
+            pattern_palette: get_u32(config, "viewers.pattern_palette", 0) as u8,
+            sprites: window(
+                "viewers.sprites",
+                "Sprites",
+                debug::SPRITES_WIDTH,
+                debug::SPRITES_HEIGHT,
+                3,
+            ),
+            palette_entries: window(
+                "viewers.palette",
+                "Palette",
+                debug::PALETTE_WIDTH,
+                debug::PALETTE_HEIGHT,
+                2,
+            ),
+            events: if get_bool(config, "viewers.events", false) {
+                let viewer = EventViewer::attach(emulator);
+                let window = DebugWindow::new(&video, "Events", viewer.width(), viewer.height(), 2);
+                Some((viewer, window))
+            } else {
+                None
+            },
+        }
+    }
+
+    fn update(&mut self, emulator: &mut Emulator) {
+        let palette = &self.palette;
+        if let Some(ref mut window) = self.nametables {
+            window.show(&emulator.render_nametables(palette));
+        }
+        if let Some(ref mut window) = self.pattern_tables {
+            window.show(&emulator.render_pattern_tables(self.pattern_palette, palette));
+        }
+        if let Some(ref mut window) = self.sprites {
+            window.show(&emulator.render_sprites(palette));
+        }
+        if let Some(ref mut window) = self.palette_entries {
+            window.show(&emulator.render_palette(palette));
+        }
+        if let Some((ref mut viewer, ref mut window)) = self.events {
+            viewer.end_frame();
+            window.show(&viewer.render());
+        }
+    }
+}
+
+fn pump_events(
+    pump: &Rc<RefCell<EventPump>>,
+    screenshot: &ScreenshotRequest,
+    io_events: &SdlEvents,
+) -> bool {
+    for event in pump.borrow_mut().poll_iter() {
+        io_events.forward(&event);
+        match event {
+            Event::Quit { .. } => return true,
+            Event::KeyDown { keycode: Some(Keycode::F12), .. } => screenshot.take_screenshot(),
+            _ => (),
+        }
+    }
+    false
+}
+
+enum TrackChange {
+    Quit,
+    Previous,
+    Next,
+}
+
+fn pump_nsf_events(pump: &Rc<RefCell<EventPump>>) -> Option<TrackChange> {
+    for event in pump.borrow_mut().poll_iter() {
+        match event {
+            Event::Quit { .. } => return Some(TrackChange::Quit),
+            Event::KeyDown { keycode: Some(Keycode::Left), .. } => {
+                return Some(TrackChange::Previous)
+            }
+            Event::KeyDown { keycode: Some(Keycode::Right), .. } => {
+                return Some(TrackChange::Next)
+            }
+            _ => (),
+        }
+    }
+    None
+}
+
+/// How often to print the audio statistics, in frames.
+const AUDIO_STATS_INTERVAL: u64 = 300;
+
+fn print_audio_stats(emulator: &Emulator) {
+    let stats = emulator.audio_stats();
+    println!(
+        "Audio buffer {:.0}% full, {} underruns, {} overruns",
+        stats.fill_level * 100.0,
+        stats.underruns,
+        stats.overruns
+    );
+}
+
+fn get_arg(name: &str) -> Option<String> {
+    std::env::args()
+        .skip_while(|arg| arg != name)
+        .skip(1)
+        .next()
+}
+
+fn get_movie_file() -> Option<String> {
+    get_arg("--movie")
+}
+
+/// Sets up recording to the paths given by `--record-video` (a .y4m file)
+/// and `--record-audio` (a .wav file). A path of `-` writes to standard
+/// output, so a recording can be piped straight into an encoder.
+fn make_recorder(builder: &EmulatorBuilder) -> Option<Recorder> {
+    let open = |arg: &str| {
+        get_arg(arg).map(|path| {
+            Sink::open(Path::new(&path))
+                .unwrap_or_else(|err| panic!("Failed to open {}: {}", path, err))
+        })
+    };
+    let video = open("--record-video");
+    let audio = open("--record-audio");
+    if video.is_none() && audio.is_none() {
+        return None;
+    }
+    let sample_rate = builder.audio_out.sample_rate();
+    let channels = builder.audio_out.channels();
+    let recorder = Recorder::new(builder.region(), sample_rate, channels, video, audio)
+        .expect("Failed to start recording");
+    Some(recorder)
+}
+
+fn start_emulator(cart: Cart, config: Config) {
+    let sdl = corrosion::sdl2::init().unwrap();
+    let event_pump = Rc::new(RefCell::new(sdl.event_pump().unwrap()));
+
+    let mut builder =
+        EmulatorBuilder::new_sdl(cart, make_emulator_settings(&config), &sdl, &event_pump);
+
+    let directory = config
+        .get_str("screenshots.directory")
+        .unwrap_or_else(|_| "screenshots".to_owned());
+    let sdl_screen = mem::replace(&mut builder.screen, Box::new(DummyScreen::default()));
+    let mut png_screen = PngScreen::wrapping(PathBuf::from(directory), sdl_screen);
+    png_screen.set_interval(get_u32(&config, "screenshots.every_nth_frame", 0));
+    let screenshot = png_screen.request();
+    builder.screen = Box::new(png_screen);
+
+    if let Some(recorder) = make_recorder(&builder) {
+        let screen = mem::replace(&mut builder.screen, Box::new(DummyScreen::default()));
+        builder.screen = recorder.wrap_screen(screen);
+        let audio_out = mem::replace(&mut builder.audio_out, Box::new(DummyAudioOut));
+        builder.audio_out = recorder.wrap_audio(audio_out);
+    }
+
+    let palette = make_palette(&config);
+    builder.screen.set_palette(&palette);
+
+    let mut io = SdlIO::with_config(
+        &sdl,
+        event_pump.clone(),
+        get_port_layout(&config),
+        make_input_config(&config),
+    );
+    if get_bool(&config, "zapper", false) {
+        io.connect_zapper();
+    }
+    let io_events = io.events();
+    builder.io = Box::new(io);
+
+    if let Some(file) = get_movie_file() {
+        let fm2io = corrosion::io::fm2::FM2IO::read(file).unwrap();
+        builder.io = Box::new(fm2io)
+    }
+
+    let sample_rate = builder.audio_out.sample_rate();
+    let mut emulator = builder.build();
+    set_channel_mixes(&config, &mut emulator);
+    if let Some(directory) = get_arg("--record-stems") {
+        let stems = StemWriter::new(Path::new(&directory), sample_rate)
+            .expect("Failed to create stem files");
+        emulator.set_stem_out(Some(Box::new(stems)));
+    }
+    let mut viewers = DebugViewers::new(&sdl, &config, &palette, &mut emulator);
+
+    let mut stopwatch = Stopwatch::start_new();
+    let smoothing = 0.9;
+    let mut avg_frame_time = 0.0f64;
+    let mousepick_enabled = config.get_bool("debug.mousepick").unwrap_or(false);
+    let audio_stats_enabled = get_bool(&config, "debug.audio_stats", false);
+    let mut frames = 0u64;
+    loop {
+        if pump_events(&event_pump, &screenshot, &io_events) || emulator.halted() {
+            break;
+        }
+        emulator.run_frame();
+        viewers.update(&mut emulator);
+        frames += 1;
+        if audio_stats_enabled && frames % AUDIO_STATS_INTERVAL == 0 {
+            print_audio_stats(&emulator);
+        }
+        let current = stopwatch.elapsed().num_nanoseconds().unwrap() as f64;
+        avg_frame_time = (avg_frame_time * smoothing) + (current * (1.0 - smoothing));
+
+        if mousepick_enabled {
+            mouse_pick(&event_pump, &emulator);
+        }
+
+        // println!("Frames per second:{:.*}", 2, 1000000000.0 / avg_frame_time);
+        stopwatch.restart();
+    }
+}
+
+fn print_track(nsf: &Nsf, song: u8) {
+    let title = nsf.track_title(song).unwrap_or("");
+    match nsf.track_time(song) {
+        Some(ms) => println!(
+            "Track {}/{}: {} ({}:{:02})",
+            song + 1,
+            nsf.song_count,
+            title,
+            ms / 60000,
+            ms / 1000 % 60
+        ),
+        None => println!("Track {}/{}: {}", song + 1, nsf.song_count, title),
+    }
+}
+
+/// Plays an NSF or NSFe file. The track to start on can be given with
+/// `--track N`, counting from one; Left and Right switch tracks. Tracks with
+/// a known length advance to the next one when they end.
+fn play_nsf(nsf: Nsf, config: Config) {
+    let sdl = corrosion::sdl2::init().unwrap();
+    let event_pump = Rc::new(RefCell::new(sdl.event_pump().unwrap()));
+
+    let region = get_region(&config).unwrap_or_else(|| nsf.default_region());
+    let mut settings = make_emulator_settings(&config);
+    settings.region = Some(region);
+
+    println!("{}", nsf.title);
+    println!("{}", nsf.artist);
+    println!("{}", nsf.copyright);
+    if !nsf.expansion.is_empty() {
+        println!(
+            "Warning: expansion audio ({:?}) isn't supported and will be silent.",
+            nsf.expansion
+        );
+    }
+    for song in 0..nsf.song_count {
+        print_track(&nsf, song);
+    }
+
+    let mut song = match get_arg("--track") {
+        Some(track) => {
+            let track: u8 = track.parse().expect("Invalid track number");
+            assert!(
+                track >= 1 && track <= nsf.song_count,
+                "Track number must be between 1 and {}",
+                nsf.song_count
+            );
+            track - 1
+        }
+        None => nsf.starting_song,
+    };
+
+    let mut builder =
+        EmulatorBuilder::new_sdl(nsf.cart(song, region), settings.clone(), &sdl, &event_pump);
+    builder.io = Box::new(DummyIO::Dummy);
+    let mut emulator = builder.build();
+    set_channel_mixes(&config, &mut emulator);
+    println!();
+    print_track(&nsf, song);
+
+    let (frame_rate_num, frame_rate_den) = region.frame_rate();
+    let mut frames = 0u64;
+    loop {
+        let elapsed_ms = frames * 1000 * frame_rate_den / frame_rate_num;
+        let ended = match nsf.track_time(song) {
+            Some(ms) => elapsed_ms >= ms as u64,
+            None => false,
+        };
+        let change = if ended {
+            Some(TrackChange::Next)
+        } else {
+            pump_nsf_events(&event_pump)
+        };
+
+        let next_song = match change {
+            Some(TrackChange::Quit) => break,
+            Some(TrackChange::Previous) if song > 0 => Some(song - 1),
+            Some(TrackChange::Next) if song + 1 < nsf.song_count => Some(song + 1),
+            Some(TrackChange::Next) if ended => break,
+            _ => None,
+        };
+        if let Some(next_song) = next_song {
+            song = next_song;
+            let mut next = EmulatorBuilder::new(nsf.cart(song, region), settings.clone()).build();
+            next.set_screen(emulator.set_screen(Box::new(DummyScreen::default())));
+            next.set_audio_out(emulator.set_audio_out(Box::new(DummyAudioOut)));
+            set_channel_mixes(&config, &mut next);
+            emulator = next;
+            frames = 0;
+            print_track(&nsf, song);
+        }
+
+        emulator.run_frame();
+        frames += 1;
+    }
+}
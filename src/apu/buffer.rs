@@ -1,85 +1,139 @@
-//! Thin layer over `BlipBuf` which provides a slightly more convenient
-//! interface.
-
-use apu::Sample;
-use blip_buf::BlipBuf;
-use std::cell::RefCell;
-use std::rc::Rc;
-
-const NES_CLOCK_RATE: u64 = 1789773;
-const NES_FPS: usize = 60;
-const FRAMES_PER_BUFFER: usize = 1;
-
-pub struct SampleBuffer {
-    blip: BlipBuf,
-    samples: Vec<Sample>,
-    transfer_samples: u32,
-}
-
-/// Blip Buffer combined with a Vec to store the samples transferred out of the
-/// buffer, so we don't have to either allocate memory every transfer.
-impl SampleBuffer {
-    pub fn new(out_rate: f64) -> SampleBuffer {
-        let samples_per_frame = out_rate as u32 / NES_FPS as u32;
-        let transfer_samples = samples_per_frame * FRAMES_PER_BUFFER as u32;
-
-        let mut buf = BlipBuf::new(transfer_samples);
-        buf.set_rates(NES_CLOCK_RATE as f64, out_rate);
-        let samples = vec![0; (transfer_samples) as usize];
-
-        SampleBuffer {
-            blip: buf,
-            samples: samples,
-            transfer_samples: transfer_samples,
-        }
-    }
-
-    pub fn read(&mut self) -> &[Sample] {
-        let samples_read = self.blip.read_samples(&mut self.samples, false);
-        let slice: &[Sample] = &self.samples;
-        &slice[0..samples_read]
-    }
-
-    pub fn add_delta(&mut self, clock_time: u32, delta: i32) {
-        self.blip.add_delta(clock_time, delta)
-    }
-
-    pub fn end_frame(&mut self, clock_duration: u32) {
-        self.blip.end_frame(clock_duration)
-    }
-
-    pub fn clocks_needed(&self) -> u32 {
-        self.blip.clocks_needed(self.transfer_samples)
-    }
-}
-
-/// Allows multiple channels to share a `SampleBuffer` but maintain separate
-/// waveforms.
-pub struct Waveform {
-    buffer: Rc<RefCell<SampleBuffer>>,
-    last_amp: Sample,
-    volume_mult: i32,
-}
-
-impl Waveform {
-    pub fn new(buffer: Rc<RefCell<SampleBuffer>>, volume_mult: i32) -> Waveform {
-        Waveform {
-            buffer: buffer,
-            last_amp: 0,
-            volume_mult: volume_mult,
-        }
-    }
-
-    pub fn set_amplitude(&mut self, amp: Sample, cycle: u32) {
-        let last_amp = self.last_amp;
-        let delta = (amp - last_amp) as i32;
-        if delta == 0 {
-            return;
-        }
-        self.buffer.borrow_mut().add_delta(
-            cycle,
-            delta * self.volume_mult,
-        );
-        self.last_amp = amp;
-    }
-}
+//! Thin layer over `BlipBuf` which provides a slightly more convenient
+//! interface.
+
+use Region;
+use apu::Sample;
+use apu::mixer::{Channel, Mixer};
+use audio::MAX_RATE_ADJUSTMENT;
+use blip_buf::BlipBuf;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+const FRAMES_PER_BUFFER: usize = 1;
+
+pub struct SampleBuffer {
+    blip: BlipBuf,
+    samples: Vec<Sample>,
+    /// The exact number of samples per transfer, which is rarely a whole
+    /// number.
+    transfer_samples: f64,
+    /// The fraction of a sample left over from previous transfers. Carrying
+    /// it forward keeps the transfers in step with the frames.
+    remainder: f64,
+    clock_rate: f64,
+    out_rate: f64,
+}
+
+/// Blip Buffer combined with a Vec to store the samples transferred out of the
+/// buffer, so we don't have to either allocate memory every transfer.
+impl SampleBuffer {
+    pub fn new(out_rate: f64, region: Region) -> SampleBuffer {
+        let (frame_rate_num, frame_rate_den) = region.frame_rate();
+        let samples_per_frame = out_rate * frame_rate_den as f64 / frame_rate_num as f64;
+        let transfer_samples = samples_per_frame * FRAMES_PER_BUFFER as f64;
+        // Rate control can stretch a frame's clocks by up to the maximum
+        // adjustment, and buffers left at the nominal rate (like the stems)
+        // then get that many more samples.
+        let capacity = (transfer_samples / (1.0 - MAX_RATE_ADJUSTMENT)).ceil() as u32 + 1;
+
+        let mut buf = BlipBuf::new(capacity);
+        buf.set_rates(region.cpu_clock_rate() as f64, out_rate);
+        let samples = vec![0; capacity as usize];
+
+        SampleBuffer {
+            blip: buf,
+            samples: samples,
+            transfer_samples: transfer_samples,
+            remainder: 0.0,
+            clock_rate: region.cpu_clock_rate() as f64,
+            out_rate: out_rate,
+        }
+    }
+
+    /// The number of whole samples the next transfer should have.
+    fn next_transfer_samples(&self) -> u32 {
+        (self.transfer_samples + self.remainder) as u32
+    }
+
+    /// Scales the output rate by `ratio` from the one the buffer was created
+    /// with, to speed up or slow down the rate samples are produced at.
+    pub fn set_rate_adjustment(&mut self, ratio: f64) {
+        self.blip.set_rates(self.clock_rate, self.out_rate * ratio);
+    }
+
+    pub fn read(&mut self) -> &[Sample] {
+        let next = self.next_transfer_samples();
+        self.remainder += self.transfer_samples - next as f64;
+        let samples_read = self.blip.read_samples(&mut self.samples, false);
+        let slice: &[Sample] = &self.samples;
+        &slice[0..samples_read]
+    }
+
+    pub fn add_delta(&mut self, clock_time: u32, delta: i32) {
+        self.blip.add_delta(clock_time, delta)
+    }
+
+    pub fn end_frame(&mut self, clock_duration: u32) {
+        self.blip.end_frame(clock_duration)
+    }
+
+    pub fn clocks_needed(&self) -> u32 {
+        self.blip.clocks_needed(self.next_transfer_samples())
+    }
+}
+
+/// The level of one of the APU's pulses at full volume, on the scale of
+/// `Waveform::set_level`. Expansion audio chips are scaled relative to it,
+/// since their loudness is usually described against the APU's pulses.
+pub const PULSE_LEVEL: f32 = 0.00752 * 15.0;
+
+/// Allows multiple channels to share a `Mixer` while each sets its own
+/// output level.
+#[derive(Clone)]
+pub struct Waveform {
+    mixer: Rc<RefCell<Mixer>>,
+    channel: Channel,
+}
+
+impl Waveform {
+    pub fn new(mixer: Rc<RefCell<Mixer>>, channel: Channel) -> Waveform {
+        Waveform {
+            mixer: mixer,
+            channel: channel,
+        }
+    }
+
+    pub fn set_amplitude(&mut self, amp: Sample, cycle: u32) {
+        self.mixer.borrow_mut().set_level(
+            self.channel,
+            amp as f32,
+            cycle,
+        );
+    }
+
+    /// Sets a level directly on the mixer's scale, where 1.0 is about the
+    /// loudest the APU's channels get. Used for expansion audio, which isn't
+    /// mixed through the APU's DACs.
+    pub fn set_level(&mut self, level: f32, cycle: u32) {
+        self.mixer.borrow_mut().set_level(self.channel, level, cycle);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transfers_follow_the_exact_frame_rate() {
+        // 44100Hz over 60.0988 NTSC frames per second is about 733.8 samples
+        // per frame, which an integer transfer size would round away.
+        let mut buffer = SampleBuffer::new(44100.0, Region::NTSC);
+        let mut samples = 0;
+        for _ in 0..600 {
+            let clocks = buffer.clocks_needed();
+            buffer.end_frame(clocks);
+            samples += buffer.read().len();
+        }
+        assert!((samples as i64 - 440274).abs() <= 1);
+    }
+}
@@ -1,30 +1,243 @@
-//! Contains structures used by the NES's DMC channel.
-
-use apu::Writable;
-
-#[allow(dead_code)]
-pub struct DMC {
-    freq: u8,
-    direct: u8,
-    sample_addr: u8,
-    sample_length: u8,
-}
-
-#[allow(unused_variables)]
-impl DMC {
-    pub fn new() -> DMC {
-        DMC {
-            freq: 0,
-            direct: 0,
-            sample_addr: 0,
-            sample_length: 0,
-        }
-    }
-
-    pub fn play(&mut self, from_cyc: u32, to_cyc: u32) {}
-}
-
-#[allow(unused_variables)]
-impl Writable for DMC {
-    fn write(&mut self, idx: u16, val: u8) {}
-}
+//! Contains structures used by the NES's DMC channel.
+
+use Region;
+use apu::Writable;
+use apu::buffer::Waveform;
+use cart::Cart;
+use std::cell::UnsafeCell;
+use std::rc::Rc;
+
+static NTSC_RATE_TABLE: [u16; 16] = [
+    428,
+    380,
+    340,
+    320,
+    286,
+    254,
+    226,
+    214,
+    190,
+    160,
+    142,
+    128,
+    106,
+    84,
+    72,
+    54,
+];
+
+static PAL_RATE_TABLE: [u16; 16] = [
+    398,
+    354,
+    316,
+    298,
+    276,
+    236,
+    210,
+    198,
+    176,
+    148,
+    132,
+    118,
+    98,
+    78,
+    66,
+    50,
+];
+
+/// The DMC plays 1-bit delta-encoded samples, which it fetches a byte at a
+/// time from $8000-$FFFF. Those addresses always map to the cartridge, so it
+/// reads the sample straight from there.
+#[derive(Clone)]
+pub struct DMC {
+    irq_enabled: bool,
+    loop_flag: bool,
+    rate_index: u8,
+    sample_addr: u16,
+    sample_length: u16,
+
+    rate_table: &'static [u16; 16],
+    cart: Rc<UnsafeCell<Cart>>,
+
+    // Memory reader
+    current_addr: u16,
+    bytes_remaining: u16,
+    sample_buffer: Option<u8>,
+
+    // Output unit
+    timer_remaining: u32,
+    shift_register: u8,
+    bits_remaining: u8,
+    silence: bool,
+
+    pub irq_flag: bool,
+
+    /// The 7-bit output level, which is set directly by writes to $4011.
+    output_level: u8,
+    waveform: Waveform,
+}
+
+impl DMC {
+    pub fn new(waveform: Waveform, cart: Rc<UnsafeCell<Cart>>, region: Region) -> DMC {
+        let rate_table = match region {
+            Region::PAL => &PAL_RATE_TABLE,
+            Region::NTSC | Region::Dendy => &NTSC_RATE_TABLE,
+        };
+        DMC {
+            irq_enabled: false,
+            loop_flag: false,
+            rate_index: 0,
+            sample_addr: 0xC000,
+            sample_length: 1,
+
+            rate_table: rate_table,
+            cart: cart,
+
+            current_addr: 0xC000,
+            bytes_remaining: 0,
+            sample_buffer: None,
+
+            timer_remaining: rate_table[0] as u32,
+            shift_register: 0,
+            bits_remaining: 8,
+            silence: true,
+
+            irq_flag: false,
+
+            output_level: 0,
+            waveform: waveform,
+        }
+    }
+
+    /// The number of CPU cycles between output bits at the current rate.
+    pub fn period(&self) -> u16 {
+        self.rate_table[self.rate_index as usize]
+    }
+
+    /// True while there are bytes of the sample left to fetch.
+    pub fn active(&self) -> bool {
+        self.bytes_remaining > 0
+    }
+
+    /// Handles the DMC's bit of a $4015 write, which also acknowledges its
+    /// interrupt.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.irq_flag = false;
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    fn restart(&mut self) {
+        self.current_addr = self.sample_addr;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    /// The number of cycles from the last cycle run until the DMC raises its
+    /// interrupt, if it's going to.
+    pub fn cycles_until_irq(&self) -> Option<u64> {
+        if !self.irq_enabled || self.loop_flag || self.bytes_remaining == 0 {
+            return None;
+        }
+        if self.sample_buffer.is_none() {
+            return Some(0);
+        }
+        // The buffer is refilled each time the output unit empties it, which
+        // happens after its current bits and then every eight bits.
+        let period = self.period() as u64;
+        let first_refill = self.timer_remaining as u64 + (self.bits_remaining as u64 - 1) * period;
+        Some(first_refill + (self.bytes_remaining as u64 - 1) * 8 * period)
+    }
+
+    /// Fetches the next byte of the sample if the buffer is empty. Returns
+    /// true if that raised the interrupt.
+    fn fill_buffer(&mut self) -> bool {
+        if self.sample_buffer.is_some() || self.bytes_remaining == 0 {
+            return false;
+        }
+        let addr = self.current_addr;
+        let byte = unsafe { (*self.cart.get()).prg_read(addr) };
+        self.sample_buffer = Some(byte);
+        self.current_addr = if addr == 0xFFFF { 0x8000 } else { addr + 1 };
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.loop_flag {
+                self.restart();
+            } else if self.irq_enabled && !self.irq_flag {
+                self.irq_flag = true;
+                return true;
+            }
+        }
+        false
+    }
+
+    fn clock_output(&mut self, cycle: u32, output: bool) {
+        if !self.silence {
+            if self.shift_register & 0x01 != 0 {
+                if self.output_level <= 125 {
+                    self.output_level += 2;
+                }
+            } else if self.output_level >= 2 {
+                self.output_level -= 2;
+            }
+            if output {
+                self.waveform.set_amplitude(self.output_level as i16, cycle);
+            }
+        }
+        self.shift_register >>= 1;
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.sample_buffer.take() {
+                Some(byte) => {
+                    self.silence = false;
+                    self.shift_register = byte;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+
+    /// Runs the DMC, producing sound only if `output` is true. The memory
+    /// reader and interrupt have to keep running even when the sound isn't
+    /// wanted. Returns true if the interrupt was raised.
+    pub fn run(&mut self, from_cyc: u32, to_cyc: u32, output: bool) -> bool {
+        if output {
+            self.waveform.set_amplitude(self.output_level as i16, from_cyc);
+        }
+        let mut irq = false;
+        let mut current_cyc = from_cyc;
+        loop {
+            irq |= self.fill_buffer();
+            if current_cyc + self.timer_remaining > to_cyc {
+                self.timer_remaining -= to_cyc - current_cyc;
+                break;
+            }
+            current_cyc += self.timer_remaining;
+            self.timer_remaining = self.period() as u32;
+            self.clock_output(current_cyc, output);
+        }
+        irq
+    }
+}
+
+impl Writable for DMC {
+    fn write(&mut self, idx: u16, val: u8, _: bool) {
+        match idx % 4 {
+            0 => {
+                self.irq_enabled = val & 0b1000_0000 != 0;
+                self.loop_flag = val & 0b0100_0000 != 0;
+                self.rate_index = val & 0b0000_1111;
+                if !self.irq_enabled {
+                    self.irq_flag = false;
+                }
+            }
+            1 => self.output_level = val & 0x7F,
+            2 => self.sample_addr = 0xC000 | (val as u16) << 6,
+            3 => self.sample_length = (val as u16) << 4 | 1,
+            _ => (),
+        }
+    }
+}
@@ -0,0 +1,628 @@
+//! Combines the output levels of the APU's channels into a single signal.
+//!
+//! The real APU mixes the two pulse channels through one resistor DAC and
+//! the triangle, noise and DMC channels through another. Neither is linear:
+//! each channel gets quieter the louder the others are. The NES then passes
+//! the result through two high-pass filters (at 90Hz and 440Hz) and a 14kHz
+//! low-pass filter on its way to the TV. Because the mix isn't linear, the
+//! channels can't each add their own deltas to the sample buffer; instead
+//! they report their levels here, and the mixer adds the change in the
+//! combined output whenever any of them changes.
+
+use Region;
+use apu::Sample;
+use apu::buffer::SampleBuffer;
+use std::f32::consts::PI;
+
+/// How the channels are mixed.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MixerMode {
+    /// Mix through the nonlinear DAC curves of the real APU, then through the
+    /// console's high- and low-pass output filters.
+    Accurate,
+    /// Mix linearly, with no filtering. Brighter and cleaner than the real
+    /// thing.
+    Clean,
+}
+
+/// The sound channels, each of which can be mixed separately.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Channel {
+    Square1 = 0,
+    Square2 = 1,
+    Triangle = 2,
+    Noise = 3,
+    DMC = 4,
+    /// The channels of the cartridge's own audio hardware, on mappers which
+    /// have any, in the order the mapper numbers them.
+    Expansion1 = 5,
+    Expansion2 = 6,
+    Expansion3 = 7,
+    Expansion4 = 8,
+    Expansion5 = 9,
+    Expansion6 = 10,
+    Expansion7 = 11,
+    Expansion8 = 12,
+}
+
+pub const ALL_CHANNELS: [Channel; 13] = [
+    Channel::Square1,
+    Channel::Square2,
+    Channel::Triangle,
+    Channel::Noise,
+    Channel::DMC,
+    Channel::Expansion1,
+    Channel::Expansion2,
+    Channel::Expansion3,
+    Channel::Expansion4,
+    Channel::Expansion5,
+    Channel::Expansion6,
+    Channel::Expansion7,
+    Channel::Expansion8,
+];
+
+/// The expansion channels, enough for the Namco 163's eight.
+pub const EXPANSION_CHANNELS: [Channel; 8] = [
+    Channel::Expansion1,
+    Channel::Expansion2,
+    Channel::Expansion3,
+    Channel::Expansion4,
+    Channel::Expansion5,
+    Channel::Expansion6,
+    Channel::Expansion7,
+    Channel::Expansion8,
+];
+
+const CHANNELS: usize = 13;
+
+impl Channel {
+    /// The name used for this channel in config files and stem file names.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Channel::Square1 => "square1",
+            Channel::Square2 => "square2",
+            Channel::Triangle => "triangle",
+            Channel::Noise => "noise",
+            Channel::DMC => "dmc",
+            Channel::Expansion1 => "expansion1",
+            Channel::Expansion2 => "expansion2",
+            Channel::Expansion3 => "expansion3",
+            Channel::Expansion4 => "expansion4",
+            Channel::Expansion5 => "expansion5",
+            Channel::Expansion6 => "expansion6",
+            Channel::Expansion7 => "expansion7",
+            Channel::Expansion8 => "expansion8",
+        }
+    }
+
+    /// Whether this is one of the cartridge's channels, whose levels are
+    /// already on the mixer's output scale.
+    fn is_expansion(&self) -> bool {
+        *self as usize >= Channel::Expansion1 as usize
+    }
+}
+
+/// How loud a channel is and where it sits in the stereo field.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ChannelMix {
+    /// 0.0 is silent and 1.0 the normal volume.
+    pub volume: f32,
+    /// -1.0 is fully left, 0.0 centered and 1.0 fully right.
+    pub pan: f32,
+    pub muted: bool,
+    /// While any channel is soloed, only the soloed channels are heard.
+    pub solo: bool,
+}
+
+impl Default for ChannelMix {
+    fn default() -> ChannelMix {
+        ChannelMix {
+            volume: 1.0,
+            pan: 0.0,
+            muted: false,
+            solo: false,
+        }
+    }
+}
+
+/// The output for the loudest possible mix of the APU's channels, which is
+/// just over 1.0 before scaling.
+const OUTPUT_SCALE: f32 = 32000.0;
+
+const LEFT: usize = 0;
+const RIGHT: usize = 1;
+
+/// Looks up a fractional index in a table, interpolating between entries.
+/// Channels mixed at other than full volume don't land on whole entries.
+fn lookup(table: &[f32], index: f32) -> f32 {
+    let index = index.max(0.0).min((table.len() - 1) as f32);
+    let whole = index as usize;
+    let fraction = index - whole as f32;
+    if fraction == 0.0 {
+        table[whole]
+    } else {
+        table[whole] + (table[whole + 1] - table[whole]) * fraction
+    }
+}
+
+/// A first-order high-pass filter.
+struct HighPass {
+    alpha: f32,
+    last_input: f32,
+    last_output: f32,
+}
+
+impl HighPass {
+    fn new(cutoff: f32, sample_rate: f32) -> HighPass {
+        let rc = 1.0 / (2.0 * PI * cutoff);
+        let dt = 1.0 / sample_rate;
+        HighPass {
+            alpha: rc / (rc + dt),
+            last_input: 0.0,
+            last_output: 0.0,
+        }
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        self.last_output = self.alpha * (self.last_output + input - self.last_input);
+        self.last_input = input;
+        self.last_output
+    }
+}
+
+/// A first-order low-pass filter.
+struct LowPass {
+    alpha: f32,
+    last_output: f32,
+}
+
+impl LowPass {
+    fn new(cutoff: f32, sample_rate: f32) -> LowPass {
+        let rc = 1.0 / (2.0 * PI * cutoff);
+        let dt = 1.0 / sample_rate;
+        LowPass {
+            alpha: dt / (rc + dt),
+            last_output: 0.0,
+        }
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        self.last_output += self.alpha * (input - self.last_output);
+        self.last_output
+    }
+}
+
+/// The filters between the APU and the NES's audio output.
+struct FilterChain {
+    high_pass_90: HighPass,
+    high_pass_440: HighPass,
+    low_pass_14k: LowPass,
+}
+
+impl FilterChain {
+    fn new(sample_rate: f32) -> FilterChain {
+        FilterChain {
+            high_pass_90: HighPass::new(90.0, sample_rate),
+            high_pass_440: HighPass::new(440.0, sample_rate),
+            low_pass_14k: LowPass::new(14000.0, sample_rate),
+        }
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        let sample = self.high_pass_90.process(input);
+        let sample = self.high_pass_440.process(sample);
+        self.low_pass_14k.process(sample)
+    }
+}
+
+/// A channel's output on its own, kept for exporting stems.
+struct Stem {
+    buffer: SampleBuffer,
+    filters: FilterChain,
+    last_output: i32,
+    samples: Vec<Sample>,
+}
+
+pub struct Mixer {
+    mode: MixerMode,
+    sample_rate: f64,
+    /// The number of channels `read` returns. Mono output is the average of
+    /// the left and right sides.
+    output_channels: usize,
+    region: Region,
+
+    buffers: [SampleBuffer; 2],
+    filters: [FilterChain; 2],
+    samples: Vec<Sample>,
+    stems: Vec<Stem>,
+
+    /// Indexed by the sum of the two pulse levels
+    pulse_table: Vec<f32>,
+    /// Indexed by 3 * triangle + 2 * noise + DMC
+    tnd_table: Vec<f32>,
+
+    mixes: [ChannelMix; CHANNELS],
+    /// The left and right gain for each channel, worked out from the mixes.
+    gains: [[f32; 2]; CHANNELS],
+
+    levels: [f32; CHANNELS],
+    last_output: [i32; 2],
+}
+
+impl Mixer {
+    pub fn new(mode: MixerMode, sample_rate: f64, output_channels: usize, region: Region) -> Mixer {
+        let pulse_table = (0..31)
+            .map(|n| if n == 0 {
+                0.0
+            } else {
+                95.52 / (8128.0 / n as f32 + 100.0)
+            })
+            .collect();
+        let tnd_table = (0..203)
+            .map(|n| if n == 0 {
+                0.0
+            } else {
+                163.67 / (24329.0 / n as f32 + 100.0)
+            })
+            .collect();
+
+        Mixer {
+            mode: mode,
+            sample_rate: sample_rate,
+            output_channels: output_channels,
+            region: region,
+
+            buffers: [
+                SampleBuffer::new(sample_rate, region),
+                SampleBuffer::new(sample_rate, region),
+            ],
+            filters: [
+                FilterChain::new(sample_rate as f32),
+                FilterChain::new(sample_rate as f32),
+            ],
+            samples: vec![],
+            stems: vec![],
+
+            pulse_table: pulse_table,
+            tnd_table: tnd_table,
+
+            mixes: [ChannelMix::default(); CHANNELS],
+            gains: [[1.0; 2]; CHANNELS],
+
+            levels: [0.0; CHANNELS],
+            last_output: [0; 2],
+        }
+    }
+
+    fn new_stem(&self) -> Stem {
+        Stem {
+            buffer: SampleBuffer::new(self.sample_rate, self.region),
+            filters: FilterChain::new(self.sample_rate as f32),
+            last_output: 0,
+            samples: vec![],
+        }
+    }
+
+    /// Replaces the sample buffers with ones for a different output rate or
+    /// number of channels.
+    pub fn set_output_format(&mut self, sample_rate: f64, output_channels: usize, region: Region) {
+        self.sample_rate = sample_rate;
+        self.output_channels = output_channels;
+        self.region = region;
+        for side in 0..2 {
+            self.buffers[side] = SampleBuffer::new(sample_rate, region);
+            self.filters[side] = FilterChain::new(sample_rate as f32);
+            // The new buffer starts from silence, so bring it up to the
+            // current output.
+            self.buffers[side].add_delta(0, self.last_output[side]);
+        }
+        let stems = !self.stems.is_empty();
+        self.set_stems_enabled(stems);
+    }
+
+    /// Starts or stops mixing each channel on its own as well, for
+    /// `read_stem`.
+    pub fn set_stems_enabled(&mut self, enabled: bool) {
+        self.stems.clear();
+        if enabled {
+            for &channel in ALL_CHANNELS.iter() {
+                let mut stem = self.new_stem();
+                stem.last_output = self.stem_output(channel);
+                stem.buffer.add_delta(0, stem.last_output);
+                self.stems.push(stem);
+            }
+        }
+    }
+
+    pub fn channel_mix(&self, channel: Channel) -> ChannelMix {
+        self.mixes[channel as usize]
+    }
+
+    pub fn set_channel_mix(&mut self, channel: Channel, mix: ChannelMix) {
+        self.mixes[channel as usize] = mix;
+        let any_solo = self.mixes.iter().any(|mix| mix.solo);
+        for (gains, mix) in self.gains.iter_mut().zip(self.mixes.iter()) {
+            let audible = !mix.muted && (mix.solo || !any_solo);
+            let volume = if audible { mix.volume.max(0.0) } else { 0.0 };
+            let pan = mix.pan.max(-1.0).min(1.0);
+            gains[LEFT] = volume * (1.0 - pan).min(1.0);
+            gains[RIGHT] = volume * (1.0 + pan).min(1.0);
+        }
+        self.update_output(0);
+    }
+
+    /// The output of one side, from the channels' levels scaled by their
+    /// gains on that side.
+    fn output(&self, side: usize) -> i32 {
+        let level = |channel: Channel| {
+            self.levels[channel as usize] * self.gains[channel as usize][side]
+        };
+        let pulse = level(Channel::Square1) + level(Channel::Square2);
+        let (triangle, noise, dmc) = (
+            level(Channel::Triangle),
+            level(Channel::Noise),
+            level(Channel::DMC),
+        );
+        let output = match self.mode {
+            MixerMode::Accurate => {
+                lookup(&self.pulse_table, pulse) +
+                    lookup(&self.tnd_table, 3.0 * triangle + 2.0 * noise + dmc)
+            }
+            MixerMode::Clean => {
+                0.00752 * pulse + 0.00851 * triangle + 0.00494 * noise + 0.00335 * dmc
+            }
+        };
+        let expansion: f32 = EXPANSION_CHANNELS.iter().map(|&channel| level(channel)).sum();
+        ((output + expansion) * OUTPUT_SCALE) as i32
+    }
+
+    /// The output of a channel on its own, at full volume.
+    fn stem_output(&self, channel: Channel) -> i32 {
+        let level = self.levels[channel as usize];
+        let output = match (self.mode, channel) {
+            (_, channel) if channel.is_expansion() => level,
+            (MixerMode::Accurate, Channel::Square1) |
+            (MixerMode::Accurate, Channel::Square2) => lookup(&self.pulse_table, level),
+            (MixerMode::Accurate, Channel::Triangle) => lookup(&self.tnd_table, 3.0 * level),
+            (MixerMode::Accurate, Channel::Noise) => lookup(&self.tnd_table, 2.0 * level),
+            (MixerMode::Accurate, Channel::DMC) => lookup(&self.tnd_table, level),
+            (MixerMode::Clean, Channel::Square1) |
+            (MixerMode::Clean, Channel::Square2) => 0.00752 * level,
+            (MixerMode::Clean, Channel::Triangle) => 0.00851 * level,
+            (MixerMode::Clean, Channel::Noise) => 0.00494 * level,
+            (MixerMode::Clean, Channel::DMC) => 0.00335 * level,
+            _ => unreachable!(),
+        };
+        (output * OUTPUT_SCALE) as i32
+    }
+
+    fn update_output(&mut self, cycle: u32) {
+        for side in 0..2 {
+            let output = self.output(side);
+            self.buffers[side].add_delta(cycle, output - self.last_output[side]);
+            self.last_output[side] = output;
+        }
+    }
+
+    /// Sets a channel's output level from the given cycle of the current
+    /// frame. For the APU's channels this is the value sent to the DAC
+    /// (0-15, or 0-127 for the DMC).
+    pub fn set_level(&mut self, channel: Channel, level: f32, cycle: u32) {
+        if self.levels[channel as usize] == level {
+            return;
+        }
+        self.levels[channel as usize] = level;
+        self.update_output(cycle);
+        if !self.stems.is_empty() {
+            let output = self.stem_output(channel);
+            let stem = &mut self.stems[channel as usize];
+            stem.buffer.add_delta(cycle, output - stem.last_output);
+            stem.last_output = output;
+        }
+    }
+
+    pub fn end_frame(&mut self, clock_duration: u32) {
+        for buffer in &mut self.buffers {
+            buffer.end_frame(clock_duration);
+        }
+        for stem in &mut self.stems {
+            stem.buffer.end_frame(clock_duration);
+        }
+    }
+
+    pub fn clocks_needed(&self) -> u32 {
+        self.buffers[LEFT].clocks_needed()
+    }
+
+    /// Scales the rate of the mixed output by `ratio`, for dynamic rate
+    /// control. Stems are always produced at the nominal rate, since they're
+    /// only written to files, so they get more or fewer samples per frame
+    /// than the mixed output.
+    pub fn set_rate_adjustment(&mut self, ratio: f64) {
+        for buffer in &mut self.buffers {
+            buffer.set_rate_adjustment(ratio);
+        }
+    }
+
+    /// Reads the samples mixed so far as interleaved left and right
+    /// samples, or mono ones, filtered if the mode calls for it.
+    pub fn read(&mut self) -> &[Sample] {
+        let output_channels = self.output_channels;
+        self.samples.clear();
+        let (left, right) = self.buffers.split_at_mut(RIGHT);
+        let (left, right) = (left[0].read(), right[0].read());
+        for (&left, &right) in left.iter().zip(right.iter()) {
+            if output_channels == 1 {
+                self.samples.push(((left as i32 + right as i32) / 2) as Sample);
+            } else {
+                self.samples.push(left);
+                self.samples.push(right);
+            }
+        }
+        if self.mode == MixerMode::Accurate {
+            for (idx, sample) in self.samples.iter_mut().enumerate() {
+                *sample = filter(&mut self.filters[idx % output_channels], *sample);
+            }
+        }
+        &self.samples
+    }
+
+    /// Reads the samples of a channel on its own since the last read. Only
+    /// available while stems are enabled.
+    pub fn read_stem(&mut self, channel: Channel) -> &[Sample] {
+        let filtered = self.mode == MixerMode::Accurate;
+        let stem = &mut self.stems[channel as usize];
+        stem.samples.clear();
+        stem.samples.extend_from_slice(stem.buffer.read());
+        if filtered {
+            for sample in &mut stem.samples {
+                *sample = filter(&mut stem.filters, *sample);
+            }
+        }
+        &stem.samples
+    }
+}
+
+fn filter(filters: &mut FilterChain, sample: Sample) -> Sample {
+    filters.process(sample as f32).max(-32768.0).min(32767.0) as Sample
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_frames(mixer: &mut Mixer, frames: usize) -> Vec<Sample> {
+        let mut samples = vec![];
+        for _ in 0..frames {
+            let clocks = mixer.clocks_needed();
+            mixer.end_frame(clocks);
+            samples = mixer.read().to_vec();
+        }
+        samples
+    }
+
+    #[test]
+    fn pulse_channels_mix_nonlinearly() {
+        let mut mixer = Mixer::new(MixerMode::Accurate, 44100.0, 2, Region::NTSC);
+        mixer.set_level(Channel::Square1, 15.0, 0);
+        let one = mixer.output(LEFT);
+        mixer.set_level(Channel::Square2, 15.0, 0);
+        let both = mixer.output(LEFT);
+        assert!(both < one * 2);
+        // 95.52 / (8128 / 30 + 100), the loudest the pulses can get
+        let expected = (0.257_512_6 * OUTPUT_SCALE) as i32;
+        assert!((both - expected).abs() <= 1);
+    }
+
+    #[test]
+    fn clean_mode_mixes_linearly() {
+        let mut mixer = Mixer::new(MixerMode::Clean, 44100.0, 2, Region::NTSC);
+        mixer.set_level(Channel::Triangle, 10.0, 0);
+        let triangle = mixer.output(LEFT);
+        mixer.set_level(Channel::Triangle, 0.0, 0);
+        mixer.set_level(Channel::Noise, 10.0, 0);
+        let noise = mixer.output(LEFT);
+        mixer.set_level(Channel::Triangle, 10.0, 0);
+        assert!((mixer.output(LEFT) - (triangle + noise)).abs() <= 1);
+    }
+
+    #[test]
+    fn filters_remove_dc_offset() {
+        let mut mixer = Mixer::new(MixerMode::Accurate, 44100.0, 2, Region::NTSC);
+        mixer.set_level(Channel::Triangle, 15.0, 0);
+        let samples = run_frames(&mut mixer, 30);
+        assert!(!samples.is_empty());
+        assert!(samples.iter().all(|&sample| sample.abs() < 10));
+    }
+
+    #[test]
+    fn channels_are_panned_muted_and_soloed() {
+        let mut mixer = Mixer::new(MixerMode::Clean, 44100.0, 2, Region::NTSC);
+        mixer.set_level(Channel::Square1, 15.0, 0);
+        mixer.set_level(Channel::Noise, 15.0, 0);
+        let square1 = (0.00752 * 15.0 * OUTPUT_SCALE) as i32;
+        let noise = (0.00494 * 15.0 * OUTPUT_SCALE) as i32;
+
+        let mut left = ChannelMix::default();
+        left.pan = -1.0;
+        mixer.set_channel_mix(Channel::Square1, left);
+        assert!((mixer.output(LEFT) - (square1 + noise)).abs() <= 1);
+        assert!((mixer.output(RIGHT) - noise).abs() <= 1);
+
+        let mut solo = ChannelMix::default();
+        solo.solo = true;
+        mixer.set_channel_mix(Channel::Noise, solo);
+        assert!((mixer.output(LEFT) - noise).abs() <= 1);
+        assert!((mixer.output(RIGHT) - noise).abs() <= 1);
+
+        let mut muted = solo;
+        muted.muted = true;
+        mixer.set_channel_mix(Channel::Noise, muted);
+        assert_eq!((0, 0), (mixer.output(LEFT), mixer.output(RIGHT)));
+    }
+
+    #[test]
+    fn expansion_channels_are_mixed_separately() {
+        let mut mixer = Mixer::new(MixerMode::Accurate, 44100.0, 2, Region::NTSC);
+        mixer.set_level(Channel::Expansion1, 0.1, 0);
+        mixer.set_level(Channel::Expansion2, 0.2, 0);
+        let both = mixer.output(LEFT);
+        assert!((both - (0.3 * OUTPUT_SCALE) as i32).abs() <= 1);
+
+        let mut muted = ChannelMix::default();
+        muted.muted = true;
+        mixer.set_channel_mix(Channel::Expansion2, muted);
+        assert!((mixer.output(LEFT) - (0.1 * OUTPUT_SCALE) as i32).abs() <= 1);
+    }
+
+    #[test]
+    fn stems_ignore_the_mix() {
+        let mut mixer = Mixer::new(MixerMode::Clean, 44100.0, 2, Region::NTSC);
+        mixer.set_stems_enabled(true);
+        let mut muted = ChannelMix::default();
+        muted.muted = true;
+        mixer.set_channel_mix(Channel::Triangle, muted);
+        mixer.set_level(Channel::Triangle, 15.0, 0);
+
+        let samples = run_frames(&mut mixer, 1);
+        assert!(samples.iter().all(|&sample| sample == 0));
+        let triangle = (0.00851 * 15.0 * OUTPUT_SCALE) as i32;
+        let stem = mixer.read_stem(Channel::Triangle).to_vec();
+        assert!(!stem.is_empty());
+        assert!((*stem.last().unwrap() as i32 - triangle).abs() <= 1);
+        assert!(mixer.read_stem(Channel::Noise).iter().all(|&sample| sample == 0));
+    }
+
+    #[test]
+    fn stems_stay_at_the_nominal_rate_under_rate_control() {
+        let mut mixer = Mixer::new(MixerMode::Clean, 44100.0, 2, Region::NTSC);
+        mixer.set_stems_enabled(true);
+        mixer.set_rate_adjustment(1.0 - ::audio::MAX_RATE_ADJUSTMENT);
+        mixer.set_level(Channel::Triangle, 15.0, 0);
+
+        let mut stem_samples = 0;
+        for _ in 0..600 {
+            let clocks = mixer.clocks_needed();
+            mixer.end_frame(clocks);
+            mixer.read();
+            stem_samples += mixer.read_stem(Channel::Triangle).len();
+        }
+        // The frames are stretched to give the slowed mixed output its 440274
+        // samples, so the stem gets 1 / 0.995 times as many.
+        assert!((stem_samples as i64 - 442486).abs() <= 2);
+    }
+
+    #[test]
+    fn mono_output_averages_both_sides() {
+        let mut mixer = Mixer::new(MixerMode::Clean, 44100.0, 1, Region::NTSC);
+        let mut left = ChannelMix::default();
+        left.pan = -1.0;
+        mixer.set_channel_mix(Channel::Triangle, left);
+        mixer.set_level(Channel::Triangle, 15.0, 0);
+
+        let samples = run_frames(&mut mixer, 2);
+        assert!(samples.len() > 700 && samples.len() < 800);
+        let triangle = (0.00851 * 15.0 * OUTPUT_SCALE) as i32;
+        assert!((*samples.last().unwrap() as i32 - triangle / 2).abs() <= 1);
+    }
+}
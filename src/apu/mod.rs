@@ -1,325 +1,540 @@
-mod components;
-mod buffer;
-mod square;
-mod triangle;
-mod noise;
-mod dmc;
-
-use Settings;
-use apu::buffer::*;
-use apu::dmc::*;
-use apu::noise::*;
-use apu::square::*;
-use apu::triangle::*;
-use audio::AudioOut;
-use cpu::IrqInterrupt;
-use std::cell::RefCell;
-use std::cmp;
-use std::rc::Rc;
-
-pub type Sample = i16;
-
-#[allow(zero_prefixed_literal)]
-static NTSC_TICK_LENGTH_TABLE: [[u64; 6]; 2] = [
-    [7459, 7456, 7458, 7458, 7458, 0000],
-    [0001, 7458, 7456, 7458, 7458, 7452],
-];
-
-const VOLUME_MULT: i32 = ((32767i16 / 16) / 3) as i32;
-
-bitflags! {
-    struct Frame : u8 {
-        const MODE = 0b1000_0000; //0 = 4-step, 1 = 5-step
-        const SUPPRESS_IRQ  = 0b0100_0000; //0 = disabled, 1 = enabled
-    }
-}
-
-impl Frame {
-    fn mode(&self) -> usize {
-        if self.contains(MODE) {
-            1
-        } else {
-            0
-        }
-    }
-}
-
-trait Writable {
-    fn write(&mut self, idx: u16, val: u8);
-}
-
-enum Jitter {
-    Delay(u64, u8),
-    None,
-}
-
-pub struct APU {
-    settings: Rc<Settings>,
-
-    square1: Square,
-    square2: Square,
-    triangle: Triangle,
-    noise: Noise,
-    dmc: DMC,
-    frame: Frame,
-
-    square_buffer: Rc<RefCell<SampleBuffer>>,
-    tnd_buffer: Rc<RefCell<SampleBuffer>>,
-
-    device: Box<AudioOut>,
-
-    global_cyc: u64,
-    tick: u8,
-    next_tick_cyc: u64,
-    next_transfer_cyc: u64,
-    last_frame_cyc: u64,
-
-    irq_requested: bool,
-
-    jitter: Jitter,
-}
-
-impl APU {
-    pub fn new(settings: Rc<Settings>, device: Box<AudioOut>) -> APU {
-        let sample_rate = device.sample_rate();
-
-        let square_buffer = Rc::new(RefCell::new(SampleBuffer::new(sample_rate)));
-        let tnd_buffer = Rc::new(RefCell::new(SampleBuffer::new(sample_rate)));
-        let clocks_needed = square_buffer.borrow().clocks_needed() as u64;
-
-        APU {
-            settings: settings,
-            square1: Square::new(false, Waveform::new(square_buffer.clone(), VOLUME_MULT)),
-            square2: Square::new(true, Waveform::new(square_buffer.clone(), VOLUME_MULT)),
-            triangle: Triangle::new(Waveform::new(tnd_buffer.clone(), VOLUME_MULT)),
-            noise: Noise::new(Waveform::new(tnd_buffer.clone(), VOLUME_MULT)),
-            dmc: DMC::new(),
-            frame: Frame::empty(),
-
-            square_buffer: square_buffer,
-            tnd_buffer: tnd_buffer,
-
-            device: device,
-
-            global_cyc: 0,
-            tick: 0,
-            next_tick_cyc: NTSC_TICK_LENGTH_TABLE[0][0],
-            next_transfer_cyc: clocks_needed,
-            last_frame_cyc: 0,
-
-            irq_requested: false,
-
-            jitter: Jitter::None,
-        }
-    }
-
-    pub fn run_to(&mut self, cpu_cycle: u64) -> IrqInterrupt {
-        let mut interrupt = IrqInterrupt::None;
-
-        while self.global_cyc < cpu_cycle {
-            let current_cycle = self.global_cyc;
-
-            let mut next_step = cmp::min(cpu_cycle, self.next_tick_cyc);
-            next_step = cmp::min(next_step, self.next_transfer_cyc);
-
-            if let Jitter::Delay(time, _) = self.jitter {
-                next_step = cmp::min(next_step, time);
-            }
-
-            if self.settings.sound_enabled {
-                self.play(current_cycle, next_step);
-            }
-            self.global_cyc = next_step;
-
-            if let Jitter::Delay(time, val) = self.jitter {
-                if self.global_cyc == time {
-                    self.set_4017(val);
-                    self.jitter = Jitter::None;
-                }
-            }
-            if self.global_cyc == self.next_tick_cyc {
-                interrupt = interrupt.or(self.tick());
-            }
-            if self.global_cyc == self.next_transfer_cyc {
-                self.transfer();
-            }
-        }
-        interrupt
-    }
-
-    /// Represents the 240Hz output of the frame sequencer's divider
-    fn tick(&mut self) -> IrqInterrupt {
-        self.tick += 1;
-        let mode = self.frame.mode();
-        self.next_tick_cyc = self.global_cyc + NTSC_TICK_LENGTH_TABLE[mode][self.tick as usize];
-
-        match mode {
-            0 => {
-                match self.tick {
-                    1 => {
-                        self.envelope_tick();
-                    }
-                    2 => {
-                        self.envelope_tick();
-                        self.length_tick();
-                    }
-                    3 => {
-                        self.envelope_tick();
-                    }
-                    4 => {
-                        self.tick = 0;
-                        self.envelope_tick();
-                        self.length_tick();
-                        return self.raise_irq();
-                    }
-                    _ => {
-                        self.tick = 0;
-                    }
-                }
-            }
-            1 => {
-                match self.tick {
-                    1 => {
-                        self.envelope_tick();
-                        self.length_tick()
-                    }
-                    2 => {
-                        self.envelope_tick();
-                    }
-                    3 => {
-                        self.envelope_tick();
-                        self.length_tick()
-                    }
-                    4 => {
-                        self.envelope_tick();
-                    }
-                    5 => {
-                        self.tick = 0;
-                    } //4 is the actual last tick in the cycle.
-                    _ => {
-                        self.tick = 0;
-                    }
-                }
-            }
-            _ => (),
-        }
-        IrqInterrupt::None
-    }
-
-    fn envelope_tick(&mut self) {
-        self.square1.envelope_tick();
-        self.square2.envelope_tick();
-        self.triangle.envelope_tick();
-        self.noise.envelope_tick();
-    }
-
-    fn length_tick(&mut self) {
-        self.square1.length_tick();
-        self.square2.length_tick();
-        self.triangle.length_tick();
-        self.noise.length_tick();
-    }
-
-    fn raise_irq(&mut self) -> IrqInterrupt {
-        if !self.frame.contains(SUPPRESS_IRQ) {
-            self.irq_requested = true;
-            IrqInterrupt::IRQ
-        } else {
-            IrqInterrupt::None
-        }
-    }
-
-    fn play(&mut self, from_cyc: u64, to_cyc: u64) {
-        let from = (from_cyc - self.last_frame_cyc) as u32;
-        let to = (to_cyc - self.last_frame_cyc) as u32;
-        self.square1.play(from, to);
-        self.square2.play(from, to);
-        self.triangle.play(from, to);
-        self.noise.play(from, to);
-        self.dmc.play(from, to);
-    }
-
-    fn transfer(&mut self) {
-        let cpu_cyc = self.global_cyc;
-        let cycles_since_last_frame = (cpu_cyc - self.last_frame_cyc) as u32;
-        self.last_frame_cyc = cpu_cyc;
-
-        if self.settings.sound_enabled {
-            let mut square_buf = self.square_buffer.borrow_mut();
-            let mut tnd_buf = self.tnd_buffer.borrow_mut();
-            tnd_buf.end_frame(cycles_since_last_frame);
-            square_buf.end_frame(cycles_since_last_frame);
-            let samples: Vec<Sample> = {
-                let iter1 = square_buf.read().iter().cloned();
-                let iter2 = tnd_buf.read().iter().cloned();
-                iter1.zip(iter2).map(|(s, t)| s.saturating_add(t)).collect()
-            };
-            self.next_transfer_cyc = cpu_cyc + square_buf.clocks_needed() as u64;
-            self.device.play(&samples);
-        } else {
-            self.next_transfer_cyc += 100000000;
-        }
-    }
-
-    /// Returns the cycle number representing the next time the CPU should run
-    /// the APU.
-    /// Min of the next APU IRQ, the next DMC IRQ, and the next tick time. When
-    /// the CPU cycle reaches
-    /// this number, the CPU must run the APU.
-    pub fn requested_run_cycle(&self) -> u64 {
-        // In practice, the next tick time should cover the APU IRQ as well, since the
-        // IRQ happens on tick boundaries. The DMC IRQ isn't implemented yet.
-        // Using the tick time ensures that the APU will never get too far behind the
-        // CPU.
-        self.next_tick_cyc
-    }
-
-    fn set_4017(&mut self, val: u8) {
-        self.frame = Frame::from_bits_truncate(val);
-        if self.frame.contains(SUPPRESS_IRQ) {
-            self.irq_requested = false;
-        }
-
-        self.tick = 0;
-        self.next_tick_cyc = self.global_cyc + NTSC_TICK_LENGTH_TABLE[self.frame.mode()][0];
-    }
-
-    #[cfg_attr(rustfmt, rustfmt_skip)]
-    pub fn read_status(&mut self, cycle: u64) -> (IrqInterrupt, u8) {
-        let interrupt = self.run_to(cycle - 1);
-
-        let mut status: u8 = 0;
-        status |= self.square1.length.active();
-        status |= self.square2.length.active() << 1;
-        status |= self.triangle.length.active() << 2;
-        status |= self.noise.length.active() << 3;
-        status |= if self.irq_requested { 1 << 6 } else { 0 };
-        self.irq_requested = false;
-
-        (interrupt.or(self.run_to(cycle)), status)
-    }
-
-    pub fn write(&mut self, idx: u16, val: u8) {
-        match idx % 0x20 {
-            x @ 0x00...0x03 => self.square1.write(x, val),
-            x @ 0x04...0x07 => self.square2.write(x, val),
-            x @ 0x08...0x0B => self.triangle.write(x, val),
-            x @ 0x0C...0x0F => self.noise.write(x, val),
-            x @ 0x10...0x13 => self.dmc.write(x, val),
-            0x0015 => {
-                self.noise.length.set_enable(val & 0b0000_1000 != 0);
-                self.triangle.length.set_enable(val & 0b0000_0100 != 0);
-                self.square2.length.set_enable(val & 0b0000_0010 != 0);
-                self.square1.length.set_enable(val & 0b0000_0001 != 0);
-            }
-            0x0017 => {
-                if self.global_cyc % 2 == 0 {
-                    self.set_4017(val);
-                } else {
-                    self.jitter = Jitter::Delay(self.global_cyc + 1, val);
-                }
-            }
-            _ => (),
-        }
-    }
-}
+mod components;
+mod buffer;
+mod square;
+mod triangle;
+mod noise;
+mod dmc;
+mod mixer;
+
+use Region;
+use Settings;
+use apu::buffer::*;
+use apu::dmc::*;
+use apu::mixer::Mixer;
+use apu::noise::*;
+use apu::square::*;
+use apu::triangle::*;
+use audio;
+use audio::{AudioOut, AudioStats, StemOut};
+use cpu::IrqInterrupt;
+use cart::Cart;
+use std::cell::{RefCell, UnsafeCell};
+use std::cmp;
+use std::mem;
+use std::rc::Rc;
+
+pub use apu::buffer::{PULSE_LEVEL, Waveform};
+// The MMC5's pulse channels are copies of the APU's.
+pub use apu::components::{Envelope, Length};
+pub use apu::mixer::{ALL_CHANNELS, Channel, ChannelMix, EXPANSION_CHANNELS, MixerMode};
+
+pub type Sample = i16;
+
+// What happens on each step of the frame sequencer
+/// Envelopes and the triangle's linear counter are clocked
+const QUARTER_FRAME: u8 = 0b001;
+/// Length counters and sweeps are clocked
+const HALF_FRAME: u8 = 0b010;
+/// The frame interrupt flag is set
+const FRAME_IRQ: u8 = 0b100;
+
+const QH: u8 = QUARTER_FRAME | HALF_FRAME;
+
+/// The steps of the frame sequencer in each mode, as the number of CPU cycles
+/// after it was reset. The sequence restarts from zero on the last step. In
+/// 4-step mode, the interrupt flag is set on three cycles in a row.
+#[cfg_attr(rustfmt, rustfmt_skip)]
+static NTSC_SEQUENCES: [[(u64, u8); 6]; 2] = [
+    [(7457, QUARTER_FRAME), (14913, QH), (22371, QUARTER_FRAME),
+     (29828, FRAME_IRQ), (29829, QH | FRAME_IRQ), (29830, FRAME_IRQ)],
+    [(7457, QUARTER_FRAME), (14913, QH), (22371, QUARTER_FRAME),
+     (29829, 0), (37281, QH), (37282, 0)],
+];
+
+#[cfg_attr(rustfmt, rustfmt_skip)]
+static PAL_SEQUENCES: [[(u64, u8); 6]; 2] = [
+    [(8313, QUARTER_FRAME), (16627, QH), (24939, QUARTER_FRAME),
+     (33252, FRAME_IRQ), (33253, QH | FRAME_IRQ), (33254, FRAME_IRQ)],
+    [(8313, QUARTER_FRAME), (16627, QH), (24939, QUARTER_FRAME),
+     (33253, 0), (41565, QH), (41566, 0)],
+];
+
+/// At power-on and reset, the sequencer starts as if $4017 had been written
+/// 9 to 12 cycles before the CPU's first instruction, which puts its reset
+/// this many cycles before that instruction.
+const POWER_UP_SEQUENCER_LEAD: u64 = 6;
+
+/// The CPU takes 7 cycles to start running again after a reset, so the
+/// sequencer restarts this many cycles after the reset.
+const RESET_SEQUENCER_DELAY: u64 = 7 - POWER_UP_SEQUENCER_LEAD;
+
+bitflags! {
+    struct Frame : u8 {
+        const MODE = 0b1000_0000; //0 = 4-step, 1 = 5-step
+        const SUPPRESS_IRQ  = 0b0100_0000; //0 = disabled, 1 = enabled
+    }
+}
+
+impl Frame {
+    fn mode(&self) -> usize {
+        if self.contains(MODE) {
+            1
+        } else {
+            0
+        }
+    }
+}
+
+trait Writable {
+    /// `half_frame` is true if the length counters were clocked on the same
+    /// cycle as the write.
+    fn write(&mut self, idx: u16, val: u8, half_frame: bool);
+}
+
+#[derive(Clone)]
+enum Jitter {
+    Delay(u64, u8),
+    None,
+}
+
+pub struct APU {
+    settings: Rc<Settings>,
+    sequences: &'static [[(u64, u8); 6]; 2],
+
+    square1: Square,
+    square2: Square,
+    triangle: Triangle,
+    noise: Noise,
+    dmc: DMC,
+    frame: Frame,
+
+    cart: Rc<UnsafeCell<Cart>>,
+    /// Where the channels of the cartridge's own sound hardware, if it has
+    /// any, are mixed in.
+    expansion: Vec<Waveform>,
+
+    mixer: Rc<RefCell<Mixer>>,
+
+    device: Box<AudioOut>,
+    stem_out: Option<Box<StemOut>>,
+
+    global_cyc: u64,
+    tick: u8,
+    next_tick_cyc: u64,
+    next_transfer_cyc: u64,
+    last_frame_cyc: u64,
+    /// The cycle the length counters were last clocked on.
+    half_frame_cyc: u64,
+    last_4017: u8,
+
+    irq_requested: bool,
+
+    jitter: Jitter,
+
+    /// When false, the channels are still clocked but no audio is generated
+    /// or sent to the device.
+    output_enabled: bool,
+}
+
+/// A snapshot of the APU, taken by `APU::save_state`. The sample buffers
+/// aren't included, so state should only be restored over periods where
+/// output was disabled.
+pub struct APUState {
+    square1: Square,
+    square2: Square,
+    triangle: Triangle,
+    noise: Noise,
+    dmc: DMC,
+    frame: Frame,
+
+    global_cyc: u64,
+    tick: u8,
+    next_tick_cyc: u64,
+    next_transfer_cyc: u64,
+    last_frame_cyc: u64,
+    half_frame_cyc: u64,
+    last_4017: u8,
+
+    irq_requested: bool,
+
+    jitter: Jitter,
+}
+
+impl APU {
+    pub fn new(settings: Rc<Settings>, cart: Rc<UnsafeCell<Cart>>, device: Box<AudioOut>) -> APU {
+        let sample_rate = device.sample_rate();
+        let channels = device.channels();
+        let region = settings.region();
+        // Dendy famiclones use the NTSC APU's timing, just clocked slower.
+        let sequences = match region {
+            Region::PAL => &PAL_SEQUENCES,
+            Region::NTSC | Region::Dendy => &NTSC_SEQUENCES,
+        };
+
+        let mixer = Mixer::new(settings.mixer_mode, sample_rate, channels, region);
+        let mixer = Rc::new(RefCell::new(mixer));
+        let clocks_needed = mixer.borrow().clocks_needed() as u64;
+        let waveform = |channel| Waveform::new(mixer.clone(), channel);
+
+        APU {
+            settings: settings,
+            sequences: sequences,
+            square1: Square::new(false, waveform(Channel::Square1)),
+            square2: Square::new(true, waveform(Channel::Square2)),
+            triangle: Triangle::new(waveform(Channel::Triangle)),
+            noise: Noise::new(waveform(Channel::Noise), region),
+            dmc: DMC::new(waveform(Channel::DMC), cart.clone(), region),
+            frame: Frame::empty(),
+
+            cart: cart,
+            expansion: EXPANSION_CHANNELS.iter().map(|&channel| waveform(channel)).collect(),
+
+            mixer: mixer.clone(),
+
+            device: device,
+            stem_out: None,
+
+            global_cyc: 0,
+            tick: 0,
+            next_tick_cyc: sequences[0][0].0 - POWER_UP_SEQUENCER_LEAD,
+            next_transfer_cyc: clocks_needed,
+            last_frame_cyc: 0,
+            half_frame_cyc: 0,
+            last_4017: 0,
+
+            irq_requested: false,
+
+            jitter: Jitter::None,
+
+            output_enabled: true,
+        }
+    }
+
+    pub fn run_to(&mut self, cpu_cycle: u64) -> IrqInterrupt {
+        let mut interrupt = IrqInterrupt::None;
+
+        while self.global_cyc < cpu_cycle {
+            let current_cycle = self.global_cyc;
+
+            let mut next_step = cmp::min(cpu_cycle, self.next_tick_cyc);
+            next_step = cmp::min(next_step, self.next_transfer_cyc);
+
+            if let Jitter::Delay(time, _) = self.jitter {
+                next_step = cmp::min(next_step, time);
+            }
+
+            let output = self.settings.sound_enabled && self.output_enabled;
+            if output {
+                self.play(current_cycle, next_step);
+            }
+            if self.run_dmc(current_cycle, next_step, output) {
+                interrupt = IrqInterrupt::IRQ;
+            }
+            self.global_cyc = next_step;
+
+            if let Jitter::Delay(time, val) = self.jitter {
+                if self.global_cyc == time {
+                    self.reset_sequencer(val);
+                    self.jitter = Jitter::None;
+                }
+            }
+            if self.global_cyc == self.next_tick_cyc {
+                interrupt = interrupt.or(self.tick());
+            }
+            if self.global_cyc == self.next_transfer_cyc {
+                self.transfer();
+            }
+        }
+        interrupt
+    }
+
+    /// Runs the current step of the frame sequencer and schedules the next.
+    fn tick(&mut self) -> IrqInterrupt {
+        let sequence = &self.sequences[self.frame.mode()];
+        let (cycle, actions) = sequence[self.tick as usize];
+        self.tick += 1;
+        if self.tick as usize == sequence.len() {
+            self.tick = 0;
+            self.next_tick_cyc += sequence[0].0;
+        } else {
+            self.next_tick_cyc += sequence[self.tick as usize].0 - cycle;
+        }
+
+        if actions & QUARTER_FRAME != 0 {
+            self.envelope_tick();
+        }
+        if actions & HALF_FRAME != 0 {
+            self.length_tick();
+        }
+        if actions & FRAME_IRQ != 0 {
+            return self.raise_irq();
+        }
+        IrqInterrupt::None
+    }
+
+    fn envelope_tick(&mut self) {
+        self.square1.envelope_tick();
+        self.square2.envelope_tick();
+        self.triangle.envelope_tick();
+        self.noise.envelope_tick();
+    }
+
+    fn length_tick(&mut self) {
+        self.half_frame_cyc = self.global_cyc;
+        self.square1.length_tick();
+        self.square2.length_tick();
+        self.triangle.length_tick();
+        self.noise.length_tick();
+    }
+
+    /// Sets the frame interrupt flag, signalling the CPU only when the flag
+    /// wasn't already set.
+    fn raise_irq(&mut self) -> IrqInterrupt {
+        if self.frame.contains(SUPPRESS_IRQ) || self.irq_requested {
+            return IrqInterrupt::None;
+        }
+        self.irq_requested = true;
+        IrqInterrupt::IRQ
+    }
+
+    fn play(&mut self, from_cyc: u64, to_cyc: u64) {
+        let from = (from_cyc - self.last_frame_cyc) as u32;
+        let to = (to_cyc - self.last_frame_cyc) as u32;
+        self.square1.play(from, to);
+        self.square2.play(from, to);
+        self.triangle.play(from, to);
+        self.noise.play(from, to);
+        let cart = unsafe { &mut *self.cart.get() };
+        let channels = cart.audio_channels();
+        cart.play_audio(from, to, &mut self.expansion[..channels]);
+    }
+
+    /// The DMC has to be clocked even when no audio is being generated,
+    /// since its memory reads and IRQ are visible to the CPU. Returns true if
+    /// it raised an IRQ.
+    fn run_dmc(&mut self, from_cyc: u64, to_cyc: u64, output: bool) -> bool {
+        let from = (from_cyc - self.last_frame_cyc) as u32;
+        let to = (to_cyc - self.last_frame_cyc) as u32;
+        self.dmc.run(from, to, output)
+    }
+
+    fn transfer(&mut self) {
+        let cpu_cyc = self.global_cyc;
+        let cycles_since_last_frame = (cpu_cyc - self.last_frame_cyc) as u32;
+        self.last_frame_cyc = cpu_cyc;
+
+        if self.settings.sound_enabled && self.output_enabled {
+            let mut mixer = self.mixer.borrow_mut();
+            mixer.end_frame(cycles_since_last_frame);
+            self.device.play(mixer.read());
+            if let Some(ref mut stem_out) = self.stem_out {
+                for &channel in ALL_CHANNELS.iter() {
+                    stem_out.play(channel, mixer.read_stem(channel));
+                }
+            }
+            // Devices can stop reporting their fill level, as they do while
+            // being recorded, and then go back to the nominal rate.
+            let ratio = match self.device.fill_level() {
+                Some(fill_level) if self.settings.dynamic_rate_control => {
+                    audio::rate_adjustment(fill_level)
+                }
+                _ => 1.0,
+            };
+            mixer.set_rate_adjustment(ratio);
+            self.next_transfer_cyc = cpu_cyc + mixer.clocks_needed() as u64;
+        } else if self.settings.sound_enabled {
+            self.next_transfer_cyc = cpu_cyc + self.mixer.borrow().clocks_needed() as u64;
+        } else {
+            self.next_transfer_cyc += 100000000;
+        }
+    }
+
+    pub fn set_output_enabled(&mut self, enabled: bool) {
+        self.output_enabled = enabled;
+    }
+
+    /// Replaces the device that samples are sent to, returning the old one.
+    pub fn set_audio_out(&mut self, device: Box<AudioOut>) -> Box<AudioOut> {
+        let sample_rate = device.sample_rate();
+        let channels = device.channels();
+        if sample_rate != self.device.sample_rate() || channels != self.device.channels() {
+            let region = self.settings.region();
+            self.mixer.borrow_mut().set_output_format(sample_rate, channels, region);
+            self.last_frame_cyc = self.global_cyc;
+            self.next_transfer_cyc = self.global_cyc + self.mixer.borrow().clocks_needed() as u64;
+        }
+        mem::replace(&mut self.device, device)
+    }
+
+    /// Sets where each channel's isolated output is sent, returning the old
+    /// destination. Mixing the channels separately costs some speed, so it's
+    /// only done while this is set.
+    pub fn set_stem_out(&mut self, stem_out: Option<Box<StemOut>>) -> Option<Box<StemOut>> {
+        self.mixer.borrow_mut().set_stems_enabled(stem_out.is_some());
+        mem::replace(&mut self.stem_out, stem_out)
+    }
+
+    pub fn channel_mix(&self, channel: Channel) -> ChannelMix {
+        self.mixer.borrow().channel_mix(channel)
+    }
+
+    pub fn audio_stats(&self) -> AudioStats {
+        self.device.stats()
+    }
+
+    pub fn set_channel_mix(&mut self, channel: Channel, mix: ChannelMix) {
+        self.mixer.borrow_mut().set_channel_mix(channel, mix);
+    }
+
+    pub fn save_state(&self) -> APUState {
+        APUState {
+            square1: self.square1.clone(),
+            square2: self.square2.clone(),
+            triangle: self.triangle.clone(),
+            noise: self.noise.clone(),
+            dmc: self.dmc.clone(),
+            frame: self.frame,
+
+            global_cyc: self.global_cyc,
+            tick: self.tick,
+            next_tick_cyc: self.next_tick_cyc,
+            next_transfer_cyc: self.next_transfer_cyc,
+            last_frame_cyc: self.last_frame_cyc,
+            half_frame_cyc: self.half_frame_cyc,
+            last_4017: self.last_4017,
+
+            irq_requested: self.irq_requested,
+
+            jitter: self.jitter.clone(),
+        }
+    }
+
+    pub fn load_state(&mut self, state: &APUState) {
+        self.square1 = state.square1.clone();
+        self.square2 = state.square2.clone();
+        self.triangle = state.triangle.clone();
+        self.noise = state.noise.clone();
+        self.dmc = state.dmc.clone();
+        self.frame = state.frame;
+
+        self.global_cyc = state.global_cyc;
+        self.tick = state.tick;
+        self.next_tick_cyc = state.next_tick_cyc;
+        self.next_transfer_cyc = state.next_transfer_cyc;
+        self.last_frame_cyc = state.last_frame_cyc;
+        self.half_frame_cyc = state.half_frame_cyc;
+        self.last_4017 = state.last_4017;
+
+        self.irq_requested = state.irq_requested;
+
+        self.jitter = state.jitter.clone();
+    }
+
+    /// Returns the cycle number representing the next time the CPU should run
+    /// the APU.
+    /// Min of the next DMC IRQ and the next tick time. When the CPU cycle
+    /// reaches this number, the CPU must run the APU.
+    pub fn requested_run_cycle(&self) -> u64 {
+        // The frame IRQ happens on tick boundaries, so the tick time covers it
+        // as well. Using the tick time ensures that the APU will never get too
+        // far behind the CPU.
+        match self.dmc.cycles_until_irq() {
+            Some(cycles) => cmp::min(self.next_tick_cyc, self.global_cyc + cycles),
+            None => self.next_tick_cyc,
+        }
+    }
+
+    /// Restarts the frame sequencer after a write to $4017 has taken effect.
+    /// Entering 5-step mode clocks the envelopes and length counters
+    /// immediately.
+    fn reset_sequencer(&mut self, val: u8) {
+        if val & MODE.bits() != 0 {
+            self.frame.insert(MODE);
+        } else {
+            self.frame.remove(MODE);
+        }
+
+        self.tick = 0;
+        self.next_tick_cyc = self.global_cyc + self.sequences[self.frame.mode()][0].0;
+        if self.frame.mode() == 1 {
+            self.envelope_tick();
+            self.length_tick();
+        }
+    }
+
+    fn write_4015(&mut self, val: u8) {
+        self.noise.length.set_enable(val & 0b0000_1000 != 0);
+        self.triangle.length.set_enable(val & 0b0000_0100 != 0);
+        self.square2.length.set_enable(val & 0b0000_0010 != 0);
+        self.square1.length.set_enable(val & 0b0000_0001 != 0);
+        self.dmc.set_enabled(val & 0b0001_0000 != 0);
+    }
+
+    fn write_4017(&mut self, val: u8, write_cycle: u64) {
+        self.last_4017 = val;
+
+        // The interrupt inhibit flag takes effect straight away, but the
+        // sequencer is only reset 3 or 4 cycles later, depending on whether
+        // the write landed on an odd or even cycle.
+        if val & SUPPRESS_IRQ.bits() != 0 {
+            self.frame.insert(SUPPRESS_IRQ);
+            self.irq_requested = false;
+        } else {
+            self.frame.remove(SUPPRESS_IRQ);
+        }
+
+        let delay = if write_cycle % 2 == 1 { 3 } else { 4 };
+        self.jitter = Jitter::Delay(write_cycle + delay, val);
+    }
+
+    #[cfg_attr(rustfmt, rustfmt_skip)]
+    pub fn read_status(&mut self, cycle: u64) -> (IrqInterrupt, u8) {
+        let interrupt = self.run_to(cycle - 1);
+
+        let mut status: u8 = 0;
+        status |= self.square1.length.active();
+        status |= self.square2.length.active() << 1;
+        status |= self.triangle.length.active() << 2;
+        status |= self.noise.length.active() << 3;
+        status |= if self.dmc.active() { 1 << 4 } else { 0 };
+        status |= if self.irq_requested { 1 << 6 } else { 0 };
+        status |= if self.dmc.irq_flag { 1 << 7 } else { 0 };
+        self.irq_requested = false;
+
+        (interrupt.or(self.run_to(cycle)), status)
+    }
+
+    /// Writes to an APU register. Like reads, writes land on the last cycle
+    /// of the instruction, `cycle - 1`.
+    pub fn write(&mut self, idx: u16, val: u8, cycle: u64) -> IrqInterrupt {
+        let write_cycle = cycle - 1;
+        let interrupt = self.run_to(write_cycle);
+        let half_frame = self.half_frame_cyc == write_cycle;
+
+        match idx % 0x20 {
+            x @ 0x00...0x03 => self.square1.write(x, val, half_frame),
+            x @ 0x04...0x07 => self.square2.write(x, val, half_frame),
+            x @ 0x08...0x0B => self.triangle.write(x, val, half_frame),
+            x @ 0x0C...0x0F => self.noise.write(x, val, half_frame),
+            x @ 0x10...0x13 => self.dmc.write(x, val, half_frame),
+            0x0015 => self.write_4015(val),
+            0x0017 => self.write_4017(val, write_cycle),
+            _ => (),
+        }
+        interrupt
+    }
+
+    /// Handles the console's reset button. The channels are silenced, the
+    /// frame interrupt flag is cleared, and the sequencer restarts with the
+    /// last value written to $4017.
+    pub fn reset(&mut self, cycle: u64) {
+        // Interrupts are disabled by the reset, so any raised here are lost.
+        self.run_to(cycle);
+        self.write_4015(0);
+        self.irq_requested = false;
+        self.triangle.reset_phase();
+
+        let val = self.last_4017;
+        self.jitter = Jitter::Delay(cycle + RESET_SEQUENCER_DELAY, val);
+    }
+}
@@ -1,17 +1,85 @@
-pub mod sdl;
-
-use apu::Sample;
-
-pub trait AudioOut {
-    fn play(&mut self, buffer: &[Sample]);
-    fn sample_rate(&self) -> f64;
-}
-
-pub struct DummyAudioOut;
-
-impl AudioOut for DummyAudioOut {
-    fn play(&mut self, _: &[Sample]) {}
-    fn sample_rate(&self) -> f64 {
-        44100.0
-    }
-}
+pub mod sdl;
+
+use apu::{Channel, Sample};
+
+/// The number of channels output unless configured otherwise.
+pub const CHANNELS: usize = 2;
+
+/// The range of supported output sample rates, in Hz.
+pub const MIN_SAMPLE_RATE: u32 = 22050;
+pub const MAX_SAMPLE_RATE: u32 = 96000;
+
+/// The most the output sample rate is adjusted by to keep the device's
+/// buffer half full. Half a percent is too small a change in pitch to hear.
+pub const MAX_RATE_ADJUSTMENT: f64 = 0.005;
+
+/// The ratio to scale the output sample rate by, given how full the device's
+/// buffer is (from 0 to 1). Producing more samples while the buffer is
+/// draining and fewer while it's filling keeps it near half full, so the
+/// emulator can run at the display's rate without audio gaps.
+pub fn rate_adjustment(fill_level: f64) -> f64 {
+    let fill_level = fill_level.max(0.0).min(1.0);
+    1.0 + MAX_RATE_ADJUSTMENT * (1.0 - 2.0 * fill_level)
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct AudioStats {
+    /// The number of times the device ran out of samples and played silence.
+    pub underruns: u64,
+    /// The number of times the device's buffer was full and the emulator had
+    /// to wait for room.
+    pub overruns: u64,
+    /// How full the buffer was after the last transfer, from 0 to 1.
+    pub fill_level: f64,
+}
+
+pub trait AudioOut {
+    /// Plays interleaved samples with `channels()` channels, left first.
+    fn play(&mut self, buffer: &[Sample]);
+    fn sample_rate(&self) -> f64;
+
+    /// Either 1 for mono, or 2 for stereo.
+    fn channels(&self) -> usize {
+        CHANNELS
+    }
+
+    /// How full the device's buffer is, from 0 to 1. Devices which return
+    /// `None` don't get dynamic rate control.
+    fn fill_level(&self) -> Option<f64> {
+        None
+    }
+
+    fn stats(&self) -> AudioStats {
+        AudioStats::default()
+    }
+}
+
+/// Receives the sound of each channel on its own, unaffected by its volume,
+/// panning or muting.
+pub trait StemOut {
+    /// Takes mono samples from one channel.
+    fn play(&mut self, channel: Channel, buffer: &[Sample]);
+}
+
+pub struct DummyAudioOut;
+
+impl AudioOut for DummyAudioOut {
+    fn play(&mut self, _: &[Sample]) {}
+    fn sample_rate(&self) -> f64 {
+        44100.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_adjustment_steers_towards_half_full() {
+        assert_eq!(1.0, rate_adjustment(0.5));
+        assert_eq!(1.0 + MAX_RATE_ADJUSTMENT, rate_adjustment(0.0));
+        assert_eq!(1.0 - MAX_RATE_ADJUSTMENT, rate_adjustment(1.0));
+        assert_eq!(1.0 - MAX_RATE_ADJUSTMENT, rate_adjustment(1.5));
+        assert!(rate_adjustment(0.25) > 1.0);
+    }
+}
@@ -1,156 +1,196 @@
-use super::AudioOut;
-use apu::Sample;
-use sdl2::AudioSubsystem;
-use sdl2::Sdl;
-use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
-use std::cmp;
-use std::sync::{Condvar, Mutex};
-use std::sync::Arc;
-
-const OUT_SAMPLE_RATE: i32 = 44100;
-const BUFFER_SIZE: usize = OUT_SAMPLE_RATE as usize / 15;
-
-struct BufferOut {
-    samples: [Sample; BUFFER_SIZE],
-    input_counter: usize,
-    playback_counter: usize,
-    input_samples: usize,
-    too_slow: bool,
-    condvar: Arc<Condvar>,
-}
-
-impl AudioCallback for BufferOut {
-    type Channel = Sample;
-
-    fn callback(&mut self, out: &mut [Sample]) {
-        {
-            let out_iter = out.iter_mut();
-            let in_iter = self.samples
-                .iter()
-                .cycle()
-                .skip(self.playback_counter)
-                .take(self.input_samples);
-
-            for (dest, src) in out_iter.zip(in_iter) {
-                *dest = *src;
-            }
-        }
-
-        let transferred = cmp::min(out.len(), self.input_samples);
-        self.input_samples -= transferred;
-        self.playback_counter = (self.playback_counter + transferred) % self.samples.len();
-
-        {
-            let out_iter = out.iter_mut().skip(transferred);
-            // This should rarely, if ever, execute.
-            for dest in out_iter {
-                self.too_slow = true;
-                *dest = 0;
-            }
-            if self.too_slow {
-                self.input_counter = self.playback_counter;
-            }
-        }
-
-        self.condvar.notify_one();
-    }
-}
-
-#[allow(dead_code)]
-pub struct SDLAudioOut {
-    system: AudioSubsystem,
-    device: AudioDevice<BufferOut>,
-    mutex: Mutex<()>,
-    condvar: Arc<Condvar>,
-}
-
-impl AudioOut for SDLAudioOut {
-    fn play(&mut self, buffer: &[Sample]) {
-        self.wait(buffer.len());
-        let mut out = self.device.lock();
-
-        if out.too_slow {
-            println!("Audio transfer can't keep up");
-            out.too_slow = false;
-        }
-
-        let mut in_index = 0;
-        let mut out_index = out.input_counter;
-        let out_len = out.samples.len();
-        let in_len = buffer.len();
-
-        while in_index < in_len {
-            out.samples[out_index] = buffer[in_index];
-            in_index += 1;
-            out_index += 1;
-            if out_index == out_len {
-                out_index = 0;
-            }
-        }
-        out.input_counter = (out.input_counter + in_len) % out_len;
-        out.input_samples += in_len;
-    }
-
-    fn sample_rate(&self) -> f64 {
-        OUT_SAMPLE_RATE as f64
-    }
-}
-
-impl SDLAudioOut {
-    pub fn new(sdl: &Sdl) -> SDLAudioOut {
-        let mutex = Mutex::new(());
-        let condvar = Condvar::new();
-        let condvar = Arc::new(condvar);
-
-        let audio_subsystem = sdl.audio().unwrap();
-
-        let desired_spec = AudioSpecDesired {
-            freq: Some(OUT_SAMPLE_RATE),
-            channels: Some(1),
-            samples: None,
-        };
-
-        let device = audio_subsystem
-            .open_playback(None, &desired_spec, |_| {
-                BufferOut {
-                    samples: [0; BUFFER_SIZE],
-                    input_counter: 0,
-                    playback_counter: 0,
-                    input_samples: 0,
-                    too_slow: false,
-                    condvar: condvar.clone(),
-                }
-            })
-            .unwrap();
-
-        // Start playback
-        device.resume();
-
-        SDLAudioOut {
-            system: audio_subsystem,
-            device: device,
-            mutex: mutex,
-            condvar: condvar,
-        }
-    }
-
-    fn wait(&mut self, in_size: usize) {
-        {
-            let callback = self.device.lock();
-            if callback.input_samples + in_size <= callback.samples.len() {
-                return;
-            }
-        }
-
-        // If there isn't enough room for the transfer, wait until the callback is
-        // called once, then check again.
-        loop {
-            let lock = self.mutex.lock().unwrap();
-            let _lock = self.condvar.wait(lock).unwrap();
-            let callback = self.device.lock();
-            if callback.input_samples + in_size <= callback.samples.len() {
-                return;
-            }
-        }
-    }
-}
+use super::{AudioOut, AudioStats, MAX_SAMPLE_RATE, MIN_SAMPLE_RATE};
+use apu::Sample;
+use sdl2::AudioSubsystem;
+use sdl2::Sdl;
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use std::cmp;
+use std::sync::{Condvar, Mutex};
+use std::sync::Arc;
+
+struct BufferOut {
+    samples: Vec<Sample>,
+    input_counter: usize,
+    playback_counter: usize,
+    input_samples: usize,
+    /// Set once samples have arrived, so the silence before the emulator
+    /// starts isn't counted as an underrun.
+    started: bool,
+    underruns: u64,
+    condvar: Arc<Condvar>,
+}
+
+impl AudioCallback for BufferOut {
+    type Channel = Sample;
+
+    fn callback(&mut self, out: &mut [Sample]) {
+        {
+            let out_iter = out.iter_mut();
+            let in_iter = self.samples
+                .iter()
+                .cycle()
+                .skip(self.playback_counter)
+                .take(self.input_samples);
+
+            for (dest, src) in out_iter.zip(in_iter) {
+                *dest = *src;
+            }
+        }
+
+        let transferred = cmp::min(out.len(), self.input_samples);
+        self.input_samples -= transferred;
+        self.playback_counter = (self.playback_counter + transferred) % self.samples.len();
+
+        if transferred < out.len() {
+            for dest in out.iter_mut().skip(transferred) {
+                *dest = 0;
+            }
+            if self.started {
+                self.underruns += 1;
+            }
+            self.input_counter = self.playback_counter;
+        }
+
+        self.condvar.notify_one();
+    }
+}
+
+#[allow(dead_code)]
+pub struct SDLAudioOut {
+    system: AudioSubsystem,
+    device: AudioDevice<BufferOut>,
+    mutex: Mutex<()>,
+    condvar: Arc<Condvar>,
+    sample_rate: i32,
+    channels: usize,
+    /// Copied from the callback after each transfer, since reading it there
+    /// needs the device locked.
+    stats: AudioStats,
+}
+
+impl AudioOut for SDLAudioOut {
+    fn play(&mut self, buffer: &[Sample]) {
+        if self.wait(buffer.len()) {
+            self.stats.overruns += 1;
+        }
+        let mut out = self.device.lock();
+        out.started = true;
+
+        let mut in_index = 0;
+        let mut out_index = out.input_counter;
+        let out_len = out.samples.len();
+        let in_len = buffer.len();
+
+        while in_index < in_len {
+            out.samples[out_index] = buffer[in_index];
+            in_index += 1;
+            out_index += 1;
+            if out_index == out_len {
+                out_index = 0;
+            }
+        }
+        out.input_counter = (out.input_counter + in_len) % out_len;
+        out.input_samples += in_len;
+
+        self.stats.underruns = out.underruns;
+        self.stats.fill_level = out.input_samples as f64 / out_len as f64;
+    }
+
+    fn sample_rate(&self) -> f64 {
+        self.sample_rate as f64
+    }
+
+    fn channels(&self) -> usize {
+        self.channels
+    }
+
+    fn fill_level(&self) -> Option<f64> {
+        Some(self.stats.fill_level)
+    }
+
+    fn stats(&self) -> AudioStats {
+        self.stats
+    }
+}
+
+impl SDLAudioOut {
+    /// Opens the default audio device. The device may not support the
+    /// requested sample rate or number of channels, in which case the nearest
+    /// it does support are used. The buffer is sized so that when it's half
+    /// full, as dynamic rate control tries to keep it, it holds `latency_ms`
+    /// of sound.
+    pub fn new(sdl: &Sdl, sample_rate: u32, channels: usize, latency_ms: u32) -> SDLAudioOut {
+        assert!(
+            sample_rate >= MIN_SAMPLE_RATE && sample_rate <= MAX_SAMPLE_RATE,
+            "Unsupported sample rate {}",
+            sample_rate
+        );
+        assert!(channels == 1 || channels == 2, "Unsupported channel count {}", channels);
+        let mutex = Mutex::new(());
+        let condvar = Condvar::new();
+        let condvar = Arc::new(condvar);
+
+        let audio_subsystem = sdl.audio().unwrap();
+
+        let desired_spec = AudioSpecDesired {
+            freq: Some(sample_rate as i32),
+            channels: Some(channels as u8),
+            samples: None,
+        };
+
+        let device = audio_subsystem
+            .open_playback(None, &desired_spec, |spec| {
+                let frames = spec.freq as usize * latency_ms as usize * 2 / 1000;
+                // The buffer has to hold at least one transfer from the APU,
+                // which is a frame long, and one callback's worth of samples.
+                let frames = cmp::max(frames, spec.freq as usize / 50 + spec.samples as usize);
+                BufferOut {
+                    samples: vec![0; frames * spec.channels as usize],
+                    input_counter: 0,
+                    playback_counter: 0,
+                    input_samples: 0,
+                    started: false,
+                    underruns: 0,
+                    condvar: condvar.clone(),
+                }
+            })
+            .unwrap();
+
+        let sample_rate = device.spec().freq;
+        let channels = device.spec().channels as usize;
+
+        // Start playback
+        device.resume();
+
+        SDLAudioOut {
+            system: audio_subsystem,
+            device: device,
+            mutex: mutex,
+            condvar: condvar,
+            sample_rate: sample_rate,
+            channels: channels,
+            stats: AudioStats::default(),
+        }
+    }
+
+    /// Waits for room for `in_size` samples in the buffer. Returns true if
+    /// there wasn't room straight away.
+    fn wait(&mut self, in_size: usize) -> bool {
+        {
+            let callback = self.device.lock();
+            if callback.input_samples + in_size <= callback.samples.len() {
+                return false;
+            }
+        }
+
+        // If there isn't enough room for the transfer, wait until the callback is
+        // called once, then check again.
+        loop {
+            let lock = self.mutex.lock().unwrap();
+            let _lock = self.condvar.wait(lock).unwrap();
+            let callback = self.device.lock();
+            if callback.input_samples + in_size <= callback.samples.len() {
+                return true;
+            }
+        }
+    }
+}
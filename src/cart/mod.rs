@@ -1,124 +1,182 @@
-pub mod ines;
-
-
-use cart::ines::{Rom, RomError};
-use mappers::{Mapper, MapperParams, RomAddress, RomBank};
-use std::fs::File;
-use std::io;
-use std::io::prelude::*;
-use std::path::Path;
-
-#[derive(PartialEq, Debug, Clone, Copy)]
-pub enum ScreenMode {
-    Horizontal,
-    Vertical,
-    FourScreen,
-    OneScreenLow,
-    OneScreenHigh,
-}
-
-#[derive(PartialEq, Debug, Clone, Copy)]
-pub enum System {
-    NES,
-    Vs,
-    PC10,
-}
-
-#[derive(PartialEq, Debug, Clone, Copy)]
-pub enum TvFormat {
-    NTSC,
-    PAL,
-}
-
-pub struct Cart {
-    mapper: Box<Mapper>,
-    pub system: System,
-    pub tv: TvFormat,
-}
-
-quick_error! {
-    #[derive(Debug)]
-    pub enum RomReadError {
-        Io(err: io::Error) {
-            display("IO Error: {}", err)
-            description(err.description())
-            cause(err)
-            from()
-        }
-        Parse(err: RomError) {
-            display("ROM Error: {}", err)
-            description(err.description())
-            cause(err)
-            from()
-        }
-    }
-}
-
-impl Cart {
-    pub fn prg_rom_read(&mut self, idx: u16) -> &RomBank {
-        self.mapper.prg_rom_read(idx)
-    }
-    pub fn prg_rom_write(&mut self, idx: u16, val: u8) -> &mut RomBank {
-        self.mapper.prg_rom_write(idx, val)
-    }
-    pub fn prg_rom_address(&self, idx: u16) -> RomAddress {
-        self.mapper.prg_rom_address(idx)
-    }
-    pub fn prg_ram_read(&mut self, idx: u16) -> u8 {
-        self.mapper.prg_ram_read(idx)
-    }
-    pub fn prg_ram_write(&mut self, idx: u16, val: u8) {
-        self.mapper.prg_ram_write(idx, val)
-    }
-    pub fn chr_read(&mut self, idx: u16) -> u8 {
-        self.mapper.chr_read(idx)
-    }
-    pub fn chr_write(&mut self, idx: u16, val: u8) {
-        self.mapper.chr_write(idx, val)
-    }
-
-    pub fn new(mapper: Box<Mapper>) -> Cart {
-        Cart {
-            mapper: mapper,
-            system: System::NES,
-            tv: TvFormat::NTSC,
-        }
-    }
-
-    pub fn get_mirroring_table(&self) -> &[u16; 4] {
-        self.mapper.get_mirroring_table()
-    }
-
-    pub fn read(path: &Path) -> Result<Cart, RomReadError> {
-        let mut file = try!(File::open(path));
-        let mut buf = vec![];
-        try!(file.read_to_end(&mut buf));
-        let rom = try!(Rom::parse(&buf));
-
-        let mapper = rom.mapper();
-        let screen_mode = rom.screen_mode();
-        let system = rom.system();
-        let tv = rom.tv_system();
-        let sram = rom.sram();
-        let (prg_rom, chr_rom, prg_ram_size) = (rom.prg_rom, rom.chr_rom, rom.prg_ram_size);
-
-        let params = MapperParams {
-            prg_rom: prg_rom,
-            chr_rom: chr_rom,
-
-            prg_ram_size: prg_ram_size,
-
-            rom_path: path,
-
-            has_battery_backed_ram: sram,
-            mirroring_mode: screen_mode,
-        };
-
-        let mapper = Mapper::new(mapper as u16, params);
-        Ok(Cart {
-            mapper: mapper,
-            system: system,
-            tv: tv,
-        })
-    }
-}
+pub mod ines;
+
+
+use cart::ines::{Rom, RomError};
+use apu::Waveform;
+use mappers::{Mapper, MapperParams, PpuFetch, RomAddress, RomBank};
+use std::any::Any;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum ScreenMode {
+    Horizontal,
+    Vertical,
+    FourScreen,
+    OneScreenLow,
+    OneScreenHigh,
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum System {
+    NES,
+    Vs,
+    PC10,
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum TvFormat {
+    NTSC,
+    PAL,
+}
+
+pub struct Cart {
+    mapper: Box<Mapper>,
+    pub system: System,
+    pub tv: TvFormat,
+}
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum RomReadError {
+        Io(err: io::Error) {
+            display("IO Error: {}", err)
+            description(err.description())
+            cause(err)
+            from()
+        }
+        Parse(err: RomError) {
+            display("ROM Error: {}", err)
+            description(err.description())
+            cause(err)
+            from()
+        }
+    }
+}
+
+impl Cart {
+    pub fn prg_rom_read(&mut self, idx: u16) -> &RomBank {
+        self.mapper.prg_rom_read(idx)
+    }
+    pub fn prg_rom_write(&mut self, idx: u16, val: u8) -> &mut RomBank {
+        self.mapper.prg_rom_write(idx, val)
+    }
+    pub fn prg_read(&mut self, idx: u16) -> u8 {
+        self.mapper.prg_read(idx)
+    }
+    pub fn register_read(&mut self, idx: u16) -> u8 {
+        self.mapper.register_read(idx)
+    }
+    pub fn register_watches_ppu(&self, idx: u16) -> bool {
+        self.mapper.register_watches_ppu(idx)
+    }
+    pub fn prg_rom_address(&self, idx: u16) -> RomAddress {
+        self.mapper.prg_rom_address(idx)
+    }
+    pub fn expansion_rom(&self) -> bool {
+        self.mapper.expansion_rom()
+    }
+    pub fn prg_ram_read(&mut self, idx: u16) -> u8 {
+        self.mapper.prg_ram_read(idx)
+    }
+    pub fn prg_ram_write(&mut self, idx: u16, val: u8) {
+        self.mapper.prg_ram_write(idx, val)
+    }
+    pub fn chr_read(&mut self, idx: u16) -> u8 {
+        self.mapper.chr_read(idx)
+    }
+    pub fn chr_write(&mut self, idx: u16, val: u8) {
+        self.mapper.chr_write(idx, val)
+    }
+
+    pub fn new(mapper: Box<Mapper>) -> Cart {
+        Cart {
+            mapper: mapper,
+            system: System::NES,
+            tv: TvFormat::NTSC,
+        }
+    }
+
+    pub fn get_mirroring_table(&self) -> &[u16; 4] {
+        self.mapper.get_mirroring_table()
+    }
+
+    pub fn nametable_read(&mut self, idx: u16) -> Option<u8> {
+        self.mapper.nametable_read(idx)
+    }
+
+    pub fn nametable_write(&mut self, idx: u16, val: u8) -> bool {
+        self.mapper.nametable_write(idx, val)
+    }
+
+    pub fn ppu_fetch(&mut self, fetch: PpuFetch, tall_sprites: bool) {
+        self.mapper.ppu_fetch(fetch, tall_sprites)
+    }
+
+    pub fn watched_scanline(&self) -> Option<i16> {
+        self.mapper.watched_scanline()
+    }
+
+    pub fn run_to(&mut self, cycle: u64) -> bool {
+        self.mapper.run_to(cycle)
+    }
+
+    pub fn requested_run_cycle(&self) -> u64 {
+        self.mapper.requested_run_cycle()
+    }
+
+    pub fn irq_pending(&self) -> bool {
+        self.mapper.irq_pending()
+    }
+
+    pub fn audio_channels(&self) -> usize {
+        self.mapper.audio_channels()
+    }
+
+    pub fn play_audio(&mut self, from_cyc: u32, to_cyc: u32, out: &mut [Waveform]) {
+        self.mapper.play_audio(from_cyc, to_cyc, out)
+    }
+
+    pub fn save_state(&self) -> Box<Any> {
+        self.mapper.save_state()
+    }
+
+    pub fn load_state(&mut self, state: &Any) {
+        self.mapper.load_state(state)
+    }
+
+    pub fn read(path: &Path) -> Result<Cart, RomReadError> {
+        let mut file = try!(File::open(path));
+        let mut buf = vec![];
+        try!(file.read_to_end(&mut buf));
+        let rom = try!(Rom::parse(&buf));
+
+        let mapper = rom.mapper();
+        let screen_mode = rom.screen_mode();
+        let system = rom.system();
+        let tv = rom.tv_system();
+        let sram = rom.sram();
+        let (prg_rom, chr_rom, prg_ram_size) = (rom.prg_rom, rom.chr_rom, rom.prg_ram_size);
+
+        let params = MapperParams {
+            prg_rom: prg_rom,
+            chr_rom: chr_rom,
+
+            prg_ram_size: prg_ram_size,
+
+            rom_path: path,
+
+            has_battery_backed_ram: sram,
+            mirroring_mode: screen_mode,
+        };
+
+        let mapper = Mapper::new(mapper as u16, params);
+        Ok(Cart {
+            mapper: mapper,
+            system: system,
+            tv: tv,
+        })
+    }
+}
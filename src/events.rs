@@ -0,0 +1,238 @@
+//! Hooks which let frontends and scripts watch the PPU as it runs, and an
+//! event viewer built on them which plots register writes against the beam.
+
+use Emulator;
+use Region;
+use ppu;
+use screen::RgbImage;
+use std::cell::RefCell;
+use std::mem;
+use std::rc::Rc;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PPUEventKind {
+    /// The PPU reached a watched scanline and dot.
+    Dot,
+    /// The CPU wrote `value` to PPU register `addr` ($2000-$2007).
+    RegisterWrite { addr: u16, value: u8 },
+}
+
+/// Something that happened in the PPU, with the state of the PPU just after
+/// it.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PPUEvent {
+    pub kind: PPUEventKind,
+    pub frame: u32,
+    pub scanline: i16,
+    pub dot: u16,
+    /// The address of the CPU instruction which was running.
+    pub pc: u16,
+    /// The temporary VRAM address (the scroll position for the next frame).
+    pub t: u16,
+    /// The current VRAM address.
+    pub v: u16,
+    /// The fine X scroll.
+    pub x: u8,
+}
+
+pub type Hook = Box<FnMut(&PPUEvent)>;
+
+/// The hooks registered with an emulator.
+pub struct Hooks {
+    dot_hooks: Vec<(i16, u16, Hook)>,
+    write_hooks: Vec<Hook>,
+    enabled: bool,
+}
+
+impl Default for Hooks {
+    fn default() -> Hooks {
+        Hooks {
+            dot_hooks: vec![],
+            write_hooks: vec![],
+            enabled: true,
+        }
+    }
+}
+
+impl Hooks {
+    pub fn add_dot_hook(&mut self, scanline: i16, dot: u16, hook: Hook) {
+        self.dot_hooks.push((scanline, dot, hook));
+    }
+
+    pub fn add_write_hook(&mut self, hook: Hook) {
+        self.write_hooks.push(hook);
+    }
+
+    pub fn clear(&mut self) {
+        self.dot_hooks.clear();
+        self.write_hooks.clear();
+    }
+
+    pub fn has_write_hooks(&self) -> bool {
+        self.enabled && !self.write_hooks.is_empty()
+    }
+
+    /// While disabled, events are dropped. Used to hide the frames emulated
+    /// for run-ahead.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn fire(&mut self, event: &PPUEvent) {
+        if !self.enabled {
+            return;
+        }
+        match event.kind {
+            PPUEventKind::Dot => {
+                let position = (event.scanline, event.dot);
+                for entry in &mut self.dot_hooks {
+                    if (entry.0, entry.1) == position {
+                        (entry.2)(event);
+                    }
+                }
+            }
+            PPUEventKind::RegisterWrite { .. } => {
+                for hook in &mut self.write_hooks {
+                    hook(event);
+                }
+            }
+        }
+    }
+}
+
+const VISIBLE_COLOR: [u8; 3] = [48, 48, 48];
+const BLANK_COLOR: [u8; 3] = [16, 16, 16];
+
+#[cfg_attr(rustfmt, rustfmt_skip)]
+static REGISTER_COLORS: [[u8; 3]; 8] = [
+    [255, 64, 64],   // $2000 PPUCTRL
+    [64, 255, 64],   // $2001 PPUMASK
+    [160, 160, 160], // $2002 PPUSTATUS
+    [255, 160, 0],   // $2003 OAMADDR
+    [255, 255, 64],  // $2004 OAMDATA
+    [64, 160, 255],  // $2005 PPUSCROLL
+    [255, 64, 255],  // $2006 PPUADDR
+    [64, 255, 255],  // $2007 PPUDATA
+];
+
+/// Collects the PPU register writes made each frame and plots them by the
+/// scanline and dot they happened on.
+pub struct EventViewer {
+    scanlines: usize,
+    pending: Rc<RefCell<Vec<PPUEvent>>>,
+    events: Vec<PPUEvent>,
+}
+
+impl EventViewer {
+    /// Starts recording the emulator's PPU register writes.
+    pub fn attach(emulator: &mut Emulator) -> EventViewer {
+        let pending = Rc::new(RefCell::new(vec![]));
+        let sink = pending.clone();
+        emulator.add_register_write_hook(move |event| sink.borrow_mut().push(*event));
+        EventViewer::new(emulator.region(), pending)
+    }
+
+    fn new(region: Region, pending: Rc<RefCell<Vec<PPUEvent>>>) -> EventViewer {
+        EventViewer {
+            scanlines: ppu::scanlines_per_frame(region) as usize,
+            pending: pending,
+            events: vec![],
+        }
+    }
+
+    /// Makes the writes recorded since the last call the ones shown. Call
+    /// this after each frame.
+    pub fn end_frame(&mut self) {
+        self.events = mem::replace(&mut *self.pending.borrow_mut(), vec![]);
+    }
+
+    /// The writes made during the last frame.
+    pub fn events(&self) -> &[PPUEvent] {
+        &self.events
+    }
+
+    pub fn width(&self) -> usize {
+        341
+    }
+
+    /// One row for each scanline, starting with the pre-render line.
+    pub fn height(&self) -> usize {
+        self.scanlines
+    }
+
+    /// Draws the last frame's writes as dots colored by register over a map
+    /// of the frame, with the visible area lighter than the blanking periods.
+    pub fn render(&self) -> RgbImage {
+        let mut image = RgbImage::new(self.width(), self.height());
+        image.fill_rect(0, 0, self.width(), self.height(), BLANK_COLOR);
+        image.fill_rect(1, 1, ppu::SCREEN_WIDTH, ppu::SCREEN_HEIGHT, VISIBLE_COLOR);
+        for event in &self.events {
+            if let PPUEventKind::RegisterWrite { addr, .. } = event.kind {
+                let row = (event.scanline + 1) as usize;
+                if row < self.height() && (event.dot as usize) < self.width() {
+                    let color = REGISTER_COLORS[(addr & 0x07) as usize];
+                    image.set_pixel(event.dot as usize, row, color);
+                }
+            }
+        }
+        image
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_event(addr: u16, scanline: i16, dot: u16) -> PPUEvent {
+        PPUEvent {
+            kind: PPUEventKind::RegisterWrite {
+                addr: addr,
+                value: 0,
+            },
+            frame: 1,
+            scanline: scanline,
+            dot: dot,
+            pc: 0x8000,
+            t: 0,
+            v: 0,
+            x: 0,
+        }
+    }
+
+    #[test]
+    fn dot_hooks_only_see_their_dot() {
+        let seen = Rc::new(RefCell::new(vec![]));
+        let mut hooks = Hooks::default();
+        let sink = seen.clone();
+        hooks.add_dot_hook(
+            100,
+            5,
+            Box::new(move |event| sink.borrow_mut().push(event.scanline)),
+        );
+        let mut event = write_event(0x2000, 100, 5);
+        event.kind = PPUEventKind::Dot;
+        hooks.fire(&event);
+        event.dot = 6;
+        hooks.fire(&event);
+        hooks.fire(&write_event(0x2005, 100, 5));
+        assert_eq!(vec![100], *seen.borrow());
+    }
+
+    #[test]
+    fn event_viewer_plots_writes_by_register() {
+        let pending = Rc::new(RefCell::new(vec![]));
+        let mut viewer = EventViewer::new(Region::NTSC, pending.clone());
+        pending.borrow_mut().push(write_event(0x2005, 20, 300));
+        pending.borrow_mut().push(write_event(0x2006, -1, 10));
+        viewer.end_frame();
+
+        assert_eq!(262, viewer.height());
+        assert_eq!(2, viewer.events().len());
+        assert!(pending.borrow().is_empty());
+        let image = viewer.render();
+        assert_eq!(REGISTER_COLORS[5], image.pixel(300, 21));
+        assert_eq!(REGISTER_COLORS[6], image.pixel(10, 0));
+        assert_eq!(VISIBLE_COLOR, image.pixel(10, 10));
+        assert_eq!(BLANK_COLOR, image.pixel(300, 10));
+    }
+}
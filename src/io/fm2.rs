@@ -1,77 +1,126 @@
-use io::IO;
-
-use io::OPEN_BUS;
-use memory::MemSegment;
-use std::fs::File;
-use std::io::BufRead;
-use std::io::BufReader;
-use std::io::Result;
-use std::iter::Iterator;
-use util::ShiftRegister8;
-
-pub struct FM2IO {
-    iter: Box<Iterator<Item = String>>,
-    controller1: ShiftRegister8,
-    controller2: ShiftRegister8,
-}
-
-impl FM2IO {
-    pub fn read(file: String) -> Result<FM2IO> {
-        let file = try!(File::open(file));
-        let reader = BufReader::new(file);
-        let iter = reader
-            .lines()
-            .map(|result| result.unwrap())
-            .skip_while(|line| !line.contains('|'))
-            .skip(1);
-        Ok(FM2IO {
-            iter: Box::new(iter),
-            controller1: ShiftRegister8::new(0),
-            controller2: ShiftRegister8::new(0),
-        })
-    }
-}
-
-fn parse(string: &str) -> u8 {
-    string.char_indices().filter(|&(_, c)| c != '.').fold(
-        0u8,
-        |acc,
-         (idx,
-          _)| {
-            acc | 1u8 << (7 - idx)
-        },
-    )
-}
-
-impl MemSegment for FM2IO {
-    fn read(&mut self, idx: u16) -> u8 {
-        match idx {
-            0x4016 => OPEN_BUS | self.controller1.shift(),
-            0x4017 => OPEN_BUS | self.controller2.shift(),
-            x => invalid_address!(x),
-        }
-    }
-
-    fn write(&mut self, idx: u16, val: u8) {
-        match idx {
-            0x4016 => {
-                if val & 0x01 != 0 {
-                    if let Some(line) = self.iter.next() {
-                        // Ignore the commands for now.
-                        let mut split = line.split('|').skip(1).skip(1);
-                        self.controller1.load(parse(split.next().unwrap()));
-                        self.controller2.load(parse(split.next().unwrap()));
-                    }
-                }
-            }
-            0x4017 => (),
-            x => invalid_address!(x),
-        }
-    }
-}
-
-impl IO for FM2IO {
-    fn poll(&mut self) {
-        // Do nothing.
-    }
-}
+use io::IO;
+
+use io::ports::{ControllerPorts, MAX_PLAYERS, Players, PortLayout};
+use memory::MemSegment;
+use std::any::Any;
+use std::fs::File;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Result;
+
+pub struct FM2IO {
+    lines: Vec<String>,
+    next_line: usize,
+    ports: ControllerPorts,
+}
+
+impl FM2IO {
+    pub fn read(file: String) -> Result<FM2IO> {
+        let file = try!(File::open(file));
+        let reader = BufReader::new(file);
+        let lines = try!(reader.lines().collect::<Result<Vec<String>>>());
+        let four_score = lines
+            .iter()
+            .take_while(|line| !line.contains('|'))
+            .any(|line| line.trim() == "fourscore 1");
+        let lines = lines
+            .into_iter()
+            .skip_while(|line| !line.contains('|'))
+            .skip(1)
+            .collect();
+        let layout = if four_score {
+            PortLayout::FourScore
+        } else {
+            PortLayout::Standard
+        };
+        Ok(FM2IO {
+            lines: lines,
+            next_line: 0,
+            ports: ControllerPorts::with_layout(layout),
+        })
+    }
+}
+
+fn parse(string: &str) -> u8 {
+    string.char_indices().filter(|&(_, c)| c != '.').fold(
+        0u8,
+        |acc,
+         (idx,
+          _)| {
+            acc | 1u8 << (7 - idx)
+        },
+    )
+}
+
+/// Parses the controller fields of an input line. With the Four Score, there
+/// are four gamepad fields instead of two; the remaining fields (eg. the
+/// Famicom expansion port) are ignored.
+fn parse_players(line: &str) -> Players {
+    let mut players = [0u8; MAX_PLAYERS];
+    // Ignore the commands for now.
+    let fields = line.split('|').skip(1).skip(1);
+    for (player, field) in players.iter_mut().zip(fields) {
+        if field.len() == 8 {
+            *player = parse(field);
+        }
+    }
+    players
+}
+
+impl MemSegment for FM2IO {
+    fn read(&mut self, idx: u16) -> u8 {
+        self.ports.read(idx)
+    }
+
+    fn write(&mut self, idx: u16, val: u8) {
+        match idx {
+            0x4016 => {
+                if val & 0x01 != 0 {
+                    if let Some(line) = self.lines.get(self.next_line) {
+                        self.ports.load(&parse_players(line));
+                    }
+                    self.next_line += 1;
+                }
+            }
+            0x4017 => (),
+            x => invalid_address!(x),
+        }
+    }
+}
+
+impl IO for FM2IO {
+    fn poll(&mut self) {
+        // Do nothing.
+    }
+
+    fn save_state(&self) -> Box<Any> {
+        Box::new((self.next_line, self.ports.save_state()))
+    }
+
+    fn load_state(&mut self, state: &Any) {
+        let &(next_line, ref ports) = state.downcast_ref::<(usize, Box<Any>)>().unwrap();
+        self.next_line = next_line;
+        self.ports.load_state(&**ports);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_two_player_lines() {
+        assert_eq!(
+            [0b1000_0001, 0b0000_1000, 0, 0],
+            parse_players("|0|R......A|....T...||")
+        );
+    }
+
+    #[test]
+    fn parses_four_player_lines() {
+        assert_eq!(
+            [0x01, 0x02, 0x04, 0x80],
+            parse_players("|0|.......A|......B.|.....S..|R.......||")
+        );
+    }
+}
@@ -1,39 +1,75 @@
-pub mod sdl;
-pub mod fm2;
-
-use super::memory::MemSegment;
-
-/// Some bits of the controller reads return open bus garbage. Since the last
-/// byte on the bus is almost always 0x40, we can just use that as a constant
-/// for now.
-pub const OPEN_BUS: u8 = 0x40;
-
-pub trait IO: MemSegment {
-    fn poll(&mut self);
-}
-
-pub enum DummyIO {
-    Dummy,
-}
-
-impl DummyIO {
-    pub fn new() -> DummyIO {
-        DummyIO::Dummy
-    }
-}
-
-impl MemSegment for DummyIO {
-    fn read(&mut self, _: u16) -> u8 {
-        0
-    }
-
-    fn write(&mut self, _: u16, _: u8) {
-        ()
-    }
-}
-
-impl IO for DummyIO {
-    fn poll(&mut self) {
-        ()
-    }
-}
+pub mod sdl;
+pub mod fm2;
+pub mod ports;
+pub mod zapper;
+
+use super::memory::MemSegment;
+use ppu::{Color, SCREEN_BUFFER_SIZE};
+use std::any::Any;
+
+/// Some bits of the controller reads return open bus garbage. Since the last
+/// byte on the bus is almost always 0x40, we can just use that as a constant
+/// for now.
+pub const OPEN_BUS: u8 = 0x40;
+
+/// What the PPU has drawn so far, and where the beam currently is.
+pub struct ScreenView<'a> {
+    pub buffer: &'a [Color; SCREEN_BUFFER_SIZE],
+    pub scanline: i16,
+    pub dot: u16,
+}
+
+pub trait IO: MemSegment {
+    fn poll(&mut self);
+
+    /// Whether a light-sensing device like the Zapper is connected, which
+    /// needs `observe_screen` to be called.
+    fn observes_screen(&self) -> bool {
+        false
+    }
+
+    /// Called before each read from $4017 so that light-sensing devices like
+    /// the Zapper can see the screen as it is at that moment.
+    fn observe_screen(&mut self, _: &ScreenView) {}
+
+    /// Called after each frame the CPU runs, for devices which keep time in
+    /// frames.
+    fn end_frame(&mut self) {}
+
+    /// Captures the state of the input devices (eg. partially-read shift
+    /// registers) so it can be restored later with `load_state`.
+    fn save_state(&self) -> Box<Any>;
+    fn load_state(&mut self, state: &Any);
+}
+
+pub enum DummyIO {
+    Dummy,
+}
+
+impl DummyIO {
+    pub fn new() -> DummyIO {
+        DummyIO::Dummy
+    }
+}
+
+impl MemSegment for DummyIO {
+    fn read(&mut self, _: u16) -> u8 {
+        0
+    }
+
+    fn write(&mut self, _: u16, _: u8) {
+        ()
+    }
+}
+
+impl IO for DummyIO {
+    fn poll(&mut self) {
+        ()
+    }
+
+    fn save_state(&self) -> Box<Any> {
+        Box::new(())
+    }
+
+    fn load_state(&mut self, _: &Any) {}
+}
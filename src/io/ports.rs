@@ -0,0 +1,286 @@
+use io::OPEN_BUS;
+use std::any::Any;
+use util::ShiftRegister8;
+
+/// The number of players whose buttons are passed to the input devices.
+pub const MAX_PLAYERS: usize = 4;
+
+/// The buttons held by each player, one byte per player in the order the
+/// standard controller reports them (A in bit 0 through Right in bit 7).
+pub type Players = [u8; MAX_PLAYERS];
+
+/// Something plugged into one of the two controller ports.
+pub trait InputDevice {
+    /// Latches the current state of the buttons. Called when the CPU sets the
+    /// strobe bit in $4016.
+    fn load(&mut self, players: &Players);
+
+    /// Returns the next bits of the report, in the low bits of the byte. The
+    /// upper bits are filled in with open bus.
+    fn read(&mut self) -> u8;
+
+    fn save_state(&self) -> Box<Any>;
+    fn load_state(&mut self, state: &Any);
+}
+
+/// A standard NES controller, reporting the buttons of a single player.
+pub struct StandardController {
+    player: usize,
+    register: ShiftRegister8,
+}
+
+impl StandardController {
+    pub fn new(player: usize) -> StandardController {
+        StandardController {
+            player: player,
+            register: ShiftRegister8::new(0),
+        }
+    }
+}
+
+impl InputDevice for StandardController {
+    fn load(&mut self, players: &Players) {
+        self.register.load(players[self.player]);
+    }
+
+    fn read(&mut self) -> u8 {
+        self.register.shift()
+    }
+
+    fn save_state(&self) -> Box<Any> {
+        Box::new(self.register.clone())
+    }
+
+    fn load_state(&mut self, state: &Any) {
+        self.register = state.downcast_ref::<ShiftRegister8>().unwrap().clone();
+    }
+}
+
+/// One port's half of the NES Four Score. Each port reports two players
+/// followed by a signature byte identifying the port, for 24 bits in total.
+/// After that, further reads return 1.
+pub struct FourScore {
+    first: usize,
+    second: usize,
+    signature: u8,
+    report: u32,
+}
+
+impl FourScore {
+    /// The half of the Four Score connected to $4016 - players 1 and 3.
+    pub fn port1() -> FourScore {
+        FourScore {
+            first: 0,
+            second: 2,
+            signature: 0b0001_0000,
+            report: 0,
+        }
+    }
+
+    /// The half of the Four Score connected to $4017 - players 2 and 4.
+    pub fn port2() -> FourScore {
+        FourScore {
+            first: 1,
+            second: 3,
+            signature: 0b0010_0000,
+            report: 0,
+        }
+    }
+}
+
+impl InputDevice for FourScore {
+    fn load(&mut self, players: &Players) {
+        self.report = players[self.first] as u32 | (players[self.second] as u32) << 8 |
+            (self.signature as u32) << 16 | 0xFF00_0000;
+    }
+
+    fn read(&mut self) -> u8 {
+        let result = (self.report & 0x01) as u8;
+        self.report = (self.report >> 1) | 0x8000_0000;
+        result
+    }
+
+    fn save_state(&self) -> Box<Any> {
+        Box::new(self.report)
+    }
+
+    fn load_state(&mut self, state: &Any) {
+        self.report = *state.downcast_ref::<u32>().unwrap();
+    }
+}
+
+/// A port on a Famicom with a four-player adapter in the expansion port. The
+/// built-in controller is reported on D0 and the expansion controller on D1.
+pub struct FamicomExpansion {
+    builtin: StandardController,
+    expansion: StandardController,
+}
+
+impl FamicomExpansion {
+    /// $4016 - players 1 and 3.
+    pub fn port1() -> FamicomExpansion {
+        FamicomExpansion {
+            builtin: StandardController::new(0),
+            expansion: StandardController::new(2),
+        }
+    }
+
+    /// $4017 - players 2 and 4.
+    pub fn port2() -> FamicomExpansion {
+        FamicomExpansion {
+            builtin: StandardController::new(1),
+            expansion: StandardController::new(3),
+        }
+    }
+}
+
+impl InputDevice for FamicomExpansion {
+    fn load(&mut self, players: &Players) {
+        self.builtin.load(players);
+        self.expansion.load(players);
+    }
+
+    fn read(&mut self) -> u8 {
+        self.builtin.read() | self.expansion.read() << 1
+    }
+
+    fn save_state(&self) -> Box<Any> {
+        Box::new((self.builtin.save_state(), self.expansion.save_state()))
+    }
+
+    fn load_state(&mut self, state: &Any) {
+        let &(ref builtin, ref expansion) = state.downcast_ref::<(Box<Any>, Box<Any>)>().unwrap();
+        self.builtin.load_state(&**builtin);
+        self.expansion.load_state(&**expansion);
+    }
+}
+
+/// Which devices are plugged into the controller ports.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PortLayout {
+    /// A standard controller in each port.
+    Standard,
+    /// An NES Four Score, with players 3 and 4 after players 1 and 2.
+    FourScore,
+    /// A Famicom four-player adapter, with players 3 and 4 on D1.
+    FamicomFourPlayer,
+}
+
+/// The two controller ports, $4016 and $4017.
+pub struct ControllerPorts {
+    port1: Box<InputDevice>,
+    port2: Box<InputDevice>,
+}
+
+impl ControllerPorts {
+    pub fn new(port1: Box<InputDevice>, port2: Box<InputDevice>) -> ControllerPorts {
+        ControllerPorts {
+            port1: port1,
+            port2: port2,
+        }
+    }
+
+    pub fn with_layout(layout: PortLayout) -> ControllerPorts {
+        match layout {
+            PortLayout::Standard => ControllerPorts::new(
+                Box::new(StandardController::new(0)),
+                Box::new(StandardController::new(1)),
+            ),
+            PortLayout::FourScore => {
+                ControllerPorts::new(Box::new(FourScore::port1()), Box::new(FourScore::port2()))
+            }
+            PortLayout::FamicomFourPlayer => ControllerPorts::new(
+                Box::new(FamicomExpansion::port1()),
+                Box::new(FamicomExpansion::port2()),
+            ),
+        }
+    }
+
+    pub fn load(&mut self, players: &Players) {
+        self.port1.load(players);
+        self.port2.load(players);
+    }
+
+    pub fn read(&mut self, idx: u16) -> u8 {
+        match idx {
+            0x4016 => OPEN_BUS | self.port1.read(),
+            0x4017 => OPEN_BUS | self.port2.read(),
+            x => invalid_address!(x),
+        }
+    }
+
+    pub fn save_state(&self) -> Box<Any> {
+        Box::new((self.port1.save_state(), self.port2.save_state()))
+    }
+
+    pub fn load_state(&mut self, state: &Any) {
+        let &(ref port1, ref port2) = state.downcast_ref::<(Box<Any>, Box<Any>)>().unwrap();
+        self.port1.load_state(&**port1);
+        self.port2.load_state(&**port2);
+    }
+}
+
+impl Default for ControllerPorts {
+    fn default() -> ControllerPorts {
+        ControllerPorts::with_layout(PortLayout::Standard)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_bits(ports: &mut ControllerPorts, idx: u16, count: usize) -> Vec<u8> {
+        (0..count).map(|_| ports.read(idx) & 0x03).collect()
+    }
+
+    fn bits_of(byte: u8) -> Vec<u8> {
+        (0..8).map(|bit| (byte >> bit) & 0x01).collect()
+    }
+
+    #[test]
+    fn standard_controllers_report_one_player_each() {
+        let mut ports = ControllerPorts::with_layout(PortLayout::Standard);
+        ports.load(&[0b1000_0001, 0b0100_0010, 0xFF, 0xFF]);
+        assert_eq!(bits_of(0b1000_0001), read_bits(&mut ports, 0x4016, 8));
+        assert_eq!(bits_of(0b0100_0010), read_bits(&mut ports, 0x4017, 8));
+    }
+
+    #[test]
+    fn four_score_reports_two_players_and_signature() {
+        let mut ports = ControllerPorts::with_layout(PortLayout::FourScore);
+        ports.load(&[0x01, 0x02, 0x04, 0x08]);
+
+        let mut expected = bits_of(0x01);
+        expected.extend(bits_of(0x04));
+        expected.extend(bits_of(0b0001_0000));
+        expected.extend(vec![1, 1]);
+        assert_eq!(expected, read_bits(&mut ports, 0x4016, 26));
+
+        let mut expected = bits_of(0x02);
+        expected.extend(bits_of(0x08));
+        expected.extend(bits_of(0b0010_0000));
+        expected.extend(vec![1, 1]);
+        assert_eq!(expected, read_bits(&mut ports, 0x4017, 26));
+    }
+
+    #[test]
+    fn famicom_adapter_reports_expansion_players_on_d1() {
+        let mut ports = ControllerPorts::with_layout(PortLayout::FamicomFourPlayer);
+        ports.load(&[0x01, 0x00, 0x03, 0x80]);
+
+        assert_eq!(vec![3, 2, 0, 0, 0, 0, 0, 0], read_bits(&mut ports, 0x4016, 8));
+        assert_eq!(vec![0, 0, 0, 0, 0, 0, 0, 2], read_bits(&mut ports, 0x4017, 8));
+    }
+
+    #[test]
+    fn state_is_restored() {
+        let mut ports = ControllerPorts::with_layout(PortLayout::FourScore);
+        ports.load(&[0x55, 0xAA, 0x0F, 0xF0]);
+        ports.read(0x4016);
+        let state = ports.save_state();
+        let expected = read_bits(&mut ports, 0x4016, 10);
+        ports.load_state(&*state);
+        assert_eq!(expected, read_bits(&mut ports, 0x4016, 10));
+    }
+}
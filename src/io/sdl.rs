@@ -1,78 +1,493 @@
-
-
-use io::IO;
-use io::OPEN_BUS;
-use memory::MemSegment;
-use sdl2::EventPump;
-use sdl2::keyboard::KeyboardState;
-use sdl2::keyboard::Scancode;
-use std::cell::RefCell;
-
-use std::rc::Rc;
-use util::ShiftRegister8;
-
-const A: u8 = 1;
-const B: u8 = 1 << 1;
-const SELECT: u8 = 1 << 2;
-const START: u8 = 1 << 3;
-const UP: u8 = 1 << 4;
-const DOWN: u8 = 1 << 5;
-const LEFT: u8 = 1 << 6;
-const RIGHT: u8 = 1 << 7;
-
-pub struct SdlIO {
-    event_pump: Rc<RefCell<EventPump>>,
-    controller1: ShiftRegister8,
-    controller2: ShiftRegister8,
-}
-
-impl SdlIO {
-    pub fn new(pump: Rc<RefCell<EventPump>>) -> SdlIO {
-        SdlIO {
-            event_pump: pump,
-            controller1: ShiftRegister8::new(0),
-            controller2: ShiftRegister8::new(0),
-        }
-    }
-}
-
-impl MemSegment for SdlIO {
-    fn read(&mut self, idx: u16) -> u8 {
-        match idx {
-            0x4016 => OPEN_BUS | self.controller1.shift(),
-            0x4017 => OPEN_BUS | self.controller2.shift(),
-            x => invalid_address!(x),
-        }
-    }
-
-    fn write(&mut self, _: u16, _: u8) {
-        // Do nothing
-    }
-}
-
-fn read_key(state: &KeyboardState, key: Scancode, val: u8) -> u8 {
-    if state.is_scancode_pressed(key) {
-        val
-    } else {
-        0
-    }
-}
-
-impl IO for SdlIO {
-    #[cfg_attr(rustfmt, rustfmt_skip)]
-    fn poll(&mut self) {
-        let pump_ref = self.event_pump.borrow();
-        let state = KeyboardState::new(&*pump_ref);
-
-        let c1 =
-            read_key(&state, Scancode::Z, A) |
-            read_key(&state, Scancode::X, B) |
-            read_key(&state, Scancode::Return, START) |
-            read_key(&state, Scancode::Backspace, SELECT) |
-            read_key(&state, Scancode::Up, UP) |
-            read_key(&state, Scancode::Down, DOWN) |
-            read_key(&state, Scancode::Right, RIGHT) |
-            read_key(&state, Scancode::Left, LEFT);
-        self.controller1.load(c1);
-    }
-}
+use io::{IO, ScreenView};
+use io::OPEN_BUS;
+use io::ports::{ControllerPorts, MAX_PLAYERS, PortLayout};
+use io::zapper::Zapper;
+use memory::MemSegment;
+use screen::sdl::window_to_screen;
+use sdl2::{EventPump, GameControllerSubsystem, Sdl};
+use sdl2::controller::{Axis, Button, GameController};
+use sdl2::event::Event;
+use sdl2::keyboard::KeyboardState;
+use sdl2::keyboard::Scancode;
+use std::any::Any;
+use std::cell::RefCell;
+use std::mem;
+use std::rc::Rc;
+
+const A: u8 = 1;
+const B: u8 = 1 << 1;
+const SELECT: u8 = 1 << 2;
+const START: u8 = 1 << 3;
+const UP: u8 = 1 << 4;
+const DOWN: u8 = 1 << 5;
+const LEFT: u8 = 1 << 6;
+const RIGHT: u8 = 1 << 7;
+
+/// The buttons on an NES controller, plus turbo versions of A and B.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum NesButton {
+    A,
+    B,
+    Select,
+    Start,
+    Up,
+    Down,
+    Left,
+    Right,
+    TurboA,
+    TurboB,
+}
+
+pub const ALL_BUTTONS: [NesButton; 10] = [
+    NesButton::A,
+    NesButton::B,
+    NesButton::Select,
+    NesButton::Start,
+    NesButton::Up,
+    NesButton::Down,
+    NesButton::Left,
+    NesButton::Right,
+    NesButton::TurboA,
+    NesButton::TurboB,
+];
+
+impl NesButton {
+    /// The name used for this button in config files.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            NesButton::A => "a",
+            NesButton::B => "b",
+            NesButton::Select => "select",
+            NesButton::Start => "start",
+            NesButton::Up => "up",
+            NesButton::Down => "down",
+            NesButton::Left => "left",
+            NesButton::Right => "right",
+            NesButton::TurboA => "turbo_a",
+            NesButton::TurboB => "turbo_b",
+        }
+    }
+
+    fn bits(&self) -> u8 {
+        match *self {
+            NesButton::A | NesButton::TurboA => A,
+            NesButton::B | NesButton::TurboB => B,
+            NesButton::Select => SELECT,
+            NesButton::Start => START,
+            NesButton::Up => UP,
+            NesButton::Down => DOWN,
+            NesButton::Left => LEFT,
+            NesButton::Right => RIGHT,
+        }
+    }
+
+    fn is_turbo(&self) -> bool {
+        *self == NesButton::TurboA || *self == NesButton::TurboB
+    }
+}
+
+/// A host input which can be bound to an NES button.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Binding {
+    Key(Scancode),
+    Button(Button),
+    /// A controller axis pushed past the deadzone, in the positive direction
+    /// if the flag is true.
+    Axis(Axis, bool),
+}
+
+impl Binding {
+    /// Parses a binding from a config file. Keys are given by their SDL
+    /// scancode name (eg. "Z" or "Return"), controller buttons as
+    /// "button:<name>" (eg. "button:a") and axes as "axis:+<name>" or
+    /// "axis:-<name>" (eg. "axis:-leftx"), using SDL's controller mapping names.
+    pub fn parse(string: &str) -> Option<Binding> {
+        let string = string.trim();
+        if string.starts_with("button:") {
+            Button::from_string(&string[7..]).map(Binding::Button)
+        } else if string.starts_with("axis:+") {
+            Axis::from_string(&string[6..]).map(|axis| Binding::Axis(axis, true))
+        } else if string.starts_with("axis:-") {
+            Axis::from_string(&string[6..]).map(|axis| Binding::Axis(axis, false))
+        } else {
+            Scancode::from_name(string).map(Binding::Key)
+        }
+    }
+
+    /// Parses a comma-separated list of bindings, returning the first name
+    /// which isn't recognised if there is one.
+    pub fn parse_list(string: &str) -> Result<Vec<Binding>, String> {
+        string
+            .split(',')
+            .filter(|name| !name.trim().is_empty())
+            .map(|name| Binding::parse(name).ok_or_else(|| name.trim().to_string()))
+            .collect()
+    }
+
+    fn is_pressed(
+        &self,
+        keys: &KeyboardState,
+        pad: Option<&GameController>,
+        deadzone: i16,
+    ) -> bool {
+        match (*self, pad) {
+            (Binding::Key(key), _) => keys.is_scancode_pressed(key),
+            (Binding::Button(button), Some(pad)) => pad.button(button),
+            (Binding::Axis(axis, positive), Some(pad)) => {
+                axis_pressed(pad.axis(axis), positive, deadzone)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Whether an axis is pushed past the deadzone in the given direction.
+fn axis_pressed(value: i16, positive: bool, deadzone: i16) -> bool {
+    if positive {
+        value > deadzone
+    } else {
+        value < -deadzone
+    }
+}
+
+/// The bindings for one player's controller.
+#[derive(Debug, Clone)]
+pub struct PlayerBindings {
+    bindings: Vec<Vec<Binding>>,
+}
+
+impl Default for PlayerBindings {
+    fn default() -> PlayerBindings {
+        PlayerBindings { bindings: vec![vec![]; ALL_BUTTONS.len()] }
+    }
+}
+
+impl PlayerBindings {
+    pub fn get(&self, button: NesButton) -> &[Binding] {
+        &self.bindings[button as usize]
+    }
+
+    pub fn set(&mut self, button: NesButton, bindings: Vec<Binding>) {
+        self.bindings[button as usize] = bindings;
+    }
+
+    fn read(
+        &self,
+        keys: &KeyboardState,
+        pad: Option<&GameController>,
+        deadzone: i16,
+        turbo_on: bool,
+    ) -> u8 {
+        let mut result = 0;
+        for &button in ALL_BUTTONS.iter() {
+            if button.is_turbo() && !turbo_on {
+                continue;
+            }
+            let pressed = self.get(button)
+                .iter()
+                .any(|binding| binding.is_pressed(keys, pad, deadzone));
+            if pressed {
+                result |= button.bits();
+            }
+        }
+        result
+    }
+}
+
+fn bind(player: &mut PlayerBindings, button: NesButton, names: &[&str]) {
+    let bindings = names.iter().filter_map(|name| Binding::parse(name)).collect();
+    player.set(button, bindings);
+}
+
+fn default_gamepad_bindings() -> PlayerBindings {
+    let mut player = PlayerBindings::default();
+    bind(&mut player, NesButton::A, &["button:b"]);
+    bind(&mut player, NesButton::B, &["button:a"]);
+    bind(&mut player, NesButton::Select, &["button:back"]);
+    bind(&mut player, NesButton::Start, &["button:start"]);
+    bind(&mut player, NesButton::Up, &["button:dpup", "axis:-lefty"]);
+    bind(&mut player, NesButton::Down, &["button:dpdown", "axis:+lefty"]);
+    bind(&mut player, NesButton::Left, &["button:dpleft", "axis:-leftx"]);
+    bind(&mut player, NesButton::Right, &["button:dpright", "axis:+leftx"]);
+    bind(&mut player, NesButton::TurboA, &["button:y"]);
+    bind(&mut player, NesButton::TurboB, &["button:x"]);
+    player
+}
+
+/// Input bindings for all players, and the settings shared between them.
+#[derive(Debug, Clone)]
+pub struct InputConfig {
+    /// One entry per player. Player N also uses the Nth connected controller.
+    pub players: Vec<PlayerBindings>,
+    /// How far an axis must be pushed before it counts as pressed.
+    pub deadzone: i16,
+    /// The number of frames that turbo buttons spend pressed, and then
+    /// released, while held down.
+    pub turbo_period: u32,
+}
+
+impl Default for InputConfig {
+    fn default() -> InputConfig {
+        let mut player1 = default_gamepad_bindings();
+        for &button in ALL_BUTTONS.iter() {
+            let key = match button {
+                NesButton::A => "Z",
+                NesButton::B => "X",
+                NesButton::Select => "Backspace",
+                NesButton::Start => "Return",
+                NesButton::Up => "Up",
+                NesButton::Down => "Down",
+                NesButton::Left => "Left",
+                NesButton::Right => "Right",
+                NesButton::TurboA => "A",
+                NesButton::TurboB => "S",
+            };
+            let mut bindings = player1.get(button).to_vec();
+            bindings.extend(Binding::parse(key));
+            player1.set(button, bindings);
+        }
+
+        InputConfig {
+            players: vec![
+                player1,
+                default_gamepad_bindings(),
+                default_gamepad_bindings(),
+                default_gamepad_bindings(),
+            ],
+            deadzone: 8000,
+            turbo_period: 2,
+        }
+    }
+}
+
+/// Hands controller connections and disconnections to an `SdlIO`. The app
+/// owns the event loop, so it has to pass its events on through one of these.
+#[derive(Clone, Default)]
+pub struct SdlEvents(Rc<RefCell<Vec<Event>>>);
+
+impl SdlEvents {
+    pub fn forward(&self, event: &Event) {
+        match *event {
+            Event::ControllerDeviceAdded { .. } |
+            Event::ControllerDeviceRemoved { .. } => self.0.borrow_mut().push(event.clone()),
+            _ => (),
+        }
+    }
+}
+
+pub struct SdlIO {
+    event_pump: Rc<RefCell<EventPump>>,
+    ports: ControllerPorts,
+    zapper: Option<Zapper>,
+
+    config: InputConfig,
+    controller_subsystem: Option<GameControllerSubsystem>,
+    gamepads: Vec<Option<GameController>>,
+    events: SdlEvents,
+
+    /// Counts frames, to time the turbo buttons.
+    turbo_counter: u32,
+}
+
+impl SdlIO {
+    /// Creates an SdlIO with a standard controller in each port, using the
+    /// default keyboard bindings and no game controllers.
+    pub fn new(pump: Rc<RefCell<EventPump>>) -> SdlIO {
+        SdlIO {
+            event_pump: pump,
+            ports: ControllerPorts::with_layout(PortLayout::Standard),
+            zapper: None,
+
+            config: InputConfig::default(),
+            controller_subsystem: None,
+            gamepads: vec![],
+            events: SdlEvents::default(),
+
+            turbo_counter: 0,
+        }
+    }
+
+    /// Creates an SdlIO which also reads game controllers. Controllers can
+    /// be connected and disconnected at any time, as long as the events from
+    /// the shared event pump are forwarded to `events()`.
+    pub fn with_config(
+        sdl: &Sdl,
+        pump: Rc<RefCell<EventPump>>,
+        layout: PortLayout,
+        config: InputConfig,
+    ) -> SdlIO {
+        let mut io = SdlIO::new(pump);
+        io.ports = ControllerPorts::with_layout(layout);
+        io.config = config;
+        io.controller_subsystem = sdl.game_controller().ok();
+        io
+    }
+
+    /// Connects a Zapper to the second port instead of a controller. The gun
+    /// is aimed with the mouse and fired with the left mouse button.
+    pub fn connect_zapper(&mut self) {
+        self.zapper = Some(Zapper::new());
+    }
+
+    /// A handle to forward the event pump's events through. SDL reports the
+    /// controllers which are already plugged in as connections too.
+    pub fn events(&self) -> SdlEvents {
+        self.events.clone()
+    }
+
+    fn handle_event(&mut self, event: Event) {
+        match event {
+            Event::ControllerDeviceAdded { which, .. } => self.open_gamepad(which as u32),
+            Event::ControllerDeviceRemoved { which, .. } => {
+                for slot in self.gamepads.iter_mut() {
+                    if slot.as_ref().map_or(false, |pad| pad.instance_id() == which) {
+                        *slot = None;
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Opens a newly-connected controller in the first free player slot.
+    fn open_gamepad(&mut self, id: u32) {
+        let pad = match self.controller_subsystem {
+            Some(ref subsystem) if subsystem.is_game_controller(id) => {
+                match subsystem.open(id) {
+                    Ok(pad) => pad,
+                    Err(_) => return,
+                }
+            }
+            _ => return,
+        };
+        let instance_id = pad.instance_id();
+        let already_open = self.gamepads.iter().any(|slot| {
+            slot.as_ref()
+                .map_or(false, |open| open.instance_id() == instance_id)
+        });
+        if already_open {
+            return;
+        }
+        if let Some(slot) = self.gamepads.iter_mut().find(|slot| slot.is_none()) {
+            *slot = Some(pad);
+            return;
+        }
+        if self.gamepads.len() < MAX_PLAYERS {
+            self.gamepads.push(Some(pad));
+        }
+    }
+}
+
+impl MemSegment for SdlIO {
+    fn read(&mut self, idx: u16) -> u8 {
+        match (idx, &self.zapper) {
+            (0x4017, &Some(ref zapper)) => OPEN_BUS | zapper.read(),
+            _ => self.ports.read(idx),
+        }
+    }
+
+    fn write(&mut self, _: u16, _: u8) {}
+}
+
+impl IO for SdlIO {
+    fn poll(&mut self) {
+        let events = mem::replace(&mut *self.events.0.borrow_mut(), vec![]);
+        for event in events {
+            self.handle_event(event);
+        }
+
+        let pump_ref = self.event_pump.borrow();
+        let keys = KeyboardState::new(&*pump_ref);
+        let period = ::std::cmp::max(self.config.turbo_period, 1);
+        let turbo_on = (self.turbo_counter / period) % 2 == 0;
+
+        let mut players = [0u8; MAX_PLAYERS];
+        for (player, bindings) in self.config.players.iter().take(MAX_PLAYERS).enumerate() {
+            let pad = self.gamepads.get(player).and_then(|slot| slot.as_ref());
+            players[player] = bindings.read(&keys, pad, self.config.deadzone, turbo_on);
+        }
+        self.ports.load(&players);
+    }
+
+    fn end_frame(&mut self) {
+        self.turbo_counter = self.turbo_counter.wrapping_add(1);
+    }
+
+    fn observes_screen(&self) -> bool {
+        self.zapper.is_some()
+    }
+
+    fn observe_screen(&mut self, view: &ScreenView) {
+        if let Some(ref mut zapper) = self.zapper {
+            let mouse_state = self.event_pump.borrow().mouse_state();
+            let (x, y) = window_to_screen(mouse_state.x(), mouse_state.y());
+            zapper.aim(x, y);
+            zapper.set_trigger(mouse_state.left());
+            zapper.sense_light(view);
+        }
+    }
+
+    fn save_state(&self) -> Box<Any> {
+        Box::new((self.ports.save_state(), self.zapper.clone(), self.turbo_counter))
+    }
+
+    fn load_state(&mut self, state: &Any) {
+        let &(ref ports, ref zapper, turbo_counter) = state
+            .downcast_ref::<(Box<Any>, Option<Zapper>, u32)>()
+            .unwrap();
+        self.ports.load_state(&**ports);
+        self.zapper = zapper.clone();
+        self.turbo_counter = turbo_counter;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_keys_buttons_and_axes() {
+        assert_eq!(Some(Binding::Key(Scancode::Z)), Binding::parse("Z"));
+        assert_eq!(Some(Binding::Key(Scancode::Return)), Binding::parse(" Return "));
+        assert_eq!(Some(Binding::Button(Button::B)), Binding::parse("button:b"));
+        assert_eq!(
+            Some(Binding::Axis(Axis::LeftY, true)),
+            Binding::parse("axis:+lefty")
+        );
+        assert_eq!(
+            Some(Binding::Axis(Axis::LeftX, false)),
+            Binding::parse("axis:-leftx")
+        );
+    }
+
+    #[test]
+    fn parses_binding_lists() {
+        assert_eq!(
+            Ok(vec![Binding::Key(Scancode::Z), Binding::Button(Button::B)]),
+            Binding::parse_list("Z, button:b")
+        );
+        assert_eq!(Ok(vec![]), Binding::parse_list(" , "));
+    }
+
+    #[test]
+    fn rejects_unknown_names() {
+        assert_eq!(None, Binding::parse("NotAKey"));
+        assert_eq!(None, Binding::parse("button:nope"));
+        assert_eq!(None, Binding::parse("axis:lefty"));
+        assert_eq!(None, Binding::parse("axis:+nope"));
+        assert_eq!(
+            Err("button:nope".to_string()),
+            Binding::parse_list("Z, button:nope")
+        );
+    }
+
+    #[test]
+    fn axes_must_pass_the_deadzone() {
+        assert!(!axis_pressed(8000, true, 8000));
+        assert!(axis_pressed(8001, true, 8000));
+        assert!(!axis_pressed(-8001, true, 8000));
+        assert!(!axis_pressed(-8000, false, 8000));
+        assert!(axis_pressed(-8001, false, 8000));
+        assert!(!axis_pressed(8001, false, 8000));
+    }
+}
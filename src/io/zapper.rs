@@ -0,0 +1,180 @@
+use io::ScreenView;
+use ppu::{Color, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+/// Trigger bit - set while the trigger is held down.
+const TRIGGER: u8 = 1 << 4;
+/// Light sense bit - cleared while the sensor sees light.
+const NO_LIGHT: u8 = 1 << 3;
+
+/// How many scanlines after the beam passes a bright pixel the sensor keeps
+/// reporting light. The real photodiode decays over roughly this long.
+const LIGHT_PERSISTENCE: i32 = 20;
+
+/// The sensor doesn't see a single pixel, but a small area around the point
+/// the gun is aimed at.
+const SENSE_RADIUS: i32 = 2;
+
+/// The NES Zapper light gun.
+#[derive(Debug, Clone)]
+pub struct Zapper {
+    aim: Option<(i32, i32)>,
+    trigger: bool,
+    light: bool,
+}
+
+impl Default for Zapper {
+    fn default() -> Zapper {
+        Zapper {
+            aim: None,
+            trigger: false,
+            light: false,
+        }
+    }
+}
+
+impl Zapper {
+    pub fn new() -> Zapper {
+        Default::default()
+    }
+
+    /// Aims the gun at the given pixel. Points outside the screen are treated
+    /// as pointing away from the TV.
+    pub fn aim(&mut self, x: i32, y: i32) {
+        if x >= 0 && y >= 0 && x < SCREEN_WIDTH as i32 && y < SCREEN_HEIGHT as i32 {
+            self.aim = Some((x, y));
+        } else {
+            self.aim = None;
+        }
+    }
+
+    pub fn set_trigger(&mut self, pulled: bool) {
+        self.trigger = pulled;
+    }
+
+    /// Updates the light sensor from the current contents of the screen. The
+    /// sensor only sees pixels which the beam has drawn recently, so this
+    /// needs to be called at the time of the read.
+    pub fn sense_light(&mut self, view: &ScreenView) {
+        self.light = match self.aim {
+            Some((x, y)) => Zapper::sees_light(view, x, y),
+            None => false,
+        };
+    }
+
+    fn sees_light(view: &ScreenView, aim_x: i32, aim_y: i32) -> bool {
+        let scanline = view.scanline as i32;
+        let dot = view.dot as i32;
+
+        for y in (aim_y - SENSE_RADIUS)..(aim_y + SENSE_RADIUS + 1) {
+            if y < 0 || y >= SCREEN_HEIGHT as i32 {
+                continue;
+            }
+            if scanline < y || scanline - y > LIGHT_PERSISTENCE {
+                continue;
+            }
+            for x in (aim_x - SENSE_RADIUS)..(aim_x + SENSE_RADIUS + 1) {
+                if x < 0 || x >= SCREEN_WIDTH as i32 {
+                    continue;
+                }
+                // The beam hasn't reached this pixel yet
+                if scanline == y && dot <= x {
+                    continue;
+                }
+                let color = view.buffer[y as usize * SCREEN_WIDTH + x as usize];
+                if is_bright(color) {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    pub fn read(&self) -> u8 {
+        let mut result = 0;
+        if self.trigger {
+            result |= TRIGGER;
+        }
+        if !self.light {
+            result |= NO_LIGHT;
+        }
+        result
+    }
+}
+
+/// Whether a color is bright enough to trigger the sensor. Only the lighter
+/// two rows of the palette qualify, excluding the greys and blacks in columns
+/// $D-$F.
+fn is_bright(color: Color) -> bool {
+    let bits = color.color();
+    let hue = bits & 0x0F;
+    let luma = bits >> 4;
+    luma >= 2 && hue < 0x0D
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ppu::{Color, SCREEN_BUFFER_SIZE};
+
+    fn screen_with_white_box() -> Box<[Color; SCREEN_BUFFER_SIZE]> {
+        let mut buffer = Box::new([Color::from_bits(0x0F); SCREEN_BUFFER_SIZE]);
+        for y in 100..120 {
+            for x in 100..120 {
+                buffer[y * SCREEN_WIDTH + x] = Color::from_bits(0x30);
+            }
+        }
+        buffer
+    }
+
+    fn view(buffer: &[Color; SCREEN_BUFFER_SIZE], scanline: i16, dot: u16) -> ScreenView {
+        ScreenView {
+            buffer: buffer,
+            scanline: scanline,
+            dot: dot,
+        }
+    }
+
+    #[test]
+    fn reports_trigger() {
+        let mut zapper = Zapper::new();
+        assert_eq!(0, zapper.read() & TRIGGER);
+        zapper.set_trigger(true);
+        assert_eq!(TRIGGER, zapper.read() & TRIGGER);
+    }
+
+    #[test]
+    fn senses_light_after_beam_passes_target() {
+        let buffer = screen_with_white_box();
+        let mut zapper = Zapper::new();
+        zapper.aim(110, 110);
+
+        zapper.sense_light(&view(&buffer, 100, 0));
+        assert_eq!(NO_LIGHT, zapper.read() & NO_LIGHT);
+
+        zapper.sense_light(&view(&buffer, 112, 0));
+        assert_eq!(0, zapper.read() & NO_LIGHT);
+
+        zapper.sense_light(&view(&buffer, 150, 0));
+        assert_eq!(NO_LIGHT, zapper.read() & NO_LIGHT);
+    }
+
+    #[test]
+    fn does_not_sense_dark_pixels() {
+        let buffer = screen_with_white_box();
+        let mut zapper = Zapper::new();
+        zapper.aim(20, 20);
+
+        zapper.sense_light(&view(&buffer, 25, 0));
+        assert_eq!(NO_LIGHT, zapper.read() & NO_LIGHT);
+    }
+
+    #[test]
+    fn does_not_sense_light_when_aimed_off_screen() {
+        let buffer = screen_with_white_box();
+        let mut zapper = Zapper::new();
+        zapper.aim(-1, 110);
+
+        zapper.sense_light(&view(&buffer, 112, 0));
+        assert_eq!(NO_LIGHT, zapper.read() & NO_LIGHT);
+    }
+}
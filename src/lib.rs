@@ -1,155 +1,430 @@
-#![feature(test)]
-#![feature(plugin)]
-#![feature(asm)]
-#![feature(naked_functions)]
-
-#![plugin(dynasm)]
-
-#![allow(unused_features)]
-#![allow(unknown_lints)]
-#![allow(new_without_default)]
-#![allow(match_same_arms)]
-
-#[cfg(target_arch = "x86_64")]
-extern crate dynasmrt;
-
-#[macro_use]
-extern crate bitflags;
-
-#[macro_use]
-extern crate quick_error;
-
-#[macro_use]
-extern crate nom;
-
-pub extern crate sdl2;
-extern crate blip_buf;
-extern crate memmap;
-extern crate fnv;
-
-#[cfg(feature = "vectorize")]
-extern crate simd;
-
-pub mod cart;
-pub mod memory;
-pub mod mappers;
-pub mod ppu;
-pub mod apu;
-pub mod io;
-pub mod cpu;
-pub mod screen;
-pub mod audio;
-
-mod util;
-
-#[cfg(test)]
-mod tests;
-
-use apu::APU;
-use cart::Cart;
-use cpu::CPU;
-use io::IO;
-use ppu::PPU;
-use std::cell::RefCell;
-use std::cell::UnsafeCell;
-
-use std::rc::Rc;
-
-#[derive(Debug, Clone)]
-pub struct Settings {
-    pub jit: bool,
-    pub graphics_enabled: bool,
-    pub sound_enabled: bool,
-
-    // The following will only be used if compiled with the debug_features feature
-    pub trace_cpu: bool,
-    pub disassemble_functions: bool,
-}
-
-impl Default for Settings {
-    fn default() -> Settings {
-        Settings {
-            jit: false,
-            graphics_enabled: true,
-            sound_enabled: true,
-
-            trace_cpu: false,
-            disassemble_functions: false,
-        }
-    }
-}
-
-pub struct EmulatorBuilder {
-    cart: Cart,
-    settings: Settings,
-
-    pub screen: Box<screen::Screen>,
-    pub audio_out: Box<audio::AudioOut>,
-    pub io: Box<IO>,
-}
-
-impl EmulatorBuilder {
-    pub fn new(cart: Cart, settings: Settings) -> EmulatorBuilder {
-        EmulatorBuilder {
-            cart: cart,
-            settings: settings,
-
-            screen: Box::new(screen::DummyScreen::default()),
-            audio_out: Box::new(audio::DummyAudioOut),
-            io: Box::new(io::DummyIO::Dummy),
-        }
-    }
-
-    pub fn new_sdl(
-        cart: Cart,
-        settings: Settings,
-        sdl: &sdl2::Sdl,
-        event_pump: &Rc<RefCell<sdl2::EventPump>>,
-    ) -> EmulatorBuilder {
-        let sound_enabled = settings.sound_enabled;
-        let mut builder = EmulatorBuilder::new(cart, settings);
-
-        builder.screen = Box::new(screen::sdl::SDLScreen::new(sdl));
-        if sound_enabled {
-            builder.audio_out = Box::new(audio::sdl::SDLAudioOut::new(sdl));
-        }
-        builder.io = Box::new(io::sdl::SdlIO::new(event_pump.clone()));
-
-        builder
-    }
-
-    pub fn build(self) -> Emulator {
-        let settings = Rc::new(self.settings);
-        let dispatcher = cpu::dispatcher::Dispatcher::new();
-        let cart: Rc<UnsafeCell<Cart>> = Rc::new(UnsafeCell::new(self.cart));
-        let ppu = PPU::new(settings.clone(), cart.clone(), self.screen);
-        let apu = APU::new(settings.clone(), self.audio_out);
-        let mut cpu = CPU::new(settings, ppu, apu, self.io, cart, dispatcher);
-        cpu.init();
-
-        Emulator { cpu: cpu }
-    }
-}
-
-pub struct Emulator {
-    cpu: CPU,
-}
-
-impl Emulator {
-    pub fn run_frame(&mut self) {
-        self.cpu.run_frame();
-    }
-
-    pub fn halted(&self) -> bool {
-        self.cpu.halted()
-    }
-
-    #[cfg(feature = "debug_features")]
-    pub fn mouse_pick(&self, px_x: i32, px_y: i32) {
-        self.cpu.ppu.mouse_pick(px_x, px_y);
-    }
-
-    pub fn rendering_enabled(&self) -> bool {
-        self.cpu.ppu.rendering_enabled()
-    }
-}
+#![feature(test)]
+#![feature(plugin)]
+#![feature(asm)]
+#![feature(naked_functions)]
+
+#![plugin(dynasm)]
+
+#![allow(unused_features)]
+#![allow(unknown_lints)]
+#![allow(new_without_default)]
+#![allow(match_same_arms)]
+
+#[cfg(target_arch = "x86_64")]
+extern crate dynasmrt;
+
+#[macro_use]
+extern crate bitflags;
+
+#[macro_use]
+extern crate quick_error;
+
+#[macro_use]
+extern crate nom;
+
+pub extern crate sdl2;
+extern crate blip_buf;
+extern crate memmap;
+extern crate fnv;
+extern crate png;
+
+#[cfg(feature = "vectorize")]
+extern crate simd;
+
+pub mod cart;
+pub mod memory;
+pub mod mappers;
+pub mod ppu;
+pub mod apu;
+pub mod io;
+pub mod cpu;
+pub mod screen;
+pub mod audio;
+pub mod events;
+pub mod recording;
+pub mod nsf;
+
+mod util;
+
+#[cfg(test)]
+mod tests;
+
+use apu::{APU, MixerMode};
+use cart::{Cart, TvFormat};
+use cpu::CPU;
+use io::IO;
+use ppu::PPU;
+use std::cell::RefCell;
+use std::cell::UnsafeCell;
+
+use std::rc::Rc;
+
+/// The console whose timing is emulated.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Region {
+    NTSC,
+    PAL,
+    /// Famiclones such as the Dendy, which combine a PAL-speed clock and 312
+    /// scanline frames with NTSC's 3:1 PPU clock ratio and APU timing.
+    Dendy,
+}
+
+impl Region {
+    /// The CPU clock rate, in Hz.
+    pub fn cpu_clock_rate(&self) -> u64 {
+        match *self {
+            Region::NTSC => 1789773,
+            Region::PAL => 1662607,
+            Region::Dendy => 1773448,
+        }
+    }
+
+    /// The number of frames per second, rounded down.
+    pub fn frames_per_second(&self) -> u32 {
+        match *self {
+            Region::NTSC => 60,
+            Region::PAL | Region::Dendy => 50,
+        }
+    }
+
+    /// The exact number of frames per second as a fraction (numerator,
+    /// denominator): the CPU clock rate over the average number of CPU
+    /// cycles per frame. NTSC frames average 29780.5 cycles because of the
+    /// skipped dot on odd frames, giving about 60.0988 frames per second.
+    pub fn frame_rate(&self) -> (u64, u64) {
+        match *self {
+            Region::NTSC => (self.cpu_clock_rate() * 2, 59561),
+            Region::PAL => (self.cpu_clock_rate() * 2, 66495),
+            Region::Dendy => (self.cpu_clock_rate(), 35464),
+        }
+    }
+}
+
+impl From<TvFormat> for Region {
+    fn from(tv: TvFormat) -> Region {
+        match tv {
+            TvFormat::NTSC => Region::NTSC,
+            TvFormat::PAL => Region::PAL,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub jit: bool,
+    pub graphics_enabled: bool,
+    pub sound_enabled: bool,
+
+    /// Number of frames to emulate ahead of the displayed one to hide the
+    /// game's input lag. Zero disables run-ahead.
+    pub run_ahead: u32,
+
+    /// Draw every sprite on a scanline instead of only the first eight, to
+    /// remove flicker. The sprite overflow flag is emulated as normal.
+    pub no_sprite_limit: bool,
+
+    /// Render the picture one dot at a time, following the real PPU's memory
+    /// accesses. Slower, but needed for mid-scanline effects and mappers
+    /// which watch the PPU's fetches.
+    pub dot_accurate_ppu: bool,
+
+    /// The console timing to emulate. When `None`, `EmulatorBuilder::build`
+    /// picks NTSC or PAL from the ROM header.
+    pub region: Option<Region>,
+
+    /// Whether to mix the sound channels like the real hardware, including
+    /// its output filters, or linearly and unfiltered.
+    pub mixer_mode: MixerMode,
+
+    /// Nudge the output sample rate to keep the audio device's buffer half
+    /// full, so sound stays continuous when the display, rather than the
+    /// audio device, sets the pace.
+    pub dynamic_rate_control: bool,
+
+    /// The output sample rate in Hz, from `audio::MIN_SAMPLE_RATE` to
+    /// `audio::MAX_SAMPLE_RATE`.
+    pub sample_rate: u32,
+    /// 1 for mono or 2 for stereo.
+    pub audio_channels: usize,
+    /// How much sound to keep buffered ahead of the audio device, in
+    /// milliseconds. Less is more responsive, but more likely to crackle.
+    pub audio_latency_ms: u32,
+
+    // The following will only be used if compiled with the debug_features feature
+    pub trace_cpu: bool,
+    pub disassemble_functions: bool,
+}
+
+impl Settings {
+    /// The region being emulated. Falls back to NTSC if it hasn't been chosen
+    /// yet.
+    pub fn region(&self) -> Region {
+        self.region.unwrap_or(Region::NTSC)
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            jit: false,
+            graphics_enabled: true,
+            sound_enabled: true,
+
+            run_ahead: 0,
+            no_sprite_limit: false,
+            dot_accurate_ppu: false,
+            region: None,
+            mixer_mode: MixerMode::Accurate,
+            dynamic_rate_control: true,
+            sample_rate: 44100,
+            audio_channels: audio::CHANNELS,
+            audio_latency_ms: 33,
+
+            trace_cpu: false,
+            disassemble_functions: false,
+        }
+    }
+}
+
+pub struct EmulatorBuilder {
+    cart: Cart,
+    settings: Settings,
+
+    pub screen: Box<screen::Screen>,
+    pub audio_out: Box<audio::AudioOut>,
+    pub io: Box<IO>,
+}
+
+impl EmulatorBuilder {
+    pub fn new(cart: Cart, settings: Settings) -> EmulatorBuilder {
+        EmulatorBuilder {
+            cart: cart,
+            settings: settings,
+
+            screen: Box::new(screen::DummyScreen::default()),
+            audio_out: Box::new(audio::DummyAudioOut),
+            io: Box::new(io::DummyIO::Dummy),
+        }
+    }
+
+    pub fn new_sdl(
+        cart: Cart,
+        settings: Settings,
+        sdl: &sdl2::Sdl,
+        event_pump: &Rc<RefCell<sdl2::EventPump>>,
+    ) -> EmulatorBuilder {
+        let sound_enabled = settings.sound_enabled;
+        let audio_out = if sound_enabled {
+            Some(audio::sdl::SDLAudioOut::new(
+                sdl,
+                settings.sample_rate,
+                settings.audio_channels,
+                settings.audio_latency_ms,
+            ))
+        } else {
+            None
+        };
+        let mut builder = EmulatorBuilder::new(cart, settings);
+
+        builder.screen = Box::new(screen::sdl::SDLScreen::new(sdl));
+        if let Some(audio_out) = audio_out {
+            builder.audio_out = Box::new(audio_out);
+        }
+        builder.io = Box::new(io::sdl::SdlIO::with_config(
+            sdl,
+            event_pump.clone(),
+            io::ports::PortLayout::Standard,
+            Default::default(),
+        ));
+
+        builder
+    }
+
+    /// The region the emulator will be built for: the one in the settings,
+    /// or else the one from the ROM header.
+    pub fn region(&self) -> Region {
+        self.settings.region.unwrap_or_else(|| self.cart.tv.into())
+    }
+
+    pub fn build(mut self) -> Emulator {
+        self.settings.region = Some(self.region());
+        let run_ahead = self.settings.run_ahead;
+        let region = self.settings.region();
+        let settings = Rc::new(self.settings);
+        let dispatcher = cpu::dispatcher::Dispatcher::new();
+        let cart: Rc<UnsafeCell<Cart>> = Rc::new(UnsafeCell::new(self.cart));
+        let ppu = PPU::new(settings.clone(), cart.clone(), self.screen);
+        let apu = APU::new(settings.clone(), cart.clone(), self.audio_out);
+        let mut cpu = CPU::new(settings, ppu, apu, self.io, cart, dispatcher);
+        cpu.init();
+
+        Emulator {
+            cpu: cpu,
+            run_ahead: run_ahead,
+            region: region,
+        }
+    }
+}
+
+/// A snapshot of the emulator state, which can be restored with
+/// `Emulator::load_state`. Audio which has already been generated isn't
+/// included.
+pub struct SaveState {
+    cpu: cpu::CPUState,
+}
+
+pub struct Emulator {
+    cpu: CPU,
+    run_ahead: u32,
+    region: Region,
+}
+
+impl Emulator {
+    pub fn run_frame(&mut self) {
+        if self.run_ahead == 0 {
+            self.cpu.run_frame();
+        } else {
+            self.run_frame_ahead();
+        }
+    }
+
+    /// Runs the real frame without displaying it, then runs `run_ahead`
+    /// frames past it with sound muted, displays the last of those and
+    /// rewinds back to the end of the real frame.
+    fn run_frame_ahead(&mut self) {
+        self.cpu.ppu.set_output_enabled(false);
+        self.cpu.run_frame();
+
+        let state = self.save_state();
+        self.cpu.apu.set_output_enabled(false);
+        self.cpu.set_hooks_enabled(false);
+        for frame in 0..self.run_ahead {
+            let last = frame + 1 == self.run_ahead;
+            self.cpu.ppu.set_output_enabled(last);
+            self.cpu.run_frame();
+        }
+        self.load_state(&state);
+
+        self.cpu.ppu.set_output_enabled(true);
+        self.cpu.apu.set_output_enabled(true);
+        self.cpu.set_hooks_enabled(true);
+    }
+
+    /// Presses the reset button on the console.
+    pub fn reset(&mut self) {
+        self.cpu.reset();
+    }
+
+    pub fn save_state(&self) -> SaveState {
+        SaveState { cpu: self.cpu.save_state() }
+    }
+
+    pub fn load_state(&mut self, state: &SaveState) {
+        self.cpu.load_state(&state.cpu);
+    }
+
+    /// Replaces the screen that frames are drawn to, returning the old one.
+    pub fn set_screen(&mut self, screen: Box<screen::Screen>) -> Box<screen::Screen> {
+        self.cpu.ppu.set_screen(screen)
+    }
+
+    /// Replaces the device that audio is sent to, returning the old one.
+    pub fn set_audio_out(&mut self, audio_out: Box<audio::AudioOut>) -> Box<audio::AudioOut> {
+        self.cpu.apu.set_audio_out(audio_out)
+    }
+
+    /// Sends each sound channel's output, on its own, to `stem_out`. Pass
+    /// `None` to stop.
+    pub fn set_stem_out(
+        &mut self,
+        stem_out: Option<Box<audio::StemOut>>,
+    ) -> Option<Box<audio::StemOut>> {
+        self.cpu.apu.set_stem_out(stem_out)
+    }
+
+    /// The volume, panning, mute and solo settings of a sound channel.
+    pub fn channel_mix(&self, channel: apu::Channel) -> apu::ChannelMix {
+        self.cpu.apu.channel_mix(channel)
+    }
+
+    pub fn set_channel_mix(&mut self, channel: apu::Channel, mix: apu::ChannelMix) {
+        self.cpu.apu.set_channel_mix(channel, mix)
+    }
+
+    /// The region whose timing is being emulated.
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    /// Underrun and overrun counts and the buffer level of the audio device.
+    pub fn audio_stats(&self) -> audio::AudioStats {
+        self.cpu.apu.audio_stats()
+    }
+
+    /// Calls `hook` each time the PPU reaches the given scanline (-1 for the
+    /// pre-render line) and dot. The event holds the PPU's state on that
+    /// dot, and the PC of the instruction running on or just after it.
+    pub fn add_dot_hook<F>(&mut self, scanline: i16, dot: u16, hook: F)
+    where
+        F: FnMut(&events::PPUEvent) + 'static,
+    {
+        self.cpu.add_dot_hook(scanline, dot, Box::new(hook));
+    }
+
+    /// Calls `hook` after every CPU write to a PPU register ($2000-$2007 and
+    /// their mirrors).
+    pub fn add_register_write_hook<F>(&mut self, hook: F)
+    where
+        F: FnMut(&events::PPUEvent) + 'static,
+    {
+        self.cpu.add_register_write_hook(Box::new(hook));
+    }
+
+    /// Removes all dot and register write hooks.
+    pub fn clear_hooks(&mut self) {
+        self.cpu.clear_hooks();
+    }
+
+    /// Draws the four nametables, with the visible area outlined. See
+    /// `ppu::debug` for the sizes of the debug images.
+    pub fn render_nametables(&mut self, palette: &screen::Palette) -> screen::RgbImage {
+        self.cpu.ppu.render_nametables(palette)
+    }
+
+    /// Draws both pattern tables using one of the eight palettes.
+    pub fn render_pattern_tables(
+        &mut self,
+        palette_id: u8,
+        palette: &screen::Palette,
+    ) -> screen::RgbImage {
+        self.cpu.ppu.render_pattern_tables(palette_id, palette)
+    }
+
+    /// Decodes the 64 sprites in OAM.
+    pub fn sprites(&self) -> Vec<ppu::debug::SpriteInfo> {
+        self.cpu.ppu.sprites()
+    }
+
+    /// Draws the 64 sprites in OAM in a grid.
+    pub fn render_sprites(&mut self, palette: &screen::Palette) -> screen::RgbImage {
+        self.cpu.ppu.render_sprites(palette)
+    }
+
+    /// Draws the 32 palette entries.
+    pub fn render_palette(&mut self, palette: &screen::Palette) -> screen::RgbImage {
+        self.cpu.ppu.render_palette(palette)
+    }
+
+    pub fn halted(&self) -> bool {
+        self.cpu.halted()
+    }
+
+    #[cfg(feature = "debug_features")]
+    pub fn mouse_pick(&self, px_x: i32, px_y: i32) {
+        self.cpu.ppu.mouse_pick(px_x, px_y);
+    }
+
+    pub fn rendering_enabled(&self) -> bool {
+        self.cpu.ppu.rendering_enabled()
+    }
+}
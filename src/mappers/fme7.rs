@@ -0,0 +1,583 @@
+//! Sunsoft's FME-7, used by Gimmick! and Batman: Return of the Joker, and
+//! its 5B variant which adds a YM2149-style sound chip. Everything is set
+//! through a command register and a parameter register: eight 1KB CHR
+//! banks, four 8KB PRG banks (the one at $6000 can be RAM instead of ROM),
+//! mirroring, and a 16-bit IRQ counter clocked every CPU cycle.
+//!
+//! The 5B's sound is three square waves, each of which can be mixed with a
+//! shared noise generator and take its volume from a shared envelope. Its
+//! volume steps are logarithmic rather than linear like the APU's.
+
+use super::{Mapper, MapperParams, RamSegment, RomAddress};
+use super::bank::*;
+use super::battery::BatteryBackedRam;
+use super::volatile::VolatileRam;
+use apu::{PULSE_LEVEL, Waveform};
+use cart::ScreenMode;
+use memory::MemSegment;
+use std::any::Any;
+use std::cmp;
+
+/// The tone and envelope counters count once every 16 CPU cycles, and
+/// the noise once every 32.
+const TONE_CLOCK: u32 = 16;
+const NOISE_CLOCK: u32 = 32;
+const ENVELOPE_CLOCK: u32 = 16;
+
+/// Scales a channel's amplitude, at most 1.0, so that a channel at full
+/// volume is about as loud as one of the APU's pulses.
+const LEVEL_SCALE: f32 = PULSE_LEVEL;
+
+/// Each of the 32 envelope levels is 1.5dB quieter than the one above.
+/// The 4-bit channel volumes use every other level.
+fn amplitude(level: u8) -> f32 {
+    if level == 0 {
+        0.0
+    } else {
+        10f32.powf((level as f32 - 31.0) * 1.5 / 20.0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Regs {
+    command: u8,
+    chr: [u8; 8],
+    /// The bank at $6000, and whether it's RAM and enabled.
+    prg_6000: u8,
+    prg: [u8; 3],
+    mirroring: u8,
+}
+
+impl Regs {
+    fn ram_selected(&self) -> bool {
+        self.prg_6000 & 0b0100_0000 != 0
+    }
+
+    fn ram_enabled(&self) -> bool {
+        self.prg_6000 & 0b1000_0000 != 0
+    }
+}
+
+/// Counts down every CPU cycle while enabled, raising the IRQ (if that's
+/// enabled) as it wraps from $0000 to $FFFF.
+#[derive(Debug, Clone, PartialEq)]
+struct Irq {
+    counter: u16,
+    irq_enabled: bool,
+    counter_enabled: bool,
+    /// Set when the IRQ fires, until the control register is written.
+    pending: bool,
+    last_cycle: u64,
+}
+
+impl Irq {
+    fn run_to(&mut self, cycle: u64) -> bool {
+        if cycle <= self.last_cycle {
+            return false;
+        }
+        let cycles = cycle - self.last_cycle;
+        self.last_cycle = cycle;
+        if !self.counter_enabled {
+            return false;
+        }
+        let wrapped = cycles > self.counter as u64;
+        self.counter = (self.counter as u64).wrapping_sub(cycles) as u16;
+        let fired = wrapped && self.irq_enabled;
+        self.pending |= fired;
+        fired
+    }
+
+    fn next_irq_cycle(&self) -> u64 {
+        if !self.counter_enabled || !self.irq_enabled {
+            return ::std::u64::MAX;
+        }
+        self.last_cycle + self.counter as u64 + 1
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Tone {
+    period: u16,
+    remaining: u32,
+    high: bool,
+    /// The volume, or 16 and up to take it from the envelope.
+    volume: u8,
+}
+
+impl Tone {
+    fn new() -> Tone {
+        Tone {
+            period: 0,
+            remaining: TONE_CLOCK,
+            high: false,
+            volume: 0,
+        }
+    }
+
+    fn reload(&mut self) {
+        self.remaining = cmp::max(self.period as u32, 1) * TONE_CLOCK;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Envelope {
+    period: u16,
+    remaining: u32,
+    shape: u8,
+    step: u8,
+    attack: bool,
+    holding: bool,
+}
+
+impl Envelope {
+    fn new() -> Envelope {
+        Envelope {
+            period: 0,
+            remaining: ENVELOPE_CLOCK,
+            shape: 0,
+            step: 0,
+            attack: false,
+            holding: false,
+        }
+    }
+
+    fn restart(&mut self, shape: u8) {
+        self.shape = shape & 0x0F;
+        self.step = 0;
+        self.attack = shape & 0b0100 != 0;
+        self.holding = false;
+    }
+
+    fn level(&self) -> u8 {
+        if self.attack {
+            self.step
+        } else {
+            31 - self.step
+        }
+    }
+
+    /// Steps through the current ramp, then depending on the shape either
+    /// repeats it, alternates direction, or holds.
+    fn clock(&mut self) {
+        if self.holding {
+            return;
+        }
+        self.step += 1;
+        if self.step < 32 {
+            return;
+        }
+        let (continuing, alternate, hold) =
+            (self.shape & 0b1000 != 0, self.shape & 0b0010 != 0, self.shape & 0b0001 != 0);
+        if !continuing || hold {
+            self.holding = true;
+            self.step = 31;
+            if !continuing {
+                // Drop to silence
+                self.attack = false;
+            } else if alternate {
+                self.attack = !self.attack;
+            }
+        } else {
+            self.step = 0;
+            if alternate {
+                self.attack = !self.attack;
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Audio {
+    register: u8,
+    tones: [Tone; 3],
+    noise_period: u8,
+    noise_remaining: u32,
+    lfsr: u32,
+    /// Bits 0-2 disable the tones and bits 3-5 the noise, on each channel.
+    disable: u8,
+    envelope: Envelope,
+}
+
+impl Audio {
+    fn new() -> Audio {
+        Audio {
+            register: 0,
+            tones: [Tone::new(), Tone::new(), Tone::new()],
+            noise_period: 0,
+            noise_remaining: NOISE_CLOCK,
+            lfsr: 1,
+            disable: 0,
+            envelope: Envelope::new(),
+        }
+    }
+
+    fn write(&mut self, val: u8) {
+        match self.register {
+            reg @ 0x00...0x05 => {
+                let tone = &mut self.tones[reg as usize / 2];
+                tone.period = if reg % 2 == 0 {
+                    (tone.period & 0x0F00) | val as u16
+                } else {
+                    (tone.period & 0x00FF) | (val as u16 & 0x0F) << 8
+                };
+            }
+            0x06 => self.noise_period = val & 0x1F,
+            0x07 => self.disable = val,
+            reg @ 0x08...0x0A => self.tones[reg as usize - 8].volume = val & 0x1F,
+            0x0B => self.envelope.period = (self.envelope.period & 0xFF00) | val as u16,
+            0x0C => self.envelope.period = (self.envelope.period & 0x00FF) | (val as u16) << 8,
+            0x0D => self.envelope.restart(val),
+            _ => (),
+        }
+    }
+
+    /// Sets the level of each of the three channels.
+    fn set_levels(&self, out: &mut [Waveform], cycle: u32) {
+        let noise = self.lfsr & 1 != 0;
+        for (idx, (tone, out)) in self.tones.iter().zip(out.iter_mut()).enumerate() {
+            let tone_on = tone.high || self.disable & (1 << idx) != 0;
+            let noise_on = noise || self.disable & (8 << idx) != 0;
+            let level = if !tone_on || !noise_on {
+                0
+            } else if tone.volume & 0x10 != 0 {
+                self.envelope.level()
+            } else if tone.volume == 0 {
+                0
+            } else {
+                tone.volume * 2 + 1
+            };
+            out.set_level(amplitude(level) * LEVEL_SCALE, cycle);
+        }
+    }
+
+    fn play(&mut self, from_cyc: u32, to_cyc: u32, out: &mut [Waveform]) {
+        self.set_levels(out, from_cyc);
+
+        let mut cycle = from_cyc;
+        while cycle < to_cyc {
+            let mut step = cmp::min(to_cyc - cycle, self.noise_remaining);
+            step = cmp::min(step, self.envelope.remaining);
+            for tone in &self.tones {
+                step = cmp::min(step, tone.remaining);
+            }
+            cycle += step;
+
+            for tone in &mut self.tones {
+                tone.remaining -= step;
+                if tone.remaining == 0 {
+                    tone.reload();
+                    tone.high = !tone.high;
+                }
+            }
+            self.noise_remaining -= step;
+            if self.noise_remaining == 0 {
+                self.noise_remaining = cmp::max(self.noise_period as u32, 1) * NOISE_CLOCK;
+                let bit = (self.lfsr ^ (self.lfsr >> 3)) & 1;
+                self.lfsr = (self.lfsr >> 1) | (bit << 16);
+            }
+            self.envelope.remaining -= step;
+            if self.envelope.remaining == 0 {
+                let period = cmp::max(self.envelope.period as u32, 1);
+                self.envelope.remaining = period * ENVELOPE_CLOCK;
+                self.envelope.clock();
+            }
+            self.set_levels(out, cycle);
+        }
+    }
+}
+
+struct Fme7 {
+    regs: Regs,
+    irq: Irq,
+    audio: Audio,
+
+    prg_rom: MappingTable,
+    /// A copy of the PRG ROM for reads at $6000, which the mapping table
+    /// doesn't cover.
+    prg_rom_data: Box<[u8]>,
+    chr_rom: Box<[u8]>,
+    chr_ram: Box<[u8]>,
+    prg_ram: Box<RamSegment>,
+
+    mirroring: &'static [u16; 4],
+}
+
+struct Fme7State {
+    regs: Regs,
+    irq: Irq,
+    audio: Audio,
+    chr_ram: Box<[u8]>,
+    prg_ram: Vec<u8>,
+}
+
+pub fn new(params: MapperParams) -> Box<Mapper> {
+    let chr_ram = if params.chr_rom.is_empty() {
+        vec![0u8; 0x2000].into_boxed_slice()
+    } else {
+        vec![0u8; 0].into_boxed_slice()
+    };
+
+    let prg_ram: Box<RamSegment> = if params.has_battery_backed_ram {
+        Box::new(
+            BatteryBackedRam::new(params.rom_path, params.prg_ram_size as u32).unwrap(),
+        )
+    } else {
+        Box::new(VolatileRam::new(params.prg_ram_size as usize))
+    };
+
+    let mut mapper = Fme7 {
+        regs: Regs {
+            command: 0,
+            chr: [0; 8],
+            prg_6000: 0,
+            prg: [0; 3],
+            mirroring: 0,
+        },
+        irq: Irq {
+            counter: 0,
+            irq_enabled: false,
+            counter_enabled: false,
+            pending: false,
+            last_cycle: 0,
+        },
+        audio: Audio::new(),
+
+        prg_rom_data: params.prg_rom.clone().into_boxed_slice(),
+        prg_rom: MappingTable::new(params.prg_rom, 2),
+        chr_rom: params.chr_rom.into_boxed_slice(),
+        chr_ram: chr_ram,
+        prg_ram: prg_ram,
+
+        mirroring: super::standard_mapping_tables(ScreenMode::Vertical),
+    };
+    mapper.update_mapping();
+
+    Box::new(mapper)
+}
+
+impl Fme7 {
+    fn update_mapping(&mut self) {
+        let bank_count = self.prg_rom.bank_count();
+        for (window, &bank) in self.regs.prg.iter().enumerate() {
+            let bank = (bank as usize & 0x3F) * 2 % bank_count;
+            self.prg_rom
+                .map_pages_linear(window * 2..window * 2 + 2, bank);
+        }
+        self.prg_rom.map_pages_linear(6..8, bank_count - 2);
+
+        let mode = match self.regs.mirroring & 0x03 {
+            0 => ScreenMode::Vertical,
+            1 => ScreenMode::Horizontal,
+            2 => ScreenMode::OneScreenLow,
+            _ => ScreenMode::OneScreenHigh,
+        };
+        self.mirroring = super::standard_mapping_tables(mode);
+    }
+
+    fn write_parameter(&mut self, val: u8) {
+        match self.regs.command {
+            cmd @ 0x0...0x7 => self.regs.chr[cmd as usize] = val,
+            0x8 => self.regs.prg_6000 = val,
+            cmd @ 0x9...0xB => self.regs.prg[cmd as usize - 9] = val,
+            0xC => self.regs.mirroring = val,
+            0xD => {
+                // Any write to the control register acknowledges the IRQ
+                self.irq.pending = false;
+                self.irq.irq_enabled = val & 0b0000_0001 != 0;
+                self.irq.counter_enabled = val & 0b1000_0000 != 0;
+            }
+            0xE => self.irq.counter = (self.irq.counter & 0xFF00) | val as u16,
+            _ => self.irq.counter = (self.irq.counter & 0x00FF) | (val as u16) << 8,
+        }
+        self.update_mapping();
+    }
+}
+
+impl Mapper for Fme7 {
+    fn prg_rom_read(&mut self, idx: u16) -> &RomBank {
+        self.prg_rom.get_bank(idx)
+    }
+
+    fn prg_rom_write(&mut self, idx: u16, val: u8) -> &mut RomBank {
+        match idx {
+            0x8000...0x9FFF => self.regs.command = val & 0x0F,
+            0xA000...0xBFFF => self.write_parameter(val),
+            0xC000...0xDFFF => self.audio.register = val & 0x0F,
+            0xE000...0xFFFF => self.audio.write(val),
+            _ => (),
+        }
+        match idx {
+            0x8000...0xFFFF => self.prg_rom.get_bank_mut(idx),
+            // There's no ROM below $8000; this bank just swallows the write.
+            _ => self.prg_rom.get_bank_mut(0xE000),
+        }
+    }
+
+    fn prg_rom_address(&self, idx: u16) -> RomAddress {
+        self.prg_rom.get_rom_address(idx)
+    }
+
+    fn prg_ram_read(&mut self, idx: u16) -> u8 {
+        if !self.regs.ram_selected() {
+            let bank = (self.regs.prg_6000 & 0x3F) as usize;
+            let addr = bank * 0x2000 + (idx - 0x6000) as usize;
+            self.prg_rom_data[addr % self.prg_rom_data.len()]
+        } else if self.regs.ram_enabled() {
+            self.prg_ram.read(idx - 0x6000)
+        } else {
+            0
+        }
+    }
+
+    fn prg_ram_write(&mut self, idx: u16, val: u8) {
+        if self.regs.ram_selected() && self.regs.ram_enabled() {
+            self.prg_ram.write(idx - 0x6000, val);
+        }
+    }
+
+    fn chr_read(&mut self, idx: u16) -> u8 {
+        if self.chr_rom.is_empty() {
+            self.chr_ram[idx as usize % 0x2000]
+        } else {
+            let bank = self.regs.chr[(idx >> 10) as usize & 0x07] as usize;
+            let addr = bank * 0x400 + (idx & 0x3FF) as usize;
+            self.chr_rom[addr % self.chr_rom.len()]
+        }
+    }
+
+    fn chr_write(&mut self, idx: u16, val: u8) {
+        if self.chr_rom.is_empty() {
+            self.chr_ram[idx as usize % 0x2000] = val;
+        }
+    }
+
+    fn get_mirroring_table(&self) -> &[u16; 4] {
+        self.mirroring
+    }
+
+    fn run_to(&mut self, cycle: u64) -> bool {
+        self.irq.run_to(cycle)
+    }
+
+    fn requested_run_cycle(&self) -> u64 {
+        self.irq.next_irq_cycle()
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq.pending
+    }
+
+    fn audio_channels(&self) -> usize {
+        3
+    }
+
+    fn play_audio(&mut self, from_cyc: u32, to_cyc: u32, out: &mut [Waveform]) {
+        self.audio.play(from_cyc, to_cyc, out);
+    }
+
+    fn save_state(&self) -> Box<Any> {
+        Box::new(Fme7State {
+            regs: self.regs.clone(),
+            irq: self.irq.clone(),
+            audio: self.audio.clone(),
+            chr_ram: self.chr_ram.clone(),
+            prg_ram: self.prg_ram.contents().to_vec(),
+        })
+    }
+
+    fn load_state(&mut self, state: &Any) {
+        let state = state.downcast_ref::<Fme7State>().unwrap();
+        self.regs = state.regs.clone();
+        self.irq = state.irq.clone();
+        self.audio = state.audio.clone();
+        self.chr_ram.copy_from_slice(&state.chr_ram);
+        self.prg_ram.restore(&state.prg_ram);
+        self.update_mapping();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mappers::{Mapper, MapperParams};
+
+    /// PRG ROM where each 8KB bank is filled with its own number.
+    fn create_test_mapper() -> Box<Mapper> {
+        let path_buf = ::std::path::PathBuf::new();
+        let path = path_buf.as_path();
+        let prg_rom = (0..16).flat_map(|bank| vec![bank as u8; 0x2000]).collect();
+        new(MapperParams::simple(path, prg_rom, vec![0u8; 0x2000]))
+    }
+
+    fn command(mapper: &mut Box<Mapper>, command: u8, param: u8) {
+        mapper.prg_rom_write(0x8000, command);
+        mapper.prg_rom_write(0xA000, param);
+    }
+
+    #[test]
+    fn switches_prg_banks() {
+        let mut mapper = create_test_mapper();
+        command(&mut mapper, 0x9, 3);
+        command(&mut mapper, 0xB, 7);
+        assert_eq!(mapper.prg_rom_read(0x8000).read(0x8000), 3);
+        assert_eq!(mapper.prg_rom_read(0xC000).read(0xC000), 7);
+        assert_eq!(mapper.prg_rom_read(0xE000).read(0xE000), 15);
+    }
+
+    #[test]
+    fn bank_at_6000_can_be_rom_or_ram() {
+        let mut mapper = create_test_mapper();
+        command(&mut mapper, 0x8, 5);
+        assert_eq!(mapper.prg_ram_read(0x6000), 5);
+        mapper.prg_ram_write(0x6000, 0x42);
+        assert_eq!(mapper.prg_ram_read(0x6000), 5);
+
+        command(&mut mapper, 0x8, 0b1100_0000);
+        mapper.prg_ram_write(0x6000, 0x42);
+        assert_eq!(mapper.prg_ram_read(0x6000), 0x42);
+    }
+
+    #[test]
+    fn irq_fires_when_the_counter_wraps() {
+        let mut mapper = create_test_mapper();
+        command(&mut mapper, 0xE, 0x10);
+        command(&mut mapper, 0xF, 0x00);
+        command(&mut mapper, 0xD, 0b1000_0001);
+        assert_eq!(mapper.requested_run_cycle(), 0x11);
+        assert!(!mapper.run_to(0x10));
+        assert!(mapper.run_to(0x11));
+        assert_eq!(mapper.requested_run_cycle(), 0x11 + 0x10000);
+        // The line is held until the control register is written
+        assert!(mapper.irq_pending());
+        assert!(!mapper.run_to(0x20));
+        assert!(mapper.irq_pending());
+        command(&mut mapper, 0xD, 0b1000_0001);
+        assert!(!mapper.irq_pending());
+    }
+
+    #[test]
+    fn envelope_holds_or_repeats_depending_on_shape() {
+        let mut envelope = Envelope::new();
+        // Attack, then hold at the top
+        envelope.restart(0b1101);
+        for _ in 0..40 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.level(), 31);
+
+        // A single decay, then silence
+        envelope.restart(0b0000);
+        assert_eq!(envelope.level(), 31);
+        for _ in 0..40 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.level(), 0);
+
+        // Sawtooth
+        envelope.restart(0b1100);
+        for _ in 0..33 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.level(), 1);
+    }
+}
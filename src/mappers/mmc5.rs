@@ -0,0 +1,1052 @@
+//! Nintendo's MMC5, used by Castlevania III, the Koei strategy games and a
+//! few others. Along with flexible PRG and CHR banking, it has 1KB of
+//! expansion RAM (ExRAM), an 8x8 bit multiplier, a scanline IRQ and three
+//! extra sound channels: two pulses and an 8-bit PCM channel.
+//!
+//! The chip watches the PPU's fetches. That's how it counts scanlines, uses
+//! a separate set of CHR banks for the background when sprites are 8x16,
+//! and feeds the PPU from ExRAM: per-tile palettes and CHR banks (extended
+//! attributes), a vertically-scrolling split screen, or an extra nametable.
+//! A fill-mode nametable of a single tile is also available.
+//!
+//! In read mode, the PCM channel plays the bytes the CPU reads from
+//! $8000-$BFFF, though instruction fetches by compiled code aren't seen. The
+//! CPU compiles code above $8000 as though it were ROM, so changes to code
+//! run from PRG RAM mapped there aren't noticed either. Code run from ExRAM
+//! is always interpreted.
+
+use super::{Mapper, MapperParams, PpuFetch, RamSegment, RomAddress};
+use super::bank::*;
+use super::battery::BatteryBackedRam;
+use super::volatile::VolatileRam;
+use apu::{Envelope, Length, PULSE_LEVEL, Waveform};
+use memory::MemSegment;
+use std::any::Any;
+use std::cmp;
+use std::mem;
+
+/// The pulses are as loud as the APU's.
+const PULSE_SCALE: f32 = PULSE_LEVEL / 15.0;
+
+/// Scales the 8-bit PCM channel like the DMC's 7 bits.
+const PCM_SCALE: f32 = 0.00335 / 2.0;
+
+/// The pulses' envelopes and length counters are clocked by the chip's own
+/// divider at about 240Hz, rather than by the APU's frame sequencer.
+const QUARTER_FRAME_CYCLES: u32 = 7457;
+
+/// The pulses' waveforms, one bit per step.
+static DUTY_CYCLES: [u8; 4] = [0b0100_0000, 0b0110_0000, 0b0111_1000, 0b1001_1111];
+
+/// What each nametable can be mapped to by $5105.
+const NAMETABLE_CIRAM_1: u8 = 1;
+const NAMETABLE_EXRAM: u8 = 2;
+
+#[derive(Debug, Clone, PartialEq)]
+struct Regs {
+    prg_mode: u8,
+    chr_mode: u8,
+    /// $5102 and $5103, which must be %10 and %01 to write to PRG RAM.
+    ram_protect: [u8; 2],
+    exram_mode: u8,
+    /// Two bits per nametable, selecting console RAM page 0 or 1, ExRAM or
+    /// the fill tile.
+    nametables: u8,
+    fill_tile: u8,
+    fill_attr: u8,
+    /// $5113-$5117. Bit 7 selects ROM for the windows above $8000.
+    prg: [u8; 5],
+    /// $5120-$5127, with the upper bits from $5130 when they were written.
+    chr_sprites: [u16; 8],
+    /// $5128-$512B, used for the background when sprites are 8x16.
+    chr_background: [u16; 4],
+    /// Whether the background banks were written after the sprite banks.
+    background_last: bool,
+    chr_upper: u8,
+    split_control: u8,
+    split_scroll: u8,
+    split_bank: u8,
+    multiplicand: u8,
+    multiplier: u8,
+}
+
+impl Regs {
+    fn new() -> Regs {
+        Regs {
+            prg_mode: 3,
+            chr_mode: 0,
+            ram_protect: [0; 2],
+            exram_mode: 0,
+            nametables: 0,
+            fill_tile: 0,
+            fill_attr: 0,
+            prg: [0, 0xFF, 0xFF, 0xFF, 0xFF],
+            chr_sprites: [0; 8],
+            chr_background: [0; 4],
+            background_last: false,
+            chr_upper: 0,
+            split_control: 0,
+            split_scroll: 0,
+            split_bank: 0,
+            multiplicand: 0xFF,
+            multiplier: 0xFF,
+        }
+    }
+
+    fn prg_ram_writable(&self) -> bool {
+        self.ram_protect == [0b10, 0b01]
+    }
+
+    fn nametable_source(&self, idx: u16) -> u8 {
+        let nametable = (idx >> 10) & 0x03;
+        (self.nametables >> (nametable * 2)) & 0x03
+    }
+
+    /// The split screen only works while ExRAM is being used by the PPU.
+    fn in_split(&self, tile: u8) -> bool {
+        if self.split_control & 0x80 == 0 || self.exram_mode >= 2 {
+            return false;
+        }
+        let threshold = self.split_control & 0x1F;
+        if self.split_control & 0x40 != 0 {
+            tile >= threshold
+        } else {
+            tile < threshold
+        }
+    }
+
+    fn product(&self) -> u16 {
+        self.multiplicand as u16 * self.multiplier as u16
+    }
+}
+
+/// The scanline IRQ, which compares the scanline the chip has counted with
+/// the value written to $5203 at the start of each line.
+#[derive(Debug, Clone, PartialEq)]
+struct Irq {
+    target: u8,
+    enabled: bool,
+    pending: bool,
+    /// Set when the IRQ line goes active, until the CPU is told.
+    raised: bool,
+}
+
+impl Irq {
+    fn write_enable(&mut self, val: u8) {
+        self.enabled = val & 0x80 != 0;
+        if self.enabled && self.pending {
+            self.raised = true;
+        }
+    }
+
+    fn start_scanline(&mut self, scanline: u8) {
+        if scanline == self.target {
+            self.pending = true;
+            if self.enabled {
+                self.raised = true;
+            }
+        }
+    }
+}
+
+/// What the chip has worked out about the PPU from watching its fetches.
+#[derive(Debug, Clone, PartialEq)]
+struct PpuWatch {
+    fetch: PpuFetch,
+    tall_sprites: bool,
+    in_frame: bool,
+    scanline: u8,
+    /// The column of the next background tile to be fetched. The first two
+    /// tiles of each line are fetched at the end of the one before.
+    tile: u8,
+    /// The line of the split screen being drawn.
+    split_y: u8,
+    /// Whether the last tile fetched was in the split screen.
+    in_split: bool,
+    /// The ExRAM byte for the last tile fetched, which holds its palette and
+    /// CHR bank in extended attribute mode.
+    ex_attr: u8,
+}
+
+impl PpuWatch {
+    fn new() -> PpuWatch {
+        PpuWatch {
+            fetch: PpuFetch::Idle,
+            tall_sprites: false,
+            in_frame: false,
+            scanline: 0,
+            tile: 0,
+            split_y: 0,
+            in_split: false,
+            ex_attr: 0,
+        }
+    }
+}
+
+/// One of the two pulse channels. These are the same as the APU's, except
+/// that they have no sweep unit.
+#[derive(Debug, Clone)]
+struct Pulse {
+    envelope: Envelope,
+    length: Length,
+    duty: u8,
+    period: u16,
+    remaining: u32,
+    step: u8,
+}
+
+impl Pulse {
+    fn new() -> Pulse {
+        Pulse {
+            envelope: Envelope::new(),
+            length: Length::new(5),
+            duty: 0,
+            period: 0,
+            remaining: 2,
+            step: 0,
+        }
+    }
+
+    fn write(&mut self, reg: u16, val: u8) {
+        match reg {
+            0 => {
+                self.duty = val >> 6;
+                self.envelope.write(val);
+                self.length.write_halt(val);
+            }
+            2 => self.period = (self.period & 0x0700) | val as u16,
+            3 => {
+                self.period = (self.period & 0x00FF) | ((val as u16 & 0x07) << 8);
+                self.length.write_counter(val, false);
+                self.envelope.restart();
+                self.step = 0;
+            }
+            _ => (),
+        }
+    }
+
+    fn remaining(&self) -> Option<u32> {
+        if self.length.audible() {
+            Some(self.remaining)
+        } else {
+            None
+        }
+    }
+
+    /// Runs the timer, which is clocked every other cycle, for the given
+    /// number of cycles. These mustn't be more than are remaining.
+    fn run(&mut self, cycles: u32) {
+        if !self.length.audible() {
+            return;
+        }
+        self.remaining -= cycles;
+        if self.remaining == 0 {
+            self.remaining = (self.period as u32 + 1) * 2;
+            self.step = (self.step + 1) % 8;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.length.audible() && DUTY_CYCLES[self.duty as usize] & (0x80 >> self.step) != 0 {
+            self.envelope.volume() as u8
+        } else {
+            0
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Audio {
+    pulse1: Pulse,
+    pulse2: Pulse,
+    pcm: u8,
+    pcm_read_mode: bool,
+    pcm_irq_enabled: bool,
+    /// Set when a zero is read in read mode, until $5010 is read.
+    pcm_irq_flag: bool,
+    /// Cycles until the envelopes and length counters are next clocked.
+    quarter_frame_remaining: u32,
+}
+
+impl Audio {
+    fn new() -> Audio {
+        Audio {
+            pulse1: Pulse::new(),
+            pulse2: Pulse::new(),
+            pcm: 0,
+            pcm_read_mode: false,
+            pcm_irq_enabled: false,
+            pcm_irq_flag: false,
+            quarter_frame_remaining: QUARTER_FRAME_CYCLES,
+        }
+    }
+
+    fn write(&mut self, idx: u16, val: u8) {
+        match idx {
+            0x5000...0x5003 => self.pulse1.write(idx & 0x03, val),
+            0x5004...0x5007 => self.pulse2.write(idx & 0x03, val),
+            0x5010 => {
+                self.pcm_read_mode = val & 0x01 != 0;
+                self.pcm_irq_enabled = val & 0x80 != 0;
+            }
+            // Zero can't be written, since in read mode it raises the IRQ.
+            0x5011 => {
+                if !self.pcm_read_mode && val != 0 {
+                    self.pcm = val;
+                }
+            }
+            0x5015 => {
+                self.pulse1.length.set_enable(val & 0x01 != 0);
+                self.pulse2.length.set_enable(val & 0x02 != 0);
+            }
+            _ => (),
+        }
+    }
+
+    fn status(&self) -> u8 {
+        self.pulse1.length.active() | self.pulse2.length.active() << 1
+    }
+
+    /// Latches a byte the CPU read from $8000-$BFFF in read mode. A zero
+    /// leaves the output alone and raises the IRQ instead; returns true if
+    /// that makes the IRQ line go active.
+    fn read_sample(&mut self, val: u8) -> bool {
+        if val != 0 {
+            self.pcm = val;
+            return false;
+        }
+        let was_active = self.pcm_irq();
+        self.pcm_irq_flag = true;
+        self.pcm_irq() && !was_active
+    }
+
+    fn pcm_irq(&self) -> bool {
+        self.pcm_irq_enabled && self.pcm_irq_flag
+    }
+
+    /// Reads $5010, which acknowledges the IRQ.
+    fn read_pcm_status(&mut self) -> u8 {
+        let status = (self.pcm_irq() as u8) << 7;
+        self.pcm_irq_flag = false;
+        status
+    }
+
+    fn quarter_frame(&mut self) {
+        for pulse in &mut [&mut self.pulse1, &mut self.pulse2] {
+            pulse.envelope.tick();
+            pulse.length.tick();
+        }
+    }
+
+    /// Sets the levels of the two pulses and the PCM channel, in that order.
+    fn set_levels(&self, out: &mut [Waveform], cycle: u32) {
+        out[0].set_level(self.pulse1.output() as f32 * PULSE_SCALE, cycle);
+        out[1].set_level(self.pulse2.output() as f32 * PULSE_SCALE, cycle);
+        out[2].set_level(self.pcm as f32 * PCM_SCALE, cycle);
+    }
+
+    fn play(&mut self, from_cyc: u32, to_cyc: u32, out: &mut [Waveform]) {
+        self.set_levels(out, from_cyc);
+
+        let mut cycle = from_cyc;
+        while cycle < to_cyc {
+            let mut step = cmp::min(to_cyc - cycle, self.quarter_frame_remaining);
+            for remaining in &[self.pulse1.remaining(), self.pulse2.remaining()] {
+                if let Some(remaining) = *remaining {
+                    step = cmp::min(step, remaining);
+                }
+            }
+            cycle += step;
+
+            self.pulse1.run(step);
+            self.pulse2.run(step);
+            self.quarter_frame_remaining -= step;
+            if self.quarter_frame_remaining == 0 {
+                self.quarter_frame_remaining = QUARTER_FRAME_CYCLES;
+                self.quarter_frame();
+            }
+            self.set_levels(out, cycle);
+        }
+    }
+}
+
+struct Mmc5 {
+    regs: Regs,
+    irq: Irq,
+    ppu: PpuWatch,
+    audio: Audio,
+
+    prg_rom: MappingTable,
+    chr_rom: Box<[u8]>,
+    chr_ram: Box<[u8]>,
+    prg_ram: Box<RamSegment>,
+    exram: Box<[u8]>,
+
+    /// The PRG RAM bank in each 8KB window from $8000, for the windows which
+    /// have RAM rather than ROM.
+    prg_ram_windows: [Option<u8>; 4],
+    mirroring: [u16; 4],
+}
+
+struct Mmc5State {
+    regs: Regs,
+    irq: Irq,
+    ppu: PpuWatch,
+    audio: Audio,
+    chr_ram: Box<[u8]>,
+    prg_ram: Vec<u8>,
+    exram: Box<[u8]>,
+}
+
+pub fn new(params: MapperParams) -> Box<Mapper> {
+    let chr_ram = if params.chr_rom.is_empty() {
+        vec![0u8; 0x2000].into_boxed_slice()
+    } else {
+        vec![0u8; 0].into_boxed_slice()
+    };
+
+    let prg_ram: Box<RamSegment> = if params.has_battery_backed_ram {
+        Box::new(
+            BatteryBackedRam::new(params.rom_path, params.prg_ram_size as u32).unwrap(),
+        )
+    } else {
+        Box::new(VolatileRam::new(params.prg_ram_size as usize))
+    };
+
+    let mut mapper = Mmc5 {
+        regs: Regs::new(),
+        irq: Irq {
+            target: 0,
+            enabled: false,
+            pending: false,
+            raised: false,
+        },
+        ppu: PpuWatch::new(),
+        audio: Audio::new(),
+
+        prg_rom: MappingTable::new(params.prg_rom, 2),
+        chr_rom: params.chr_rom.into_boxed_slice(),
+        chr_ram: chr_ram,
+        prg_ram: prg_ram,
+        exram: vec![0u8; 0x400].into_boxed_slice(),
+
+        prg_ram_windows: [None; 4],
+        mirroring: [0x2000; 4],
+    };
+    mapper.update_prg();
+    mapper.update_nametables();
+
+    Box::new(mapper)
+}
+
+/// The address in PRG RAM of an address in an 8KB window.
+fn prg_ram_addr(bank: u8, idx: u16) -> u16 {
+    ((bank as u16 & 0x07) << 13) | (idx & 0x1FFF)
+}
+
+fn prg_window(idx: u16) -> usize {
+    ((idx - 0x8000) >> 13) as usize
+}
+
+impl Mmc5 {
+    fn update_prg(&mut self) {
+        let prg = self.regs.prg;
+        // The last window always has ROM.
+        let last = prg[4] | 0x80;
+        // The 8KB bank in each window, with bit 7 set for ROM.
+        let windows = match self.regs.prg_mode {
+            0 => {
+                let bank = last & !0x03;
+                [bank, bank | 1, bank | 2, bank | 3]
+            }
+            1 => {
+                let (low, high) = (prg[2] & !0x01, last & !0x01);
+                [low, low | 1, high, high | 1]
+            }
+            2 => {
+                let low = prg[2] & !0x01;
+                [low, low | 1, prg[3], last]
+            }
+            _ => [prg[1], prg[2], prg[3], last],
+        };
+
+        let bank_count = self.prg_rom.bank_count();
+        for (window, &bank) in windows.iter().enumerate() {
+            if bank & 0x80 != 0 {
+                let first_page = (bank as usize & 0x7F) * 2 % bank_count;
+                self.prg_rom
+                    .map_pages_linear(window * 2..window * 2 + 2, first_page);
+                self.prg_ram_windows[window] = None;
+            } else {
+                self.prg_ram_windows[window] = Some(bank);
+            }
+        }
+    }
+
+    /// Nametables which aren't in the console's RAM are handled by
+    /// `nametable_read`, so their entries here don't matter.
+    fn update_nametables(&mut self) {
+        for nametable in 0..4 {
+            let source = self.regs.nametable_source(nametable << 10);
+            self.mirroring[nametable as usize] = if source == NAMETABLE_CIRAM_1 {
+                0x2400
+            } else {
+                0x2000
+            };
+        }
+    }
+
+    fn write_exram(&mut self, idx: usize, val: u8) {
+        match self.regs.exram_mode {
+            // While the PPU is using ExRAM, the CPU can only write to it
+            // during rendering, and writes zero otherwise.
+            0 | 1 => self.exram[idx] = if self.ppu.in_frame { val } else { 0 },
+            2 => self.exram[idx] = val,
+            _ => (),
+        }
+    }
+
+    /// Background fetches carrying straight on into a new line, with no
+    /// sprite fetches between, are how the chip spots the start of a
+    /// scanline. On the real thing, that's three reads of the same nametable
+    /// address in a row.
+    fn start_scanline(&mut self) {
+        if self.ppu.in_frame {
+            self.ppu.scanline = self.ppu.scanline.wrapping_add(1);
+            self.irq.start_scanline(self.ppu.scanline);
+        } else {
+            self.ppu.in_frame = true;
+            self.ppu.scanline = 0;
+        }
+        self.ppu.tile = 2;
+        self.ppu.split_y = self.split_y(self.ppu.scanline as u16);
+    }
+
+    fn split_y(&self, scanline: u16) -> u8 {
+        ((self.regs.split_scroll as u16 + scanline) % 240) as u8
+    }
+
+    /// Keeps track of a background tile fetch. Returns the split screen's
+    /// tile if the fetch is in the split.
+    fn fetch_tile(&mut self, offset: usize) -> Option<u8> {
+        let tile = self.ppu.tile;
+        self.ppu.tile = tile.wrapping_add(1);
+        self.ppu.in_split = self.regs.in_split(tile);
+        if self.ppu.in_split {
+            let row = self.ppu.split_y as usize / 8;
+            return Some(self.exram[row * 32 + (tile as usize & 0x1F)]);
+        }
+        self.ppu.ex_attr = self.exram[offset];
+        None
+    }
+
+    /// Returns the attribute for the last tile fetched, if it comes from
+    /// ExRAM. The tile's palette is copied to all four quadrants, since the
+    /// PPU picks the quadrant from its own scroll position.
+    fn fetch_attribute(&self) -> Option<u8> {
+        if self.ppu.in_split {
+            let row = self.ppu.split_y as usize / 8;
+            let column = self.ppu.tile.wrapping_sub(1) as usize & 0x1F;
+            let attr = self.exram[0x3C0 + row / 4 * 8 + column / 4];
+            let shift = (row & 0x02) * 2 + (column & 0x02);
+            return Some(((attr >> shift) & 0x03) * 0x55);
+        }
+        if self.regs.exram_mode == 1 {
+            return Some((self.ppu.ex_attr >> 6) * 0x55);
+        }
+        None
+    }
+
+    /// Whether CHR fetches use the background banks ($5128-$512B). With 8x8
+    /// sprites, and for CPU accesses, the last set written is used.
+    fn background_chr(&self) -> bool {
+        match (self.ppu.tall_sprites, self.ppu.fetch) {
+            (true, PpuFetch::Background) => true,
+            (true, PpuFetch::Sprites) => false,
+            _ => self.regs.background_last,
+        }
+    }
+
+    fn chr_addr(&self, idx: u16) -> usize {
+        if self.ppu.fetch == PpuFetch::Background {
+            if self.ppu.in_split {
+                // The split screen has its own 4KB bank and vertical scroll.
+                let bank = self.regs.split_bank as usize;
+                let fine_y = self.ppu.split_y as usize & 0x07;
+                return bank * 0x1000 + (idx as usize & 0x0FF8) + fine_y;
+            }
+            if self.regs.exram_mode == 1 {
+                let bank = (self.ppu.ex_attr as usize & 0x3F) |
+                    (self.regs.chr_upper as usize) << 6;
+                return bank * 0x1000 + (idx as usize & 0x0FFF);
+            }
+        }
+
+        let window = (idx >> 10) as usize & 0x07;
+        let background = self.background_chr();
+        let regs = &self.regs;
+        // The background set only has four registers, which are repeated for
+        // the upper pattern table.
+        let reg = |i: usize| -> usize {
+            if background {
+                regs.chr_background[i & 0x03] as usize
+            } else {
+                regs.chr_sprites[i] as usize
+            }
+        };
+        let (bank, size) = match regs.chr_mode {
+            0 => (reg(7), 0x2000),
+            1 => (reg(3 | (window & 4)), 0x1000),
+            2 => (reg(window | 1), 0x0800),
+            _ => (reg(window), 0x0400),
+        };
+        bank * size + (idx as usize & (size - 1))
+    }
+}
+
+impl Mapper for Mmc5 {
+    fn prg_rom_read(&mut self, idx: u16) -> &RomBank {
+        self.prg_rom.get_bank(idx)
+    }
+
+    fn prg_rom_write(&mut self, idx: u16, val: u8) -> &mut RomBank {
+        match idx {
+            0x5000...0x5015 => self.audio.write(idx, val),
+            0x5100 => {
+                self.regs.prg_mode = val & 0x03;
+                self.update_prg();
+            }
+            0x5101 => self.regs.chr_mode = val & 0x03,
+            0x5102 | 0x5103 => self.regs.ram_protect[(idx - 0x5102) as usize] = val & 0x03,
+            0x5104 => self.regs.exram_mode = val & 0x03,
+            0x5105 => {
+                self.regs.nametables = val;
+                self.update_nametables();
+            }
+            0x5106 => self.regs.fill_tile = val,
+            0x5107 => self.regs.fill_attr = val & 0x03,
+            0x5113...0x5117 => {
+                self.regs.prg[(idx - 0x5113) as usize] = val;
+                self.update_prg();
+            }
+            0x5120...0x5127 => {
+                let bank = val as u16 | (self.regs.chr_upper as u16) << 8;
+                self.regs.chr_sprites[(idx - 0x5120) as usize] = bank;
+                self.regs.background_last = false;
+            }
+            0x5128...0x512B => {
+                let bank = val as u16 | (self.regs.chr_upper as u16) << 8;
+                self.regs.chr_background[(idx - 0x5128) as usize] = bank;
+                self.regs.background_last = true;
+            }
+            0x5130 => self.regs.chr_upper = val & 0x03,
+            0x5200 => self.regs.split_control = val,
+            0x5201 => self.regs.split_scroll = val,
+            0x5202 => self.regs.split_bank = val,
+            0x5203 => self.irq.target = val,
+            0x5204 => self.irq.write_enable(val),
+            0x5205 => self.regs.multiplicand = val,
+            0x5206 => self.regs.multiplier = val,
+            0x5C00...0x5FFF => self.write_exram((idx - 0x5C00) as usize, val),
+            0x8000...0xDFFF => {
+                if let Some(bank) = self.prg_ram_windows[prg_window(idx)] {
+                    if self.regs.prg_ram_writable() {
+                        self.prg_ram.write(prg_ram_addr(bank, idx), val);
+                    }
+                }
+            }
+            _ => (),
+        }
+        match idx {
+            0x8000...0xFFFF => self.prg_rom.get_bank_mut(idx),
+            // There's no ROM below $8000; this bank just swallows the write.
+            _ => self.prg_rom.get_bank_mut(0xE000),
+        }
+    }
+
+    fn prg_read(&mut self, idx: u16) -> u8 {
+        let val = match self.prg_ram_windows[prg_window(idx)] {
+            Some(bank) => self.prg_ram.read(prg_ram_addr(bank, idx)),
+            None => self.prg_rom.get_bank(idx).read(idx),
+        };
+        if self.audio.pcm_read_mode && idx < 0xC000 && self.audio.read_sample(val) {
+            self.irq.raised = true;
+        }
+        val
+    }
+
+    fn register_read(&mut self, idx: u16) -> u8 {
+        match idx {
+            0x5010 => self.audio.read_pcm_status(),
+            0x5015 => self.audio.status(),
+            0x5204 => {
+                let status = (self.irq.pending as u8) << 7 | (self.ppu.in_frame as u8) << 6;
+                self.irq.pending = false;
+                status
+            }
+            0x5205 => self.regs.product() as u8,
+            0x5206 => (self.regs.product() >> 8) as u8,
+            0x5C00...0x5FFF if self.regs.exram_mode >= 2 => self.exram[(idx - 0x5C00) as usize],
+            // Open bus
+            _ => (idx >> 8) as u8,
+        }
+    }
+
+    fn register_watches_ppu(&self, idx: u16) -> bool {
+        // The scanline IRQ and in-frame flags come from the PPU's fetches.
+        idx == 0x5204
+    }
+
+    fn prg_rom_address(&self, idx: u16) -> RomAddress {
+        match self.prg_ram_windows[prg_window(idx)] {
+            // Counting down from the top keeps these apart from the ROM.
+            Some(bank) => {
+                RomAddress {
+                    window_id: !0 - bank as usize,
+                    offset: idx & 0x1FFF,
+                }
+            }
+            None => self.prg_rom.get_rom_address(idx),
+        }
+    }
+
+    fn prg_ram_read(&mut self, idx: u16) -> u8 {
+        let bank = self.regs.prg[0];
+        self.prg_ram.read(prg_ram_addr(bank, idx))
+    }
+
+    fn prg_ram_write(&mut self, idx: u16, val: u8) {
+        if self.regs.prg_ram_writable() {
+            let bank = self.regs.prg[0];
+            self.prg_ram.write(prg_ram_addr(bank, idx), val);
+        }
+    }
+
+    fn chr_read(&mut self, idx: u16) -> u8 {
+        let addr = self.chr_addr(idx);
+        if self.chr_rom.is_empty() {
+            self.chr_ram[addr % self.chr_ram.len()]
+        } else {
+            self.chr_rom[addr % self.chr_rom.len()]
+        }
+    }
+
+    fn chr_write(&mut self, idx: u16, val: u8) {
+        if self.chr_rom.is_empty() {
+            let addr = self.chr_addr(idx) % self.chr_ram.len();
+            self.chr_ram[addr] = val;
+        }
+    }
+
+    fn get_mirroring_table(&self) -> &[u16; 4] {
+        &self.mirroring
+    }
+
+    fn nametable_read(&mut self, idx: u16) -> Option<u8> {
+        let offset = idx as usize & 0x3FF;
+        if self.ppu.fetch == PpuFetch::Background {
+            let substitute = if offset < 0x3C0 {
+                self.fetch_tile(offset)
+            } else {
+                self.fetch_attribute()
+            };
+            if substitute.is_some() {
+                return substitute;
+            }
+        }
+
+        match self.regs.nametable_source(idx) {
+            0 | NAMETABLE_CIRAM_1 => None,
+            NAMETABLE_EXRAM if self.regs.exram_mode < 2 => Some(self.exram[offset]),
+            NAMETABLE_EXRAM => Some(0),
+            _ if offset < 0x3C0 => Some(self.regs.fill_tile),
+            _ => Some(self.regs.fill_attr * 0x55),
+        }
+    }
+
+    fn nametable_write(&mut self, idx: u16, val: u8) -> bool {
+        match self.regs.nametable_source(idx) {
+            0 | NAMETABLE_CIRAM_1 => false,
+            NAMETABLE_EXRAM => {
+                if self.regs.exram_mode < 2 {
+                    self.exram[idx as usize & 0x3FF] = val;
+                }
+                true
+            }
+            // The fill tile can't be written.
+            _ => true,
+        }
+    }
+
+    fn ppu_fetch(&mut self, fetch: PpuFetch, tall_sprites: bool) {
+        self.ppu.tall_sprites = tall_sprites;
+        match fetch {
+            PpuFetch::Background if self.ppu.fetch == PpuFetch::Background => {
+                self.start_scanline()
+            }
+            PpuFetch::Background => {
+                // Fetching the first two tiles of the next line
+                let next_line = if self.ppu.in_frame {
+                    self.ppu.scanline as u16 + 1
+                } else {
+                    0
+                };
+                self.ppu.tile = 0;
+                self.ppu.split_y = self.split_y(next_line);
+            }
+            PpuFetch::Sprites => (),
+            PpuFetch::Idle => self.ppu.in_frame = false,
+        }
+        self.ppu.fetch = fetch;
+    }
+
+    /// The PPU has to be run at the IRQ's scanline even while the IRQ is
+    /// disabled, as games may poll for it.
+    fn watched_scanline(&self) -> Option<i16> {
+        match self.irq.target {
+            1...239 if !self.irq.pending => Some(self.irq.target as i16),
+            _ => None,
+        }
+    }
+
+    fn run_to(&mut self, _cycle: u64) -> bool {
+        mem::replace(&mut self.irq.raised, false)
+    }
+
+    /// The IRQ is raised while the PPU runs, so it's passed on to the CPU
+    /// straight away.
+    fn requested_run_cycle(&self) -> u64 {
+        if self.irq.raised {
+            0
+        } else {
+            ::std::u64::MAX
+        }
+    }
+
+    /// The scanline IRQ is released by reading $5204 or disabling it, and
+    /// the PCM IRQ by reading $5010 or disabling it.
+    fn irq_pending(&self) -> bool {
+        self.irq.pending && self.irq.enabled || self.audio.pcm_irq()
+    }
+
+    fn audio_channels(&self) -> usize {
+        3
+    }
+
+    fn play_audio(&mut self, from_cyc: u32, to_cyc: u32, out: &mut [Waveform]) {
+        self.audio.play(from_cyc, to_cyc, out);
+    }
+
+    fn save_state(&self) -> Box<Any> {
+        Box::new(Mmc5State {
+            regs: self.regs.clone(),
+            irq: self.irq.clone(),
+            ppu: self.ppu.clone(),
+            audio: self.audio.clone(),
+            chr_ram: self.chr_ram.clone(),
+            prg_ram: self.prg_ram.contents().to_vec(),
+            exram: self.exram.clone(),
+        })
+    }
+
+    fn load_state(&mut self, state: &Any) {
+        let state = state.downcast_ref::<Mmc5State>().unwrap();
+        self.regs = state.regs.clone();
+        self.irq = state.irq.clone();
+        self.ppu = state.ppu.clone();
+        self.audio = state.audio.clone();
+        self.chr_ram.copy_from_slice(&state.chr_ram);
+        self.prg_ram.restore(&state.prg_ram);
+        self.exram.copy_from_slice(&state.exram);
+        self.update_prg();
+        self.update_nametables();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mappers::{Mapper, MapperParams, PpuFetch};
+
+    /// PRG ROM where each 8KB bank is filled with its own number, and CHR ROM
+    /// where each 4KB bank is.
+    fn create_test_mapper() -> Box<Mapper> {
+        let path_buf = ::std::path::PathBuf::new();
+        let path = path_buf.as_path();
+        let prg_rom = (0..16).flat_map(|bank| vec![bank as u8; 0x2000]).collect();
+        let chr_rom = (0..8).flat_map(|bank| vec![bank as u8; 0x1000]).collect();
+        new(MapperParams::simple(path, prg_rom, chr_rom))
+    }
+
+    /// Gives the mapper the fetches the PPU makes from dot 1 of one scanline
+    /// to dot 1 of the next.
+    fn run_scanline(mapper: &mut Box<Mapper>) {
+        mapper.ppu_fetch(PpuFetch::Sprites, false);
+        mapper.ppu_fetch(PpuFetch::Background, false);
+        mapper.ppu_fetch(PpuFetch::Background, false);
+    }
+
+    #[test]
+    fn switches_prg_banks_in_each_mode() {
+        let mut mapper = create_test_mapper();
+        let read_windows = |mapper: &mut Box<Mapper>| -> Vec<u8> {
+            [0x8000, 0xA000, 0xC000, 0xE000]
+                .iter()
+                .map(|&addr| mapper.prg_read(addr))
+                .collect()
+        };
+        // Power-on state: mode 3 with the last bank everywhere
+        assert_eq!(read_windows(&mut mapper), vec![15, 15, 15, 15]);
+
+        mapper.prg_rom_write(0x5100, 0);
+        mapper.prg_rom_write(0x5117, 0x85);
+        assert_eq!(read_windows(&mut mapper), vec![4, 5, 6, 7]);
+
+        mapper.prg_rom_write(0x5100, 1);
+        mapper.prg_rom_write(0x5115, 0x83);
+        assert_eq!(read_windows(&mut mapper), vec![2, 3, 4, 5]);
+
+        mapper.prg_rom_write(0x5100, 2);
+        mapper.prg_rom_write(0x5116, 0x89);
+        assert_eq!(read_windows(&mut mapper), vec![2, 3, 9, 5]);
+
+        mapper.prg_rom_write(0x5100, 3);
+        mapper.prg_rom_write(0x5114, 0x8C);
+        assert_eq!(read_windows(&mut mapper), vec![12, 3, 9, 5]);
+    }
+
+    #[test]
+    fn maps_prg_ram_above_8000() {
+        let mut mapper = create_test_mapper();
+        mapper.prg_rom_write(0x5114, 0x00);
+
+        // Writes are ignored until both protect registers are set.
+        mapper.prg_rom_write(0x8000, 0x42);
+        assert_eq!(mapper.prg_read(0x8000), 0);
+
+        mapper.prg_rom_write(0x5102, 0b10);
+        mapper.prg_rom_write(0x5103, 0b01);
+        mapper.prg_rom_write(0x8000, 0x42);
+        assert_eq!(mapper.prg_read(0x8000), 0x42);
+        assert_eq!(mapper.prg_ram_read(0x6000), 0x42);
+    }
+
+    #[test]
+    fn multiplies() {
+        let mut mapper = create_test_mapper();
+        mapper.prg_rom_write(0x5205, 200);
+        mapper.prg_rom_write(0x5206, 100);
+        assert_eq!(mapper.register_read(0x5205), 0x20);
+        assert_eq!(mapper.register_read(0x5206), 0x4E);
+    }
+
+    #[test]
+    fn fill_mode_and_exram_nametables() {
+        let mut mapper = create_test_mapper();
+        mapper.prg_rom_write(0x5105, 0b11_10_01_00);
+        mapper.prg_rom_write(0x5106, 0x12);
+        mapper.prg_rom_write(0x5107, 0x02);
+        assert_eq!(&mapper.get_mirroring_table()[..2], &[0x2000, 0x2400]);
+        assert_eq!(mapper.nametable_read(0x2000), None);
+        assert_eq!(mapper.nametable_read(0x2C00), Some(0x12));
+        assert_eq!(mapper.nametable_read(0x2FC0), Some(0xAA));
+
+        assert!(mapper.nametable_write(0x2805, 0x34));
+        assert_eq!(mapper.nametable_read(0x2805), Some(0x34));
+    }
+
+    #[test]
+    fn extended_attributes_set_palette_and_chr_bank_per_tile() {
+        let mut mapper = create_test_mapper();
+        mapper.prg_rom_write(0x5104, 2);
+        mapper.prg_rom_write(0x5C05, 0b11_000010);
+        mapper.prg_rom_write(0x5104, 1);
+
+        mapper.ppu_fetch(PpuFetch::Background, false);
+        assert_eq!(mapper.nametable_read(0x2005), None);
+        assert_eq!(mapper.nametable_read(0x23C1), Some(0xFF));
+        assert_eq!(mapper.chr_read(0x0010), 2);
+    }
+
+    #[test]
+    fn uses_background_chr_banks_for_8x16_sprites() {
+        let mut mapper = create_test_mapper();
+        mapper.prg_rom_write(0x5101, 1);
+        mapper.prg_rom_write(0x5123, 3);
+        mapper.prg_rom_write(0x5127, 4);
+        mapper.prg_rom_write(0x512B, 5);
+
+        mapper.ppu_fetch(PpuFetch::Sprites, true);
+        assert_eq!(mapper.chr_read(0x0000), 3);
+        assert_eq!(mapper.chr_read(0x1000), 4);
+        mapper.ppu_fetch(PpuFetch::Background, true);
+        assert_eq!(mapper.chr_read(0x0000), 5);
+        assert_eq!(mapper.chr_read(0x1000), 5);
+    }
+
+    #[test]
+    fn scanline_irq_fires_at_the_target_line() {
+        let mut mapper = create_test_mapper();
+        mapper.prg_rom_write(0x5203, 3);
+        mapper.prg_rom_write(0x5204, 0x80);
+        assert_eq!(mapper.watched_scanline(), Some(3));
+
+        // The pre-render line's fetches for line 0
+        mapper.ppu_fetch(PpuFetch::Sprites, false);
+        mapper.ppu_fetch(PpuFetch::Background, false);
+        mapper.ppu_fetch(PpuFetch::Background, false);
+        assert_eq!(mapper.register_read(0x5204), 0x40);
+
+        for _ in 0..2 {
+            run_scanline(&mut mapper);
+            assert!(!mapper.run_to(0));
+        }
+        run_scanline(&mut mapper);
+        assert_eq!(mapper.requested_run_cycle(), 0);
+        assert!(mapper.run_to(0));
+        assert!(mapper.irq_pending());
+        assert_eq!(mapper.register_read(0x5204), 0xC0);
+        assert!(!mapper.irq_pending());
+        assert_eq!(mapper.register_read(0x5204), 0x40);
+
+        mapper.ppu_fetch(PpuFetch::Idle, false);
+        assert_eq!(mapper.register_read(0x5204), 0x00);
+    }
+
+    #[test]
+    fn pcm_read_mode_plays_reads_and_raises_the_irq_on_zero() {
+        let mut mapper = create_test_mapper();
+        mapper.prg_rom_write(0x5114, 0x80);
+        mapper.prg_rom_write(0x5115, 0x83);
+        mapper.prg_rom_write(0x5010, 0x81);
+
+        assert_eq!(mapper.prg_read(0xA000), 3);
+        assert!(!mapper.irq_pending());
+        // Bank 0 is all zeroes
+        assert_eq!(mapper.prg_read(0x8000), 0);
+        assert!(mapper.irq_pending());
+        assert_eq!(mapper.requested_run_cycle(), 0);
+        assert!(mapper.run_to(0));
+
+        assert_eq!(mapper.register_read(0x5010), 0x80);
+        assert!(!mapper.irq_pending());
+        assert_eq!(mapper.register_read(0x5010), 0x00);
+    }
+
+    #[test]
+    fn pcm_zero_reads_keep_the_last_sample() {
+        let mut audio = Audio::new();
+        audio.write(0x5010, 0x01);
+        assert!(!audio.read_sample(0x40));
+        assert_eq!(audio.pcm, 0x40);
+        // The IRQ is disabled, so the flag is set but the line stays low
+        assert!(!audio.read_sample(0));
+        assert_eq!(audio.pcm, 0x40);
+        assert!(!audio.pcm_irq());
+        audio.write(0x5010, 0x81);
+        assert!(audio.pcm_irq());
+        // Writes are ignored in read mode
+        audio.write(0x5011, 0x20);
+        assert_eq!(audio.pcm, 0x40);
+    }
+}
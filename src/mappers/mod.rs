@@ -1,98 +1,220 @@
-mod volatile;
-mod battery;
-
-mod bank;
-
-mod mapper000;
-mod mmc1;
-
-use cart::ScreenMode;
-pub use mappers::bank::RomBank;
-use std::path::Path;
-
-static VERTICAL: [u16; 4] = [0x2000, 0x2400, 0x2000, 0x2400];
-static HORIZONTAL: [u16; 4] = [0x2000, 0x2000, 0x2400, 0x2400];
-static ONE_SCREEN_LOW: [u16; 4] = [0x2000, 0x2000, 0x2000, 0x2000];
-static ONE_SCREEN_HIGH: [u16; 4] = [0x2400, 0x2400, 0x2400, 0x2400];
-static FOUR_SCREEN: [u16; 4] = [0x2000, 0x2400, 0x2800, 0x2C00];
-
-fn standard_mapping_tables(mode: ScreenMode) -> &'static [u16; 4] {
-    match mode {
-        ScreenMode::Vertical => &VERTICAL,
-        ScreenMode::Horizontal => &HORIZONTAL,
-        ScreenMode::OneScreenHigh => &ONE_SCREEN_HIGH,
-        ScreenMode::OneScreenLow => &ONE_SCREEN_LOW,
-        ScreenMode::FourScreen => &FOUR_SCREEN,
-    }
-}
-
-#[derive(Debug, Eq, PartialEq, Hash, Clone, Copy)]
-pub struct RomAddress {
-    pub window_id: usize,
-    pub offset: u16,
-}
-
-pub trait Mapper {
-    fn prg_rom_read(&mut self, idx: u16) -> &RomBank;
-    fn prg_rom_write(&mut self, idx: u16, val: u8) -> &mut RomBank;
-
-    /// Returns a struct which uniquely identifies the ROM cell backing the
-    /// given address.
-    fn prg_rom_address(&self, idx: u16) -> RomAddress;
-
-    fn prg_ram_read(&mut self, idx: u16) -> u8;
-    fn prg_ram_write(&mut self, idx: u16, val: u8);
-
-    fn chr_read(&mut self, idx: u16) -> u8;
-    fn chr_write(&mut self, idx: u16, val: u8);
-
-    fn get_mirroring_table(&self) -> &[u16; 4];
-}
-
-pub struct MapperParams<'a> {
-    pub prg_rom: Vec<u8>,
-    pub chr_rom: Vec<u8>,
-
-    pub prg_ram_size: usize,
-
-    pub rom_path: &'a Path,
-
-    pub has_battery_backed_ram: bool,
-    pub mirroring_mode: ScreenMode,
-}
-
-impl<'a> MapperParams<'a> {
-    #[cfg(test)]
-    pub fn simple(rom_path: &'a Path, prg_rom: Vec<u8>, chr_rom: Vec<u8>) -> MapperParams<'a> {
-        MapperParams {
-            prg_rom: prg_rom,
-            chr_rom: chr_rom,
-
-            prg_ram_size: 0x2000,
-
-            rom_path: rom_path,
-
-            has_battery_backed_ram: false,
-            mirroring_mode: ScreenMode::OneScreenLow,
-        }
-    }
-}
-
-impl Mapper {
-    pub fn new(id: u16, params: MapperParams) -> Box<Mapper> {
-        match id {
-            0 => mapper000::new(params),
-            1 => mmc1::new(params),
-            m => panic!("Unsupported Mapper: {}", m),
-        }
-    }
-}
-
-#[cfg(test)]
-pub fn create_test_mapper(prg_rom: Vec<u8>, chr_rom: Vec<u8>, mode: ScreenMode) -> Box<Mapper> {
-    let path_buf = ::std::path::PathBuf::new();
-    let path = path_buf.as_path();
-    let mut params = MapperParams::simple(path, prg_rom, chr_rom);
-    params.mirroring_mode = mode;
-    Mapper::new(0, params)
-}
+mod volatile;
+mod battery;
+
+mod bank;
+
+mod mapper000;
+mod mmc1;
+mod vrc6;
+mod namco163;
+mod fme7;
+mod mmc5;
+mod nsf;
+
+use apu::Waveform;
+use cart::ScreenMode;
+pub use mappers::bank::RomBank;
+pub use mappers::nsf::{DRIVER_ADDR as NSF_DRIVER_ADDR, NsfParams, TIMER_REG as NSF_TIMER_REG};
+use memory::MemSegment;
+use std::any::Any;
+use std::path::Path;
+
+static VERTICAL: [u16; 4] = [0x2000, 0x2400, 0x2000, 0x2400];
+static HORIZONTAL: [u16; 4] = [0x2000, 0x2000, 0x2400, 0x2400];
+static ONE_SCREEN_LOW: [u16; 4] = [0x2000, 0x2000, 0x2000, 0x2000];
+static ONE_SCREEN_HIGH: [u16; 4] = [0x2400, 0x2400, 0x2400, 0x2400];
+static FOUR_SCREEN: [u16; 4] = [0x2000, 0x2400, 0x2800, 0x2C00];
+
+fn standard_mapping_tables(mode: ScreenMode) -> &'static [u16; 4] {
+    match mode {
+        ScreenMode::Vertical => &VERTICAL,
+        ScreenMode::Horizontal => &HORIZONTAL,
+        ScreenMode::OneScreenHigh => &ONE_SCREEN_HIGH,
+        ScreenMode::OneScreenLow => &ONE_SCREEN_LOW,
+        ScreenMode::FourScreen => &FOUR_SCREEN,
+    }
+}
+
+/// What the PPU's memory reads are currently for, as far as a mapper
+/// watching them can tell.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PpuFetch {
+    /// Background tiles, from dot 321 of one scanline to dot 256 of the next.
+    Background,
+    /// Sprite patterns, from dot 257 to 320.
+    Sprites,
+    /// Rendering is off or the frame is finished, so reads come from the CPU
+    /// through $2007.
+    Idle,
+}
+
+#[derive(Debug, Eq, PartialEq, Hash, Clone, Copy)]
+pub struct RomAddress {
+    pub window_id: usize,
+    pub offset: u16,
+}
+
+pub trait Mapper {
+    fn prg_rom_read(&mut self, idx: u16) -> &RomBank;
+    fn prg_rom_write(&mut self, idx: u16, val: u8) -> &mut RomBank;
+
+    /// Reads from $8000-$FFFF. Mappers which can put RAM there override this.
+    fn prg_read(&mut self, idx: u16) -> u8 {
+        self.prg_rom_read(idx).read(idx)
+    }
+
+    /// Reads from $4020-$5FFF, where some mappers have readable registers
+    /// instead of ROM.
+    fn register_read(&mut self, idx: u16) -> u8 {
+        self.prg_rom_read(idx).read(idx)
+    }
+
+    /// Whether the register at `idx` reports on what the PPU is doing, so
+    /// the PPU has to be run up to the read first.
+    fn register_watches_ppu(&self, _idx: u16) -> bool {
+        false
+    }
+
+    /// Returns a struct which uniquely identifies the ROM cell backing the
+    /// given address.
+    fn prg_rom_address(&self, idx: u16) -> RomAddress;
+
+    /// Whether $4020-$5FFF holds ROM, so code run from there can be
+    /// compiled. Usually it's registers or RAM, which the interpreter has to
+    /// run since compiled code isn't recompiled when it's overwritten.
+    fn expansion_rom(&self) -> bool {
+        false
+    }
+
+    fn prg_ram_read(&mut self, idx: u16) -> u8;
+    fn prg_ram_write(&mut self, idx: u16, val: u8);
+
+    fn chr_read(&mut self, idx: u16) -> u8;
+    fn chr_write(&mut self, idx: u16, val: u8);
+
+    fn get_mirroring_table(&self) -> &[u16; 4];
+
+    /// Reads a nametable byte from the cartridge rather than the console's
+    /// VRAM, for mappers which can put ROM or their own RAM there. Returns
+    /// None to use the VRAM as mirrored by `get_mirroring_table`.
+    fn nametable_read(&mut self, _idx: u16) -> Option<u8> {
+        None
+    }
+
+    /// Returns true if the cartridge took the write, in which case the
+    /// console's VRAM isn't written.
+    fn nametable_write(&mut self, _idx: u16, _val: u8) -> bool {
+        false
+    }
+
+    /// Tells the mapper what the PPU's following reads are for, and whether
+    /// it's drawing 8x16 sprites. Called as rendering moves between the
+    /// background and sprites, and when it stops.
+    fn ppu_fetch(&mut self, _fetch: PpuFetch, _tall_sprites: bool) {}
+
+    /// A scanline the CPU has to run the PPU up to on time, for mappers which
+    /// count scanlines by watching the PPU's fetches.
+    fn watched_scanline(&self) -> Option<i16> {
+        None
+    }
+
+    /// Runs the mapper's own hardware, such as IRQ counters, up to the given
+    /// CPU cycle. Returns true if it raised an IRQ.
+    fn run_to(&mut self, _cycle: u64) -> bool {
+        false
+    }
+
+    /// The CPU cycle by which `run_to` must next be called.
+    fn requested_run_cycle(&self) -> u64 {
+        ::std::u64::MAX
+    }
+
+    /// Whether the cartridge is holding the IRQ line. It stays held until
+    /// the game acknowledges the IRQ through the mapper's registers, so an
+    /// IRQ raised while interrupts are disabled isn't lost.
+    fn irq_pending(&self) -> bool {
+        false
+    }
+
+    /// The number of sound channels the cartridge's own audio hardware has,
+    /// up to eight. Each is mixed separately.
+    fn audio_channels(&self) -> usize {
+        0
+    }
+
+    /// Generates the cartridge's expansion audio from `from_cyc` to
+    /// `to_cyc`, counted in CPU cycles from the start of the APU's current
+    /// audio frame, setting each channel's level on its entry in `out` as it
+    /// changes. Only called while sound is being output.
+    fn play_audio(&mut self, _from_cyc: u32, _to_cyc: u32, _out: &mut [Waveform]) {}
+
+    /// Captures the mapper's registers and RAM so they can be restored later
+    /// with `load_state`.
+    fn save_state(&self) -> Box<Any>;
+    fn load_state(&mut self, state: &Any);
+}
+
+/// RAM on the cartridge board. Unlike `MemSegment`, this allows the whole
+/// contents to be captured and restored at once.
+pub trait RamSegment: MemSegment {
+    fn contents(&self) -> &[u8];
+    fn restore(&mut self, data: &[u8]);
+}
+
+pub struct MapperParams<'a> {
+    pub prg_rom: Vec<u8>,
+    pub chr_rom: Vec<u8>,
+
+    pub prg_ram_size: usize,
+
+    pub rom_path: &'a Path,
+
+    pub has_battery_backed_ram: bool,
+    pub mirroring_mode: ScreenMode,
+}
+
+impl<'a> MapperParams<'a> {
+    #[cfg(test)]
+    pub fn simple(rom_path: &'a Path, prg_rom: Vec<u8>, chr_rom: Vec<u8>) -> MapperParams<'a> {
+        MapperParams {
+            prg_rom: prg_rom,
+            chr_rom: chr_rom,
+
+            prg_ram_size: 0x2000,
+
+            rom_path: rom_path,
+
+            has_battery_backed_ram: false,
+            mirroring_mode: ScreenMode::OneScreenLow,
+        }
+    }
+}
+
+impl Mapper {
+    pub fn new(id: u16, params: MapperParams) -> Box<Mapper> {
+        match id {
+            0 => mapper000::new(params),
+            1 => mmc1::new(params),
+            5 => mmc5::new(params),
+            19 => namco163::new(params),
+            24 => vrc6::new(params, false),
+            26 => vrc6::new(params, true),
+            69 => fme7::new(params),
+            m => panic!("Unsupported Mapper: {}", m),
+        }
+    }
+
+    /// Creates the mapper for a cartridge which plays an NSF file.
+    pub fn nsf(params: NsfParams) -> Box<Mapper> {
+        nsf::new(params)
+    }
+}
+
+#[cfg(test)]
+pub fn create_test_mapper(prg_rom: Vec<u8>, chr_rom: Vec<u8>, mode: ScreenMode) -> Box<Mapper> {
+    let path_buf = ::std::path::PathBuf::new();
+    let path = path_buf.as_path();
+    let mut params = MapperParams::simple(path, prg_rom, chr_rom);
+    params.mirroring_mode = mode;
+    Mapper::new(0, params)
+}
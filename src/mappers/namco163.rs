@@ -0,0 +1,523 @@
+//! Namco's 163, used by many of Namco's later Famicom games. It banks PRG
+//! and CHR ROM in 8KB and 1KB units and can put CHR ROM in place of the
+//! nametables. It also has a 15-bit IRQ counter and up to eight wavetable
+//! sound channels which play 4-bit samples from 128 bytes of internal RAM.
+//!
+//! The chip sits between the PPU and the console's nametable RAM, so this
+//! mapper keeps that RAM itself. That lets the pattern tables be mapped to
+//! it as well, which the chip also allows.
+
+use super::{Mapper, MapperParams, RamSegment, RomAddress};
+use super::bank::*;
+use super::battery::BatteryBackedRam;
+use super::volatile::VolatileRam;
+use apu::{PULSE_LEVEL, Waveform};
+use memory::MemSegment;
+use std::any::Any;
+use std::cmp;
+
+/// The sound RAM follows the PRG RAM in the same (possibly battery-backed)
+/// segment, so that both end up in the save file.
+const SOUND_RAM_SIZE: usize = 0x80;
+
+/// The channels' registers are in the top half of the sound RAM.
+const CHANNEL_REGS: usize = 0x40;
+
+/// The chip updates one channel every this many CPU cycles, and only
+/// outputs that channel until the next update.
+const CYCLES_PER_CHANNEL: u32 = 15;
+
+/// Scales a channel's output, which is between -120 and 105, so that one
+/// channel at full volume peaks about as loud as one of the APU's pulses.
+const LEVEL_SCALE: f32 = PULSE_LEVEL / 120.0;
+
+/// CHR bank numbers from this up select nametable RAM instead of ROM.
+const CIRAM_BANKS: u8 = 0xE0;
+
+#[derive(Debug, Clone, PartialEq)]
+struct Regs {
+    prg: [u8; 3],
+    /// The 1KB banks for the pattern tables, then for the nametables.
+    chr: [u8; 12],
+    /// Use CHR ROM even for banks from `CIRAM_BANKS` in the lower and upper
+    /// pattern tables.
+    rom_only: [bool; 2],
+    sound_enabled: bool,
+    /// The sound RAM address port at $F800, which doubles as the PRG RAM
+    /// write protection.
+    sound_addr: u8,
+}
+
+impl Regs {
+    fn sound_ram_index(&self) -> usize {
+        (self.sound_addr & 0x7F) as usize
+    }
+
+    /// PRG RAM is writable in 2KB windows when the upper bits of $F800 are
+    /// %0100 and the window's protection bit is clear.
+    fn prg_ram_writable(&self, idx: u16) -> bool {
+        let window = (idx - 0x6000) >> 11;
+        self.sound_addr & 0xF0 == 0x40 && self.sound_addr & (1u8 << window) == 0
+    }
+}
+
+/// A 15-bit counter which counts up every CPU cycle and raises the IRQ when
+/// it reaches $7FFF, where it stops.
+#[derive(Debug, Clone, PartialEq)]
+struct Irq {
+    counter: u16,
+    enabled: bool,
+    /// Set when the counter reaches $7FFF, until either counter register is
+    /// written.
+    pending: bool,
+    last_cycle: u64,
+}
+
+impl Irq {
+    fn run_to(&mut self, cycle: u64) -> bool {
+        if cycle <= self.last_cycle {
+            return false;
+        }
+        let cycles = cycle - self.last_cycle;
+        self.last_cycle = cycle;
+        if !self.enabled || self.counter == 0x7FFF {
+            return false;
+        }
+        let counter = cmp::min(self.counter as u64 + cycles, 0x7FFF);
+        self.counter = counter as u16;
+        let fired = self.counter == 0x7FFF;
+        self.pending |= fired;
+        fired
+    }
+
+    fn next_irq_cycle(&self) -> u64 {
+        if !self.enabled || self.counter == 0x7FFF {
+            return ::std::u64::MAX;
+        }
+        self.last_cycle + (0x7FFF - self.counter) as u64
+    }
+}
+
+/// Where each channel is in the multiplexing cycle.
+#[derive(Debug, Clone, PartialEq)]
+struct Audio {
+    channel: usize,
+    remaining: u32,
+    /// The channel being output, and its output.
+    playing: usize,
+    output: i16,
+}
+
+impl Audio {
+    fn new() -> Audio {
+        Audio {
+            channel: 7,
+            remaining: CYCLES_PER_CHANNEL,
+            playing: 7,
+            output: 0,
+        }
+    }
+
+    /// Only the channel being output is heard; the others are silent until
+    /// their turn.
+    fn set_levels(&self, out: &mut [Waveform], cycle: u32) {
+        for (channel, out) in out.iter_mut().enumerate() {
+            let output = if channel == self.playing { self.output } else { 0 };
+            out.set_level(output as f32 * LEVEL_SCALE, cycle);
+        }
+    }
+}
+
+struct Namco163 {
+    regs: Regs,
+    irq: Irq,
+    audio: Audio,
+
+    prg_rom: MappingTable,
+    chr_rom: Box<[u8]>,
+    chr_ram: Box<[u8]>,
+    /// PRG RAM followed by a copy of the sound RAM, which is what's saved.
+    ram: Box<RamSegment>,
+    prg_ram_size: usize,
+    /// The sound RAM itself. The channels' phases are updated in it every
+    /// few cycles, so only the CPU's writes are copied to `ram`, rather than
+    /// flushing the save file each time.
+    sound_ram: Box<[u8]>,
+    ciram: Box<[u8]>,
+}
+
+struct Namco163State {
+    regs: Regs,
+    irq: Irq,
+    audio: Audio,
+    chr_ram: Box<[u8]>,
+    ram: Vec<u8>,
+    sound_ram: Box<[u8]>,
+    ciram: Box<[u8]>,
+}
+
+static UNUSED_MIRRORING: [u16; 4] = [0x2000, 0x2400, 0x2000, 0x2400];
+
+pub fn new(params: MapperParams) -> Box<Mapper> {
+    let chr_ram = if params.chr_rom.is_empty() {
+        vec![0u8; 0x2000].into_boxed_slice()
+    } else {
+        vec![0u8; 0].into_boxed_slice()
+    };
+
+    let ram_size = params.prg_ram_size + SOUND_RAM_SIZE;
+    let ram: Box<RamSegment> = if params.has_battery_backed_ram {
+        Box::new(
+            BatteryBackedRam::new(params.rom_path, ram_size as u32).unwrap(),
+        )
+    } else {
+        Box::new(VolatileRam::new(ram_size))
+    };
+    let sound_ram = ram.contents()[params.prg_ram_size..].to_vec().into_boxed_slice();
+
+    let mut mapper = Namco163 {
+        regs: Regs {
+            prg: [0; 3],
+            chr: [0; 12],
+            rom_only: [false; 2],
+            sound_enabled: true,
+            sound_addr: 0,
+        },
+        irq: Irq {
+            counter: 0,
+            enabled: false,
+            pending: false,
+            last_cycle: 0,
+        },
+        audio: Audio::new(),
+
+        prg_rom: MappingTable::new(params.prg_rom, 2),
+        chr_rom: params.chr_rom.into_boxed_slice(),
+        chr_ram: chr_ram,
+        ram: ram,
+        prg_ram_size: params.prg_ram_size,
+        sound_ram: sound_ram,
+        ciram: vec![0u8; 0x800].into_boxed_slice(),
+    };
+    mapper.update_mapping();
+
+    Box::new(mapper)
+}
+
+impl Namco163 {
+    fn update_mapping(&mut self) {
+        let bank_count = self.prg_rom.bank_count();
+        for (window, &bank) in self.regs.prg.iter().enumerate() {
+            let bank = (bank as usize & 0x3F) * 2 % bank_count;
+            self.prg_rom
+                .map_pages_linear(window * 2..window * 2 + 2, bank);
+        }
+        self.prg_rom.map_pages_linear(6..8, bank_count - 2);
+    }
+
+    /// Writes a byte of sound RAM from the CPU, keeping the saved copy up
+    /// to date.
+    fn sound_ram_write(&mut self, idx: usize, val: u8) {
+        self.sound_ram[idx] = val;
+        let addr = (self.prg_ram_size + idx) as u16;
+        self.ram.write(addr, val);
+    }
+
+    /// Steps the sound RAM address after an access through the data port at
+    /// $4800, if auto-increment is on.
+    fn step_sound_addr(&mut self) {
+        if self.regs.sound_addr & 0x80 != 0 {
+            let addr = self.regs.sound_addr.wrapping_add(1) & 0x7F;
+            self.regs.sound_addr = 0x80 | addr;
+        }
+    }
+
+    /// Reads a byte of a 1KB CHR bank, or of nametable RAM if `ciram`.
+    fn bank_read(&self, bank: u8, ciram: bool, idx: u16) -> u8 {
+        let offset = (idx & 0x3FF) as usize;
+        if ciram {
+            self.ciram[(bank as usize & 1) * 0x400 + offset]
+        } else if self.chr_rom.is_empty() {
+            self.chr_ram[idx as usize % 0x2000]
+        } else {
+            self.chr_rom[(bank as usize * 0x400 + offset) % self.chr_rom.len()]
+        }
+    }
+
+    /// The bank and whether it's nametable RAM for a 1KB window of PPU
+    /// memory, from 0 for $0000 to 11 for $2C00.
+    fn chr_window(&self, window: usize) -> (u8, bool) {
+        let bank = self.regs.chr[window];
+        let rom_only = window < 8 && self.regs.rom_only[window / 4];
+        (bank, bank >= CIRAM_BANKS && !rom_only)
+    }
+
+    fn chr_write_window(&mut self, window: usize, idx: u16, val: u8) {
+        let (bank, ciram) = self.chr_window(window);
+        if ciram {
+            self.ciram[(bank as usize & 1) * 0x400 + (idx & 0x3FF) as usize] = val;
+        } else if self.chr_rom.is_empty() {
+            self.chr_ram[idx as usize % 0x2000] = val;
+        }
+    }
+
+    /// Updates the next channel, returning it and its new output.
+    fn update_channel(&mut self) -> (usize, i16) {
+        let active_channels = ((self.sound_ram[0x7F] >> 4) & 0x07) as usize + 1;
+        let lowest = 8 - active_channels;
+        if self.audio.channel < lowest {
+            self.audio.channel = 7;
+        }
+        let channel = self.audio.channel;
+        self.audio.channel = if channel == lowest { 7 } else { channel - 1 };
+
+        let base = CHANNEL_REGS + channel * 8;
+        let mut regs = [0u8; 8];
+        regs.copy_from_slice(&self.sound_ram[base..base + 8]);
+        let frequency = regs[0] as u32 | (regs[2] as u32) << 8 | (regs[4] as u32 & 0x03) << 16;
+        let phase = regs[1] as u32 | (regs[3] as u32) << 8 | (regs[5] as u32) << 16;
+        let length = (256 - (regs[4] & 0xFC) as u32) << 16;
+        let phase = (phase + frequency) % length;
+        self.sound_ram[base + 1] = phase as u8;
+        self.sound_ram[base + 3] = (phase >> 8) as u8;
+        self.sound_ram[base + 5] = (phase >> 16) as u8;
+
+        let sample_idx = (regs[6] as u32 + (phase >> 16)) as usize & 0xFF;
+        let byte = self.sound_ram[sample_idx / 2];
+        let sample = if sample_idx % 2 == 0 { byte & 0x0F } else { byte >> 4 };
+        (channel, (sample as i16 - 8) * (regs[7] & 0x0F) as i16)
+    }
+}
+
+impl Mapper for Namco163 {
+    fn prg_rom_read(&mut self, idx: u16) -> &RomBank {
+        self.prg_rom.get_bank(idx)
+    }
+
+    fn prg_rom_write(&mut self, idx: u16, val: u8) -> &mut RomBank {
+        match idx {
+            0x4800...0x4FFF => {
+                let addr = self.regs.sound_ram_index();
+                self.sound_ram_write(addr, val);
+                self.step_sound_addr();
+            }
+            0x5000...0x57FF => {
+                self.irq.counter = (self.irq.counter & 0x7F00) | val as u16;
+                self.irq.pending = false;
+            }
+            0x5800...0x5FFF => {
+                self.irq.pending = false;
+                self.irq.counter = (self.irq.counter & 0x00FF) | (val as u16 & 0x7F) << 8;
+                self.irq.enabled = val & 0x80 != 0;
+            }
+            0x8000...0xDFFF => {
+                let window = ((idx - 0x8000) >> 11) as usize;
+                self.regs.chr[window] = val;
+            }
+            0xE000...0xE7FF => {
+                self.regs.prg[0] = val;
+                self.regs.sound_enabled = val & 0x40 == 0;
+            }
+            0xE800...0xEFFF => {
+                self.regs.prg[1] = val;
+                self.regs.rom_only = [val & 0x40 != 0, val & 0x80 != 0];
+            }
+            0xF000...0xF7FF => self.regs.prg[2] = val,
+            0xF800...0xFFFF => self.regs.sound_addr = val,
+            _ => (),
+        }
+        self.update_mapping();
+        match idx {
+            0x8000...0xFFFF => self.prg_rom.get_bank_mut(idx),
+            // There's no ROM below $8000; this bank just swallows the write.
+            _ => self.prg_rom.get_bank_mut(0xE000),
+        }
+    }
+
+    fn register_read(&mut self, idx: u16) -> u8 {
+        match idx {
+            0x4800...0x4FFF => {
+                let addr = self.regs.sound_ram_index();
+                let val = self.sound_ram[addr];
+                self.step_sound_addr();
+                val
+            }
+            0x5000...0x57FF => self.irq.counter as u8,
+            0x5800...0x5FFF => {
+                let enabled = if self.irq.enabled { 0x80 } else { 0 };
+                (self.irq.counter >> 8) as u8 | enabled
+            }
+            _ => 0,
+        }
+    }
+
+    fn prg_rom_address(&self, idx: u16) -> RomAddress {
+        self.prg_rom.get_rom_address(idx)
+    }
+
+    fn prg_ram_read(&mut self, idx: u16) -> u8 {
+        let addr = (idx - 0x6000) as usize % self.prg_ram_size;
+        self.ram.read(addr as u16)
+    }
+
+    fn prg_ram_write(&mut self, idx: u16, val: u8) {
+        if self.regs.prg_ram_writable(idx) {
+            let addr = (idx - 0x6000) as usize % self.prg_ram_size;
+            self.ram.write(addr as u16, val);
+        }
+    }
+
+    fn chr_read(&mut self, idx: u16) -> u8 {
+        let (bank, ciram) = self.chr_window((idx >> 10) as usize & 0x07);
+        self.bank_read(bank, ciram, idx)
+    }
+
+    fn chr_write(&mut self, idx: u16, val: u8) {
+        self.chr_write_window((idx >> 10) as usize & 0x07, idx, val);
+    }
+
+    fn get_mirroring_table(&self) -> &[u16; 4] {
+        // The nametables are handled by `nametable_read` instead.
+        &UNUSED_MIRRORING
+    }
+
+    fn nametable_read(&mut self, idx: u16) -> Option<u8> {
+        let (bank, ciram) = self.chr_window(8 + ((idx >> 10) as usize & 0x03));
+        Some(self.bank_read(bank, ciram, idx))
+    }
+
+    fn nametable_write(&mut self, idx: u16, val: u8) -> bool {
+        self.chr_write_window(8 + ((idx >> 10) as usize & 0x03), idx, val);
+        true
+    }
+
+    fn run_to(&mut self, cycle: u64) -> bool {
+        self.irq.run_to(cycle)
+    }
+
+    fn requested_run_cycle(&self) -> u64 {
+        self.irq.next_irq_cycle()
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq.pending
+    }
+
+    fn audio_channels(&self) -> usize {
+        8
+    }
+
+    fn play_audio(&mut self, from_cyc: u32, to_cyc: u32, out: &mut [Waveform]) {
+        if !self.regs.sound_enabled {
+            for out in out.iter_mut() {
+                out.set_level(0.0, from_cyc);
+            }
+            return;
+        }
+        self.audio.set_levels(out, from_cyc);
+
+        let mut cycle = from_cyc;
+        while cycle < to_cyc {
+            let step = cmp::min(to_cyc - cycle, self.audio.remaining);
+            cycle += step;
+            self.audio.remaining -= step;
+            if self.audio.remaining == 0 {
+                self.audio.remaining = CYCLES_PER_CHANNEL;
+                let (channel, output) = self.update_channel();
+                self.audio.playing = channel;
+                self.audio.output = output;
+                self.audio.set_levels(out, cycle);
+            }
+        }
+    }
+
+    fn save_state(&self) -> Box<Any> {
+        Box::new(Namco163State {
+            regs: self.regs.clone(),
+            irq: self.irq.clone(),
+            audio: self.audio.clone(),
+            chr_ram: self.chr_ram.clone(),
+            ram: self.ram.contents().to_vec(),
+            sound_ram: self.sound_ram.clone(),
+            ciram: self.ciram.clone(),
+        })
+    }
+
+    fn load_state(&mut self, state: &Any) {
+        let state = state.downcast_ref::<Namco163State>().unwrap();
+        self.regs = state.regs.clone();
+        self.irq = state.irq.clone();
+        self.audio = state.audio.clone();
+        self.chr_ram.copy_from_slice(&state.chr_ram);
+        self.ram.restore(&state.ram);
+        self.sound_ram.copy_from_slice(&state.sound_ram);
+        self.ciram.copy_from_slice(&state.ciram);
+        self.update_mapping();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mappers::{Mapper, MapperParams};
+
+    /// CHR ROM where each 1KB bank is filled with its own number.
+    fn create_test_mapper() -> Box<Mapper> {
+        let path_buf = ::std::path::PathBuf::new();
+        let path = path_buf.as_path();
+        let chr_rom = (0..256).flat_map(|bank| vec![bank as u8; 0x400]).collect();
+        new(MapperParams::simple(path, vec![0u8; 0x8000], chr_rom))
+    }
+
+    #[test]
+    fn nametables_can_use_chr_rom_or_ram() {
+        let mut mapper = create_test_mapper();
+        mapper.prg_rom_write(0xC000, 0x12);
+        mapper.prg_rom_write(0xC800, 0xE1);
+        assert_eq!(mapper.nametable_read(0x2000), Some(0x12));
+        // ROM can't be written
+        assert!(mapper.nametable_write(0x2000, 0x55));
+        assert_eq!(mapper.nametable_read(0x2000), Some(0x12));
+
+        assert!(mapper.nametable_write(0x2405, 0x55));
+        assert_eq!(mapper.nametable_read(0x2405), Some(0x55));
+        // The same RAM can be mapped into the pattern tables
+        mapper.prg_rom_write(0x8000, 0xE1);
+        assert_eq!(mapper.chr_read(0x0005), 0x55);
+        mapper.prg_rom_write(0xE800, 0x40);
+        assert_eq!(mapper.chr_read(0x0005), 0xE1);
+    }
+
+    #[test]
+    fn sound_ram_port_auto_increments() {
+        let mut mapper = create_test_mapper();
+        mapper.prg_rom_write(0xF800, 0x80 | 0x7E);
+        mapper.prg_rom_write(0x4800, 1);
+        mapper.prg_rom_write(0x4800, 2);
+        mapper.prg_rom_write(0x4800, 3);
+        mapper.prg_rom_write(0xF800, 0x7E);
+        assert_eq!(mapper.register_read(0x4800), 1);
+        mapper.prg_rom_write(0xF800, 0x7F);
+        assert_eq!(mapper.register_read(0x4800), 2);
+        mapper.prg_rom_write(0xF800, 0x00);
+        assert_eq!(mapper.register_read(0x4800), 3);
+    }
+
+    #[test]
+    fn irq_fires_when_the_counter_reaches_7fff() {
+        let mut mapper = create_test_mapper();
+        mapper.prg_rom_write(0x5000, 0xF0);
+        mapper.prg_rom_write(0x5800, 0xFF);
+        assert_eq!(mapper.requested_run_cycle(), 0x0F);
+        assert!(!mapper.run_to(0x0E));
+        assert_eq!(mapper.register_read(0x5000), 0xFE);
+        assert!(mapper.run_to(0x0F));
+        // The counter stops at $7FFF, holding the line until it's written
+        assert!(!mapper.run_to(0x100));
+        assert!(mapper.irq_pending());
+        assert_eq!(mapper.register_read(0x5000), 0xFF);
+        assert_eq!(mapper.register_read(0x5800), 0xFF);
+        mapper.prg_rom_write(0x5800, 0x00);
+        assert!(!mapper.irq_pending());
+    }
+}
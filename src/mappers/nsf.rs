@@ -0,0 +1,300 @@
+//! The mapper for the synthetic cartridge built to play NSF music files.
+//!
+//! It has the NSF bankswitching hardware (eight 4KB windows over
+//! $8000-$FFFF, selected by writes to $5FF8-$5FFF), 8KB of RAM at
+//! $6000-$7FFF, a small ROM at $4100 holding the player's driver code, and a
+//! timer which raises an IRQ at the tune's play rate. The interrupt vectors
+//! always point into the driver, whatever is banked in at $F000.
+
+use super::{Mapper, RomAddress};
+use super::bank::RomBank;
+use std::any::Any;
+use std::cmp;
+
+/// Where the driver ROM appears.
+pub const DRIVER_ADDR: u16 = 0x4100;
+
+/// Writing here starts the play timer.
+pub const TIMER_REG: u16 = 0x41F0;
+
+const BANK_SIZE: usize = 0x1000;
+const VECTORS_OFFSET: usize = 0x0FFA;
+
+/// Stands in for a bank number in the `RomAddress` of the driver ROM.
+const DRIVER_WINDOW: usize = ::std::usize::MAX;
+
+static NO_MIRRORING: [u16; 4] = [0x2000, 0x2400, 0x2800, 0x2C00];
+
+pub struct NsfParams {
+    /// The tune's data, laid out so that bank N is the Nth 4KB of it.
+    pub prg_rom: Vec<u8>,
+    /// The banks mapped at $8000, $9000 ... $F000 on reset.
+    pub initial_banks: [u8; 8],
+    /// Up to 4KB of code and data mapped at `DRIVER_ADDR`.
+    pub driver: Vec<u8>,
+    /// The NMI, reset and IRQ vectors.
+    pub vectors: [u16; 3],
+    /// The time between calls to the tune's play routine, in microseconds.
+    pub play_period_us: u64,
+    pub cpu_clock_rate: u64,
+}
+
+#[derive(Clone)]
+struct Timer {
+    running: bool,
+    /// The mapper doesn't know the cycle when the timer is started, so this
+    /// is filled in the next time it's run.
+    start_cycle: Option<u64>,
+    ticks: u64,
+}
+
+struct NsfMapper {
+    banks: Vec<RomBank>,
+    mappings: [usize; 8],
+    /// A copy of the bank mapped at $F000 with the vectors replaced.
+    vector_bank: RomBank,
+    vectors: [u16; 3],
+    driver: RomBank,
+    prg_ram: Box<[u8]>,
+    chr_ram: Box<[u8]>,
+
+    play_period_us: u64,
+    cpu_clock_rate: u64,
+    timer: Timer,
+}
+
+struct NsfState {
+    mappings: [usize; 8],
+    prg_ram: Box<[u8]>,
+    chr_ram: Box<[u8]>,
+    timer: Timer,
+}
+
+fn to_bank(mut data: Vec<u8>) -> RomBank {
+    data.resize(BANK_SIZE, 0);
+    RomBank::new(data)
+}
+
+pub fn new(params: NsfParams) -> Box<Mapper> {
+    let mut rom = params.prg_rom;
+    let size = cmp::max(8, (rom.len() + BANK_SIZE - 1) / BANK_SIZE) * BANK_SIZE;
+    rom.resize(size, 0);
+    let banks: Vec<RomBank> = rom.chunks(BANK_SIZE)
+        .map(|chunk| RomBank::new(chunk.to_vec()))
+        .collect();
+
+    let mut mapper = NsfMapper {
+        banks: banks,
+        mappings: [0; 8],
+        vector_bank: to_bank(vec![]),
+        vectors: params.vectors,
+        driver: to_bank(params.driver),
+        prg_ram: vec![0u8; 0x2000].into_boxed_slice(),
+        chr_ram: vec![0u8; 0x2000].into_boxed_slice(),
+
+        play_period_us: params.play_period_us,
+        cpu_clock_rate: params.cpu_clock_rate,
+        timer: Timer {
+            running: false,
+            start_cycle: None,
+            ticks: 0,
+        },
+    };
+    for (page, &bank) in params.initial_banks.iter().enumerate() {
+        mapper.map_page(page, bank);
+    }
+    mapper.update_vector_bank();
+    Box::new(mapper)
+}
+
+impl NsfMapper {
+    fn map_page(&mut self, page: usize, bank: u8) {
+        self.mappings[page] = bank as usize % self.banks.len();
+    }
+
+    fn update_vector_bank(&mut self) {
+        let bank = &self.banks[self.mappings[7]];
+        let mut data: Vec<u8> = (0..BANK_SIZE as u16).map(|idx| bank.read(idx)).collect();
+        for (idx, vector) in self.vectors.iter().enumerate() {
+            data[VECTORS_OFFSET + idx * 2] = *vector as u8;
+            data[VECTORS_OFFSET + idx * 2 + 1] = (*vector >> 8) as u8;
+        }
+        self.vector_bank = RomBank::new(data);
+    }
+
+    /// The cycle on which the timer next fires.
+    fn next_tick_cycle(&self, start_cycle: u64) -> u64 {
+        let elapsed_us = (self.timer.ticks + 1) * self.play_period_us;
+        start_cycle + elapsed_us * self.cpu_clock_rate / 1_000_000
+    }
+}
+
+impl Mapper for NsfMapper {
+    fn prg_rom_read(&mut self, idx: u16) -> &RomBank {
+        match idx {
+            0x4020...0x5FFF => &self.driver,
+            0xF000...0xFFFF => &self.vector_bank,
+            _ => &self.banks[self.mappings[((idx >> 12) & 0x07) as usize]],
+        }
+    }
+
+    fn prg_rom_write(&mut self, idx: u16, val: u8) -> &mut RomBank {
+        match idx {
+            TIMER_REG => self.timer.running = true,
+            0x5FF8...0x5FFF => {
+                let page = (idx - 0x5FF8) as usize;
+                self.map_page(page, val);
+                if page == 7 {
+                    self.update_vector_bank();
+                }
+            }
+            _ => (),
+        }
+        match idx {
+            0x4020...0x5FFF => &mut self.driver,
+            0xF000...0xFFFF => &mut self.vector_bank,
+            _ => &mut self.banks[self.mappings[((idx >> 12) & 0x07) as usize]],
+        }
+    }
+
+    fn prg_rom_address(&self, idx: u16) -> RomAddress {
+        let window_id = match idx {
+            0x4020...0x5FFF => DRIVER_WINDOW,
+            _ => self.mappings[((idx >> 12) & 0x07) as usize],
+        };
+        RomAddress {
+            window_id: window_id,
+            offset: idx & (BANK_SIZE as u16 - 1),
+        }
+    }
+
+    fn expansion_rom(&self) -> bool {
+        true
+    }
+
+    fn prg_ram_read(&mut self, idx: u16) -> u8 {
+        self.prg_ram[(idx - 0x6000) as usize]
+    }
+
+    fn prg_ram_write(&mut self, idx: u16, val: u8) {
+        self.prg_ram[(idx - 0x6000) as usize] = val;
+    }
+
+    fn chr_read(&mut self, idx: u16) -> u8 {
+        self.chr_ram[idx as usize]
+    }
+
+    fn chr_write(&mut self, idx: u16, val: u8) {
+        self.chr_ram[idx as usize] = val;
+    }
+
+    fn get_mirroring_table(&self) -> &[u16; 4] {
+        &NO_MIRRORING
+    }
+
+    fn run_to(&mut self, cycle: u64) -> bool {
+        if !self.timer.running {
+            return false;
+        }
+        let start_cycle = match self.timer.start_cycle {
+            Some(start_cycle) => start_cycle,
+            None => {
+                self.timer.start_cycle = Some(cycle);
+                return false;
+            }
+        };
+        if cycle < self.next_tick_cycle(start_cycle) {
+            return false;
+        }
+        // If the CPU fell behind, skip the missed ticks rather than trying
+        // to catch up on them.
+        while cycle >= self.next_tick_cycle(start_cycle) {
+            self.timer.ticks += 1;
+        }
+        true
+    }
+
+    fn requested_run_cycle(&self) -> u64 {
+        match (self.timer.running, self.timer.start_cycle) {
+            (false, _) => ::std::u64::MAX,
+            (true, None) => 0,
+            (true, Some(start_cycle)) => self.next_tick_cycle(start_cycle),
+        }
+    }
+
+    fn save_state(&self) -> Box<Any> {
+        Box::new(NsfState {
+            mappings: self.mappings,
+            prg_ram: self.prg_ram.clone(),
+            chr_ram: self.chr_ram.clone(),
+            timer: self.timer.clone(),
+        })
+    }
+
+    fn load_state(&mut self, state: &Any) {
+        let state = state.downcast_ref::<NsfState>().unwrap();
+        self.mappings = state.mappings;
+        self.prg_ram.copy_from_slice(&state.prg_ram);
+        self.chr_ram.copy_from_slice(&state.chr_ram);
+        self.timer = state.timer.clone();
+        self.update_vector_bank();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_test_mapper() -> Box<Mapper> {
+        let mut prg_rom = vec![0u8; BANK_SIZE * 10];
+        for (bank, chunk) in prg_rom.chunks_mut(BANK_SIZE).enumerate() {
+            chunk[0] = bank as u8;
+        }
+        new(NsfParams {
+            prg_rom: prg_rom,
+            initial_banks: [0, 1, 2, 3, 4, 5, 6, 7],
+            driver: vec![0xEA; 16],
+            vectors: [0x4110, 0x4100, 0x4120],
+            play_period_us: 16639,
+            cpu_clock_rate: 1789773,
+        })
+    }
+
+    #[test]
+    fn bank_registers_switch_4kb_pages() {
+        let mut mapper = create_test_mapper();
+        assert_eq!(2, mapper.prg_rom_read(0xA000).read(0xA000));
+        mapper.prg_rom_write(0x5FFA, 9);
+        assert_eq!(9, mapper.prg_rom_read(0xA000).read(0xA000));
+        assert_eq!(9, mapper.prg_rom_address(0xA123).window_id);
+        assert_eq!(0xEA, mapper.prg_rom_read(0x4100).read(0x4100));
+    }
+
+    #[test]
+    fn vectors_point_into_the_driver() {
+        let mut mapper = create_test_mapper();
+        mapper.prg_rom_write(0x5FFF, 8);
+        assert_eq!(8, mapper.prg_rom_read(0xF000).read(0xF000));
+        let reset_lo = mapper.prg_rom_read(0xFFFC).read(0xFFFC);
+        let reset_hi = mapper.prg_rom_read(0xFFFD).read(0xFFFD);
+        assert_eq!((0x00, 0x41), (reset_lo, reset_hi));
+        let irq_lo = mapper.prg_rom_read(0xFFFE).read(0xFFFE);
+        assert_eq!(0x20, irq_lo);
+    }
+
+    #[test]
+    fn timer_fires_at_the_play_rate() {
+        let mut mapper = create_test_mapper();
+        assert!(!mapper.run_to(100000));
+        assert_eq!(::std::u64::MAX, mapper.requested_run_cycle());
+
+        mapper.prg_rom_write(TIMER_REG, 0);
+        assert_eq!(0, mapper.requested_run_cycle());
+        assert!(!mapper.run_to(100000));
+        // 16639us at 1.789773MHz
+        assert_eq!(100000 + 29780, mapper.requested_run_cycle());
+        assert!(!mapper.run_to(100000 + 29779));
+        assert!(mapper.run_to(100000 + 29780));
+        assert_eq!(100000 + 59560, mapper.requested_run_cycle());
+    }
+}
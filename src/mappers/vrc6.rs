@@ -0,0 +1,676 @@
+//! Konami's VRC6, used by Akumajou Densetsu (the Japanese Castlevania III)
+//! and Madara. Mapper 24 is the standard wiring; mapper 26 has the A0 and A1
+//! lines to the chip swapped.
+//!
+//! Besides the usual PRG and CHR banking it has an IRQ counter, clocked
+//! either every CPU cycle or roughly once a scanline, and three extra sound
+//! channels: two pulses with eight duty settings and a sawtooth.
+
+use super::{Mapper, MapperParams, RamSegment, RomAddress};
+use super::bank::*;
+use super::battery::BatteryBackedRam;
+use super::volatile::VolatileRam;
+use apu::{PULSE_LEVEL, Waveform};
+use cart::ScreenMode;
+use memory::MemSegment;
+use std::any::Any;
+use std::cmp;
+
+/// Scales the channels' 4-bit outputs, so that a pulse at full volume is
+/// about as loud as one of the APU's.
+const LEVEL_SCALE: f32 = PULSE_LEVEL / 15.0;
+
+/// The IRQ prescaler counts down by 3 each CPU cycle from this, clocking the
+/// counter when it runs out. That's once per 341 PPU dots, or one scanline.
+const PRESCALER_PERIOD: i64 = 341;
+
+#[derive(Debug, Clone, PartialEq)]
+struct Regs {
+    prg_16k: u8,
+    prg_8k: u8,
+    chr: [u8; 8],
+    /// The "PPU banking style" register at $B003.
+    banking: u8,
+}
+
+impl Regs {
+    fn chr_mode(&self) -> u8 {
+        self.banking & 0b0000_0011
+    }
+
+    fn mirroring(&self) -> ScreenMode {
+        match (self.banking >> 2) & 0b11 {
+            0 => ScreenMode::Vertical,
+            1 => ScreenMode::Horizontal,
+            2 => ScreenMode::OneScreenLow,
+            _ => ScreenMode::OneScreenHigh,
+        }
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        self.banking & 0b1000_0000 != 0
+    }
+}
+
+/// The IRQ counter, which counts up to $FF then reloads from the latch.
+#[derive(Debug, Clone, PartialEq)]
+struct Irq {
+    latch: u8,
+    counter: u8,
+    prescaler: i64,
+    enabled: bool,
+    /// Copied to `enabled` when the IRQ is acknowledged.
+    enable_after_ack: bool,
+    /// Clock the counter every cycle rather than every scanline.
+    cycle_mode: bool,
+    /// Set when the counter overflows, until the IRQ is acknowledged.
+    pending: bool,
+    last_cycle: u64,
+}
+
+impl Irq {
+    fn new() -> Irq {
+        Irq {
+            latch: 0,
+            counter: 0,
+            prescaler: PRESCALER_PERIOD,
+            enabled: false,
+            enable_after_ack: false,
+            cycle_mode: false,
+            pending: false,
+            last_cycle: 0,
+        }
+    }
+
+    fn write_control(&mut self, val: u8) {
+        self.enable_after_ack = val & 0b001 != 0;
+        self.enabled = val & 0b010 != 0;
+        self.cycle_mode = val & 0b100 != 0;
+        self.pending = false;
+        if self.enabled {
+            self.counter = self.latch;
+            self.prescaler = PRESCALER_PERIOD;
+        }
+    }
+
+    fn acknowledge(&mut self) {
+        self.pending = false;
+        self.enabled = self.enable_after_ack;
+    }
+
+    /// The number of times the counter is clocked over the given number of
+    /// cycles, advancing the prescaler.
+    fn clocks_in(&mut self, cycles: u64) -> u64 {
+        if self.cycle_mode {
+            return cycles;
+        }
+        let prescaler = self.prescaler - 3 * cycles as i64;
+        if prescaler > 0 {
+            self.prescaler = prescaler;
+            return 0;
+        }
+        let clocks = -prescaler / PRESCALER_PERIOD + 1;
+        self.prescaler = prescaler + clocks * PRESCALER_PERIOD;
+        clocks as u64
+    }
+
+    /// Clocks the counter, returning true if it overflowed.
+    fn clock_counter(&mut self, mut clocks: u64) -> bool {
+        let mut overflowed = false;
+        while clocks > 0 {
+            let to_overflow = 0x100 - self.counter as u64;
+            if clocks < to_overflow {
+                self.counter += clocks as u8;
+                break;
+            }
+            clocks -= to_overflow;
+            self.counter = self.latch;
+            overflowed = true;
+        }
+        overflowed
+    }
+
+    fn run_to(&mut self, cycle: u64) -> bool {
+        if cycle <= self.last_cycle {
+            return false;
+        }
+        let cycles = cycle - self.last_cycle;
+        self.last_cycle = cycle;
+        if !self.enabled {
+            return false;
+        }
+        let clocks = self.clocks_in(cycles);
+        let overflowed = self.clock_counter(clocks);
+        self.pending |= overflowed;
+        overflowed
+    }
+
+    fn next_irq_cycle(&self) -> u64 {
+        if !self.enabled {
+            return ::std::u64::MAX;
+        }
+        let clocks = 0x100 - self.counter as i64;
+        let cycles = if self.cycle_mode {
+            clocks
+        } else {
+            // The prescaler has to run out once for each clock.
+            (self.prescaler + (clocks - 1) * PRESCALER_PERIOD + 2) / 3
+        };
+        self.last_cycle + cycles as u64
+    }
+}
+
+/// The frequency divider shared by all three channels.
+#[derive(Debug, Clone, PartialEq)]
+struct Divider {
+    period: u16,
+    enabled: bool,
+    remaining: u32,
+}
+
+impl Divider {
+    fn new() -> Divider {
+        Divider {
+            period: 0,
+            enabled: false,
+            remaining: 1,
+        }
+    }
+
+    fn write_low(&mut self, val: u8) {
+        self.period = (self.period & 0x0F00) | val as u16;
+    }
+
+    /// Returns true if the channel was switched off.
+    fn write_high(&mut self, val: u8) -> bool {
+        self.period = (self.period & 0x00FF) | ((val as u16 & 0x0F) << 8);
+        let was_enabled = self.enabled;
+        self.enabled = val & 0b1000_0000 != 0;
+        was_enabled && !self.enabled
+    }
+
+    fn remaining(&self) -> Option<u32> {
+        if self.enabled {
+            Some(self.remaining)
+        } else {
+            None
+        }
+    }
+
+    /// Runs for the given number of cycles, which mustn't be more than are
+    /// remaining. Returns true if the channel should be clocked.
+    fn run(&mut self, cycles: u32, shift: u8) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        self.remaining -= cycles;
+        if self.remaining == 0 {
+            self.remaining = (self.period >> shift) as u32 + 1;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Pulse {
+    divider: Divider,
+    volume: u8,
+    duty: u8,
+    /// Output the volume constantly, ignoring the duty cycle.
+    ignore_duty: bool,
+    step: u8,
+}
+
+impl Pulse {
+    fn new() -> Pulse {
+        Pulse {
+            divider: Divider::new(),
+            volume: 0,
+            duty: 0,
+            ignore_duty: false,
+            step: 15,
+        }
+    }
+
+    fn write(&mut self, reg: u16, val: u8) {
+        match reg {
+            0 => {
+                self.volume = val & 0b0000_1111;
+                self.duty = (val >> 4) & 0b0111;
+                self.ignore_duty = val & 0b1000_0000 != 0;
+            }
+            1 => self.divider.write_low(val),
+            _ => {
+                if self.divider.write_high(val) {
+                    self.step = 15;
+                }
+            }
+        }
+    }
+
+    fn clock(&mut self) {
+        self.step = if self.step == 0 { 15 } else { self.step - 1 };
+    }
+
+    fn output(&self) -> u8 {
+        if self.divider.enabled && (self.ignore_duty || self.step <= self.duty) {
+            self.volume
+        } else {
+            0
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Sawtooth {
+    divider: Divider,
+    rate: u8,
+    accumulator: u8,
+    step: u8,
+}
+
+impl Sawtooth {
+    fn new() -> Sawtooth {
+        Sawtooth {
+            divider: Divider::new(),
+            rate: 0,
+            accumulator: 0,
+            step: 0,
+        }
+    }
+
+    fn write(&mut self, reg: u16, val: u8) {
+        match reg {
+            0 => self.rate = val & 0b0011_1111,
+            1 => self.divider.write_low(val),
+            _ => {
+                if self.divider.write_high(val) {
+                    self.accumulator = 0;
+                    self.step = 0;
+                }
+            }
+        }
+    }
+
+    /// The rate is added on every second clock, and the accumulator is reset
+    /// on the fourteenth.
+    fn clock(&mut self) {
+        self.step += 1;
+        if self.step == 14 {
+            self.step = 0;
+            self.accumulator = 0;
+        } else if self.step % 2 == 0 {
+            self.accumulator = self.accumulator.wrapping_add(self.rate);
+        }
+    }
+
+    fn output(&self) -> u8 {
+        self.accumulator >> 3
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Audio {
+    pulse1: Pulse,
+    pulse2: Pulse,
+    saw: Sawtooth,
+    halted: bool,
+    /// How far right the channels' periods are shifted, which speeds them
+    /// all up.
+    shift: u8,
+}
+
+impl Audio {
+    fn new() -> Audio {
+        Audio {
+            pulse1: Pulse::new(),
+            pulse2: Pulse::new(),
+            saw: Sawtooth::new(),
+            halted: false,
+            shift: 0,
+        }
+    }
+
+    fn write_frequency_control(&mut self, val: u8) {
+        self.halted = val & 0b001 != 0;
+        self.shift = if val & 0b010 != 0 {
+            4
+        } else if val & 0b100 != 0 {
+            8
+        } else {
+            0
+        };
+    }
+
+    /// Sets the levels of the two pulses and the saw, in that order.
+    fn set_levels(&self, out: &mut [Waveform], cycle: u32) {
+        let outputs = [self.pulse1.output(), self.pulse2.output(), self.saw.output()];
+        for (out, &output) in out.iter_mut().zip(outputs.iter()) {
+            out.set_level(output as f32 * LEVEL_SCALE, cycle);
+        }
+    }
+
+    fn play(&mut self, from_cyc: u32, to_cyc: u32, out: &mut [Waveform]) {
+        self.set_levels(out, from_cyc);
+        if self.halted {
+            return;
+        }
+
+        let mut cycle = from_cyc;
+        while cycle < to_cyc {
+            let mut step = to_cyc - cycle;
+            for remaining in &[
+                self.pulse1.divider.remaining(),
+                self.pulse2.divider.remaining(),
+                self.saw.divider.remaining(),
+            ] {
+                if let Some(remaining) = *remaining {
+                    step = cmp::min(step, remaining);
+                }
+            }
+            cycle += step;
+
+            let shift = self.shift;
+            if self.pulse1.divider.run(step, shift) {
+                self.pulse1.clock();
+            }
+            if self.pulse2.divider.run(step, shift) {
+                self.pulse2.clock();
+            }
+            if self.saw.divider.run(step, shift) {
+                self.saw.clock();
+            }
+            self.set_levels(out, cycle);
+        }
+    }
+}
+
+struct Vrc6 {
+    regs: Regs,
+    irq: Irq,
+    audio: Audio,
+    /// Mapper 26 swaps the two low address lines.
+    swap_lines: bool,
+
+    prg_rom: MappingTable,
+    chr_rom: Box<[u8]>,
+    chr_ram: Box<[u8]>,
+    prg_ram: Box<RamSegment>,
+
+    /// The 1KB CHR bank in each 1KB window of the pattern tables.
+    chr_mapping: [usize; 8],
+    mirroring: &'static [u16; 4],
+}
+
+struct Vrc6State {
+    regs: Regs,
+    irq: Irq,
+    audio: Audio,
+    chr_ram: Box<[u8]>,
+    prg_ram: Vec<u8>,
+}
+
+pub fn new(params: MapperParams, swap_lines: bool) -> Box<Mapper> {
+    let chr_ram = if params.chr_rom.is_empty() {
+        vec![0u8; 0x2000].into_boxed_slice()
+    } else {
+        vec![0u8; 0].into_boxed_slice()
+    };
+
+    let prg_ram: Box<RamSegment> = if params.has_battery_backed_ram {
+        Box::new(
+            BatteryBackedRam::new(params.rom_path, params.prg_ram_size as u32).unwrap(),
+        )
+    } else {
+        Box::new(VolatileRam::new(params.prg_ram_size as usize))
+    };
+
+    let mut mapper = Vrc6 {
+        regs: Regs {
+            prg_16k: 0,
+            prg_8k: 0,
+            chr: [0; 8],
+            banking: 0,
+        },
+        irq: Irq::new(),
+        audio: Audio::new(),
+        swap_lines: swap_lines,
+
+        prg_rom: MappingTable::new(params.prg_rom, 2),
+        chr_rom: params.chr_rom.into_boxed_slice(),
+        chr_ram: chr_ram,
+        prg_ram: prg_ram,
+
+        chr_mapping: [0; 8],
+        mirroring: super::standard_mapping_tables(ScreenMode::Vertical),
+    };
+    mapper.update_mapping();
+
+    Box::new(mapper)
+}
+
+impl Vrc6 {
+    /// Decodes the register an address selects, undoing mapper 26's swapped
+    /// address lines.
+    fn register(&self, idx: u16) -> u16 {
+        let reg = idx & 0xF003;
+        if self.swap_lines {
+            (reg & 0xF000) | ((reg & 0b01) << 1) | ((reg & 0b10) >> 1)
+        } else {
+            reg
+        }
+    }
+
+    fn update_mapping(&mut self) {
+        let bank_count = self.prg_rom.bank_count();
+        let prg_16k = (self.regs.prg_16k as usize & 0x0F) * 4 % bank_count;
+        let prg_8k = (self.regs.prg_8k as usize & 0x1F) * 2 % bank_count;
+        self.prg_rom.map_pages_linear(0..4, prg_16k);
+        self.prg_rom.map_pages_linear(4..6, prg_8k);
+        self.prg_rom.map_pages_linear(6..8, bank_count - 2);
+
+        let chr = self.regs.chr;
+        // In the 2KB modes the low bit of the bank comes from the PPU's A10.
+        let two_kb = |reg: u8, half: usize| (reg as usize & !1) | half;
+        for window in 0..8 {
+            self.chr_mapping[window] = match (self.regs.chr_mode(), window) {
+                (0, _) => chr[window] as usize,
+                (1, _) => two_kb(chr[window / 2], window % 2),
+                (_, 0...3) => chr[window] as usize,
+                (_, _) => two_kb(chr[4 + (window - 4) / 2], window % 2),
+            };
+        }
+
+        self.mirroring = super::standard_mapping_tables(self.regs.mirroring());
+    }
+
+    fn chr_addr(&self, idx: u16) -> usize {
+        let bank = self.chr_mapping[(idx >> 10) as usize & 0x07];
+        bank * 0x400 + (idx & 0x3FF) as usize
+    }
+}
+
+impl Mapper for Vrc6 {
+    fn prg_rom_read(&mut self, idx: u16) -> &RomBank {
+        self.prg_rom.get_bank(idx)
+    }
+
+    fn prg_rom_write(&mut self, idx: u16, val: u8) -> &mut RomBank {
+        let reg = self.register(idx);
+        match reg {
+            0x8000...0x8003 => self.regs.prg_16k = val,
+            0x9000...0x9002 => self.audio.pulse1.write(reg & 0x03, val),
+            0x9003 => self.audio.write_frequency_control(val),
+            0xA000...0xA002 => self.audio.pulse2.write(reg & 0x03, val),
+            0xB000...0xB002 => self.audio.saw.write(reg & 0x03, val),
+            0xB003 => self.regs.banking = val,
+            0xC000...0xC003 => self.regs.prg_8k = val,
+            0xD000...0xD003 => self.regs.chr[(reg & 0x03) as usize] = val,
+            0xE000...0xE003 => self.regs.chr[4 + (reg & 0x03) as usize] = val,
+            0xF000 => self.irq.latch = val,
+            0xF001 => self.irq.write_control(val),
+            0xF002 => self.irq.acknowledge(),
+            _ => (),
+        }
+        self.update_mapping();
+        self.prg_rom.get_bank_mut(idx)
+    }
+
+    fn prg_rom_address(&self, idx: u16) -> RomAddress {
+        self.prg_rom.get_rom_address(idx)
+    }
+
+    fn prg_ram_read(&mut self, idx: u16) -> u8 {
+        if self.regs.prg_ram_enabled() {
+            self.prg_ram.read(idx - 0x6000)
+        } else {
+            0
+        }
+    }
+
+    fn prg_ram_write(&mut self, idx: u16, val: u8) {
+        if self.regs.prg_ram_enabled() {
+            self.prg_ram.write(idx - 0x6000, val);
+        }
+    }
+
+    fn chr_read(&mut self, idx: u16) -> u8 {
+        if self.chr_rom.is_empty() {
+            self.chr_ram[idx as usize % 0x2000]
+        } else {
+            let addr = self.chr_addr(idx) % self.chr_rom.len();
+            self.chr_rom[addr]
+        }
+    }
+
+    fn chr_write(&mut self, idx: u16, val: u8) {
+        if self.chr_rom.is_empty() {
+            self.chr_ram[idx as usize % 0x2000] = val;
+        }
+    }
+
+    fn get_mirroring_table(&self) -> &[u16; 4] {
+        self.mirroring
+    }
+
+    fn run_to(&mut self, cycle: u64) -> bool {
+        self.irq.run_to(cycle)
+    }
+
+    fn requested_run_cycle(&self) -> u64 {
+        self.irq.next_irq_cycle()
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq.pending
+    }
+
+    fn audio_channels(&self) -> usize {
+        3
+    }
+
+    fn play_audio(&mut self, from_cyc: u32, to_cyc: u32, out: &mut [Waveform]) {
+        self.audio.play(from_cyc, to_cyc, out);
+    }
+
+    fn save_state(&self) -> Box<Any> {
+        Box::new(Vrc6State {
+            regs: self.regs.clone(),
+            irq: self.irq.clone(),
+            audio: self.audio.clone(),
+            chr_ram: self.chr_ram.clone(),
+            prg_ram: self.prg_ram.contents().to_vec(),
+        })
+    }
+
+    fn load_state(&mut self, state: &Any) {
+        let state = state.downcast_ref::<Vrc6State>().unwrap();
+        self.regs = state.regs.clone();
+        self.irq = state.irq.clone();
+        self.audio = state.audio.clone();
+        self.chr_ram.copy_from_slice(&state.chr_ram);
+        self.prg_ram.restore(&state.prg_ram);
+        self.update_mapping();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mappers::{Mapper, MapperParams};
+
+    /// PRG ROM where each 8KB bank is filled with its own number.
+    fn create_test_mapper(swap_lines: bool) -> Box<Mapper> {
+        let path_buf = ::std::path::PathBuf::new();
+        let path = path_buf.as_path();
+        let prg_rom = (0..16).flat_map(|bank| vec![bank as u8; 0x2000]).collect();
+        new(MapperParams::simple(path, prg_rom, vec![0u8; 0x2000]), swap_lines)
+    }
+
+    #[test]
+    fn switches_prg_banks() {
+        let mut mapper = create_test_mapper(false);
+        mapper.prg_rom_write(0x8000, 3);
+        mapper.prg_rom_write(0xC000, 9);
+        assert_eq!(mapper.prg_rom_read(0x8000).read(0x8000), 6);
+        assert_eq!(mapper.prg_rom_read(0xA000).read(0xA000), 7);
+        assert_eq!(mapper.prg_rom_read(0xC000).read(0xC000), 9);
+        assert_eq!(mapper.prg_rom_read(0xE000).read(0xE000), 15);
+    }
+
+    #[test]
+    fn mapper_26_swaps_address_lines() {
+        let mut mapper = create_test_mapper(true);
+        // $F001 on mapper 26 is the IRQ acknowledge, so nothing is enabled.
+        mapper.prg_rom_write(0xF000, 0xFE);
+        mapper.prg_rom_write(0xF001, 0b110);
+        assert_eq!(mapper.requested_run_cycle(), ::std::u64::MAX);
+
+        mapper.prg_rom_write(0xF002, 0b110);
+        assert_eq!(mapper.requested_run_cycle(), 2);
+    }
+
+    #[test]
+    fn cycle_mode_irq_fires_when_the_counter_overflows() {
+        let mut mapper = create_test_mapper(false);
+        mapper.prg_rom_write(0xF000, 0xF0);
+        mapper.prg_rom_write(0xF001, 0b111);
+        assert_eq!(mapper.requested_run_cycle(), 16);
+        assert!(!mapper.run_to(15));
+        assert!(mapper.run_to(16));
+        // Reloaded from the latch
+        assert_eq!(mapper.requested_run_cycle(), 32);
+        // The line is held until the IRQ is acknowledged
+        assert!(mapper.irq_pending());
+        mapper.prg_rom_write(0xF002, 0);
+        assert!(!mapper.irq_pending());
+    }
+
+    #[test]
+    fn scanline_mode_irq_counts_every_341_dots() {
+        let mut mapper = create_test_mapper(false);
+        mapper.prg_rom_write(0xF000, 0xFD);
+        mapper.prg_rom_write(0xF001, 0b010);
+        // Three scanlines of 341 dots, at three dots per CPU cycle
+        assert_eq!(mapper.requested_run_cycle(), 341);
+        assert!(!mapper.run_to(340));
+        assert!(mapper.run_to(341));
+    }
+
+    #[test]
+    fn sawtooth_resets_every_fourteen_clocks() {
+        let mut saw = Sawtooth::new();
+        saw.write(0, 42);
+        let mut outputs = vec![];
+        for _ in 0..15 {
+            saw.clock();
+            outputs.push(saw.accumulator);
+        }
+        assert_eq!(
+            outputs,
+            vec![0, 42, 42, 84, 84, 126, 126, 168, 168, 210, 210, 252, 252, 0, 0]
+        );
+    }
+}
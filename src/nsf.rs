@@ -0,0 +1,536 @@
+//! Loading of NSF and NSFe music files.
+//!
+//! An NSF file holds the code and data of a game's sound engine, along with
+//! the addresses of its init and play routines. To play one, we build a
+//! synthetic cartridge around it: the NSF mapper provides the bankswitching
+//! registers, a play timer and a small driver ROM which calls init for the
+//! chosen song, then calls play every time the timer raises an IRQ.
+
+use Region;
+use cart::{Cart, TvFormat};
+use mappers::{Mapper, NSF_DRIVER_ADDR, NSF_TIMER_REG, NsfParams};
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+
+const NSF_HEADER_SIZE: usize = 0x80;
+
+/// The play rates assumed by NSFe files without a RATE chunk.
+const DEFAULT_NTSC_SPEED: u16 = 16639;
+const DEFAULT_PAL_SPEED: u16 = 19997;
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum NsfError {
+        Io(err: io::Error) {
+            display("IO Error: {}", err)
+            description(err.description())
+            cause(err)
+            from()
+        }
+        DamagedHeader {
+            description("NSF data had missing or damaged header.")
+        }
+        UnexpectedEndOfData {
+            description("Unexpected end of data.")
+        }
+        MissingChunk(id: &'static str) {
+            display("NSFe file has no {} chunk", id)
+            description("NSFe file is missing a required chunk.")
+        }
+        UnsupportedChunk(id: String) {
+            display("NSFe file has unsupported required chunk {}", id)
+            description("NSFe file has an unsupported required chunk.")
+        }
+    }
+}
+
+bitflags! {
+    /// The expansion sound chips a tune uses.
+    pub struct ExpansionChips: u8 {
+        const VRC6 = 0b0000_0001;
+        const VRC7 = 0b0000_0010;
+        const FDS =  0b0000_0100;
+        const MMC5 = 0b0000_1000;
+        const N163 = 0b0001_0000;
+        const S5B =  0b0010_0000;
+    }
+}
+
+bitflags! {
+    struct Flags: u8 {
+        const PAL =  0b0000_0001;
+        const DUAL = 0b0000_0010;
+    }
+}
+
+pub struct Nsf {
+    pub load_addr: u16,
+    pub init_addr: u16,
+    pub play_addr: u16,
+    /// The initial values of the bankswitching registers. The tune uses
+    /// bankswitching if any of them are nonzero.
+    pub bank_init: [u8; 8],
+    pub song_count: u8,
+    /// The song to play first, counting from zero.
+    pub starting_song: u8,
+    /// The time between calls to play, in microseconds.
+    pub ntsc_speed: u16,
+    pub pal_speed: u16,
+    pub pal: bool,
+    /// True if the tune plays correctly in both regions.
+    pub dual: bool,
+    pub expansion: ExpansionChips,
+    pub title: String,
+    pub artist: String,
+    pub copyright: String,
+    /// Per-song titles and lengths in milliseconds. Only NSFe files have
+    /// these, and they may not cover every song.
+    pub track_titles: Vec<String>,
+    pub track_times: Vec<Option<u32>>,
+    pub data: Vec<u8>,
+}
+
+fn read_u16(buf: &[u8], offset: usize) -> u16 {
+    buf[offset] as u16 | (buf[offset + 1] as u16) << 8
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    read_u16(buf, offset) as u32 | (read_u16(buf, offset + 2) as u32) << 16
+}
+
+/// Reads a string which is either null-terminated or fills the slice.
+fn read_str(buf: &[u8]) -> String {
+    let end = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..end]).into_owned()
+}
+
+/// Splits a chunk of null-terminated strings.
+fn read_strs(buf: &[u8]) -> Vec<String> {
+    let mut strs: Vec<String> = buf.split(|&b| b == 0).map(read_str).collect();
+    if buf.last() == Some(&0) {
+        strs.pop();
+    }
+    strs
+}
+
+impl Nsf {
+    pub fn read(path: &Path) -> Result<Nsf, NsfError> {
+        let mut file = try!(File::open(path));
+        let mut buf = vec![];
+        try!(file.read_to_end(&mut buf));
+        Nsf::parse(&buf)
+    }
+
+    /// Parses either an NSF or an NSFe file.
+    pub fn parse(buf: &[u8]) -> Result<Nsf, NsfError> {
+        if buf.starts_with(b"NESM\x1A") {
+            Nsf::parse_nsf(buf)
+        } else if buf.starts_with(b"NSFE") {
+            Nsf::parse_nsfe(&buf[4..])
+        } else {
+            Err(NsfError::DamagedHeader)
+        }
+    }
+
+    fn parse_nsf(buf: &[u8]) -> Result<Nsf, NsfError> {
+        if buf.len() < NSF_HEADER_SIZE {
+            return Err(NsfError::UnexpectedEndOfData);
+        }
+        if buf[6] == 0 || buf[7] == 0 || buf[7] > buf[6] {
+            return Err(NsfError::DamagedHeader);
+        }
+        let flags = Flags::from_bits_truncate(buf[0x7A]);
+        let mut bank_init = [0u8; 8];
+        bank_init.copy_from_slice(&buf[0x70..0x78]);
+        Ok(Nsf {
+            load_addr: read_u16(buf, 0x08),
+            init_addr: read_u16(buf, 0x0A),
+            play_addr: read_u16(buf, 0x0C),
+            bank_init: bank_init,
+            song_count: buf[6],
+            starting_song: buf[7] - 1,
+            ntsc_speed: read_u16(buf, 0x6E),
+            pal_speed: read_u16(buf, 0x78),
+            pal: flags.contains(PAL),
+            dual: flags.contains(DUAL),
+            expansion: ExpansionChips::from_bits_truncate(buf[0x7B]),
+            title: read_str(&buf[0x0E..0x2E]),
+            artist: read_str(&buf[0x2E..0x4E]),
+            copyright: read_str(&buf[0x4E..0x6E]),
+            track_titles: vec![],
+            track_times: vec![],
+            data: buf[NSF_HEADER_SIZE..].to_vec(),
+        })
+    }
+
+    fn parse_nsfe(mut buf: &[u8]) -> Result<Nsf, NsfError> {
+        let mut nsf = Nsf {
+            load_addr: 0,
+            init_addr: 0,
+            play_addr: 0,
+            bank_init: [0; 8],
+            song_count: 1,
+            starting_song: 0,
+            ntsc_speed: DEFAULT_NTSC_SPEED,
+            pal_speed: DEFAULT_PAL_SPEED,
+            pal: false,
+            dual: false,
+            expansion: ExpansionChips::empty(),
+            title: String::new(),
+            artist: String::new(),
+            copyright: String::new(),
+            track_titles: vec![],
+            track_times: vec![],
+            data: vec![],
+        };
+        let (mut has_info, mut has_data) = (false, false);
+        loop {
+            if buf.len() < 8 {
+                return Err(NsfError::UnexpectedEndOfData);
+            }
+            let len = read_u32(buf, 0) as usize;
+            let id = [buf[4], buf[5], buf[6], buf[7]];
+            if buf.len() - 8 < len {
+                return Err(NsfError::UnexpectedEndOfData);
+            }
+            let chunk = &buf[8..8 + len];
+            match &id {
+                b"INFO" => {
+                    if chunk.len() < 8 {
+                        return Err(NsfError::DamagedHeader);
+                    }
+                    nsf.load_addr = read_u16(chunk, 0);
+                    nsf.init_addr = read_u16(chunk, 2);
+                    nsf.play_addr = read_u16(chunk, 4);
+                    let flags = Flags::from_bits_truncate(chunk[6]);
+                    nsf.pal = flags.contains(PAL);
+                    nsf.dual = flags.contains(DUAL);
+                    nsf.expansion = ExpansionChips::from_bits_truncate(chunk[7]);
+                    if chunk.len() > 8 {
+                        nsf.song_count = chunk[8];
+                    }
+                    if chunk.len() > 9 {
+                        nsf.starting_song = chunk[9];
+                    }
+                    has_info = true;
+                }
+                b"DATA" => {
+                    nsf.data = chunk.to_vec();
+                    has_data = true;
+                }
+                b"BANK" => {
+                    let len = ::std::cmp::min(8, chunk.len());
+                    nsf.bank_init[..len].copy_from_slice(&chunk[..len]);
+                }
+                b"RATE" => {
+                    if chunk.len() >= 2 {
+                        nsf.ntsc_speed = read_u16(chunk, 0);
+                    }
+                    if chunk.len() >= 4 {
+                        nsf.pal_speed = read_u16(chunk, 2);
+                    }
+                }
+                b"auth" => {
+                    let mut strs = read_strs(chunk).into_iter();
+                    nsf.title = strs.next().unwrap_or_default();
+                    nsf.artist = strs.next().unwrap_or_default();
+                    nsf.copyright = strs.next().unwrap_or_default();
+                }
+                b"tlbl" => nsf.track_titles = read_strs(chunk),
+                b"time" => {
+                    nsf.track_times = chunk
+                        .chunks(4)
+                        .filter(|time| time.len() == 4)
+                        .map(|time| read_u32(time, 0) as i32)
+                        .map(|ms| if ms < 0 { None } else { Some(ms as u32) })
+                        .collect();
+                }
+                b"NEND" => break,
+                // Chunks with an uppercase ID must be understood to play the
+                // file; the rest are optional.
+                _ if id[0] >= b'A' && id[0] <= b'Z' => {
+                    return Err(NsfError::UnsupportedChunk(
+                        String::from_utf8_lossy(&id).into_owned(),
+                    ))
+                }
+                _ => (),
+            }
+            buf = &buf[8 + len..];
+        }
+        if !has_info {
+            return Err(NsfError::MissingChunk("INFO"));
+        }
+        if !has_data {
+            return Err(NsfError::MissingChunk("DATA"));
+        }
+        if nsf.song_count == 0 || nsf.starting_song >= nsf.song_count {
+            return Err(NsfError::DamagedHeader);
+        }
+        Ok(nsf)
+    }
+
+    pub fn is_bankswitched(&self) -> bool {
+        self.bank_init.iter().any(|&bank| bank != 0)
+    }
+
+    /// The region the tune was written for.
+    pub fn default_region(&self) -> Region {
+        if self.pal && !self.dual {
+            Region::PAL
+        } else {
+            Region::NTSC
+        }
+    }
+
+    pub fn track_title(&self, song: u8) -> Option<&str> {
+        match self.track_titles.get(song as usize) {
+            Some(title) if !title.is_empty() => Some(title),
+            _ => None,
+        }
+    }
+
+    /// The length of a song in milliseconds, if the file says.
+    pub fn track_time(&self, song: u8) -> Option<u32> {
+        self.track_times.get(song as usize).cloned().unwrap_or(None)
+    }
+
+    /// The time between calls to the play routine in the given region, in
+    /// microseconds. Rips made for one region often leave the other's speed
+    /// as zero, so that falls back to the usual rate.
+    pub fn play_speed(&self, region: Region) -> u16 {
+        match region {
+            Region::NTSC if self.ntsc_speed == 0 => DEFAULT_NTSC_SPEED,
+            Region::NTSC => self.ntsc_speed,
+            Region::PAL | Region::Dendy if self.pal_speed == 0 => DEFAULT_PAL_SPEED,
+            Region::PAL | Region::Dendy => self.pal_speed,
+        }
+    }
+
+    /// Builds a cartridge which plays the given song (counting from zero).
+    pub fn cart(&self, song: u8, region: Region) -> Cart {
+        let (prg_rom, initial_banks) = if self.is_bankswitched() {
+            // The data is loaded at the offset of the load address within
+            // its 4KB bank.
+            let mut prg_rom = vec![0u8; (self.load_addr & 0x0FFF) as usize];
+            prg_rom.extend_from_slice(&self.data);
+            (prg_rom, self.bank_init)
+        } else {
+            let mut prg_rom = vec![0u8; self.load_addr.saturating_sub(0x8000) as usize];
+            prg_rom.extend_from_slice(&self.data);
+            prg_rom.truncate(0x8000);
+            (prg_rom, [0, 1, 2, 3, 4, 5, 6, 7])
+        };
+
+        let play_speed = self.play_speed(region);
+        let region_id = match region {
+            Region::NTSC => 0,
+            Region::PAL | Region::Dendy => 1,
+        };
+        let driver = Driver::new(song, region_id, self.init_addr, self.play_addr);
+
+        let mapper = Mapper::nsf(NsfParams {
+            prg_rom: prg_rom,
+            initial_banks: initial_banks,
+            vectors: [driver.nmi, driver.reset, driver.irq],
+            driver: driver.code,
+            play_period_us: play_speed as u64,
+            cpu_clock_rate: region.cpu_clock_rate(),
+        });
+        let mut cart = Cart::new(mapper);
+        cart.tv = match region {
+            Region::NTSC => TvFormat::NTSC,
+            Region::PAL | Region::Dendy => TvFormat::PAL,
+        };
+        cart
+    }
+}
+
+/// The 6502 code which runs the tune, assembled for `NSF_DRIVER_ADDR`.
+struct Driver {
+    code: Vec<u8>,
+    nmi: u16,
+    reset: u16,
+    irq: u16,
+}
+
+impl Driver {
+    #[cfg_attr(rustfmt, rustfmt_skip)]
+    fn new(song: u8, region: u8, init: u16, play: u16) -> Driver {
+        let mut code: Vec<u8> = vec![];
+        let addr = |code: &Vec<u8>| NSF_DRIVER_ADDR + code.len() as u16;
+
+        // Reset: set up the stack, silence the APU and call init
+        let reset = addr(&code);
+        code.extend_from_slice(&[
+            0x78,                   // SEI
+            0xD8,                   // CLD
+            0xA2, 0xFF,             // LDX #$FF
+            0x9A,                   // TXS
+            0xE8,                   // INX
+            0xA9, 0x00,             // LDA #$00
+            0x9D, 0x00, 0x40,       // STA $4000,X
+            0xE8,                   // INX
+            0xE0, 0x14,             // CPX #$14
+            0xD0, 0xF8,             // BNE -8
+            0xA9, 0x0F,             // LDA #$0F
+            0x8D, 0x15, 0x40,       // STA $4015
+            0xA9, 0x40,             // LDA #$40
+            0x8D, 0x17, 0x40,       // STA $4017
+            0xA9, song,             // LDA #song
+            0xA2, region,           // LDX #region
+            0x20, init as u8, (init >> 8) as u8,
+                                    // JSR init
+            0x8D, NSF_TIMER_REG as u8, (NSF_TIMER_REG >> 8) as u8,
+                                    // STA timer
+            0x58,                   // CLI
+        ]);
+        // Wait for the timer
+        let idle = addr(&code);
+        code.extend_from_slice(&[0x4C, idle as u8, (idle >> 8) as u8]); // JMP idle
+
+        // IRQ: call play
+        let irq = addr(&code);
+        code.extend_from_slice(&[
+            0x20, play as u8, (play >> 8) as u8,
+                                    // JSR play
+            0x40,                   // RTI
+        ]);
+
+        let nmi = addr(&code);
+        code.push(0x40); // RTI
+
+        Driver {
+            code: code,
+            nmi: nmi,
+            reset: reset,
+            irq: irq,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nsf_header() -> Vec<u8> {
+        let mut buf = vec![0u8; NSF_HEADER_SIZE];
+        buf[..5].copy_from_slice(b"NESM\x1A");
+        buf[5] = 1;
+        buf[6] = 12;
+        buf[7] = 3;
+        buf[0x08..0x0E].copy_from_slice(&[0x00, 0x80, 0x03, 0x80, 0x06, 0x80]);
+        buf[0x0E..0x13].copy_from_slice(b"Title");
+        buf[0x6E..0x70].copy_from_slice(&[0xFF, 0x40]);
+        buf[0x73] = 2;
+        buf[0x7A] = 0b10;
+        buf[0x7B] = 0b1_0001;
+        buf.extend_from_slice(&[0xEA; 16]);
+        buf
+    }
+
+    fn chunk(id: &[u8], data: &[u8]) -> Vec<u8> {
+        let len = data.len() as u32;
+        let mut buf = vec![len as u8, (len >> 8) as u8, (len >> 16) as u8, (len >> 24) as u8];
+        buf.extend_from_slice(id);
+        buf.extend_from_slice(data);
+        buf
+    }
+
+    #[test]
+    fn parses_nsf_header() {
+        let nsf = Nsf::parse(&nsf_header()).unwrap();
+        assert_eq!((0x8000, 0x8003, 0x8006), (nsf.load_addr, nsf.init_addr, nsf.play_addr));
+        assert_eq!((12, 2), (nsf.song_count, nsf.starting_song));
+        assert_eq!(16639, nsf.ntsc_speed);
+        assert!(nsf.is_bankswitched());
+        assert!(!nsf.pal && nsf.dual);
+        assert_eq!(VRC6 | N163, nsf.expansion);
+        assert_eq!("Title", nsf.title);
+        assert_eq!(16, nsf.data.len());
+    }
+
+    #[test]
+    fn zero_play_speed_falls_back_to_the_default() {
+        let nsf = Nsf::parse(&nsf_header()).unwrap();
+        assert_eq!(0, nsf.pal_speed);
+        assert_eq!(DEFAULT_PAL_SPEED, nsf.play_speed(Region::PAL));
+        assert_eq!(DEFAULT_PAL_SPEED, nsf.play_speed(Region::Dendy));
+        assert_eq!(16639, nsf.play_speed(Region::NTSC));
+
+        let mut buf = nsf_header();
+        buf[0x6E..0x70].copy_from_slice(&[0, 0]);
+        buf[0x78..0x7A].copy_from_slice(&[0x20, 0x4E]);
+        let nsf = Nsf::parse(&buf).unwrap();
+        assert_eq!(DEFAULT_NTSC_SPEED, nsf.play_speed(Region::NTSC));
+        assert_eq!(20000, nsf.play_speed(Region::PAL));
+    }
+
+    #[test]
+    fn rejects_bad_header() {
+        let mut buf = nsf_header();
+        buf[7] = 13;
+        assert!(Nsf::parse(&buf).is_err());
+        assert!(Nsf::parse(b"NES\x1A").is_err());
+        assert!(Nsf::parse(&nsf_header()[..0x40]).is_err());
+    }
+
+    #[test]
+    fn parses_nsfe_chunks() {
+        let mut buf = b"NSFE".to_vec();
+        buf.extend(chunk(b"INFO", &[0x00, 0x80, 0x03, 0x80, 0x06, 0x80, 0x01, 0x20, 3, 1]));
+        buf.extend(chunk(b"DATA", &[0xEA; 4]));
+        buf.extend(chunk(b"auth", b"Game\0Artist\0"));
+        buf.extend(chunk(b"tlbl", b"One\0\0Three\0"));
+        buf.extend(chunk(b"time", &[0x10, 0x27, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF]));
+        buf.extend(chunk(b"psfx", &[0]));
+        buf.extend(chunk(b"NEND", &[]));
+
+        let nsf = Nsf::parse(&buf).unwrap();
+        assert_eq!(0x8006, nsf.play_addr);
+        assert_eq!((3, 1), (nsf.song_count, nsf.starting_song));
+        assert_eq!(Region::PAL, nsf.default_region());
+        assert_eq!(S5B, nsf.expansion);
+        assert_eq!(DEFAULT_NTSC_SPEED, nsf.ntsc_speed);
+        assert_eq!(("Game", "Artist", ""), (&nsf.title[..], &nsf.artist[..], &nsf.copyright[..]));
+        assert_eq!(Some("One"), nsf.track_title(0));
+        assert_eq!(None, nsf.track_title(1));
+        assert_eq!(Some("Three"), nsf.track_title(2));
+        assert_eq!(Some(10000), nsf.track_time(0));
+        assert_eq!(None, nsf.track_time(1));
+        assert_eq!(None, nsf.track_time(2));
+        assert_eq!(4, nsf.data.len());
+    }
+
+    #[test]
+    fn nsfe_requires_known_chunks() {
+        let mut buf = b"NSFE".to_vec();
+        buf.extend(chunk(b"INFO", &[0x00, 0x80, 0x03, 0x80, 0x06, 0x80, 0, 0]));
+        buf.extend(chunk(b"NEND", &[]));
+        match Nsf::parse(&buf) {
+            Err(NsfError::MissingChunk("DATA")) => (),
+            _ => panic!("Expected missing DATA chunk"),
+        }
+
+        let mut buf = b"NSFE".to_vec();
+        buf.extend(chunk(b"INFO", &[0x00, 0x80, 0x03, 0x80, 0x06, 0x80, 0, 0]));
+        buf.extend(chunk(b"DATA", &[0xEA]));
+        buf.extend(chunk(b"XTRA", &[]));
+        buf.extend(chunk(b"NEND", &[]));
+        assert!(Nsf::parse(&buf).is_err());
+    }
+
+    #[test]
+    fn driver_calls_init_then_play() {
+        let driver = Driver::new(5, 1, 0x8003, 0x8006);
+        assert_eq!(NSF_DRIVER_ADDR, driver.reset);
+        let code = &driver.code;
+        let at = |addr: u16| (addr - NSF_DRIVER_ADDR) as usize;
+        assert!(code.windows(3).any(|op| op == [0x20, 0x03, 0x80]));
+        assert!(code.windows(2).any(|op| op == [0xA9, 5]));
+        assert_eq!(&[0x20, 0x06, 0x80, 0x40], &code[at(driver.irq)..at(driver.irq) + 4]);
+        assert_eq!(0x40, code[at(driver.nmi)]);
+        assert!(code.len() < 0xF0);
+    }
+}
@@ -0,0 +1,362 @@
+//! Renders the PPU's memory into images for debugging - the nametables,
+//! pattern tables, sprites and palette.
+//!
+//! These read video memory through the mapper like the PPU does, so mappers
+//! which watch CHR reads may see them.
+
+use super::{Color, PPU, SCREEN_HEIGHT, SCREEN_WIDTH};
+use memory::MemSegment;
+use screen::{Palette, RgbImage};
+
+/// Size of the image of all four nametables.
+pub const NAMETABLES_WIDTH: usize = SCREEN_WIDTH * 2;
+pub const NAMETABLES_HEIGHT: usize = SCREEN_HEIGHT * 2;
+
+/// Size of the image of both pattern tables, side by side.
+pub const PATTERN_TABLES_WIDTH: usize = 256;
+pub const PATTERN_TABLES_HEIGHT: usize = 128;
+
+/// Size of the image of all 64 sprites, in eight rows of eight. Each sprite
+/// gets an 8x16 cell so 8x16 sprites fit.
+pub const SPRITES_WIDTH: usize = 8 * 8;
+pub const SPRITES_HEIGHT: usize = 8 * 16;
+
+/// Size of the image of the palette - a row of 16 swatches each for the
+/// background and sprite palettes.
+pub const PALETTE_WIDTH: usize = 16 * PALETTE_SWATCH_SIZE;
+pub const PALETTE_HEIGHT: usize = 2 * PALETTE_SWATCH_SIZE;
+const PALETTE_SWATCH_SIZE: usize = 16;
+
+/// One entry of OAM, decoded.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SpriteInfo {
+    pub index: usize,
+    pub x: u8,
+    /// The Y coordinate as stored in OAM. Sprites are drawn one line lower.
+    pub y: u8,
+    pub tile: u8,
+    /// The sprite palette, 0-3.
+    pub palette: u8,
+    pub behind_background: bool,
+    pub flip_horizontal: bool,
+    pub flip_vertical: bool,
+    /// 8 or 16.
+    pub height: u8,
+    /// Address of the top tile in the pattern tables.
+    pub pattern_addr: u16,
+}
+
+impl SpriteInfo {
+    fn decode(index: usize, bytes: [u8; 4], tall: bool, sprite_table: u16) -> SpriteInfo {
+        let tile = bytes[1];
+        let attr = bytes[2];
+        let pattern_addr = if tall {
+            (tile as u16 & 0x01) << 12 | (tile as u16 & 0xFE) << 4
+        } else {
+            sprite_table | (tile as u16) << 4
+        };
+        SpriteInfo {
+            index: index,
+            x: bytes[3],
+            y: bytes[0],
+            tile: tile,
+            palette: attr & 0x03,
+            behind_background: attr & 0b0010_0000 != 0,
+            flip_horizontal: attr & 0b0100_0000 != 0,
+            flip_vertical: attr & 0b1000_0000 != 0,
+            height: if tall { 16 } else { 8 },
+            pattern_addr: pattern_addr,
+        }
+    }
+}
+
+impl PPU {
+    /// Draws all four nametables with the current mirroring, the way the
+    /// background would be drawn if scrolled to them. The area visible with
+    /// the scroll position in `t` is outlined by inverting its edges.
+    pub fn render_nametables(&mut self, palette: &Palette) -> RgbImage {
+        let mut image = RgbImage::new(NAMETABLES_WIDTH, NAMETABLES_HEIGHT);
+        let pattern_table = self.reg.ppuctrl.background_table();
+        for table in 0..4u16 {
+            let base = 0x2000 + table * 0x400;
+            let origin_x = (table as usize % 2) * SCREEN_WIDTH;
+            let origin_y = (table as usize / 2) * SCREEN_HEIGHT;
+            for tile_y in 0..30u16 {
+                for tile_x in 0..32u16 {
+                    let tile = self.ppu_mem.read(base + tile_y * 32 + tile_x);
+                    let attr_addr = base + 0x3C0 + (tile_y / 4) * 8 + tile_x / 4;
+                    let shift = ((tile_y & 0x02) << 1) | (tile_x & 0x02);
+                    let palette_id = (self.ppu_mem.read(attr_addr) >> shift) & 0x03;
+                    let colors = self.palette_colors(palette_id, palette);
+                    self.draw_tile(
+                        &mut image,
+                        origin_x + tile_x as usize * 8,
+                        origin_y + tile_y as usize * 8,
+                        pattern_table | (tile as u16) << 4,
+                        &colors,
+                        false,
+                    );
+                }
+            }
+        }
+        self.outline_scroll(&mut image);
+        image
+    }
+
+    fn outline_scroll(&self, image: &mut RgbImage) {
+        let t = self.reg.t;
+        let scroll_x = ((t >> 10) & 0x01) as usize * SCREEN_WIDTH + (t & 0x1F) as usize * 8 +
+            self.reg.x as usize;
+        let scroll_y = ((t >> 11) & 0x01) as usize * SCREEN_HEIGHT +
+            ((t >> 5) & 0x1F) as usize * 8 + ((t >> 12) & 0x07) as usize;
+
+        let mut invert = |x: usize, y: usize| {
+            let x = (scroll_x + x) % NAMETABLES_WIDTH;
+            let y = (scroll_y + y) % NAMETABLES_HEIGHT;
+            let rgb = image.pixel(x, y);
+            image.set_pixel(x, y, [!rgb[0], !rgb[1], !rgb[2]]);
+        };
+        for x in 0..SCREEN_WIDTH {
+            invert(x, 0);
+            invert(x, SCREEN_HEIGHT - 1);
+        }
+        for y in 1..SCREEN_HEIGHT - 1 {
+            invert(0, y);
+            invert(SCREEN_WIDTH - 1, y);
+        }
+    }
+
+    /// Draws both pattern tables side by side, using palette `palette_id`
+    /// (0-3 for the background palettes, 4-7 for the sprite palettes).
+    pub fn render_pattern_tables(&mut self, palette_id: u8, palette: &Palette) -> RgbImage {
+        let mut image = RgbImage::new(PATTERN_TABLES_WIDTH, PATTERN_TABLES_HEIGHT);
+        let colors = self.palette_colors(palette_id & 0x07, palette);
+        for tile in 0..512u16 {
+            let table = tile / 256;
+            let x = table as usize * 128 + (tile % 16) as usize * 8;
+            let y = ((tile % 256) / 16) as usize * 8;
+            self.draw_tile(&mut image, x, y, tile << 4, &colors, false);
+        }
+        image
+    }
+
+    /// Decodes every sprite in OAM.
+    pub fn sprites(&self) -> Vec<SpriteInfo> {
+        let tall = self.reg.ppuctrl.tall_sprites();
+        let sprite_table = self.reg.ppuctrl.sprite_table();
+        (0..64)
+            .map(|idx| {
+                let oam = &self.sprite_data;
+                let bytes = [
+                    oam.oam_byte(idx, 0),
+                    oam.oam_byte(idx, 1),
+                    oam.oam_byte(idx, 2),
+                    oam.oam_byte(idx, 3),
+                ];
+                SpriteInfo::decode(idx, bytes, tall, sprite_table)
+            })
+            .collect()
+    }
+
+    /// Draws all 64 sprites, in OAM order, with their palettes and flipping.
+    pub fn render_sprites(&mut self, palette: &Palette) -> RgbImage {
+        let mut image = RgbImage::new(SPRITES_WIDTH, SPRITES_HEIGHT);
+        for sprite in self.sprites() {
+            let x = (sprite.index % 8) * 8;
+            let y = (sprite.index / 8) * 16;
+            let colors = self.palette_colors(sprite.palette + 4, palette);
+            let tiles = sprite.height as usize / 8;
+            for half in 0..tiles {
+                // Vertical flipping swaps the two tiles of 8x16 sprites, too.
+                let row = if sprite.flip_vertical {
+                    tiles - 1 - half
+                } else {
+                    half
+                };
+                let addr = sprite.pattern_addr + (half as u16) * 16;
+                self.draw_tile_flipped(
+                    &mut image,
+                    x,
+                    y + row * 8,
+                    addr,
+                    &colors,
+                    sprite.flip_horizontal,
+                    sprite.flip_vertical,
+                    true,
+                );
+            }
+        }
+        image
+    }
+
+    /// Draws the 32 palette entries - the background palettes on the top
+    /// row, the sprite palettes on the bottom.
+    pub fn render_palette(&mut self, palette: &Palette) -> RgbImage {
+        let mut image = RgbImage::new(PALETTE_WIDTH, PALETTE_HEIGHT);
+        for entry in 0..32u16 {
+            let color = self.ppu_mem.read(0x3F00 | entry);
+            let x = (entry % 16) as usize * PALETTE_SWATCH_SIZE;
+            let y = (entry / 16) as usize * PALETTE_SWATCH_SIZE;
+            let rgb = palette.rgb(Color::from_bits(color as u16));
+            image.fill_rect(x, y, PALETTE_SWATCH_SIZE, PALETTE_SWATCH_SIZE, rgb);
+        }
+        image
+    }
+
+    /// The RGB colors of one of the eight palettes. Color 0 is always the
+    /// backdrop color.
+    fn palette_colors(&mut self, palette_id: u8, palette: &Palette) -> [[u8; 3]; 4] {
+        let mut colors = [[0; 3]; 4];
+        for (idx, rgb) in colors.iter_mut().enumerate() {
+            let addr = if idx == 0 {
+                0x3F00
+            } else {
+                0x3F00 | (palette_id as u16) << 2 | idx as u16
+            };
+            let color = self.ppu_mem.read(addr);
+            *rgb = palette.rgb(Color::from_bits(color as u16));
+        }
+        colors
+    }
+
+    fn draw_tile(
+        &mut self,
+        image: &mut RgbImage,
+        x: usize,
+        y: usize,
+        addr: u16,
+        colors: &[[u8; 3]; 4],
+        transparent: bool,
+    ) {
+        self.draw_tile_flipped(image, x, y, addr, colors, false, false, transparent);
+    }
+
+    /// Draws the 8x8 tile at `addr` in the pattern tables. If `transparent`
+    /// is set, pixels of color 0 are left alone.
+    #[allow(too_many_arguments)]
+    fn draw_tile_flipped(
+        &mut self,
+        image: &mut RgbImage,
+        x: usize,
+        y: usize,
+        addr: u16,
+        colors: &[[u8; 3]; 4],
+        flip_horizontal: bool,
+        flip_vertical: bool,
+        transparent: bool,
+    ) {
+        for row in 0..8 {
+            let lo = self.ppu_mem.read(addr + row);
+            let hi = self.ppu_mem.read(addr + row + 8);
+            let py = (if flip_vertical { 7 - row } else { row }) as usize;
+            for col in 0..8 {
+                let shift = 7 - col;
+                let color = ((hi >> shift) & 0x01) << 1 | ((lo >> shift) & 0x01);
+                if transparent && color == 0 {
+                    continue;
+                }
+                let px = (if flip_horizontal { 7 - col } else { col }) as usize;
+                image.set_pixel(x + px, y + py, colors[color as usize]);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ppu::tests::create_test_ppu_with_rom;
+
+    /// CHR-ROM where tile 1 is solid color 1 and tile 2 is solid color 3.
+    fn test_chr() -> Vec<u8> {
+        let mut chr = vec![0u8; 0x2000];
+        for row in 0..8 {
+            chr[0x10 + row] = 0xFF;
+            chr[0x20 + row] = 0xFF;
+            chr[0x28 + row] = 0xFF;
+        }
+        chr
+    }
+
+    fn test_palette() -> Palette {
+        let mut bytes = vec![0u8; 192];
+        for color in 0..64 {
+            bytes[color * 3] = color as u8;
+        }
+        Palette::from_bytes(&bytes).unwrap()
+    }
+
+    fn write_palette(ppu: &mut PPU, addr: u16, color: u8) {
+        ppu.ppu_mem.write(0x3F00 | addr, color);
+    }
+
+    #[test]
+    fn nametables_use_tiles_and_attributes() {
+        let mut ppu = create_test_ppu_with_rom(test_chr());
+        let palette = test_palette();
+        write_palette(&mut ppu, 0x00, 0x0F);
+        write_palette(&mut ppu, 0x05, 0x21);
+        // Tile (2, 0) in the second nametable, with palette 1
+        ppu.ppu_mem.write(0x2402, 0x01);
+        ppu.ppu_mem.write(0x27C0, 0b0000_0100);
+        // Put the scroll rectangle out of the way
+        ppu.reg.t = 0b000_10_00010_00000;
+
+        let image = ppu.render_nametables(&palette);
+        assert_eq!([0x21, 0, 0], image.pixel(256 + 16, 0));
+        assert_eq!([0x21, 0, 0], image.pixel(256 + 23, 7));
+        assert_eq!([0x0F, 0, 0], image.pixel(256 + 24, 0));
+    }
+
+    #[test]
+    fn nametables_outline_the_scroll_position() {
+        let mut ppu = create_test_ppu_with_rom(test_chr());
+        let palette = test_palette();
+        ppu.reg.t = 0b000_01_00000_00010;
+        ppu.reg.x = 3;
+
+        let image = ppu.render_nametables(&palette);
+        assert_eq!([0xFF, 0xFF, 0xFF], image.pixel(256 + 19, 0));
+        assert_eq!([0xFF, 0xFF, 0xFF], image.pixel(256 + 19, 100));
+        assert_eq!([0, 0, 0], image.pixel(256 + 20, 100));
+        // The rectangle wraps around to the first nametable
+        assert_eq!([0xFF, 0xFF, 0xFF], image.pixel(18, 239));
+    }
+
+    #[test]
+    fn tall_sprites_are_decoded_and_drawn_flipped() {
+        let mut ppu = create_test_ppu_with_rom(test_chr());
+        let palette = test_palette();
+        write_palette(&mut ppu, 0x13, 0x2A);
+        ppu.reg.ppuctrl = ::ppu::ppu_reg::PPUCtrl::new(0b0010_0000);
+        ppu.reg.oamaddr = 4;
+        for &byte in &[0x40, 0x02, 0b1000_0000, 0x10] {
+            ppu.write(0x2004, byte);
+        }
+
+        let sprite = ppu.sprites()[1];
+        assert_eq!(0x40, sprite.y);
+        assert_eq!(16, sprite.height);
+        assert_eq!(0x0020, sprite.pattern_addr);
+        assert!(sprite.flip_vertical);
+        assert!(!sprite.flip_horizontal);
+
+        // Tile 2 is drawn at the bottom of the cell, tile 3 (empty) at the top
+        let image = ppu.render_sprites(&palette);
+        assert_eq!([0, 0, 0], image.pixel(8, 0));
+        assert_eq!([0x2A, 0, 0], image.pixel(8, 8));
+        assert_eq!([0x2A, 0, 0], image.pixel(15, 15));
+    }
+
+    #[test]
+    fn palette_shows_all_entries() {
+        let mut ppu = create_test_ppu_with_rom(test_chr());
+        let palette = test_palette();
+        write_palette(&mut ppu, 0x01, 0x11);
+        write_palette(&mut ppu, 0x1F, 0x3D);
+
+        let image = ppu.render_palette(&palette);
+        assert_eq!([0x11, 0, 0], image.pixel(16, 0));
+        assert_eq!([0x3D, 0, 0], image.pixel(PALETTE_WIDTH - 1, PALETTE_HEIGHT - 1));
+    }
+}
@@ -0,0 +1,320 @@
+//! A dot-by-dot renderer which follows the PPU's real memory access pattern.
+//!
+//! The background and sprite renderers draw whole segments of scanlines at
+//! once from data fetched ahead of time. That's fast, but it can't show
+//! changes made partway through a scanline, and mappers only see some of
+//! the fetches the real PPU makes. This renderer instead runs the fetches,
+//! shift registers and sprite units every dot, with every fetch going
+//! through `PPUMemory::read`.
+
+use super::PaletteIndex;
+use super::PaletteSet;
+use super::TRANSPARENT;
+use super::background_rendering::LEFT_CLIP_WIDTH;
+use super::ppu_memory::PPUMemory;
+use super::ppu_reg::{PPUReg, S_BCK, S_BCK_L, S_SPR, S_SPR_L, SPRITE_0};
+use super::sprite_rendering::SpriteRenderer;
+use memory::MemSegment;
+
+const FLIP_VERT: u8 = 0b1000_0000;
+const FLIP_HORZ: u8 = 0b0100_0000;
+const BEHIND: u8 = 0b0010_0000;
+
+/// One of the eight sprite output units, holding a sprite for the current
+/// scanline.
+#[derive(Debug, Copy, Clone, Default)]
+struct SpriteUnit {
+    x: u8,
+    attr: u8,
+    pattern_lo: u8,
+    pattern_hi: u8,
+}
+
+impl SpriteUnit {
+    fn color_at(&self, x: usize) -> u8 {
+        let offset = x.wrapping_sub(self.x as usize);
+        if offset >= 8 {
+            return 0;
+        }
+        let shift = 7 - offset;
+        let lo = (self.pattern_lo >> shift) & 0x01;
+        let hi = (self.pattern_hi >> shift) & 0x01;
+        (hi << 1) | lo
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DotRenderer {
+    // Latches filled in by the background fetches
+    nametable: u8,
+    attribute: u8,
+    pattern_lo: u8,
+    pattern_hi: u8,
+
+    // Background shift registers. The attribute bits are expanded to a full
+    // byte per tile so they shift along with the pattern.
+    bg_pattern_lo: u16,
+    bg_pattern_hi: u16,
+    bg_attr_lo: u16,
+    bg_attr_hi: u16,
+
+    // Sprite evaluation results for the next scanline
+    secondary_oam: [u8; 32],
+    sprites_found: usize,
+    sprite_zero_found: bool,
+
+    // Sprites for the current scanline
+    units: [SpriteUnit; 8],
+    active_units: usize,
+    sprite_zero_on_line: bool,
+}
+
+impl Default for DotRenderer {
+    fn default() -> DotRenderer {
+        DotRenderer {
+            nametable: 0,
+            attribute: 0,
+            pattern_lo: 0,
+            pattern_hi: 0,
+
+            bg_pattern_lo: 0,
+            bg_pattern_hi: 0,
+            bg_attr_lo: 0,
+            bg_attr_hi: 0,
+
+            secondary_oam: [0xFF; 32],
+            sprites_found: 0,
+            sprite_zero_found: false,
+
+            units: [Default::default(); 8],
+            active_units: 0,
+            sprite_zero_on_line: false,
+        }
+    }
+}
+
+fn reverse_bits(byte: u8) -> u8 {
+    let mut result = 0;
+    for bit in 0..8 {
+        if byte & (1 << bit) != 0 {
+            result |= 0x80 >> bit;
+        }
+    }
+    result
+}
+
+impl DotRenderer {
+    /// Runs a single dot of a rendering scanline (-1 to 239) with rendering
+    /// enabled. The scroll registers must be updated separately. Returns the
+    /// pixel output on this dot, which is only meaningful on dots 1-256 of
+    /// the visible scanlines.
+    pub fn run_cycle(
+        &mut self,
+        cyc: u16,
+        sl: i16,
+        reg: &mut PPUReg,
+        mem: &mut PPUMemory,
+        oam: &SpriteRenderer,
+    ) -> PaletteIndex {
+        match cyc {
+            2...257 | 322...337 => self.shift(),
+            _ => (),
+        }
+        match cyc {
+            9 | 17 | 25 | 33 | 41 | 49 | 57 | 65 | 73 | 81 | 89 | 97 | 105 | 113 | 121 | 129 |
+            137 | 145 | 153 | 161 | 169 | 177 | 185 | 193 | 201 | 209 | 217 | 225 | 233 | 241 |
+            249 | 257 | 329 | 337 => self.reload_shifters(),
+            _ => (),
+        }
+
+        let pixel = if sl >= 0 && cyc >= 1 && cyc <= 256 {
+            self.output_pixel(cyc as usize - 1, reg)
+        } else {
+            TRANSPARENT
+        };
+
+        match cyc {
+            1...256 | 321...336 => self.fetch_background(cyc, reg, mem),
+            337 | 339 => {
+                mem.read(0x2000 | (reg.v & 0x0FFF));
+            }
+            _ => (),
+        }
+
+        match (cyc, sl) {
+            (1, -1) => {
+                self.sprites_found = 0;
+                self.sprite_zero_found = false;
+            }
+            (1, _) => {
+                // The real PPU clears secondary OAM over dots 1-64
+                for byte in self.secondary_oam.iter_mut() {
+                    *byte = 0xFF;
+                }
+                self.sprites_found = 0;
+                self.sprite_zero_found = false;
+            }
+            (65, 0...239) => self.evaluate_sprites(sl as u16, reg, oam),
+            (257...320, _) => self.fetch_sprite(cyc, sl, reg, mem),
+            _ => (),
+        }
+        pixel
+    }
+
+    fn shift(&mut self) {
+        self.bg_pattern_lo <<= 1;
+        self.bg_pattern_hi <<= 1;
+        self.bg_attr_lo <<= 1;
+        self.bg_attr_hi <<= 1;
+    }
+
+    fn reload_shifters(&mut self) {
+        self.bg_pattern_lo = (self.bg_pattern_lo & 0xFF00) | self.pattern_lo as u16;
+        self.bg_pattern_hi = (self.bg_pattern_hi & 0xFF00) | self.pattern_hi as u16;
+        let attr_lo = if self.attribute & 0x01 != 0 { 0xFF } else { 0x00 };
+        let attr_hi = if self.attribute & 0x02 != 0 { 0xFF } else { 0x00 };
+        self.bg_attr_lo = (self.bg_attr_lo & 0xFF00) | attr_lo;
+        self.bg_attr_hi = (self.bg_attr_hi & 0xFF00) | attr_hi;
+    }
+
+    #[cfg_attr(rustfmt, rustfmt_skip)]
+    fn fetch_background(&mut self, cyc: u16, reg: &PPUReg, mem: &mut PPUMemory) {
+        match cyc % 8 {
+            1 => self.nametable = mem.read(0x2000 | (reg.v & 0x0FFF)),
+            3 => {
+                let addr = 0x23C0 | (reg.v & 0x0C00) | ((reg.v >> 4) & 0x38) | ((reg.v >> 2) & 0x07);
+                let shift = ((reg.v >> 4) & 0x04) | (reg.v & 0x02);
+                self.attribute = (mem.read(addr) >> shift) & 0x03;
+            }
+            5 => self.pattern_lo = mem.read(self.background_pattern_addr(reg)),
+            7 => self.pattern_hi = mem.read(self.background_pattern_addr(reg) + 8),
+            _ => (),
+        }
+    }
+
+    fn background_pattern_addr(&self, reg: &PPUReg) -> u16 {
+        reg.ppuctrl.background_table() | (self.nametable as u16) << 4 | reg.scroll_y_fine()
+    }
+
+    fn output_pixel(&self, x: usize, reg: &mut PPUReg) -> PaletteIndex {
+        let mask = reg.ppumask;
+
+        let show_background = x >= LEFT_CLIP_WIDTH || mask.contains(S_BCK_L);
+        let background = if mask.contains(S_BCK) && show_background {
+            let shift = 15 - reg.scroll_x_fine();
+            let color = (((self.bg_pattern_hi >> shift) & 0x01) << 1) |
+                ((self.bg_pattern_lo >> shift) & 0x01);
+            let palette = (((self.bg_attr_hi >> shift) & 0x01) << 1) |
+                ((self.bg_attr_lo >> shift) & 0x01);
+            PaletteIndex::from_packed((palette << 2 | color) as u8)
+        } else {
+            TRANSPARENT
+        };
+
+        if !mask.contains(S_SPR) || (x < LEFT_CLIP_WIDTH && !mask.contains(S_SPR_L)) {
+            return background;
+        }
+        let front = self.units[..self.active_units]
+            .iter()
+            .enumerate()
+            .map(|(idx, unit)| (idx, unit, unit.color_at(x)))
+            .find(|&(_, _, color)| color != 0);
+        let (idx, unit, color) = match front {
+            Some(front) => front,
+            None => return background,
+        };
+
+        // Sprite 0 is always in the first unit, and the first opaque unit is
+        // the one in front.
+        if idx == 0 && self.sprite_zero_on_line && !background.is_transparent() && x != 255 {
+            reg.ppustat.insert(SPRITE_0);
+        }
+
+        if background.is_transparent() || unit.attr & BEHIND == 0 {
+            PaletteIndex::from_unpacked(PaletteSet::Sprite, unit.attr & 0x03, color)
+        } else {
+            background
+        }
+    }
+
+    /// Copies the first eight sprites on the line after `scanline` into
+    /// secondary OAM. The overflow flag is handled separately by
+    /// `SpriteRenderer::find_overflow`.
+    fn evaluate_sprites(&mut self, scanline: u16, reg: &PPUReg, oam: &SpriteRenderer) {
+        let sprite_height = reg.ppuctrl.sprite_height();
+        for n in 0..64 {
+            let y = oam.oam_byte(n, 0) as u16;
+            if y <= scanline && scanline < y + sprite_height {
+                let slot = self.sprites_found * 4;
+                for m in 0..4 {
+                    self.secondary_oam[slot + m] = oam.oam_byte(n, m as u16);
+                }
+                if n == 0 {
+                    self.sprite_zero_found = true;
+                }
+                self.sprites_found += 1;
+                if self.sprites_found == 8 {
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Fetches the sprites found by evaluation into the sprite units, eight
+    /// dots per sprite. Empty slots still fetch tile $FF, as the real PPU
+    /// does.
+    fn fetch_sprite(&mut self, cyc: u16, sl: i16, reg: &mut PPUReg, mem: &mut PPUMemory) {
+        reg.oamaddr = 0;
+        if cyc == 257 {
+            self.active_units = self.sprites_found;
+            self.sprite_zero_on_line = self.sprite_zero_found;
+        }
+
+        let slot = (cyc as usize - 257) / 8;
+        let entry = &self.secondary_oam[slot * 4..slot * 4 + 4];
+        let (y, tile, attr, x) = (entry[0], entry[1], entry[2], entry[3]);
+        let in_use = slot < self.sprites_found;
+
+        let row = if in_use {
+            let row = (sl as u16).wrapping_sub(y as u16);
+            if attr & FLIP_VERT != 0 {
+                reg.ppuctrl.sprite_height() - 1 - row
+            } else {
+                row
+            }
+        } else {
+            0
+        };
+        let addr = if reg.ppuctrl.tall_sprites() {
+            let table = (tile as u16 & 0x01) << 12;
+            let tile = (tile & 0xFE) as u16 + (row >> 3);
+            table | tile << 4 | (row & 0x07)
+        } else {
+            reg.ppuctrl.sprite_table() | (tile as u16) << 4 | row
+        };
+
+        let flip = |byte: u8| if attr & FLIP_HORZ != 0 {
+            reverse_bits(byte)
+        } else {
+            byte
+        };
+        match (cyc - 257) % 8 {
+            // Garbage nametable fetches
+            0 | 2 => {
+                mem.read(0x2000 | (reg.v & 0x0FFF));
+            }
+            4 => {
+                let lo = flip(mem.read(addr));
+                let unit = &mut self.units[slot];
+                unit.x = x;
+                unit.attr = attr;
+                unit.pattern_lo = if in_use { lo } else { 0 };
+            }
+            6 => {
+                let hi = flip(mem.read(addr + 8));
+                self.units[slot].pattern_hi = if in_use { hi } else { 0 };
+            }
+            _ => (),
+        }
+    }
+}
@@ -1,574 +1,1355 @@
-use Settings;
-use cart::Cart;
-use memory::MemSegment;
-use screen::Screen;
-use std::cell::UnsafeCell;
-use std::cmp;
-use std::default::Default;
-use std::rc::Rc;
-
-mod ppu_reg;
-use ppu::ppu_reg::*;
-
-mod ppu_memory;
-use ppu::ppu_memory::*;
-
-mod sprite_rendering;
-use ppu::sprite_rendering::*;
-
-mod background_rendering;
-use ppu::background_rendering::*;
-
-pub const SCREEN_WIDTH: usize = 256;
-pub const SCREEN_HEIGHT: usize = 240;
-pub const SCREEN_BUFFER_SIZE: usize = SCREEN_WIDTH * SCREEN_HEIGHT;
-
-const CYCLES_PER_SCANLINE: u64 = 341;
-const SCANLINES_PER_FRAME: u64 = 262;
-const CYCLES_PER_FRAME: u64 = CYCLES_PER_SCANLINE * SCANLINES_PER_FRAME;
-
-#[derive(Debug, Copy, Clone, PartialEq)]
-#[repr(C)]
-pub struct Color(u8);
-impl Color {
-    fn from_bits_truncate(val: u8) -> Color {
-        Color(val & 0b0011_1111)
-    }
-
-    pub fn bits(&self) -> u8 {
-        self.0
-    }
-}
-
-#[derive(Debug, Copy, Clone, PartialEq)]
-pub enum PaletteSet {
-    Background,
-    Sprite,
-}
-
-impl PaletteSet {
-    fn table(&self) -> u8 {
-        match *self {
-            PaletteSet::Background => 0x00,
-            PaletteSet::Sprite => 0x10,
-        }
-    }
-}
-
-#[derive(Debug, Copy, Clone, PartialEq)]
-#[repr(C)]
-pub struct PaletteIndex {
-    addr: u8,
-}
-
-const TRANSPARENT: PaletteIndex = PaletteIndex { addr: 0x00 };
-
-impl PaletteIndex {
-    pub fn from_packed(addr: u8) -> PaletteIndex {
-        if addr & 0x03 == 0 {
-            PaletteIndex { addr: 0 }
-        } else {
-            PaletteIndex { addr: addr }
-        }
-    }
-
-    pub fn from_unpacked(set: PaletteSet, palette_id: u8, color_id: u8) -> PaletteIndex {
-        if color_id == 0 {
-            return PaletteIndex { addr: 0 };
-        }
-        let mut addr: u8 = 0x00;
-        addr |= set.table();
-        addr |= (palette_id & 0x03) << 2;
-        addr |= color_id & 0x03;
-        PaletteIndex { addr: addr }
-    }
-
-    #[cfg(not(feature = "vectorize"))]
-    fn to_index(self) -> usize {
-        self.addr as usize
-    }
-
-    fn is_transparent(&self) -> bool {
-        self.addr == 0
-    }
-}
-
-#[derive(Debug, Copy, Clone)]
-pub struct TilePattern {
-    lo: u8,
-    hi: u8,
-}
-
-pub const NO_TILE: TilePattern = TilePattern { lo: 0, hi: 0 };
-
-impl Default for TilePattern {
-    fn default() -> TilePattern {
-        NO_TILE
-    }
-}
-
-impl TilePattern {
-    fn get_color_in_pattern(&self, fine_x: u32) -> u8 {
-        let lo = self.lo;
-        let hi = self.hi;
-        let shift = 0x07 - fine_x;
-        let color_id_lo = lo.wrapping_shr(shift) & 0x01;
-        let color_id_hi = (hi.wrapping_shr(shift) & 0x01) << 1;
-        color_id_lo | color_id_hi
-    }
-}
-
-pub struct PPU {
-    settings: Rc<Settings>,
-
-    reg: PPUReg,
-    ppudata_read_buffer: u8,
-    ppu_mem: PPUMemory,
-
-    screen: Box<Screen>,
-    palette_buffer: Box<[PaletteIndex; SCREEN_BUFFER_SIZE]>,
-    screen_buffer: Box<[Color; SCREEN_BUFFER_SIZE]>,
-
-    sprite_data: SpriteRenderer,
-    background_data: BackgroundRenderer,
-
-    global_cyc: u64,
-    cyc: u16,
-    sl: i16,
-    frame: u32,
-
-    next_vblank_ppu_cyc: u64,
-    next_vblank_cpu_cyc: u64,
-}
-
-#[derive(Copy, Debug, PartialEq, Clone)]
-pub enum StepResult {
-    NMI,
-    Continue,
-}
-
-fn div_rem(num: u64, den: u64) -> (u64, u64) {
-    (num / den, num % den)
-}
-
-fn ppu_to_cpu_cyc(ppu_cyc: u64) -> u64 {
-    let (div, rem) = div_rem(ppu_cyc, 3);
-    if rem == 0 {
-        div
-    } else {
-        div + 1
-    }
-}
-
-fn cpu_to_ppu_cyc(cpu_cyc: u64) -> u64 {
-    cpu_cyc * 3
-}
-
-fn cyc_to_px(ppu_cyc: u64) -> usize {
-    let mut pixel: usize = 0;
-    let mut rem = ppu_cyc;
-
-    rem += 241 * CYCLES_PER_SCANLINE; // Skip to the position at power-on.
-
-    let (frames, rem_t) = div_rem(rem, CYCLES_PER_FRAME);
-    rem = rem_t;
-    pixel += frames as usize * SCREEN_BUFFER_SIZE;
-
-    // Skip the pre-render scanline.
-    rem = rem.saturating_sub(CYCLES_PER_SCANLINE);
-
-    // Cut off the VBLANK scanlines.
-    rem = cmp::min(rem, SCREEN_HEIGHT as u64 * CYCLES_PER_SCANLINE);
-
-    let (scanlines, rem_t) = div_rem(rem, CYCLES_PER_SCANLINE);
-    rem = rem_t;
-    pixel += scanlines as usize * SCREEN_WIDTH;
-
-    rem = rem.saturating_sub(1); // Skip idle cycle
-    rem = cmp::min(rem, SCREEN_WIDTH as u64); // Cut off HBLANK
-
-    pixel += rem as usize;
-    pixel
-}
-
-impl PPU {
-    pub fn new(settings: Rc<Settings>, cart: Rc<UnsafeCell<Cart>>, screen: Box<Screen>) -> PPU {
-        PPU {
-            settings: settings,
-
-            reg: Default::default(),
-            ppudata_read_buffer: 0,
-            ppu_mem: PPUMemory::new(cart),
-
-            palette_buffer: Box::new([TRANSPARENT; SCREEN_BUFFER_SIZE]),
-            screen_buffer: Box::new([Color::from_bits_truncate(0x00); SCREEN_BUFFER_SIZE]),
-            screen: screen,
-
-            sprite_data: Default::default(),
-            background_data: Default::default(),
-
-            global_cyc: 0,
-            cyc: 0,
-            sl: 241,
-            frame: 0,
-
-            next_vblank_ppu_cyc: 1,
-            next_vblank_cpu_cyc: ppu_to_cpu_cyc(1),
-        }
-    }
-
-    pub fn run_to(&mut self, cpu_cycle: u64) -> StepResult {
-        let start = self.global_cyc;
-        let stop = cpu_to_ppu_cyc(cpu_cycle);
-
-        let start_px = cyc_to_px(start);
-        let delta_px = cyc_to_px(stop) - start_px;
-        let start_px = start_px % SCREEN_BUFFER_SIZE;
-        let stop_px = start_px + delta_px;
-
-        let rendering_enabled = self.reg.ppumask.rendering_enabled();
-
-        let mut hit_nmi = false;
-        while self.global_cyc < stop {
-            self.tick_cycle();
-            self.run_cycle(rendering_enabled, &mut hit_nmi);
-        }
-
-        if self.settings.graphics_enabled {
-            if self.reg.ppumask.contains(S_BCK) {
-                self.background_data
-                    .render(&mut self.palette_buffer, start_px, stop_px, &self.reg);
-            } else {
-                let slice = &mut self.palette_buffer[start_px..stop_px];
-                for dest in slice.iter_mut() {
-                    *dest = TRANSPARENT;
-                }
-            }
-            if self.reg.ppumask.contains(S_SPR) {
-                self.sprite_data
-                    .render(&mut self.palette_buffer, &mut self.reg, start_px, stop_px);
-            }
-
-            self.colorize(start_px, stop_px);
-        }
-
-        if hit_nmi {
-            StepResult::NMI
-        } else {
-            StepResult::Continue
-        }
-    }
-
-    /// Returns the CPU cycle number representing the next time the CPU should
-    /// run the PPU. When the CPU cycle reaches this number, the CPU must run
-    /// the PPU.
-    pub fn requested_run_cycle(&self) -> u64 {
-        self.next_vblank_cpu_cyc
-    }
-
-    fn tick_cycle(&mut self) {
-        self.global_cyc += 1;
-        self.cyc += 1;
-        if self.cyc == 341 {
-            self.cyc = 0;
-            self.sl += 1;
-            if self.sl == 261 {
-                self.sl = -1;
-                self.frame += 1;
-            }
-        }
-    }
-
-    fn run_cycle(&mut self, rendering_enabled: bool, hit_nmi: &mut bool) {
-        if let -1...239 = self.sl {
-            if rendering_enabled && self.settings.graphics_enabled {
-                self.background_data
-                    .run_cycle(self.cyc, self.sl, &mut self.reg, &mut self.ppu_mem);
-            }
-        }
-        match (self.cyc, self.sl) {
-            (_, -1) => self.prerender_scanline(),
-
-            // Visible scanlines
-            (0, 0...239) => {
-                if self.settings.graphics_enabled {
-                    self.sprite_data
-                        .sprite_eval(self.sl as u16, &self.reg, &mut self.ppu_mem)
-                }
-            }
-            (_, 0...239) => (),
-
-            (_, 240) => (), //Post-render idle scanline
-            (1, 241) => self.start_vblank(hit_nmi),
-            (_, 241...260) => (), //VBlank lines
-            _ => (),
-        }
-    }
-
-    fn prerender_scanline(&mut self) {
-        if self.cyc == 1 {
-            self.reg.ppustat.remove(VBLANK | SPRITE_0 | SPRITE_OVERFLOW);
-        }
-        if self.cyc == 339 && self.frame % 2 == 1 {
-            self.tick_cycle()
-        }
-    }
-
-    fn start_vblank(&mut self, hit_nmi: &mut bool) {
-        self.next_vblank_ppu_cyc += CYCLES_PER_FRAME;
-        self.next_vblank_cpu_cyc = ppu_to_cpu_cyc(self.next_vblank_ppu_cyc);
-
-        let buf = &self.screen_buffer;
-        self.screen.draw(buf);
-
-        if self.frame > 0 {
-            self.reg.ppustat.insert(VBLANK);
-            *hit_nmi |= self.reg.ppuctrl.generate_vblank_nmi();
-        }
-    }
-
-    #[cfg(feature = "vectorize")]
-    fn colorize(&mut self, start: usize, stop: usize) {
-        use std::mem;
-        use std::cmp;
-        use simd::u8x16;
-        use simd::x86::ssse3::Ssse3U8x16;
-
-        let (background_pal, sprite_pal) = self.ppu_mem.get_palettes();
-        let index_bytes: &[u8; SCREEN_BUFFER_SIZE] =
-            unsafe { mem::transmute(&self.palette_buffer) };
-        let color_bytes: &mut [u8; SCREEN_BUFFER_SIZE] =
-            unsafe { mem::transmute(&mut self.screen_buffer) };
-
-        let mut start = start;
-
-        while start < stop {
-            start = cmp::min(start, SCREEN_BUFFER_SIZE - 16);
-            let palette_idx = u8x16::load(index_bytes, start);
-
-            let table: u8x16 = palette_idx >> 4;
-            let use_sprite_table = table.ne(u8x16::splat(0));
-            let color_id = palette_idx & u8x16::splat(0b0000_1111);
-
-            let background_shuf = background_pal.shuffle_bytes(color_id);
-            let sprite_shuf = sprite_pal.shuffle_bytes(color_id);
-
-            let final_color = use_sprite_table.select(sprite_shuf, background_shuf);
-            final_color.store(&mut *color_bytes, start);
-            start += 16;
-        }
-    }
-
-    #[cfg(not(feature = "vectorize"))]
-    fn colorize(&mut self, start: usize, stop: usize) {
-        let color_slice = &mut self.screen_buffer[start..stop];
-        let index_slice = &self.palette_buffer[start..stop];
-
-        for (src, dest) in index_slice.iter().zip(color_slice.iter_mut()) {
-            *dest = self.ppu_mem.read_palette(*src);
-        }
-    }
-
-    #[cfg(feature = "debug_features")]
-    pub fn cycle(&self) -> u16 {
-        self.cyc
-    }
-
-    #[cfg(feature = "debug_features")]
-    pub fn scanline(&self) -> i16 {
-        self.sl
-    }
-
-    #[cfg(feature = "debug_features")]
-    pub fn vram_addr(&self) -> u16 {
-        self.reg.v
-    }
-
-    pub fn frame(&self) -> u32 {
-        self.frame
-    }
-
-    #[cfg(feature = "debug_features")]
-    pub fn mouse_pick(&self, px_x: i32, px_y: i32) {
-        self.background_data.mouse_pick(&self.reg, px_x, px_y);
-        self.sprite_data.mouse_pick(px_x, px_y);
-    }
-
-    pub fn rendering_enabled(&self) -> bool {
-        self.reg.ppumask.rendering_enabled()
-    }
-}
-
-impl MemSegment for PPU {
-    fn read(&mut self, idx: u16) -> u8 {
-        match idx % 8 {
-            0x0004 => self.sprite_data.read(self.reg.oamaddr as u16),
-            0x0007 => {
-                let addr = self.reg.v;
-                match addr {
-                    0x0000...0x3EFF => {
-                        let old_buffer = self.ppudata_read_buffer;
-                        self.ppudata_read_buffer = self.ppu_mem.read(addr);
-                        self.reg.incr_ppuaddr();
-                        old_buffer
-                    }
-                    0x3F00...0x3FFF => {
-                        let read_result = self.ppu_mem.read(addr);
-                        self.reg.incr_ppuaddr();
-                        self.ppudata_read_buffer = self.ppu_mem.read_bypass_palette(addr);
-                        read_result
-                    }
-                    x => invalid_address!(x),
-                }
-            }
-            _ => self.reg.read(idx),
-        }
-    }
-
-    fn write(&mut self, idx: u16, val: u8) {
-        match idx % 8 {
-            0x0004 => {
-                self.sprite_data.write(self.reg.oamaddr as u16, val);
-                self.reg.incr_oamaddr();
-            }
-            0x0007 => {
-                self.ppu_mem.write(self.reg.v, val);
-                self.reg.incr_ppuaddr();
-            }
-            _ => self.reg.write(idx, val),
-        }
-    }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use cart::{Cart, ScreenMode};
-    use mappers::create_test_mapper;
-    use memory::MemSegment;
-    use ppu::ppu_reg::PPUCtrl;
-    use screen::DummyScreen;
-    use std::cell::UnsafeCell;
-    use std::rc::Rc;
-
-    pub fn create_test_ppu() -> PPU {
-        create_test_ppu_with_rom(vec![0u8; 0x1000])
-    }
-
-    pub fn create_test_ppu_with_rom(chr_rom: Vec<u8>) -> PPU {
-        let mapper = create_test_mapper(vec![0u8; 0x1000], chr_rom, ScreenMode::FourScreen);
-        let cart = Cart::new(mapper);
-        let settings = Settings {
-            graphics_enabled: true,
-            ..Default::default()
-        };
-        PPU::new(
-            Rc::new(settings),
-            Rc::new(UnsafeCell::new(cart)),
-            Box::new(DummyScreen::default()),
-        )
-    }
-
-    pub fn create_test_ppu_with_mirroring(mode: ScreenMode) -> PPU {
-        let mapper = create_test_mapper(vec![0u8; 0x1000], vec![0u8; 0x1000], mode);
-        let cart = Cart::new(mapper);
-        let settings = Settings {
-            graphics_enabled: true,
-            ..Default::default()
-        };
-        PPU::new(
-            Rc::new(settings),
-            Rc::new(UnsafeCell::new(cart)),
-            Box::new(DummyScreen::default()),
-        )
-    }
-
-    #[test]
-    fn reading_oamdata_doesnt_increment_oamaddr() {
-        let mut ppu = create_test_ppu();
-        ppu.reg.oamaddr = 0;
-        ppu.read(0x2004);
-        assert_eq!(ppu.reg.oamaddr, 0);
-    }
-
-    #[test]
-    fn writing_oamdata_increments_oamaddr() {
-        let mut ppu = create_test_ppu();
-        ppu.reg.oamaddr = 0;
-        ppu.write(0x2004, 12);
-        assert_eq!(ppu.reg.oamaddr, 1);
-        ppu.reg.oamaddr = 255;
-        ppu.write(0x2004, 12);
-        assert_eq!(ppu.reg.oamaddr, 0);
-    }
-
-    #[test]
-    fn ppu_can_read_chr_rom() {
-        let mut chr_rom = vec![0u8; 0x2000];
-        chr_rom[0x0ABC] = 12;
-        chr_rom[0x0DBA] = 212;
-        let mut ppu = create_test_ppu_with_rom(chr_rom);
-
-        ppu.reg.v = 0x0ABC;
-        ppu.read(0x2007); // Dummy Read
-        assert_eq!(ppu.read(0x2007), 12);
-
-        ppu.reg.v = 0x0DBA;
-        ppu.read(0x2007); // Dummy Read
-        assert_eq!(ppu.read(0x2007), 212);
-    }
-
-    #[test]
-    fn ppu_can_read_write_vram() {
-        let mut ppu = create_test_ppu();
-
-        ppu.reg.v = 0x2ABC;
-        ppu.write(0x2007, 12);
-        ppu.reg.v = 0x2ABC;
-        ppu.read(0x2007); // Dummy read
-        assert_eq!(ppu.read(0x2007), 12);
-
-        ppu.reg.v = 0x2DBA;
-        ppu.write(0x2007, 212);
-        ppu.reg.v = 0x2DBA;
-        ppu.read(0x2007); // Dummy Read
-        assert_eq!(ppu.read(0x2007), 212);
-
-        // Mirroring
-        ppu.reg.v = 0x2EFC;
-        ppu.write(0x2007, 128);
-        ppu.reg.v = 0x3EFC;
-        ppu.read(0x2007); // Dummy Read
-        assert_eq!(ppu.read(0x2007), 128);
-    }
-
-    #[test]
-    fn ppu_needs_no_dummy_read_for_palette_data() {
-        let mut ppu = create_test_ppu();
-        ppu.reg.v = 0x3F16;
-        ppu.write(0x2007, 21);
-        ppu.reg.v = 0x3F16;
-        assert_eq!(ppu.read(0x2007), 21);
-    }
-
-    #[test]
-    fn accessing_ppudata_increments_ppuaddr() {
-        let mut ppu = create_test_ppu();
-        ppu.reg.v = 0x2000;
-        ppu.read(0x2007);
-        assert_eq!(ppu.reg.v, 0x2001);
-        ppu.write(0x2007, 0);
-        assert_eq!(ppu.reg.v, 0x2002);
-    }
-
-    #[test]
-    fn accessing_ppudata_increments_ppuaddr_by_32_when_ctrl_flag_is_set() {
-        let mut ppu = create_test_ppu();
-        ppu.reg.ppuctrl = PPUCtrl::new(0b0000_0100);
-        ppu.reg.v = 0x2000;
-        ppu.read(0x2007);
-        assert_eq!(ppu.reg.v, 0x2020);
-        ppu.write(0x2007, 0);
-        assert_eq!(ppu.reg.v, 0x2040);
-    }
-}
+use Region;
+use Settings;
+use cart::Cart;
+use events::{PPUEvent, PPUEventKind};
+use mappers::PpuFetch;
+use memory::MemSegment;
+use screen::Screen;
+use std::cell::UnsafeCell;
+use std::cmp;
+use std::default::Default;
+use std::mem;
+use std::rc::Rc;
+
+mod ppu_reg;
+use ppu::ppu_reg::*;
+
+mod ppu_memory;
+use ppu::ppu_memory::*;
+
+mod sprite_rendering;
+use ppu::sprite_rendering::*;
+
+mod background_rendering;
+use ppu::background_rendering::*;
+
+mod dot_rendering;
+use ppu::dot_rendering::*;
+
+pub mod debug;
+
+pub const SCREEN_WIDTH: usize = 256;
+pub const SCREEN_HEIGHT: usize = 240;
+pub const SCREEN_BUFFER_SIZE: usize = SCREEN_WIDTH * SCREEN_HEIGHT;
+
+const CYCLES_PER_SCANLINE: u64 = 341;
+const SCANLINES_PER_FRAME: u64 = 262;
+
+/// The parts of the PPU's frame timing which differ between regions.
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Timing {
+    region: Region,
+    scanlines_per_frame: u64,
+    /// The scanline on which VBlank starts. Dendy famiclones have 50 idle
+    /// lines after the post-render line before VBlank, rather than PAL's 70
+    /// lines of VBlank.
+    vblank_scanline: i16,
+    /// PPU dots per CPU cycle, as a numerator and denominator.
+    clock_ratio: (u64, u64),
+    /// Whether odd frames skip the last dot of the pre-render line.
+    skips_odd_dot: bool,
+}
+
+impl Timing {
+    fn new(region: Region) -> Timing {
+        match region {
+            Region::NTSC => {
+                Timing {
+                    region: region,
+                    scanlines_per_frame: SCANLINES_PER_FRAME,
+                    vblank_scanline: 241,
+                    clock_ratio: (3, 1),
+                    skips_odd_dot: true,
+                }
+            }
+            Region::PAL => {
+                Timing {
+                    region: region,
+                    scanlines_per_frame: 312,
+                    vblank_scanline: 241,
+                    clock_ratio: (16, 5),
+                    skips_odd_dot: false,
+                }
+            }
+            Region::Dendy => {
+                Timing {
+                    region: region,
+                    scanlines_per_frame: 312,
+                    vblank_scanline: 291,
+                    clock_ratio: (3, 1),
+                    skips_odd_dot: false,
+                }
+            }
+        }
+    }
+
+    fn dots_per_frame(&self) -> u64 {
+        CYCLES_PER_SCANLINE * self.scanlines_per_frame
+    }
+
+    /// The last scanline before the pre-render line.
+    fn last_scanline(&self) -> i16 {
+        self.scanlines_per_frame as i16 - 2
+    }
+
+    /// Returns the first CPU cycle at or after the given PPU cycle.
+    fn ppu_to_cpu_cyc(&self, ppu_cyc: u64) -> u64 {
+        let (dots, cycles) = self.clock_ratio;
+        let (div, rem) = div_rem(ppu_cyc * cycles, dots);
+        if rem == 0 {
+            div
+        } else {
+            div + 1
+        }
+    }
+
+    fn cpu_to_ppu_cyc(&self, cpu_cyc: u64) -> u64 {
+        let (dots, cycles) = self.clock_ratio;
+        cpu_cyc * dots / cycles
+    }
+
+    /// The number of PPU cycles in the given number of milliseconds.
+    fn ms_to_ppu_cyc(&self, ms: u64) -> u64 {
+        self.cpu_to_ppu_cyc(self.region.cpu_clock_rate() * ms / 1000)
+    }
+}
+
+/// A color output by the PPU - the 6-bit palette color in the low bits, and
+/// the three color emphasis bits from PPUMASK above that.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[repr(C)]
+pub struct Color(u16);
+impl Color {
+    pub fn from_bits(val: u16) -> Color {
+        Color(val & 0b1_1111_1111)
+    }
+
+    pub fn bits(&self) -> u16 {
+        self.0
+    }
+
+    /// The palette color, without emphasis.
+    pub fn color(&self) -> u8 {
+        (self.0 & 0b0011_1111) as u8
+    }
+
+    /// The emphasis bits - red, green and blue from the lowest bit up.
+    pub fn emphasis(&self) -> u8 {
+        (self.0 >> 6) as u8
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PaletteSet {
+    Background,
+    Sprite,
+}
+
+impl PaletteSet {
+    fn table(&self) -> u8 {
+        match *self {
+            PaletteSet::Background => 0x00,
+            PaletteSet::Sprite => 0x10,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[repr(C)]
+pub struct PaletteIndex {
+    addr: u8,
+}
+
+const TRANSPARENT: PaletteIndex = PaletteIndex { addr: 0x00 };
+
+impl PaletteIndex {
+    pub fn from_packed(addr: u8) -> PaletteIndex {
+        if addr & 0x03 == 0 {
+            PaletteIndex { addr: 0 }
+        } else {
+            PaletteIndex { addr: addr }
+        }
+    }
+
+    pub fn from_unpacked(set: PaletteSet, palette_id: u8, color_id: u8) -> PaletteIndex {
+        if color_id == 0 {
+            return PaletteIndex { addr: 0 };
+        }
+        let mut addr: u8 = 0x00;
+        addr |= set.table();
+        addr |= (palette_id & 0x03) << 2;
+        addr |= color_id & 0x03;
+        PaletteIndex { addr: addr }
+    }
+
+    #[cfg(not(feature = "vectorize"))]
+    fn to_index(self) -> usize {
+        self.addr as usize
+    }
+
+    fn is_transparent(&self) -> bool {
+        self.addr == 0
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct TilePattern {
+    lo: u8,
+    hi: u8,
+}
+
+pub const NO_TILE: TilePattern = TilePattern { lo: 0, hi: 0 };
+
+impl Default for TilePattern {
+    fn default() -> TilePattern {
+        NO_TILE
+    }
+}
+
+impl TilePattern {
+    fn get_color_in_pattern(&self, fine_x: u32) -> u8 {
+        let lo = self.lo;
+        let hi = self.hi;
+        let shift = 0x07 - fine_x;
+        let color_id_lo = lo.wrapping_shr(shift) & 0x01;
+        let color_id_hi = (hi.wrapping_shr(shift) & 0x01) << 1;
+        color_id_lo | color_id_hi
+    }
+}
+
+pub struct PPU {
+    settings: Rc<Settings>,
+    timing: Timing,
+
+    reg: PPUReg,
+    ppudata_read_buffer: u8,
+    ppu_mem: PPUMemory,
+
+    screen: Box<Screen>,
+    palette_buffer: Box<[PaletteIndex; SCREEN_BUFFER_SIZE]>,
+    screen_buffer: Box<[Color; SCREEN_BUFFER_SIZE]>,
+
+    sprite_data: SpriteRenderer,
+    background_data: BackgroundRenderer,
+    dot_data: DotRenderer,
+
+    global_cyc: u64,
+    cyc: u16,
+    sl: i16,
+    frame: u32,
+
+    next_vblank_ppu_cyc: u64,
+    next_vblank_cpu_cyc: u64,
+
+    /// The dot on the current scanline at which sprite evaluation sets the
+    /// sprite overflow flag, if it does.
+    sprite_overflow_dot: Option<u16>,
+
+    /// The CPU cycle from which the CPU should take a pending NMI.
+    nmi_cpu_cyc: Option<u64>,
+    /// The PPU cycle at which the pending NMI was raised.
+    nmi_edge_cyc: u64,
+    /// Set when $2002 is read on the dot before VBlank starts.
+    vblank_suppressed: bool,
+
+    /// When false, frames are still rendered (so sprite 0 hits and light
+    /// sensing work) but are not sent to the screen.
+    output_enabled: bool,
+
+    /// Positions (scanline, dot) which produce an event when reached.
+    watched_dots: Vec<(i16, u16)>,
+    /// The CPU cycle by which the next watched dot will be reached.
+    next_watch_cpu_cyc: Option<u64>,
+    /// Events which haven't been collected by `take_events` yet.
+    events: Vec<PPUEvent>,
+}
+
+/// A snapshot of the PPU, taken by `PPU::save_state`.
+pub struct PPUState {
+    reg: PPUReg,
+    ppudata_read_buffer: u8,
+    ppu_mem: PPUMemory,
+
+    palette_buffer: Box<[PaletteIndex; SCREEN_BUFFER_SIZE]>,
+    screen_buffer: Box<[Color; SCREEN_BUFFER_SIZE]>,
+
+    sprite_data: SpriteRenderer,
+    background_data: BackgroundRenderer,
+    dot_data: DotRenderer,
+
+    global_cyc: u64,
+    cyc: u16,
+    sl: i16,
+    frame: u32,
+
+    next_vblank_ppu_cyc: u64,
+    next_vblank_cpu_cyc: u64,
+
+    sprite_overflow_dot: Option<u16>,
+
+    nmi_cpu_cyc: Option<u64>,
+    nmi_edge_cyc: u64,
+    vblank_suppressed: bool,
+}
+
+#[derive(Copy, Debug, PartialEq, Clone)]
+pub enum StepResult {
+    NMI,
+    Continue,
+}
+
+/// The number of scanlines in each frame, including the pre-render line.
+pub fn scanlines_per_frame(region: Region) -> u64 {
+    Timing::new(region).scanlines_per_frame
+}
+
+fn div_rem(num: u64, den: u64) -> (u64, u64) {
+    (num / den, num % den)
+}
+
+/// The number of pixels of the frame which are ready to be drawn once the
+/// given dot has run. The pixel output on a dot is drawn along with the next
+/// one.
+fn pixels_drawn(sl: i16, cyc: u16) -> usize {
+    match sl {
+        0...239 => {
+            let x = cmp::min(cyc.saturating_sub(1) as usize, SCREEN_WIDTH);
+            sl as usize * SCREEN_WIDTH + x
+        }
+        -1 => 0,
+        _ => SCREEN_BUFFER_SIZE,
+    }
+}
+
+impl PPU {
+    pub fn new(settings: Rc<Settings>, cart: Rc<UnsafeCell<Cart>>, screen: Box<Screen>) -> PPU {
+        let timing = Timing::new(settings.region());
+        PPU {
+            settings: settings,
+            timing: timing,
+
+            reg: Default::default(),
+            ppudata_read_buffer: 0,
+            ppu_mem: PPUMemory::new(cart),
+
+            palette_buffer: Box::new([TRANSPARENT; SCREEN_BUFFER_SIZE]),
+            screen_buffer: Box::new([Color::from_bits(0x00); SCREEN_BUFFER_SIZE]),
+            screen: screen,
+
+            sprite_data: Default::default(),
+            background_data: Default::default(),
+            dot_data: Default::default(),
+
+            global_cyc: 0,
+            cyc: 0,
+            sl: timing.vblank_scanline,
+            frame: 0,
+
+            next_vblank_ppu_cyc: 1,
+            next_vblank_cpu_cyc: timing.ppu_to_cpu_cyc(1),
+
+            sprite_overflow_dot: None,
+
+            nmi_cpu_cyc: None,
+            nmi_edge_cyc: 0,
+            vblank_suppressed: false,
+
+            output_enabled: true,
+
+            watched_dots: vec![],
+            next_watch_cpu_cyc: None,
+            events: vec![],
+        }
+    }
+
+    pub fn run_to(&mut self, cpu_cycle: u64) -> StepResult {
+        let stop = self.timing.cpu_to_ppu_cyc(cpu_cycle);
+
+        let start_frame = self.frame;
+        let start_px = pixels_drawn(self.sl, self.cyc);
+
+        let rendering_enabled = self.reg.ppumask.rendering_enabled();
+
+        while self.global_cyc < stop {
+            self.tick_cycle();
+            self.run_cycle(rendering_enabled);
+            if !self.watched_dots.is_empty() {
+                self.check_watched_dots();
+            }
+        }
+        self.update_next_watch();
+
+        let start_px = if self.frame == start_frame { start_px } else { 0 };
+        let stop_px = pixels_drawn(self.sl, self.cyc);
+
+        if self.settings.graphics_enabled && !self.settings.dot_accurate_ppu {
+            if self.reg.ppumask.contains(S_BCK) {
+                self.background_data
+                    .render(&mut self.palette_buffer, start_px, stop_px, &self.reg);
+            } else {
+                let slice = &mut self.palette_buffer[start_px..stop_px];
+                for dest in slice.iter_mut() {
+                    *dest = TRANSPARENT;
+                }
+            }
+            if self.reg.ppumask.contains(S_SPR) {
+                self.sprite_data
+                    .render(&mut self.palette_buffer, &mut self.reg, start_px, stop_px);
+            }
+        }
+        if self.settings.graphics_enabled {
+            self.colorize(start_px, stop_px);
+        }
+
+        match self.nmi_cpu_cyc {
+            Some(nmi_cyc) if nmi_cyc <= cpu_cycle => {
+                self.nmi_cpu_cyc = None;
+                StepResult::NMI
+            }
+            _ => StepResult::Continue,
+        }
+    }
+
+    /// Returns the CPU cycle number representing the next time the CPU should
+    /// run the PPU. When the CPU cycle reaches this number, the CPU must run
+    /// the PPU.
+    pub fn requested_run_cycle(&self) -> u64 {
+        // Run to just after the cartridge's scanline starts, so it sees the
+        // fetches at the start of the line.
+        let cart_cyc = self.ppu_mem.watched_scanline().map(|sl| {
+            self.timing.ppu_to_cpu_cyc(self.global_cyc + self.dots_until(sl, 2))
+        });
+        [self.nmi_cpu_cyc, self.next_watch_cpu_cyc, cart_cyc]
+            .iter()
+            .filter_map(|&cyc| cyc)
+            .fold(self.next_vblank_cpu_cyc, cmp::min)
+    }
+
+    /// Makes the PPU produce an event whenever it reaches the given dot.
+    pub fn watch_dot(&mut self, scanline: i16, dot: u16) {
+        self.watched_dots.push((scanline, dot));
+        self.update_next_watch();
+    }
+
+    pub fn clear_watched_dots(&mut self) {
+        self.watched_dots.clear();
+        self.next_watch_cpu_cyc = None;
+    }
+
+    /// Returns the events produced since the last call, in order.
+    pub fn take_events(&mut self) -> Vec<PPUEvent> {
+        mem::replace(&mut self.events, vec![])
+    }
+
+    /// Describes the current state of the PPU. The PC is left for the CPU to
+    /// fill in.
+    pub fn event(&self, kind: PPUEventKind) -> PPUEvent {
+        PPUEvent {
+            kind: kind,
+            frame: self.frame,
+            scanline: self.sl,
+            dot: self.cyc,
+            pc: 0,
+            t: self.reg.t,
+            v: self.reg.v,
+            x: self.reg.x,
+        }
+    }
+
+    fn check_watched_dots(&mut self) {
+        let position = (self.sl, self.cyc);
+        if self.watched_dots.contains(&position) {
+            let event = self.event(PPUEventKind::Dot);
+            self.events.push(event);
+        }
+    }
+
+    /// Works out when the CPU next needs to run the PPU to reach a watched
+    /// dot on time. Skipped dots on odd frames are ignored, so this may be
+    /// one dot late.
+    fn update_next_watch(&mut self) {
+        let next = self.watched_dots
+            .iter()
+            .map(|&(sl, cyc)| self.dots_until(sl, cyc))
+            .min();
+        self.next_watch_cpu_cyc =
+            next.map(|dots| self.timing.ppu_to_cpu_cyc(self.global_cyc + dots));
+    }
+
+    /// The number of dots until the PPU next reaches the given position,
+    /// which is a whole frame if it's there now.
+    fn dots_until(&self, sl: i16, cyc: u16) -> u64 {
+        let frame_dots = self.timing.dots_per_frame();
+        let position = self.dots_into_frame(self.sl, self.cyc);
+        let target = self.dots_into_frame(sl, cyc);
+        match (target + frame_dots - position) % frame_dots {
+            0 => frame_dots,
+            dots => dots,
+        }
+    }
+
+    /// The number of dots from the start of the pre-render line to the
+    /// given position.
+    fn dots_into_frame(&self, sl: i16, cyc: u16) -> u64 {
+        (sl + 1) as u64 * CYCLES_PER_SCANLINE + cyc as u64
+    }
+
+    /// Raises the NMI line. The CPU only checks for interrupts before the
+    /// last cycle of each instruction, so an NMI raised during an
+    /// instruction's last cycle isn't taken until after the next one.
+    fn raise_nmi(&mut self) {
+        self.nmi_edge_cyc = self.global_cyc;
+        self.nmi_cpu_cyc = Some(self.timing.ppu_to_cpu_cyc(self.global_cyc) + 1);
+    }
+
+    fn tick_cycle(&mut self) {
+        self.global_cyc += 1;
+        self.cyc += 1;
+        if self.cyc == 341 {
+            self.cyc = 0;
+            self.sl += 1;
+            if self.sl > self.timing.last_scanline() {
+                self.sl = -1;
+                self.frame += 1;
+            }
+        }
+    }
+
+    fn run_cycle(&mut self, rendering_enabled: bool) {
+        if let Some(fetch) = self.fetch_at_dot(rendering_enabled) {
+            let tall_sprites = self.reg.ppuctrl.tall_sprites();
+            self.ppu_mem.set_fetch(fetch, tall_sprites);
+        }
+        if let -1...239 = self.sl {
+            if self.settings.dot_accurate_ppu {
+                self.run_dot(rendering_enabled);
+            } else if rendering_enabled && self.settings.graphics_enabled {
+                self.background_data
+                    .run_cycle(self.cyc, self.sl, &mut self.reg, &mut self.ppu_mem);
+            }
+        }
+        match (self.cyc, self.sl) {
+            (_, -1) => self.prerender_scanline(rendering_enabled),
+
+            // Visible scanlines
+            (0, 0...239) => {
+                self.sprite_overflow_dot = if rendering_enabled {
+                    self.sprite_data.find_overflow(self.sl as u16, &self.reg)
+                } else {
+                    None
+                };
+                if self.settings.graphics_enabled && !self.settings.dot_accurate_ppu {
+                    // All of the line's sprite patterns are fetched here, in
+                    // the middle of the background fetches.
+                    let tall_sprites = self.reg.ppuctrl.tall_sprites();
+                    if rendering_enabled {
+                        self.ppu_mem.set_fetch(PpuFetch::Sprites, tall_sprites);
+                    }
+                    self.sprite_data.sprite_eval(
+                        self.sl as u16,
+                        &self.reg,
+                        &mut self.ppu_mem,
+                        self.settings.no_sprite_limit,
+                    );
+                    if rendering_enabled {
+                        self.ppu_mem.set_fetch(PpuFetch::Background, tall_sprites);
+                    }
+                }
+            }
+            (cyc, 0...239) => {
+                if Some(cyc) == self.sprite_overflow_dot {
+                    self.reg.ppustat.insert(SPRITE_OVERFLOW);
+                }
+            }
+
+            (1, sl) if sl == self.timing.vblank_scanline => self.start_vblank(),
+            _ => (), //Post-render idle and VBlank lines
+        }
+    }
+
+    /// Returns what the PPU starts fetching on this dot, if that changes.
+    /// Mappers which watch the fetches are told even when the renderer
+    /// skips them.
+    fn fetch_at_dot(&self, rendering_enabled: bool) -> Option<PpuFetch> {
+        match (self.cyc, self.sl) {
+            (1, 0...239) if rendering_enabled => Some(PpuFetch::Background),
+            (1, 0...240) => Some(PpuFetch::Idle),
+            (257, -1...239) if rendering_enabled => Some(PpuFetch::Sprites),
+            (321, -1...239) if rendering_enabled => Some(PpuFetch::Background),
+            _ => None,
+        }
+    }
+
+    /// Runs one dot of the dot-accurate renderer and stores the pixel it
+    /// outputs. With rendering disabled, the backdrop color is shown.
+    fn run_dot(&mut self, rendering_enabled: bool) {
+        if !self.settings.graphics_enabled {
+            return;
+        }
+        let pixel = if rendering_enabled {
+            self.background_data
+                .update_vram_address(self.cyc, self.sl, &mut self.reg);
+            self.dot_data.run_cycle(
+                self.cyc,
+                self.sl,
+                &mut self.reg,
+                &mut self.ppu_mem,
+                &self.sprite_data,
+            )
+        } else {
+            TRANSPARENT
+        };
+        if let (1...256, 0...239) = (self.cyc, self.sl) {
+            let idx = self.sl as usize * SCREEN_WIDTH + self.cyc as usize - 1;
+            self.palette_buffer[idx] = pixel;
+        }
+    }
+
+    fn prerender_scanline(&mut self, rendering_enabled: bool) {
+        if self.cyc == 1 {
+            self.reg.ppustat.remove(VBLANK | SPRITE_0 | SPRITE_OVERFLOW);
+        }
+        if self.cyc == 339 && self.frame % 2 == 1 && rendering_enabled &&
+            self.timing.skips_odd_dot
+        {
+            // Odd frames skip the last dot of the pre-render line, so the
+            // next VBlank comes one dot sooner.
+            self.cyc += 1;
+            self.next_vblank_ppu_cyc -= 1;
+            self.next_vblank_cpu_cyc = self.timing.ppu_to_cpu_cyc(self.next_vblank_ppu_cyc);
+        }
+    }
+
+    fn start_vblank(&mut self) {
+        self.next_vblank_ppu_cyc += self.timing.dots_per_frame();
+        self.next_vblank_cpu_cyc = self.timing.ppu_to_cpu_cyc(self.next_vblank_ppu_cyc);
+
+        if self.output_enabled {
+            let buf = &self.screen_buffer;
+            self.screen.draw(buf);
+        }
+
+        // A $2002 read just before this dot stops the flag being set at all.
+        let suppressed = self.vblank_suppressed;
+        self.vblank_suppressed = false;
+        if self.frame > 0 && !suppressed {
+            self.reg.ppustat.insert(VBLANK);
+            if self.reg.ppuctrl.generate_vblank_nmi() {
+                self.raise_nmi();
+            }
+        }
+    }
+
+    /// Returns the mask applied to palette colors (which removes the hue in
+    /// greyscale mode) and the emphasis bits to add to them.
+    fn color_modifiers(&self) -> (u8, u16) {
+        let mask = if self.reg.ppumask.contains(GREY) {
+            0x30
+        } else {
+            0x3F
+        };
+        let emphasis = ((self.reg.ppumask.bits() & 0b1110_0000) as u16) << 1;
+        (mask, emphasis)
+    }
+
+    #[cfg(feature = "vectorize")]
+    fn colorize(&mut self, start: usize, stop: usize) {
+        use std::mem;
+        use std::cmp;
+        use simd::u8x16;
+        use simd::x86::ssse3::Ssse3U8x16;
+
+        let (mask, emphasis) = self.color_modifiers();
+        let (background_pal, sprite_pal) = self.ppu_mem.get_palettes();
+        let index_bytes: &[u8; SCREEN_BUFFER_SIZE] =
+            unsafe { mem::transmute(&self.palette_buffer) };
+
+        let mut start = start;
+        let mut color_bytes = [0u8; 16];
+
+        while start < stop {
+            start = cmp::min(start, SCREEN_BUFFER_SIZE - 16);
+            let palette_idx = u8x16::load(index_bytes, start);
+
+            let table: u8x16 = palette_idx >> 4;
+            let use_sprite_table = table.ne(u8x16::splat(0));
+            let color_id = palette_idx & u8x16::splat(0b0000_1111);
+
+            let background_shuf = background_pal.shuffle_bytes(color_id);
+            let sprite_shuf = sprite_pal.shuffle_bytes(color_id);
+
+            let final_color = use_sprite_table.select(sprite_shuf, background_shuf);
+            (final_color & u8x16::splat(mask)).store(&mut color_bytes, 0);
+
+            // The emphasis bits don't fit in a byte, so widen the colors here.
+            let dest = &mut self.screen_buffer[start..start + 16];
+            for (src, dest) in color_bytes.iter().zip(dest.iter_mut()) {
+                *dest = Color(*src as u16 | emphasis);
+            }
+            start += 16;
+        }
+    }
+
+    #[cfg(not(feature = "vectorize"))]
+    fn colorize(&mut self, start: usize, stop: usize) {
+        let (mask, emphasis) = self.color_modifiers();
+        let color_slice = &mut self.screen_buffer[start..stop];
+        let index_slice = &self.palette_buffer[start..stop];
+
+        for (src, dest) in index_slice.iter().zip(color_slice.iter_mut()) {
+            let color = self.ppu_mem.read_palette(*src) & mask;
+            *dest = Color(color as u16 | emphasis);
+        }
+    }
+
+    pub fn cycle(&self) -> u16 {
+        self.cyc
+    }
+
+    pub fn scanline(&self) -> i16 {
+        self.sl
+    }
+
+    /// The colors drawn so far. Pixels after the current beam position are
+    /// still from the previous frame.
+    pub fn screen_buffer(&self) -> &[Color; SCREEN_BUFFER_SIZE] {
+        &self.screen_buffer
+    }
+
+    #[cfg(feature = "debug_features")]
+    pub fn vram_addr(&self) -> u16 {
+        self.reg.v
+    }
+
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    #[cfg(feature = "debug_features")]
+    pub fn mouse_pick(&self, px_x: i32, px_y: i32) {
+        self.background_data.mouse_pick(&self.reg, px_x, px_y);
+        self.sprite_data.mouse_pick(px_x, px_y);
+    }
+
+    pub fn rendering_enabled(&self) -> bool {
+        self.reg.ppumask.rendering_enabled()
+    }
+
+    /// Increments v after a $2007 access. While the PPU is rendering, it
+    /// performs both its coarse X and Y increments instead.
+    fn incr_ppuaddr(&mut self) {
+        if self.reg.ppumask.rendering_enabled() && self.sl < 240 {
+            self.background_data.increment_x(&mut self.reg);
+            self.background_data.increment_y(&mut self.reg);
+        } else {
+            self.reg.incr_ppuaddr();
+        }
+    }
+
+    pub fn set_output_enabled(&mut self, enabled: bool) {
+        self.output_enabled = enabled;
+    }
+
+    /// Replaces the screen that finished frames are drawn to, returning the
+    /// old one.
+    pub fn set_screen(&mut self, screen: Box<Screen>) -> Box<Screen> {
+        mem::replace(&mut self.screen, screen)
+    }
+
+    pub fn save_state(&self) -> PPUState {
+        PPUState {
+            reg: self.reg.clone(),
+            ppudata_read_buffer: self.ppudata_read_buffer,
+            ppu_mem: self.ppu_mem.clone(),
+
+            palette_buffer: Box::new(*self.palette_buffer),
+            screen_buffer: Box::new(*self.screen_buffer),
+
+            sprite_data: self.sprite_data.clone(),
+            background_data: self.background_data.clone(),
+            dot_data: self.dot_data.clone(),
+
+            global_cyc: self.global_cyc,
+            cyc: self.cyc,
+            sl: self.sl,
+            frame: self.frame,
+
+            next_vblank_ppu_cyc: self.next_vblank_ppu_cyc,
+            next_vblank_cpu_cyc: self.next_vblank_cpu_cyc,
+            sprite_overflow_dot: self.sprite_overflow_dot,
+
+            nmi_cpu_cyc: self.nmi_cpu_cyc,
+            nmi_edge_cyc: self.nmi_edge_cyc,
+            vblank_suppressed: self.vblank_suppressed,
+        }
+    }
+
+    pub fn load_state(&mut self, state: &PPUState) {
+        self.reg = state.reg.clone();
+        self.ppudata_read_buffer = state.ppudata_read_buffer;
+        self.ppu_mem = state.ppu_mem.clone();
+
+        self.palette_buffer.copy_from_slice(&*state.palette_buffer);
+        self.screen_buffer.copy_from_slice(&*state.screen_buffer);
+
+        self.sprite_data = state.sprite_data.clone();
+        self.background_data = state.background_data.clone();
+        self.dot_data = state.dot_data.clone();
+
+        self.global_cyc = state.global_cyc;
+        self.cyc = state.cyc;
+        self.sl = state.sl;
+        self.frame = state.frame;
+
+        self.next_vblank_ppu_cyc = state.next_vblank_ppu_cyc;
+        self.next_vblank_cpu_cyc = state.next_vblank_cpu_cyc;
+        self.sprite_overflow_dot = state.sprite_overflow_dot;
+
+        self.nmi_cpu_cyc = state.nmi_cpu_cyc;
+        self.nmi_edge_cyc = state.nmi_edge_cyc;
+        self.vblank_suppressed = state.vblank_suppressed;
+
+        self.update_next_watch();
+    }
+}
+
+impl MemSegment for PPU {
+    fn read(&mut self, idx: u16) -> u8 {
+        let decay_cycles = self.timing.ms_to_ppu_cyc(DYN_LATCH_DECAY_MS);
+        self.reg.decay_latch(self.global_cyc, decay_cycles);
+        match idx % 8 {
+            0x0004 => {
+                let val = self.sprite_data.read(self.reg.oamaddr as u16);
+                self.reg.drive_latch(val, 0xFF)
+            }
+            0x0007 => {
+                let addr = self.reg.v;
+                match addr {
+                    0x0000...0x3EFF => {
+                        let old_buffer = self.ppudata_read_buffer;
+                        self.ppudata_read_buffer = self.ppu_mem.read(addr);
+                        self.incr_ppuaddr();
+                        self.reg.drive_latch(old_buffer, 0xFF)
+                    }
+                    0x3F00...0x3FFF => {
+                        let (mask, _) = self.color_modifiers();
+                        let read_result = self.ppu_mem.read(addr) & mask;
+                        self.incr_ppuaddr();
+                        self.ppudata_read_buffer = self.ppu_mem.read_bypass_palette(addr);
+                        // Palette entries are only six bits wide, the top two
+                        // bits come from the open bus.
+                        self.reg.drive_latch(read_result, 0b0011_1111)
+                    }
+                    x => invalid_address!(x),
+                }
+            }
+            0x0002 => {
+                if self.sl == self.timing.vblank_scanline {
+                    match self.cyc {
+                        // Reading on the dot before VBlank starts reads the
+                        // flag as clear and stops it being set this frame.
+                        0 => self.vblank_suppressed = true,
+                        // Reading on the same dot or the one after reads it
+                        // as set, but still cancels the NMI.
+                        1 | 2 => self.nmi_cpu_cyc = None,
+                        _ => (),
+                    }
+                }
+                self.reg.read(idx)
+            }
+            _ => self.reg.read(idx),
+        }
+    }
+
+    fn write(&mut self, idx: u16, val: u8) {
+        let decay_cycles = self.timing.ms_to_ppu_cyc(DYN_LATCH_DECAY_MS);
+        self.reg.decay_latch(self.global_cyc, decay_cycles);
+        match idx % 8 {
+            0x0000 => {
+                let was_enabled = self.reg.ppuctrl.generate_vblank_nmi();
+                self.reg.write(idx, val);
+                let enabled = self.reg.ppuctrl.generate_vblank_nmi();
+                if !was_enabled && enabled && self.reg.ppustat.contains(VBLANK) {
+                    // Enabling NMI during VBlank raises the line immediately.
+                    self.raise_nmi();
+                } else if was_enabled && !enabled &&
+                           self.global_cyc - self.nmi_edge_cyc <= 1
+                {
+                    // Disabling it right as it was raised cancels it.
+                    self.nmi_cpu_cyc = None;
+                }
+            }
+            0x0004 => {
+                self.reg.drive_latch(val, 0xFF);
+                self.sprite_data.write(self.reg.oamaddr as u16, val);
+                self.reg.incr_oamaddr();
+            }
+            0x0007 => {
+                self.reg.drive_latch(val, 0xFF);
+                self.ppu_mem.write(self.reg.v, val);
+                self.incr_ppuaddr();
+            }
+            _ => self.reg.write(idx, val),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cart::{Cart, ScreenMode};
+    use mappers::create_test_mapper;
+    use memory::MemSegment;
+    use ppu::ppu_reg::{PPUCtrl, PPUMask};
+    use screen::DummyScreen;
+    use std::cell::UnsafeCell;
+    use std::rc::Rc;
+
+    pub fn create_test_ppu() -> PPU {
+        create_test_ppu_with_rom(vec![0u8; 0x1000])
+    }
+
+    pub fn create_test_ppu_with_rom(chr_rom: Vec<u8>) -> PPU {
+        let mapper = create_test_mapper(vec![0u8; 0x1000], chr_rom, ScreenMode::FourScreen);
+        let cart = Cart::new(mapper);
+        let settings = Settings {
+            graphics_enabled: true,
+            ..Default::default()
+        };
+        PPU::new(
+            Rc::new(settings),
+            Rc::new(UnsafeCell::new(cart)),
+            Box::new(DummyScreen::default()),
+        )
+    }
+
+    fn create_dot_accurate_test_ppu(chr_rom: Vec<u8>) -> PPU {
+        let mapper = create_test_mapper(vec![0u8; 0x1000], chr_rom, ScreenMode::FourScreen);
+        let cart = Cart::new(mapper);
+        let settings = Settings {
+            graphics_enabled: true,
+            dot_accurate_ppu: true,
+            ..Default::default()
+        };
+        PPU::new(
+            Rc::new(settings),
+            Rc::new(UnsafeCell::new(cart)),
+            Box::new(DummyScreen::default()),
+        )
+    }
+
+    fn create_test_ppu_for_region(region: Region) -> PPU {
+        let mapper = create_test_mapper(
+            vec![0u8; 0x1000],
+            vec![0u8; 0x1000],
+            ScreenMode::FourScreen,
+        );
+        let cart = Cart::new(mapper);
+        let settings = Settings {
+            graphics_enabled: true,
+            region: Some(region),
+            ..Default::default()
+        };
+        PPU::new(
+            Rc::new(settings),
+            Rc::new(UnsafeCell::new(cart)),
+            Box::new(DummyScreen::default()),
+        )
+    }
+
+    pub fn create_test_ppu_with_mirroring(mode: ScreenMode) -> PPU {
+        let mapper = create_test_mapper(vec![0u8; 0x1000], vec![0u8; 0x1000], mode);
+        let cart = Cart::new(mapper);
+        let settings = Settings {
+            graphics_enabled: true,
+            ..Default::default()
+        };
+        PPU::new(
+            Rc::new(settings),
+            Rc::new(UnsafeCell::new(cart)),
+            Box::new(DummyScreen::default()),
+        )
+    }
+
+    #[test]
+    fn reading_oamdata_doesnt_increment_oamaddr() {
+        let mut ppu = create_test_ppu();
+        ppu.reg.oamaddr = 0;
+        ppu.read(0x2004);
+        assert_eq!(ppu.reg.oamaddr, 0);
+    }
+
+    #[test]
+    fn writing_oamdata_increments_oamaddr() {
+        let mut ppu = create_test_ppu();
+        ppu.reg.oamaddr = 0;
+        ppu.write(0x2004, 12);
+        assert_eq!(ppu.reg.oamaddr, 1);
+        ppu.reg.oamaddr = 255;
+        ppu.write(0x2004, 12);
+        assert_eq!(ppu.reg.oamaddr, 0);
+    }
+
+    #[test]
+    fn ppu_can_read_chr_rom() {
+        let mut chr_rom = vec![0u8; 0x2000];
+        chr_rom[0x0ABC] = 12;
+        chr_rom[0x0DBA] = 212;
+        let mut ppu = create_test_ppu_with_rom(chr_rom);
+
+        ppu.reg.v = 0x0ABC;
+        ppu.read(0x2007); // Dummy Read
+        assert_eq!(ppu.read(0x2007), 12);
+
+        ppu.reg.v = 0x0DBA;
+        ppu.read(0x2007); // Dummy Read
+        assert_eq!(ppu.read(0x2007), 212);
+    }
+
+    #[test]
+    fn ppu_can_read_write_vram() {
+        let mut ppu = create_test_ppu();
+
+        ppu.reg.v = 0x2ABC;
+        ppu.write(0x2007, 12);
+        ppu.reg.v = 0x2ABC;
+        ppu.read(0x2007); // Dummy read
+        assert_eq!(ppu.read(0x2007), 12);
+
+        ppu.reg.v = 0x2DBA;
+        ppu.write(0x2007, 212);
+        ppu.reg.v = 0x2DBA;
+        ppu.read(0x2007); // Dummy Read
+        assert_eq!(ppu.read(0x2007), 212);
+
+        // Mirroring
+        ppu.reg.v = 0x2EFC;
+        ppu.write(0x2007, 128);
+        ppu.reg.v = 0x3EFC;
+        ppu.read(0x2007); // Dummy Read
+        assert_eq!(ppu.read(0x2007), 128);
+    }
+
+    #[test]
+    fn ppu_needs_no_dummy_read_for_palette_data() {
+        let mut ppu = create_test_ppu();
+        ppu.reg.v = 0x3F16;
+        ppu.write(0x2007, 21);
+        ppu.reg.v = 0x3F16;
+        assert_eq!(ppu.read(0x2007), 21);
+    }
+
+    #[test]
+    fn accessing_ppudata_increments_ppuaddr() {
+        let mut ppu = create_test_ppu();
+        ppu.reg.v = 0x2000;
+        ppu.read(0x2007);
+        assert_eq!(ppu.reg.v, 0x2001);
+        ppu.write(0x2007, 0);
+        assert_eq!(ppu.reg.v, 0x2002);
+    }
+
+    #[test]
+    fn accessing_ppudata_increments_ppuaddr_by_32_when_ctrl_flag_is_set() {
+        let mut ppu = create_test_ppu();
+        ppu.reg.ppuctrl = PPUCtrl::new(0b0000_0100);
+        ppu.reg.v = 0x2000;
+        ppu.read(0x2007);
+        assert_eq!(ppu.reg.v, 0x2020);
+        ppu.write(0x2007, 0);
+        assert_eq!(ppu.reg.v, 0x2040);
+    }
+
+    #[test]
+    fn colorize_applies_greyscale_and_emphasis() {
+        let mut ppu = create_test_ppu();
+        ppu.reg.v = 0x3F01;
+        ppu.write(0x2007, 0x16);
+        ppu.palette_buffer[0] = PaletteIndex::from_packed(0x01);
+
+        ppu.colorize(0, 16);
+        assert_eq!(Color::from_bits(0x16), ppu.screen_buffer[0]);
+
+        ppu.reg.ppumask = PPUMask::from_bits_truncate(0b1010_0001);
+        ppu.colorize(0, 16);
+        assert_eq!(0x10, ppu.screen_buffer[0].color());
+        assert_eq!(0b101, ppu.screen_buffer[0].emphasis());
+    }
+
+    /// Creates a PPU whose OAM is filled with the given sprites, followed by
+    /// sprites which are nowhere near scanline 10.
+    fn ppu_with_sprites(sprites: &[[u8; 4]]) -> PPU {
+        let mut ppu = create_test_ppu();
+        for idx in 0..256 {
+            ppu.sprite_data.write(idx, 0xF0);
+        }
+        for (n, sprite) in sprites.iter().enumerate() {
+            for (m, &byte) in sprite.iter().enumerate() {
+                ppu.sprite_data.write((n * 4 + m) as u16, byte);
+            }
+        }
+        ppu
+    }
+
+    #[test]
+    fn sprite_overflow_set_for_ninth_sprite() {
+        let ppu = ppu_with_sprites(&[[10, 0xF0, 0, 0xF0]; 9]);
+        assert_eq!(Some(129), ppu.sprite_data.find_overflow(10, &ppu.reg));
+
+        let ppu = ppu_with_sprites(&[[10, 0xF0, 0, 0xF0]; 8]);
+        assert_eq!(None, ppu.sprite_data.find_overflow(10, &ppu.reg));
+    }
+
+    #[test]
+    fn sprite_overflow_misses_sprites_after_diagonal_scan_starts() {
+        let mut sprites = vec![[10, 0xF0, 0, 0xF0]; 8];
+        sprites.push([200, 0xF0, 0, 0xF0]);
+        // This sprite is on the line, but its tile number is checked instead.
+        sprites.push([10, 0xF0, 0, 0xF0]);
+        let ppu = ppu_with_sprites(&sprites);
+        assert_eq!(None, ppu.sprite_data.find_overflow(10, &ppu.reg));
+    }
+
+    #[test]
+    fn sprite_overflow_false_positive_from_diagonal_scan() {
+        let mut sprites = vec![[10, 0xF0, 0, 0xF0]; 8];
+        sprites.push([200, 0xF0, 0, 0xF0]);
+        // Not on the line, but the tile number is in range.
+        sprites.push([200, 10, 0, 0xF0]);
+        let ppu = ppu_with_sprites(&sprites);
+        assert_eq!(Some(131), ppu.sprite_data.find_overflow(10, &ppu.reg));
+    }
+
+    #[test]
+    fn sprite_overflow_flag_set_during_rendering() {
+        let mut ppu = ppu_with_sprites(&[[10, 0xF0, 0, 0xF0]; 9]);
+        ppu.reg.ppumask = PPUMask::from_bits_truncate(0b0001_1000);
+        ppu.sl = 10;
+        ppu.cyc = 0;
+        ppu.run_cycle(true);
+        assert!(!ppu.reg.ppustat.contains(SPRITE_OVERFLOW));
+        ppu.cyc = 129;
+        ppu.run_cycle(true);
+        assert!(ppu.reg.ppustat.contains(SPRITE_OVERFLOW));
+    }
+
+    /// A PPU with every background tile and sprite 0 solid, and sprite 0 at
+    /// (20, 11), starting at the pre-render scanline.
+    fn create_solid_tile_dot_ppu() -> PPU {
+        let mut chr_rom = vec![0u8; 0x1000];
+        for row in 0x10..0x18 {
+            chr_rom[row] = 0xFF;
+        }
+        let mut ppu = create_dot_accurate_test_ppu(chr_rom);
+        for idx in 0x2000..0x23C0 {
+            ppu.ppu_mem.write(idx, 1);
+        }
+        for (idx, &byte) in [10, 1, 0, 20].iter().enumerate() {
+            ppu.sprite_data.write(idx as u16, byte);
+        }
+        for idx in 4..256 {
+            ppu.sprite_data.write(idx, 0xFF);
+        }
+        ppu.reg.ppumask = PPUMask::from_bits_truncate(0b0001_1110);
+        ppu.sl = -1;
+        ppu.cyc = 0;
+        ppu
+    }
+
+    fn run_dots_until(ppu: &mut PPU, sl: i16, cyc: u16) {
+        while (ppu.sl, ppu.cyc) != (sl, cyc) {
+            ppu.tick_cycle();
+            ppu.run_cycle(true);
+        }
+    }
+
+    #[test]
+    fn dot_accurate_sprite_0_hit_happens_on_exact_dot() {
+        let mut ppu = create_solid_tile_dot_ppu();
+        run_dots_until(&mut ppu, 11, 20);
+        assert!(!ppu.reg.ppustat.contains(SPRITE_0));
+        run_dots_until(&mut ppu, 11, 21);
+        assert!(ppu.reg.ppustat.contains(SPRITE_0));
+    }
+
+    #[test]
+    fn dot_accurate_renderer_draws_background_and_sprites() {
+        let mut ppu = create_solid_tile_dot_ppu();
+        run_dots_until(&mut ppu, 12, 0);
+
+        let line = &ppu.palette_buffer[11 * SCREEN_WIDTH..12 * SCREEN_WIDTH];
+        assert_eq!(PaletteIndex::from_packed(0x01), line[0]);
+        assert_eq!(PaletteIndex::from_packed(0x11), line[20]);
+        assert_eq!(PaletteIndex::from_packed(0x11), line[27]);
+        assert_eq!(PaletteIndex::from_packed(0x01), line[28]);
+    }
+
+    #[test]
+    fn accessing_ppudata_while_rendering_increments_coarse_x_and_y() {
+        let mut ppu = create_test_ppu();
+        ppu.reg.ppumask = PPUMask::from_bits_truncate(0b0000_1000);
+        ppu.sl = 100;
+        ppu.reg.v = 0x2000;
+        ppu.read(0x2007);
+        assert_eq!(ppu.reg.v, 0x3001);
+    }
+
+    fn run_one_dot(ppu: &mut PPU) {
+        ppu.tick_cycle();
+        ppu.run_cycle(false);
+    }
+
+    /// A PPU on the dot before VBlank starts, with NMIs enabled.
+    fn create_ppu_before_vblank() -> PPU {
+        let mut ppu = create_test_ppu();
+        ppu.frame = 1;
+        ppu.write(0x2000, 0b1000_0000);
+        ppu
+    }
+
+    #[test]
+    fn vblank_raises_nmi() {
+        let mut ppu = create_ppu_before_vblank();
+        run_one_dot(&mut ppu);
+        assert!(ppu.reg.ppustat.contains(VBLANK));
+        assert!(ppu.nmi_cpu_cyc.is_some());
+    }
+
+    #[test]
+    fn reading_ppustat_before_vblank_suppresses_flag_and_nmi() {
+        let mut ppu = create_ppu_before_vblank();
+        assert_eq!(0, ppu.read(0x2002) & 0b1000_0000);
+        run_one_dot(&mut ppu);
+        assert!(!ppu.reg.ppustat.contains(VBLANK));
+        assert_eq!(None, ppu.nmi_cpu_cyc);
+    }
+
+    #[test]
+    fn reading_ppustat_as_vblank_starts_suppresses_nmi() {
+        let mut ppu = create_ppu_before_vblank();
+        run_one_dot(&mut ppu);
+        assert_eq!(0b1000_0000, ppu.read(0x2002) & 0b1000_0000);
+        assert_eq!(None, ppu.nmi_cpu_cyc);
+    }
+
+    #[test]
+    fn enabling_nmi_during_vblank_raises_nmi() {
+        let mut ppu = create_test_ppu();
+        ppu.reg.ppustat.insert(VBLANK);
+        ppu.write(0x2000, 0b1000_0000);
+        assert!(ppu.nmi_cpu_cyc.is_some());
+
+        // It's only raised on the transition
+        ppu.nmi_cpu_cyc = None;
+        ppu.write(0x2000, 0b1000_0000);
+        assert_eq!(None, ppu.nmi_cpu_cyc);
+    }
+
+    #[test]
+    fn disabling_nmi_as_vblank_starts_cancels_nmi() {
+        let mut ppu = create_ppu_before_vblank();
+        run_one_dot(&mut ppu);
+        ppu.write(0x2000, 0b0000_0000);
+        assert_eq!(None, ppu.nmi_cpu_cyc);
+    }
+
+    #[test]
+    fn odd_frames_skip_a_dot_only_when_rendering() {
+        let mut ppu = create_test_ppu();
+        ppu.frame = 1;
+        ppu.sl = -1;
+        ppu.cyc = 338;
+        ppu.tick_cycle();
+        ppu.run_cycle(true);
+        assert_eq!(340, ppu.cyc);
+
+        ppu.sl = -1;
+        ppu.cyc = 338;
+        ppu.tick_cycle();
+        ppu.run_cycle(false);
+        assert_eq!(339, ppu.cyc);
+    }
+
+    fn measure_frame_dots(ppu: &mut PPU) -> u64 {
+        ppu.sl = -1;
+        ppu.cyc = 0;
+        let mut dots = 0;
+        loop {
+            run_one_dot(ppu);
+            dots += 1;
+            if ppu.sl == -1 && ppu.cyc == 0 {
+                return dots;
+            }
+        }
+    }
+
+    #[test]
+    fn frame_length_depends_on_region() {
+        let mut ppu = create_test_ppu_for_region(Region::NTSC);
+        assert_eq!(341 * 262, measure_frame_dots(&mut ppu));
+        let mut ppu = create_test_ppu_for_region(Region::PAL);
+        assert_eq!(341 * 312, measure_frame_dots(&mut ppu));
+        let mut ppu = create_test_ppu_for_region(Region::Dendy);
+        assert_eq!(341 * 312, measure_frame_dots(&mut ppu));
+    }
+
+    #[test]
+    fn pal_runs_sixteen_dots_every_five_cpu_cycles() {
+        let mut ppu = create_test_ppu_for_region(Region::PAL);
+        ppu.run_to(5);
+        assert_eq!(16, ppu.global_cyc);
+        ppu.run_to(6);
+        assert_eq!(19, ppu.global_cyc);
+        ppu.run_to(10);
+        assert_eq!(32, ppu.global_cyc);
+        assert_eq!(7, ppu.timing.ppu_to_cpu_cyc(20));
+    }
+
+    #[test]
+    fn converts_milliseconds_with_the_regions_clock() {
+        assert_eq!(5369319, Timing::new(Region::NTSC).ms_to_ppu_cyc(1000));
+        assert_eq!(5320342, Timing::new(Region::PAL).ms_to_ppu_cyc(1000));
+        assert_eq!(5320344, Timing::new(Region::Dendy).ms_to_ppu_cyc(1000));
+    }
+
+    #[test]
+    fn dendy_vblank_starts_on_scanline_291() {
+        let mut ppu = create_test_ppu_for_region(Region::Dendy);
+        ppu.frame = 1;
+        ppu.sl = 240;
+        ppu.cyc = 340;
+        while ppu.sl < 291 {
+            run_one_dot(&mut ppu);
+            assert!(!ppu.reg.ppustat.contains(VBLANK));
+        }
+        run_one_dot(&mut ppu);
+        assert!(ppu.reg.ppustat.contains(VBLANK));
+    }
+
+    #[test]
+    fn pal_odd_frames_dont_skip_a_dot() {
+        let mut ppu = create_test_ppu_for_region(Region::PAL);
+        ppu.frame = 1;
+        ppu.sl = -1;
+        ppu.cyc = 338;
+        ppu.tick_cycle();
+        ppu.run_cycle(true);
+        assert_eq!(339, ppu.cyc);
+    }
+
+    #[test]
+    fn watched_dots_produce_events_on_time() {
+        let mut ppu = create_test_ppu();
+        ppu.watch_dot(10, 20);
+        // 31 scanlines and 20 dots after the starting position
+        let dots = 31 * 341 + 20;
+        assert_eq!(Some((dots + 2) / 3), ppu.next_watch_cpu_cyc);
+
+        ppu.run_to((dots + 2) / 3);
+        let events = ppu.take_events();
+        assert_eq!(1, events.len());
+        assert_eq!(PPUEventKind::Dot, events[0].kind);
+        assert_eq!((10, 20), (events[0].scanline, events[0].dot));
+        assert!(ppu.take_events().is_empty());
+    }
+}
@@ -0,0 +1,440 @@
+//! Recording the emulator's output losslessly: video as uncompressed
+//! YUV4MPEG2 and audio as 16-bit WAV, either to files or to pipes feeding an
+//! external encoder.
+//!
+//! The recorder wraps a `Screen` and an `AudioOut`, so whatever they were
+//! showing and playing carries on as normal. Video is written at the exact
+//! frame rate of the emulated console, and the audio is kept within a couple
+//! of frames of it (padding with silence if the APU isn't producing any) so
+//! the two stay in sync however long the recording runs.
+
+pub mod wav;
+pub mod y4m;
+
+use Region;
+use apu::{ALL_CHANNELS, Channel, Sample};
+use audio::{AudioOut, AudioStats, StemOut};
+use ppu::{Color, SCREEN_BUFFER_SIZE, SCREEN_HEIGHT, SCREEN_WIDTH};
+use recording::wav::WavWriter;
+use recording::y4m::Y4mWriter;
+use screen::{Palette, RgbImage, Screen};
+use std::cell::RefCell;
+use std::cmp;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+/// How far, in frames, the audio may run ahead of or behind the video
+/// before it's trimmed or padded. The APU hands over samples in chunks of
+/// about a frame, so the two are never exactly aligned.
+const SYNC_SLACK_FRAMES: u64 = 2;
+
+/// Somewhere to write a recording.
+pub enum Sink {
+    /// A regular file. WAV headers are fixed up with the final length when
+    /// the recording finishes.
+    File(BufWriter<File>),
+    /// A pipe or other stream which can't be rewound, such as the input of
+    /// an encoder. Everything is written in streaming form.
+    Pipe(Box<Write>),
+}
+
+impl Sink {
+    /// Opens `path` for writing. `-` means standard output, and anything
+    /// which isn't a regular file (such as a named pipe) is written to as a
+    /// pipe.
+    pub fn open(path: &Path) -> io::Result<Sink> {
+        if path == Path::new("-") {
+            return Ok(Sink::Pipe(Box::new(BufWriter::new(io::stdout()))));
+        }
+        let file = try!(File::create(path));
+        if try!(file.metadata()).is_file() {
+            Ok(Sink::File(BufWriter::new(file)))
+        } else {
+            Ok(Sink::Pipe(Box::new(BufWriter::new(file))))
+        }
+    }
+
+    pub fn is_seekable(&self) -> bool {
+        match *self {
+            Sink::File(_) => true,
+            Sink::Pipe(_) => false,
+        }
+    }
+}
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            Sink::File(ref mut file) => file.write(buf),
+            Sink::Pipe(ref mut pipe) => pipe.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            Sink::File(ref mut file) => file.flush(),
+            Sink::Pipe(ref mut pipe) => pipe.flush(),
+        }
+    }
+}
+
+impl Seek for Sink {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match *self {
+            Sink::File(ref mut file) => file.seek(pos),
+            Sink::Pipe(_) => Err(io::Error::new(io::ErrorKind::Other, "can't seek in a pipe")),
+        }
+    }
+}
+
+struct Recording {
+    video: Option<Y4mWriter<Sink>>,
+    audio: Option<WavWriter<Sink>>,
+    palette: Palette,
+    frame_rate: (u64, u64),
+    sample_rate: u64,
+    channels: u64,
+    frames: u64,
+}
+
+impl Recording {
+    /// The number of samples which belong with the frames recorded so far.
+    fn expected_samples(&self) -> u64 {
+        self.frames * self.sample_rate * self.frame_rate.1 / self.frame_rate.0
+    }
+
+    fn slack(&self) -> u64 {
+        SYNC_SLACK_FRAMES * self.sample_rate * self.frame_rate.1 / self.frame_rate.0
+    }
+
+    fn write_frame(&mut self, buf: &[Color; SCREEN_BUFFER_SIZE]) {
+        self.frames += 1;
+        let result = match self.video {
+            Some(ref mut video) => video.write_frame(&RgbImage::from_frame(buf, &self.palette)),
+            None => Ok(()),
+        };
+        if let Err(err) = result {
+            eprintln!("Failed to record video, stopping: {}", err);
+            self.video = None;
+        }
+
+        let minimum = self.expected_samples().saturating_sub(self.slack());
+        self.pad_audio(minimum);
+    }
+
+    fn write_samples(&mut self, samples: &[Sample]) {
+        let maximum = self.expected_samples() + self.slack();
+        let result = match self.audio {
+            Some(ref mut audio) => {
+                let room = maximum.saturating_sub(audio.samples_written()) * self.channels;
+                let count = cmp::min(room, samples.len() as u64) as usize;
+                audio.write_samples(&samples[..count])
+            }
+            None => Ok(()),
+        };
+        if let Err(err) = result {
+            eprintln!("Failed to record audio, stopping: {}", err);
+            self.audio = None;
+        }
+    }
+
+    /// Writes silence until at least `samples` samples have been written.
+    fn pad_audio(&mut self, samples: u64) {
+        let result = match self.audio {
+            Some(ref mut audio) if audio.samples_written() < samples => {
+                let missing = (samples - audio.samples_written()) as usize;
+                audio.write_samples(&vec![0; missing * self.channels as usize])
+            }
+            _ => Ok(()),
+        };
+        if let Err(err) = result {
+            eprintln!("Failed to record audio, stopping: {}", err);
+            self.audio = None;
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        let expected = self.expected_samples();
+        self.pad_audio(expected);
+        if let Some(mut video) = self.video.take() {
+            try!(video.flush());
+        }
+        if let Some(mut audio) = self.audio.take() {
+            try!(audio.flush());
+            if audio.get_ref().is_seekable() {
+                try!(audio.fix_header());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Recording {
+    fn drop(&mut self) {
+        if let Err(err) = self.finish() {
+            eprintln!("Failed to finish recording: {}", err);
+        }
+    }
+}
+
+/// Records the frames and samples passing through the screen and audio
+/// output it wraps.
+pub struct Recorder {
+    recording: Rc<RefCell<Recording>>,
+}
+
+impl Recorder {
+    /// Starts a recording of the given region's output. `sample_rate` and
+    /// `channels` must match the `AudioOut` which will be wrapped. Either
+    /// sink may be left out to record only video or only audio.
+    pub fn new(
+        region: Region,
+        sample_rate: f64,
+        channels: usize,
+        video: Option<Sink>,
+        audio: Option<Sink>,
+    ) -> io::Result<Recorder> {
+        let frame_rate = region.frame_rate();
+        let video = match video {
+            Some(sink) => Some(try!(
+                Y4mWriter::new(sink, SCREEN_WIDTH, SCREEN_HEIGHT, frame_rate)
+            )),
+            None => None,
+        };
+        let audio = match audio {
+            Some(sink) => Some(try!(WavWriter::new(sink, sample_rate as u32, channels as u16))),
+            None => None,
+        };
+        let recording = Recording {
+            video: video,
+            audio: audio,
+            palette: Palette::default(),
+            frame_rate: frame_rate,
+            sample_rate: sample_rate as u64,
+            channels: channels as u64,
+            frames: 0,
+        };
+        Ok(Recorder { recording: Rc::new(RefCell::new(recording)) })
+    }
+
+    /// Returns a screen which records each frame, then draws it to `screen`.
+    pub fn wrap_screen(&self, screen: Box<Screen>) -> Box<Screen> {
+        Box::new(RecordingScreen {
+            recording: self.recording.clone(),
+            delegate: screen,
+        })
+    }
+
+    /// Returns an audio output which records the samples, then plays them
+    /// on `audio_out`.
+    pub fn wrap_audio(&self, audio_out: Box<AudioOut>) -> Box<AudioOut> {
+        Box::new(RecordingAudioOut {
+            recording: self.recording.clone(),
+            delegate: audio_out,
+        })
+    }
+
+    pub fn frames_written(&self) -> u64 {
+        self.recording.borrow().frames
+    }
+
+    /// The number of audio samples written, or zero if audio isn't being
+    /// recorded.
+    pub fn samples_written(&self) -> u64 {
+        self.recording
+            .borrow()
+            .audio
+            .as_ref()
+            .map(|audio| audio.samples_written())
+            .unwrap_or(0)
+    }
+
+    /// Pads the audio to the length of the video and flushes both. This
+    /// also happens when the screen and audio output are dropped, but
+    /// calling it explicitly reports any errors.
+    pub fn finish(self) -> io::Result<()> {
+        let result = self.recording.borrow_mut().finish();
+        result
+    }
+}
+
+struct RecordingScreen {
+    recording: Rc<RefCell<Recording>>,
+    delegate: Box<Screen>,
+}
+
+impl Screen for RecordingScreen {
+    fn draw(&mut self, buf: &[Color; SCREEN_BUFFER_SIZE]) {
+        self.recording.borrow_mut().write_frame(buf);
+        self.delegate.draw(buf);
+    }
+
+    fn set_palette(&mut self, palette: &Palette) {
+        self.recording.borrow_mut().palette = palette.clone();
+        self.delegate.set_palette(palette);
+    }
+}
+
+struct RecordingAudioOut {
+    recording: Rc<RefCell<Recording>>,
+    delegate: Box<AudioOut>,
+}
+
+impl AudioOut for RecordingAudioOut {
+    fn play(&mut self, buffer: &[Sample]) {
+        self.recording.borrow_mut().write_samples(buffer);
+        self.delegate.play(buffer);
+    }
+
+    fn sample_rate(&self) -> f64 {
+        self.delegate.sample_rate()
+    }
+
+    /// Dynamic rate control is left off while recording. It would change
+    /// the rate of the samples recorded from the one in the WAV header, and
+    /// keeping in sync with the video would then cut or pad them.
+    fn fill_level(&self) -> Option<f64> {
+        None
+    }
+
+    fn stats(&self) -> AudioStats {
+        self.delegate.stats()
+    }
+}
+
+/// Writes each sound channel's output to its own mono WAV file, named after
+/// the channel, for mixing or analysing separately.
+pub struct StemWriter {
+    writers: Vec<WavWriter<BufWriter<File>>>,
+}
+
+impl StemWriter {
+    /// Creates the files in `directory`, replacing any which are already
+    /// there.
+    pub fn new(directory: &Path, sample_rate: f64) -> io::Result<StemWriter> {
+        try!(fs::create_dir_all(directory));
+        let mut writers = vec![];
+        for channel in ALL_CHANNELS.iter() {
+            let path = directory.join(format!("{}.wav", channel.name()));
+            let file = BufWriter::new(try!(File::create(path)));
+            writers.push(try!(WavWriter::new(file, sample_rate as u32, 1)));
+        }
+        Ok(StemWriter { writers: writers })
+    }
+}
+
+impl StemOut for StemWriter {
+    fn play(&mut self, channel: Channel, buffer: &[Sample]) {
+        if let Err(err) = self.writers[channel as usize].write_samples(buffer) {
+            eprintln!("Failed to write {} stem: {}", channel.name(), err);
+        }
+    }
+}
+
+impl Drop for StemWriter {
+    fn drop(&mut self) {
+        for writer in &mut self.writers {
+            if let Err(err) = writer.fix_header() {
+                eprintln!("Failed to finish stem: {}", err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use audio::DummyAudioOut;
+    use screen::DummyScreen;
+
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn record(recorder: &Recorder, frames: u64, samples_per_frame: usize) {
+        let mut screen = recorder.wrap_screen(Box::new(DummyScreen::default()));
+        let mut audio = recorder.wrap_audio(Box::new(DummyAudioOut));
+        let buf = [Color::from_bits(0x0F); SCREEN_BUFFER_SIZE];
+        for _ in 0..frames {
+            audio.play(&vec![0; samples_per_frame * 2]);
+            screen.draw(&buf);
+        }
+    }
+
+    struct FillingAudioOut;
+
+    impl AudioOut for FillingAudioOut {
+        fn play(&mut self, _: &[Sample]) {}
+
+        fn sample_rate(&self) -> f64 {
+            44100.0
+        }
+
+        fn fill_level(&self) -> Option<f64> {
+            Some(0.9)
+        }
+    }
+
+    fn audio_only_recorder() -> Recorder {
+        let audio = Sink::Pipe(Box::new(io::sink()));
+        Recorder::new(Region::NTSC, 44100.0, 2, None, Some(audio)).unwrap()
+    }
+
+    // At 44100Hz, an NTSC frame has about 733.8 samples, and two frames'
+    // worth of slack is 1467 samples.
+
+    #[test]
+    fn missing_audio_is_padded_with_silence() {
+        let recorder = audio_only_recorder();
+        record(&recorder, 600, 0);
+        assert_eq!(600, recorder.frames_written());
+        assert_eq!(440274 - 1467, recorder.samples_written());
+    }
+
+    #[test]
+    fn excess_audio_is_trimmed() {
+        let recorder = audio_only_recorder();
+        record(&recorder, 600, 1000);
+        // The last samples arrived when 599 frames had been drawn
+        assert_eq!(439541 + 1467, recorder.samples_written());
+    }
+
+    #[test]
+    fn rate_control_is_off_while_recording() {
+        let recorder = audio_only_recorder();
+        let audio = recorder.wrap_audio(Box::new(FillingAudioOut));
+        assert_eq!(None, audio.fill_level());
+    }
+
+    #[test]
+    fn finishing_pads_audio_to_the_video_length() {
+        let video = Rc::new(RefCell::new(vec![]));
+        let audio = Rc::new(RefCell::new(vec![]));
+        let recorder = Recorder::new(
+            Region::NTSC,
+            44100.0,
+            2,
+            Some(Sink::Pipe(Box::new(SharedBuffer(video.clone())))),
+            Some(Sink::Pipe(Box::new(SharedBuffer(audio.clone())))),
+        ).unwrap();
+        record(&recorder, 100, 700);
+        recorder.finish().unwrap();
+
+        let header = b"YUV4MPEG2 W256 H240 F3579546:59561 Ip A1:1 C444\n";
+        let frame_size = b"FRAME\n".len() + SCREEN_BUFFER_SIZE * 3;
+        assert_eq!(header.len() + frame_size * 100, video.borrow().len());
+        assert_eq!(44 + 73379 * 2 * 2, audio.borrow().len());
+    }
+}
@@ -0,0 +1,137 @@
+//! 16-bit PCM WAV audio output.
+
+use apu::Sample;
+use std::io;
+use std::io::{Seek, SeekFrom, Write};
+
+const HEADER_SIZE: u32 = 44;
+
+/// The size written into the header while the length isn't known yet.
+/// Decoders reading from a pipe treat it as "until the end of the stream".
+const UNKNOWN_SIZE: u32 = 0xFFFF_FFFF;
+
+fn write_u16(out: &mut Write, value: u16) -> io::Result<()> {
+    out.write_all(&[value as u8, (value >> 8) as u8])
+}
+
+fn write_u32(out: &mut Write, value: u32) -> io::Result<()> {
+    out.write_all(
+        &[
+            value as u8,
+            (value >> 8) as u8,
+            (value >> 16) as u8,
+            (value >> 24) as u8,
+        ],
+    )
+}
+
+/// Writes interleaved 16-bit samples as a WAV stream. The header is written
+/// for streaming, with unknown sizes; call `fix_header` at the end to fill
+/// them in if the output is seekable.
+pub struct WavWriter<W: Write> {
+    out: W,
+    channels: u16,
+    bytes: Vec<u8>,
+    samples: u64,
+}
+
+impl<W: Write> WavWriter<W> {
+    pub fn new(mut out: W, sample_rate: u32, channels: u16) -> io::Result<WavWriter<W>> {
+        let block_align = channels * 2;
+        try!(out.write_all(b"RIFF"));
+        try!(write_u32(&mut out, UNKNOWN_SIZE));
+        try!(out.write_all(b"WAVEfmt "));
+        try!(write_u32(&mut out, 16));
+        try!(write_u16(&mut out, 1)); // PCM
+        try!(write_u16(&mut out, channels));
+        try!(write_u32(&mut out, sample_rate));
+        try!(write_u32(&mut out, sample_rate * block_align as u32));
+        try!(write_u16(&mut out, block_align));
+        try!(write_u16(&mut out, 16));
+        try!(out.write_all(b"data"));
+        try!(write_u32(&mut out, UNKNOWN_SIZE));
+        Ok(WavWriter {
+            out: out,
+            channels: channels,
+            bytes: vec![],
+            samples: 0,
+        })
+    }
+
+    /// Writes interleaved samples. The length must be a multiple of the
+    /// number of channels.
+    pub fn write_samples(&mut self, samples: &[Sample]) -> io::Result<()> {
+        assert_eq!(0, samples.len() % self.channels as usize);
+        self.bytes.clear();
+        for &sample in samples {
+            self.bytes.push(sample as u8);
+            self.bytes.push((sample >> 8) as u8);
+        }
+        try!(self.out.write_all(&self.bytes));
+        self.samples += (samples.len() / self.channels as usize) as u64;
+        Ok(())
+    }
+
+    /// The number of samples written per channel.
+    pub fn samples_written(&self) -> u64 {
+        self.samples
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.out
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    /// Fills in the sizes in the header, then returns to the end of the
+    /// stream. Sizes over 4GB are left unknown.
+    pub fn fix_header(&mut self) -> io::Result<()> {
+        let data_size = self.samples * self.channels as u64 * 2;
+        if data_size + HEADER_SIZE as u64 > UNKNOWN_SIZE as u64 {
+            return Ok(());
+        }
+        let data_size = data_size as u32;
+        try!(self.out.seek(SeekFrom::Start(4)));
+        try!(write_u32(&mut self.out, data_size + HEADER_SIZE - 8));
+        try!(self.out.seek(SeekFrom::Start(HEADER_SIZE as u64 - 4)));
+        try!(write_u32(&mut self.out, data_size));
+        try!(self.out.seek(SeekFrom::End(0)));
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn header_sizes_are_fixed_at_the_end() {
+        let mut writer = WavWriter::new(Cursor::new(vec![]), 44100, 1).unwrap();
+        writer.write_samples(&[1, -2, 0x1234]).unwrap();
+        assert_eq!(3, writer.samples_written());
+        let streamed = writer.into_inner().into_inner();
+        assert_eq!(&[0xFF; 4], &streamed[4..8]);
+        assert_eq!(&[0xFF; 4], &streamed[40..44]);
+
+        let mut writer = WavWriter::new(Cursor::new(vec![]), 44100, 1).unwrap();
+        writer.write_samples(&[1, -2, 0x1234]).unwrap();
+        writer.fix_header().unwrap();
+        let out = writer.into_inner().into_inner();
+
+        assert_eq!(50, out.len());
+        assert_eq!(b"RIFF", &out[0..4]);
+        assert_eq!(&[42, 0, 0, 0], &out[4..8]);
+        assert_eq!(&[0x44, 0xAC, 0, 0], &out[24..28]);
+        assert_eq!(&[6, 0, 0, 0], &out[40..44]);
+        assert_eq!(&[1, 0, 0xFE, 0xFF, 0x34, 0x12], &out[44..]);
+    }
+}
@@ -0,0 +1,111 @@
+//! Uncompressed YUV4MPEG2 video output.
+
+use screen::RgbImage;
+use std::io;
+use std::io::Write;
+
+/// Converts an RGB pixel to limited-range BT.601 Y, Cb and Cr.
+#[cfg_attr(rustfmt, rustfmt_skip)]
+fn rgb_to_yuv(rgb: [u8; 3]) -> [u8; 3] {
+    let (r, g, b) = (rgb[0] as i32, rgb[1] as i32, rgb[2] as i32);
+    let y = ((66 * r + 129 * g + 25 * b + 128) >> 8) + 16;
+    let u = ((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128;
+    let v = ((112 * r - 94 * g - 18 * b + 128) >> 8) + 128;
+    [y as u8, u as u8, v as u8]
+}
+
+/// Writes frames as a YUV4MPEG2 stream with full-resolution (4:4:4) chroma,
+/// which most encoders, including ffmpeg, can read directly from a pipe.
+pub struct Y4mWriter<W: Write> {
+    out: W,
+    width: usize,
+    height: usize,
+    planes: Vec<u8>,
+    frames: u64,
+}
+
+impl<W: Write> Y4mWriter<W> {
+    /// Writes the stream header. The frame rate is given as a fraction.
+    pub fn new(
+        mut out: W,
+        width: usize,
+        height: usize,
+        frame_rate: (u64, u64),
+    ) -> io::Result<Y4mWriter<W>> {
+        try!(write!(
+            out,
+            "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C444\n",
+            width,
+            height,
+            frame_rate.0,
+            frame_rate.1
+        ));
+        Ok(Y4mWriter {
+            out: out,
+            width: width,
+            height: height,
+            planes: vec![0; width * height * 3],
+            frames: 0,
+        })
+    }
+
+    /// Writes a frame, which must be the size given in the header.
+    pub fn write_frame(&mut self, image: &RgbImage) -> io::Result<()> {
+        assert_eq!((self.width, self.height), (image.width(), image.height()));
+        let plane_size = self.width * self.height;
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let yuv = rgb_to_yuv(image.pixel(x, y));
+                let offset = y * self.width + x;
+                self.planes[offset] = yuv[0];
+                self.planes[plane_size + offset] = yuv[1];
+                self.planes[plane_size * 2 + offset] = yuv[2];
+            }
+        }
+        try!(self.out.write_all(b"FRAME\n"));
+        try!(self.out.write_all(&self.planes));
+        self.frames += 1;
+        Ok(())
+    }
+
+    pub fn frames_written(&self) -> u64 {
+        self.frames
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_black_and_white_to_video_range() {
+        assert_eq!([16, 128, 128], rgb_to_yuv([0, 0, 0]));
+        assert_eq!([235, 128, 128], rgb_to_yuv([255, 255, 255]));
+    }
+
+    #[test]
+    fn writes_header_and_planar_frames() {
+        let mut image = RgbImage::new(2, 1);
+        image.set_pixel(1, 0, [255, 255, 255]);
+
+        let mut writer = Y4mWriter::new(vec![], 2, 1, (3579546, 59561)).unwrap();
+        writer.write_frame(&image).unwrap();
+        assert_eq!(1, writer.frames_written());
+
+        let out = writer.into_inner();
+        let header = b"YUV4MPEG2 W2 H1 F3579546:59561 Ip A1:1 C444\n";
+        assert_eq!(&header[..], &out[..header.len()]);
+        assert_eq!(
+            &b"FRAME\n\x10\xEB\x80\x80\x80\x80"[..],
+            &out[header.len()..]
+        );
+    }
+}
@@ -0,0 +1,74 @@
+use ppu::{Color, SCREEN_BUFFER_SIZE, SCREEN_HEIGHT, SCREEN_WIDTH};
+use screen::Palette;
+
+/// A packed 24-bit RGB image, stored row by row with no padding.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RgbImage {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+impl RgbImage {
+    /// Creates a black image.
+    pub fn new(width: usize, height: usize) -> RgbImage {
+        RgbImage {
+            width: width,
+            height: height,
+            pixels: vec![0; width * height * 3],
+        }
+    }
+
+    /// Wraps existing pixel data, which must be `width * height * 3` bytes.
+    pub fn from_pixels(width: usize, height: usize, pixels: Vec<u8>) -> RgbImage {
+        assert_eq!(width * height * 3, pixels.len());
+        RgbImage {
+            width: width,
+            height: height,
+            pixels: pixels,
+        }
+    }
+
+    /// Converts a frame from the PPU using the given palette.
+    pub fn from_frame(buf: &[Color; SCREEN_BUFFER_SIZE], palette: &Palette) -> RgbImage {
+        let mut image = RgbImage::new(SCREEN_WIDTH, SCREEN_HEIGHT);
+        palette.to_rgb24(buf, &mut image.pixels, SCREEN_WIDTH * 3);
+        image
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// The pixel data, three bytes per pixel.
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> [u8; 3] {
+        let offset = (y * self.width + x) * 3;
+        [
+            self.pixels[offset],
+            self.pixels[offset + 1],
+            self.pixels[offset + 2],
+        ]
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, rgb: [u8; 3]) {
+        let offset = (y * self.width + x) * 3;
+        self.pixels[offset..offset + 3].copy_from_slice(&rgb);
+    }
+
+    /// Fills a rectangle, which must lie within the image.
+    pub fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, rgb: [u8; 3]) {
+        for py in y..y + height {
+            for px in x..x + width {
+                self.set_pixel(px, py, rgb);
+            }
+        }
+    }
+}
@@ -1,19 +1,29 @@
-use ppu::{Color, SCREEN_BUFFER_SIZE};
-
-pub mod sdl;
-
-pub trait Screen {
-    fn draw(&mut self, buf: &[Color; SCREEN_BUFFER_SIZE]);
-}
-
-pub struct DummyScreen;
-
-impl Default for DummyScreen {
-    fn default() -> DummyScreen {
-        DummyScreen
-    }
-}
-
-impl Screen for DummyScreen {
-    fn draw(&mut self, _: &[Color; SCREEN_BUFFER_SIZE]) {}
-}
+use ppu::{Color, SCREEN_BUFFER_SIZE};
+
+pub mod sdl;
+pub mod palette;
+pub mod image;
+pub mod screenshot;
+
+pub use screen::image::RgbImage;
+pub use screen::palette::Palette;
+
+pub trait Screen {
+    fn draw(&mut self, buf: &[Color; SCREEN_BUFFER_SIZE]);
+
+    /// Sets the palette used to convert the PPU's colors to RGB. Screens
+    /// which don't produce RGB output can ignore this.
+    fn set_palette(&mut self, _: &Palette) {}
+}
+
+pub struct DummyScreen;
+
+impl Default for DummyScreen {
+    fn default() -> DummyScreen {
+        DummyScreen
+    }
+}
+
+impl Screen for DummyScreen {
+    fn draw(&mut self, _: &[Color; SCREEN_BUFFER_SIZE]) {}
+}
@@ -0,0 +1,259 @@
+use ppu::{Color, SCREEN_BUFFER_SIZE, SCREEN_HEIGHT, SCREEN_WIDTH};
+use std::f64::consts::PI;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+
+/// Number of colors the PPU can output - 64 base colors, each with eight
+/// combinations of the color emphasis bits.
+pub const PALETTE_SIZE: usize = 64 * 8;
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum PaletteError {
+        Io(err: io::Error) {
+            display("IO Error: {}", err)
+            description(err.description())
+            cause(err)
+            from()
+        }
+        InvalidSize(size: usize) {
+            display("Palette files must be 192 or 1536 bytes long, got {} bytes", size)
+            description("Palette file had an invalid size.")
+        }
+    }
+}
+
+/// Parameters for generating a palette by simulating the NES's NTSC signal.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NtscParams {
+    /// Hue rotation, in degrees.
+    pub hue: f64,
+    pub saturation: f64,
+    pub contrast: f64,
+    pub brightness: f64,
+    /// The gamma of the simulated TV. The output is corrected for a 2.2
+    /// gamma display.
+    pub gamma: f64,
+}
+
+impl Default for NtscParams {
+    fn default() -> NtscParams {
+        NtscParams {
+            hue: 0.0,
+            saturation: 1.0,
+            contrast: 1.0,
+            brightness: 0.0,
+            gamma: 1.8,
+        }
+    }
+}
+
+/// Maps the colors output by the PPU to RGB.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Palette {
+    colors: Vec<[u8; 3]>,
+}
+
+// The palette used before palettes were configurable.
+#[cfg_attr(rustfmt, rustfmt_skip)]
+static DEFAULT_PALETTE: [u8; 192] = [
+    84, 84, 84,       0, 30, 116,       8, 16, 144,       48, 0, 136,       68, 0, 100,       92, 0, 48,        84, 4, 0,         60, 24, 0,        32, 42, 0,        8, 58, 0,         0, 64, 0,         0, 60, 0,         0, 50, 60,        0, 0, 0,          0, 0, 0,    0, 0, 0,
+    152, 150, 152,    8, 76, 196,       48, 50, 236,      92, 30, 228,      136, 20, 176,     160, 20, 100,     152, 34, 32,      120, 60, 0,       84, 90, 0,        40, 114, 0,       8, 124, 0,        0, 118, 40,       0, 102, 120,      0, 0, 0,          0, 0, 0,    0, 0, 0,
+    236, 238, 236,    76, 154, 236,     120, 124, 236,    176, 98, 236,     228, 84, 236,     236, 88, 180,     236, 106, 100,    212, 136, 32,     160, 170, 0,      116, 196, 0,      76, 208, 32,      56, 204, 108,     56, 180, 204,     60, 60, 60,       0, 0, 0,    0, 0, 0,
+    236, 238, 236,    168, 204, 236,    188, 188, 236,    212, 178, 236,    236, 174, 236,    236, 174, 212,    236, 180, 176,    228, 196, 144,    204, 210, 120,    180, 222, 120,    168, 226, 144,    152, 226, 180,    160, 214, 228,    160, 162, 160,    0, 0, 0,    0, 0, 0,
+];
+
+/// How much the emphasis bits darken the color channels they don't emphasize.
+const EMPHASIS_ATTENUATION: f64 = 0.746;
+
+impl Default for Palette {
+    fn default() -> Palette {
+        Palette::from_bytes(&DEFAULT_PALETTE).unwrap()
+    }
+}
+
+impl Palette {
+    /// Loads a palette from a .pal file. See `from_bytes`.
+    pub fn load(path: &Path) -> Result<Palette, PaletteError> {
+        let mut file = try!(File::open(path));
+        let mut buf = vec![];
+        try!(file.read_to_end(&mut buf));
+        Palette::from_bytes(&buf)
+    }
+
+    /// Reads a palette in the .pal format - RGB triples for the 64 colors,
+    /// optionally followed by the other seven emphasis combinations. If the
+    /// emphasized colors aren't given, they are approximated by darkening the
+    /// channels which aren't emphasized.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Palette, PaletteError> {
+        if bytes.len() != 192 && bytes.len() != 1536 {
+            return Err(PaletteError::InvalidSize(bytes.len()));
+        }
+        let mut colors: Vec<[u8; 3]> = bytes
+            .chunks(3)
+            .map(|rgb| [rgb[0], rgb[1], rgb[2]])
+            .collect();
+        if colors.len() == 64 {
+            for emphasis in 1..8 {
+                for color in 0..64 {
+                    let rgb = colors[color];
+                    colors.push(emphasize(rgb, emphasis));
+                }
+            }
+        }
+        Ok(Palette { colors: colors })
+    }
+
+    /// Generates a palette by simulating the composite video signal the PPU
+    /// produces and decoding it the way an NTSC TV would.
+    pub fn ntsc(params: &NtscParams) -> Palette {
+        let colors = (0..PALETTE_SIZE)
+            .map(|color| decode_ntsc(color as u16, params))
+            .collect();
+        Palette { colors: colors }
+    }
+
+    pub fn rgb(&self, color: Color) -> [u8; 3] {
+        self.colors[color.bits() as usize % PALETTE_SIZE]
+    }
+
+    /// Converts a frame to packed 24-bit RGB, with `pitch` bytes per row.
+    pub fn to_rgb24(&self, buf: &[Color; SCREEN_BUFFER_SIZE], out: &mut [u8], pitch: usize) {
+        for y in 0..SCREEN_HEIGHT {
+            for x in 0..SCREEN_WIDTH {
+                let rgb = self.rgb(buf[y * SCREEN_WIDTH + x]);
+                let offset = y * pitch + x * 3;
+                out[offset..offset + 3].copy_from_slice(&rgb);
+            }
+        }
+    }
+}
+
+/// Darkens the channels not selected by the emphasis bits (red, green and
+/// blue, from the lowest bit up).
+fn emphasize(rgb: [u8; 3], emphasis: usize) -> [u8; 3] {
+    let mut result = rgb;
+    for channel in 0..3 {
+        let others = emphasis & !(1 << channel);
+        if others != 0 {
+            result[channel] = (result[channel] as f64 * EMPHASIS_ATTENUATION) as u8;
+        }
+    }
+    result
+}
+
+// Voltage levels of the composite signal, relative to sync. The low and high
+// levels of the square wave for each of the four luma rows.
+const SIGNAL_LOW: [f64; 4] = [0.350, 0.518, 0.962, 1.550];
+const SIGNAL_HIGH: [f64; 4] = [1.094, 1.506, 1.962, 1.962];
+const BLACK: f64 = 0.518;
+const WHITE: f64 = 1.962;
+
+/// The signal level of a color at one of the 12 phases of the color
+/// subcarrier.
+fn ntsc_signal(color: u16, phase: usize) -> f64 {
+    let hue = (color & 0x0F) as usize;
+    let mut level = ((color >> 4) & 0x03) as usize;
+    let emphasis = (color >> 6) as usize;
+
+    // Columns $E and $F are always black.
+    if hue > 13 {
+        level = 1;
+    }
+    let mut low = SIGNAL_LOW[level];
+    let mut high = SIGNAL_HIGH[level];
+    // Column 0 is only the high level (grey), columns $D-$F only the low.
+    if hue == 0 {
+        low = high;
+    }
+    if hue > 12 {
+        high = low;
+    }
+
+    let in_phase = |hue: usize| (hue + phase) % 12 < 6;
+    let mut signal = if in_phase(hue) { high } else { low };
+
+    if (emphasis & 0x01 != 0 && in_phase(0)) || (emphasis & 0x02 != 0 && in_phase(4)) ||
+        (emphasis & 0x04 != 0 && in_phase(8))
+    {
+        signal *= EMPHASIS_ATTENUATION;
+    }
+    signal
+}
+
+fn decode_ntsc(color: u16, params: &NtscParams) -> [u8; 3] {
+    let hue = params.hue * PI / 180.0;
+    let (mut y, mut i, mut q) = (0.0, 0.0, 0.0);
+    for phase in 0..12 {
+        let level = (ntsc_signal(color, phase) - BLACK) / (WHITE - BLACK);
+        let angle = PI * phase as f64 / 6.0 + hue;
+        y += level;
+        i += level * angle.cos();
+        q += level * angle.sin();
+    }
+    let y = (y / 12.0) * params.contrast + params.brightness;
+    let i = (i / 12.0) * params.contrast * params.saturation;
+    let q = (q / 12.0) * params.contrast * params.saturation;
+
+    let to_byte = |value: f64| {
+        let corrected = if value <= 0.0 {
+            0.0
+        } else {
+            value.powf(2.2 / params.gamma)
+        };
+        (corrected * 255.0).max(0.0).min(255.0).round() as u8
+    };
+    [
+        to_byte(y + 0.946882 * i + 0.623557 * q),
+        to_byte(y - 0.274788 * i - 0.635691 * q),
+        to_byte(y - 1.108545 * i + 1.709007 * q),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn color(bits: u16) -> Color {
+        Color::from_bits(bits)
+    }
+
+    #[test]
+    fn rejects_invalid_sizes() {
+        match Palette::from_bytes(&[0u8; 100]) {
+            Err(PaletteError::InvalidSize(100)) => (),
+            _ => panic!("Expected an invalid size error"),
+        }
+    }
+
+    #[test]
+    fn loads_full_palettes_unchanged() {
+        let bytes: Vec<u8> = (0..1536).map(|x| x as u8).collect();
+        let palette = Palette::from_bytes(&bytes).unwrap();
+        assert_eq!([0, 1, 2], palette.colors[0]);
+        assert_eq!([1533u16 as u8, 1534u16 as u8, 1535u16 as u8], palette.colors[511]);
+    }
+
+    #[test]
+    fn generates_emphasis_for_short_palettes() {
+        let palette = Palette::default();
+        assert_eq!(PALETTE_SIZE, palette.colors.len());
+        assert_eq!([236, 238, 236], palette.rgb(color(0x30)));
+
+        // Red emphasis darkens green and blue, but not red.
+        let emphasized = palette.rgb(color(0x40 | 0x30));
+        assert_eq!(236, emphasized[0]);
+        assert!(emphasized[1] < 238);
+        assert!(emphasized[2] < 236);
+    }
+
+    #[test]
+    fn ntsc_palette_has_black_and_white() {
+        let palette = Palette::ntsc(&NtscParams::default());
+        assert_eq!([0, 0, 0], palette.rgb(color(0x0F)));
+        let white = palette.rgb(color(0x20));
+        assert!(white.iter().all(|&channel| channel > 200));
+    }
+}
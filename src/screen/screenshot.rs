@@ -0,0 +1,203 @@
+//! Reading and writing PNG images, and a `Screen` which saves frames as PNG
+//! screenshots.
+
+use png;
+use png::HasParameters;
+use ppu::{Color, SCREEN_BUFFER_SIZE};
+use screen::{DummyScreen, Palette, RgbImage, Screen};
+use std::cell::Cell;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum PngError {
+        Io(err: io::Error) {
+            display("IO Error: {}", err)
+            description(err.description())
+            cause(err)
+            from()
+        }
+        Encoding(err: png::EncodingError) {
+            display("PNG Encoding Error: {}", err)
+            description(err.description())
+            cause(err)
+            from()
+        }
+        Decoding(err: png::DecodingError) {
+            display("PNG Decoding Error: {}", err)
+            description(err.description())
+            cause(err)
+            from()
+        }
+        UnsupportedFormat(color: png::ColorType, depth: png::BitDepth) {
+            display("Only 8-bit RGB images are supported, got {:?} at {:?}", color, depth)
+            description("PNG file was not 8-bit RGB.")
+        }
+    }
+}
+
+pub fn write_png(path: &Path, image: &RgbImage) -> Result<(), PngError> {
+    let file = try!(File::create(path));
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(file),
+        image.width() as u32,
+        image.height() as u32,
+    );
+    encoder.set(png::ColorType::RGB).set(png::BitDepth::Eight);
+    let mut writer = try!(encoder.write_header());
+    try!(writer.write_image_data(image.pixels()));
+    Ok(())
+}
+
+/// Reads an 8-bit RGB PNG, such as those written by `write_png`.
+pub fn read_png(path: &Path) -> Result<RgbImage, PngError> {
+    let file = try!(File::open(path));
+    let decoder = png::Decoder::new(file);
+    let (info, mut reader) = try!(decoder.read_info());
+    if info.color_type != png::ColorType::RGB || info.bit_depth != png::BitDepth::Eight {
+        return Err(PngError::UnsupportedFormat(info.color_type, info.bit_depth));
+    }
+    let mut pixels = vec![0; info.buffer_size()];
+    try!(reader.next_frame(&mut pixels));
+    Ok(RgbImage::from_pixels(
+        info.width as usize,
+        info.height as usize,
+        pixels,
+    ))
+}
+
+/// A handle for asking a `PngScreen` to save the next frame.
+#[derive(Clone)]
+pub struct ScreenshotRequest(Rc<Cell<bool>>);
+
+impl ScreenshotRequest {
+    pub fn take_screenshot(&self) {
+        self.0.set(true);
+    }
+}
+
+/// Saves frames as PNG files named by frame number, either when requested or
+/// every Nth frame, and passes every frame on to another screen.
+pub struct PngScreen {
+    directory: PathBuf,
+    palette: Palette,
+    interval: u32,
+    frames: u32,
+    requested: Rc<Cell<bool>>,
+
+    delegate: Box<Screen>,
+}
+
+impl PngScreen {
+    /// Creates a screen which only saves frames, to the given directory.
+    pub fn new(directory: PathBuf) -> PngScreen {
+        PngScreen::wrapping(directory, Box::new(DummyScreen::default()))
+    }
+
+    /// Creates a screen which saves frames and also draws them to `delegate`.
+    pub fn wrapping(directory: PathBuf, delegate: Box<Screen>) -> PngScreen {
+        PngScreen {
+            directory: directory,
+            palette: Palette::default(),
+            interval: 0,
+            frames: 0,
+            requested: Rc::new(Cell::new(false)),
+
+            delegate: delegate,
+        }
+    }
+
+    /// Saves every `frames`th frame, starting with the first. Zero saves only
+    /// requested frames.
+    pub fn set_interval(&mut self, frames: u32) {
+        self.interval = frames;
+    }
+
+    pub fn request(&self) -> ScreenshotRequest {
+        ScreenshotRequest(self.requested.clone())
+    }
+
+    fn save(&self, buf: &[Color; SCREEN_BUFFER_SIZE]) -> Result<PathBuf, PngError> {
+        try!(fs::create_dir_all(&self.directory));
+        let path = self.directory
+            .join(format!("frame_{:06}.png", self.frames));
+        try!(write_png(&path, &RgbImage::from_frame(buf, &self.palette)));
+        Ok(path)
+    }
+}
+
+impl Screen for PngScreen {
+    fn draw(&mut self, buf: &[Color; SCREEN_BUFFER_SIZE]) {
+        let scheduled = self.interval != 0 && self.frames % self.interval == 0;
+        if scheduled || self.requested.replace(false) {
+            if let Err(err) = self.save(buf) {
+                eprintln!("Failed to save screenshot: {}", err);
+            }
+        }
+        self.frames += 1;
+
+        self.delegate.draw(buf);
+    }
+
+    fn set_palette(&mut self, palette: &Palette) {
+        self.palette = palette.clone();
+        self.delegate.set_palette(palette);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+    use std::env;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join("corrosion_png_tests").join(name);
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn png_round_trip() {
+        let dir = temp_dir("round_trip");
+        fs::create_dir_all(&dir).unwrap();
+        let mut image = RgbImage::new(3, 2);
+        image.set_pixel(0, 0, [255, 0, 0]);
+        image.set_pixel(2, 1, [1, 2, 3]);
+
+        let path = dir.join("image.png");
+        write_png(&path, &image).unwrap();
+        assert_eq!(image, read_png(&path).unwrap());
+    }
+
+    #[test]
+    fn saves_every_nth_frame_and_requested_frames() {
+        let dir = temp_dir("interval");
+        let mut screen = PngScreen::new(dir.clone());
+        screen.set_interval(2);
+        let request = screen.request();
+
+        let buf = [Color::from_bits(0x16); SCREEN_BUFFER_SIZE];
+        for frame in 0..4 {
+            if frame == 3 {
+                request.take_screenshot();
+            }
+            screen.draw(&buf);
+        }
+
+        assert!(dir.join("frame_000000.png").exists());
+        assert!(!dir.join("frame_000001.png").exists());
+        assert!(dir.join("frame_000002.png").exists());
+        assert!(dir.join("frame_000003.png").exists());
+
+        let image = read_png(&dir.join("frame_000002.png")).unwrap();
+        assert_eq!((SCREEN_WIDTH, SCREEN_HEIGHT), (image.width(), image.height()));
+        let expected = Palette::default().rgb(Color::from_bits(0x16));
+        assert_eq!(expected, image.pixel(100, 100));
+    }
+}
@@ -1,70 +1,181 @@
-extern crate sha1;
-
-use self::sha1::{Digest, Sha1};
-use ppu::{Color, SCREEN_BUFFER_SIZE};
-use screen::Screen;
-use std::collections::HashMap;
-
-fn hash_screen(buf: &[Color; SCREEN_BUFFER_SIZE]) -> Digest {
-    let newbuf: Vec<u8> = buf.iter().map(|col: &Color| col.bits()).collect();
-
-    let mut s = Sha1::new();
-    s.update(&newbuf);
-    s.digest()
-}
-
-#[allow(dead_code)]
-pub struct HashPrinter {
-    frames: u32,
-
-    delegate: Box<Screen>,
-}
-
-#[allow(dead_code)]
-impl HashPrinter {
-    pub fn new(delegate: Box<Screen>) -> HashPrinter {
-        HashPrinter {
-            frames: 0,
-            delegate: delegate,
-        }
-    }
-}
-
-impl Screen for HashPrinter {
-    fn draw(&mut self, buf: &[Color; SCREEN_BUFFER_SIZE]) {
-        println!(
-            "Frame: {}, Hash: {}",
-            self.frames,
-            hash_screen(buf).to_string()
-        );
-        self.frames += 1;
-
-        self.delegate.draw(buf);
-    }
-}
-
-pub struct HashVerifier {
-    hashes: HashMap<u32, &'static str>,
-    frames: u32,
-}
-
-impl HashVerifier {
-    pub fn new(hashes: HashMap<u32, &'static str>) -> HashVerifier {
-        HashVerifier {
-            frames: 0,
-            hashes: hashes,
-        }
-    }
-}
-
-impl Screen for HashVerifier {
-    fn draw(&mut self, buf: &[Color; SCREEN_BUFFER_SIZE]) {
-        if self.hashes.contains_key(&self.frames) {
-            assert_eq!(
-                self.hashes.get(&self.frames).unwrap(),
-                &hash_screen(buf).to_string()
-            );
-        }
-        self.frames += 1;
-    }
-}
+extern crate sha1;
+
+use self::sha1::{Digest, Sha1};
+use ppu::{Color, SCREEN_BUFFER_SIZE};
+use screen::{Palette, RgbImage, Screen};
+use screen::screenshot::{read_png, write_png};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const GOLDEN_DIR: &'static str = "src/tests/golden";
+const OUTPUT_DIR: &'static str = "target/test-output";
+
+/// Set this environment variable to save the checked frames as the new
+/// golden images instead of comparing against them.
+const UPDATE_GOLDEN_VAR: &'static str = "CORROSION_UPDATE_GOLDEN";
+
+fn hash_screen(buf: &[Color; SCREEN_BUFFER_SIZE]) -> Digest {
+    // Emphasized colors add a second byte, so that hashes of screens without
+    // emphasis are the same as they were when colors were only 6 bits.
+    let mut newbuf: Vec<u8> = Vec::with_capacity(SCREEN_BUFFER_SIZE);
+    for col in buf.iter() {
+        newbuf.push(col.color());
+        if col.emphasis() != 0 {
+            newbuf.push(col.emphasis());
+        }
+    }
+
+    let mut s = Sha1::new();
+    s.update(&newbuf);
+    s.digest()
+}
+
+#[allow(dead_code)]
+pub struct HashPrinter {
+    frames: u32,
+
+    delegate: Box<Screen>,
+}
+
+#[allow(dead_code)]
+impl HashPrinter {
+    pub fn new(delegate: Box<Screen>) -> HashPrinter {
+        HashPrinter {
+            frames: 0,
+            delegate: delegate,
+        }
+    }
+}
+
+impl Screen for HashPrinter {
+    fn draw(&mut self, buf: &[Color; SCREEN_BUFFER_SIZE]) {
+        println!(
+            "Frame: {}, Hash: {}",
+            self.frames,
+            hash_screen(buf).to_string()
+        );
+        self.frames += 1;
+
+        self.delegate.draw(buf);
+    }
+
+    fn set_palette(&mut self, palette: &Palette) {
+        self.delegate.set_palette(palette);
+    }
+}
+
+/// Marks the pixels which differ between two images of the same size in red,
+/// over a darkened copy of the expected image. Returns the number of
+/// differing pixels and the diff image.
+fn diff_images(expected: &RgbImage, actual: &RgbImage) -> (usize, RgbImage) {
+    let mut diff = RgbImage::new(expected.width(), expected.height());
+    let mut count = 0;
+    for y in 0..expected.height() {
+        for x in 0..expected.width() {
+            let rgb = expected.pixel(x, y);
+            if rgb == actual.pixel(x, y) {
+                diff.set_pixel(x, y, [rgb[0] / 4, rgb[1] / 4, rgb[2] / 4]);
+            } else {
+                diff.set_pixel(x, y, [255, 0, 0]);
+                count += 1;
+            }
+        }
+    }
+    (count, diff)
+}
+
+/// Checks frames against their expected hashes, and against golden images
+/// where there are any. Golden images are stored as
+/// `src/tests/golden/<test>/frame_<n>.png`. When a frame doesn't match, it's
+/// saved to `target/test-output/<test>/` (with a diff image if there's a
+/// golden image) before the test fails.
+pub struct FrameVerifier {
+    name: String,
+    hashes: HashMap<u32, &'static str>,
+    frames: u32,
+    palette: Palette,
+}
+
+impl FrameVerifier {
+    pub fn new(name: &str, hashes: HashMap<u32, &'static str>) -> FrameVerifier {
+        FrameVerifier {
+            name: name.to_owned(),
+            frames: 0,
+            hashes: hashes,
+            palette: Palette::default(),
+        }
+    }
+
+    fn file_name(&self) -> String {
+        format!("frame_{}.png", self.frames)
+    }
+
+    fn golden_path(&self) -> PathBuf {
+        Path::new(GOLDEN_DIR).join(&self.name).join(self.file_name())
+    }
+
+    fn save_output(&self, suffix: &str, image: &RgbImage) -> PathBuf {
+        let dir = Path::new(OUTPUT_DIR).join(&self.name);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(format!("frame_{}_{}.png", self.frames, suffix));
+        write_png(&path, image).unwrap();
+        path
+    }
+
+    fn check_golden(&self, image: &RgbImage) {
+        let golden_path = self.golden_path();
+        if env::var_os(UPDATE_GOLDEN_VAR).is_some() {
+            fs::create_dir_all(golden_path.parent().unwrap()).unwrap();
+            write_png(&golden_path, image).unwrap();
+            return;
+        }
+        if !golden_path.exists() {
+            return;
+        }
+        let golden = read_png(&golden_path).unwrap();
+        let (count, diff) = diff_images(&golden, image);
+        if count != 0 {
+            let actual_path = self.save_output("actual", image);
+            let diff_path = self.save_output("diff", &diff);
+            panic!(
+                "Frame {} differs from {} in {} pixels. See {} and {}",
+                self.frames,
+                golden_path.display(),
+                count,
+                actual_path.display(),
+                diff_path.display()
+            );
+        }
+    }
+
+    fn check_hash(&self, buf: &[Color; SCREEN_BUFFER_SIZE], image: &RgbImage) {
+        let expected = match self.hashes.get(&self.frames) {
+            Some(hash) => hash,
+            None => return,
+        };
+        let actual = hash_screen(buf).to_string();
+        if *expected != actual {
+            let actual_path = self.save_output("actual", image);
+            panic!(
+                "Frame {} has hash {}, expected {}. See {}",
+                self.frames,
+                actual,
+                expected,
+                actual_path.display()
+            );
+        }
+    }
+}
+
+impl Screen for FrameVerifier {
+    fn draw(&mut self, buf: &[Color; SCREEN_BUFFER_SIZE]) {
+        if self.hashes.contains_key(&self.frames) || self.golden_path().exists() {
+            let image = RgbImage::from_frame(buf, &self.palette);
+            self.check_golden(&image);
+            self.check_hash(buf, &image);
+        }
+        self.frames += 1;
+    }
+}
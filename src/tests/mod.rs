@@ -1,219 +1,622 @@
-mod hash_screen;
-mod test_io;
-mod bench;
-
-use Settings;
-use std::collections::HashMap;
-use std::path::Path;
-
-#[test]
-fn verify_completes_nestest() {
-    let mut hashes: HashMap<u32, &'static str> = HashMap::new();
-    let mut commands: HashMap<u32, &'static str> = HashMap::new();
-
-    // Run the main tests
-    commands.insert(10, "....T...|........");
-    hashes.insert(35, "2bfe5ffe2fae65fa730c04735a3b25115c5fb65e");
-
-    // Switch to the unofficial opcode tests and run them
-    commands.insert(40, ".....S..|........");
-    commands.insert(45, "....T...|........");
-    hashes.insert(65, "0b6895e6ff0e8be76e805a067be6ebec89e7d6ad");
-
-    run_system_test(
-        70,
-        Path::new("nes-test-roms/other/nestest.nes"),
-        hashes,
-        commands,
-    );
-}
-
-#[test]
-fn blargg_apu_test_len_ctr() {
-    let mut hashes: HashMap<u32, &'static str> = HashMap::new();
-    let commands: HashMap<u32, &'static str> = HashMap::new();
-
-    hashes.insert(18, "ea9ac1696a5cec416f0a9f34c052815ca59850d5");
-
-    run_system_test(
-        19,
-        Path::new("nes-test-roms/apu_test/rom_singles/1-len_ctr.nes"),
-        hashes,
-        commands,
-    );
-}
-
-#[test]
-fn blargg_apu_test_len_table() {
-    let mut hashes: HashMap<u32, &'static str> = HashMap::new();
-    let commands: HashMap<u32, &'static str> = HashMap::new();
-
-    hashes.insert(13, "90a61bd003c5794713aa5f207b9b70c8862d892b");
-
-    run_system_test(
-        14,
-        Path::new("nes-test-roms/apu_test/rom_singles/2-len_table.nes"),
-        hashes,
-        commands,
-    );
-}
-
-#[test]
-fn blargg_apu_test_irq_flag() {
-    let mut hashes: HashMap<u32, &'static str> = HashMap::new();
-    let commands: HashMap<u32, &'static str> = HashMap::new();
-
-    hashes.insert(18, "09e4ad012c8fddfd8e3b4cc6d1b395c5062768c2");
-
-    run_system_test(
-        19,
-        Path::new("nes-test-roms/apu_test/rom_singles/3-irq_flag.nes"),
-        hashes,
-        commands,
-    );
-}
-
-#[test]
-fn blargg_ppu_test_palette_ram() {
-    let mut hashes: HashMap<u32, &'static str> = HashMap::new();
-    let commands: HashMap<u32, &'static str> = HashMap::new();
-
-    hashes.insert(18, "cb15f68f631c1d409beefb775bcff990286096fb");
-
-    run_system_test(
-        19,
-        Path::new("nes-test-roms/blargg_ppu_tests_2005.09.15b/palette_ram.nes"),
-        hashes,
-        commands,
-    );
-}
-
-#[test]
-fn blargg_ppu_test_sprite_ram() {
-    let mut hashes: HashMap<u32, &'static str> = HashMap::new();
-    let commands: HashMap<u32, &'static str> = HashMap::new();
-
-    hashes.insert(18, "cb15f68f631c1d409beefb775bcff990286096fb");
-
-    run_system_test(
-        19,
-        Path::new("nes-test-roms/blargg_ppu_tests_2005.09.15b/sprite_ram.nes"),
-        hashes,
-        commands,
-    );
-}
-
-#[test]
-fn blargg_ppu_test_vram_access() {
-    let mut hashes: HashMap<u32, &'static str> = HashMap::new();
-    let commands: HashMap<u32, &'static str> = HashMap::new();
-
-    hashes.insert(18, "cb15f68f631c1d409beefb775bcff990286096fb");
-
-    run_system_test(
-        19,
-        Path::new("nes-test-roms/blargg_ppu_tests_2005.09.15b/vram_access.nes"),
-        hashes,
-        commands,
-    );
-}
-
-#[test]
-fn oam_read() {
-    let mut hashes: HashMap<u32, &'static str> = HashMap::new();
-    let commands: HashMap<u32, &'static str> = HashMap::new();
-
-    hashes.insert(27, "cc2447362cceb400803a18c2e4b5d5d4e4aa2ea7");
-
-    run_system_test(
-        28,
-        Path::new("nes-test-roms/oam_read/oam_read.nes"),
-        hashes,
-        commands,
-    );
-}
-
-#[test]
-fn sprite_hit_basics() {
-    let mut hashes: HashMap<u32, &'static str> = HashMap::new();
-    let commands: HashMap<u32, &'static str> = HashMap::new();
-
-    hashes.insert(33, "1437c48bb22dd3be0d37449171d2120e13877326");
-
-    run_system_test(
-        33,
-        Path::new("nes-test-roms/sprite_hit_tests_2005.10.05/01.basics.nes"),
-        hashes,
-        commands,
-    );
-}
-
-#[test]
-fn sprite_hit_alignment() {
-    let mut hashes: HashMap<u32, &'static str> = HashMap::new();
-    let commands: HashMap<u32, &'static str> = HashMap::new();
-
-    hashes.insert(31, "33815f5682dda683d1a9fe7495f6358c0e741a9d");
-
-    run_system_test(
-        32,
-        Path::new("nes-test-roms/sprite_hit_tests_2005.10.05/02.alignment.nes"),
-        hashes,
-        commands,
-    );
-}
-
-#[test]
-fn sprite_hit_corners() {
-    let mut hashes: HashMap<u32, &'static str> = HashMap::new();
-    let commands: HashMap<u32, &'static str> = HashMap::new();
-
-    hashes.insert(21, "760203cab0bc4df16bda48438f67a91e8a152fb9");
-
-    run_system_test(
-        22,
-        Path::new("nes-test-roms/sprite_hit_tests_2005.10.05/03.corners.nes"),
-        hashes,
-        commands,
-    );
-}
-
-#[test]
-fn sprite_hit_flip() {
-    let mut hashes: HashMap<u32, &'static str> = HashMap::new();
-    let commands: HashMap<u32, &'static str> = HashMap::new();
-
-    hashes.insert(21, "e16e43e5efdeacfd999a8ea031fa5058ec202f96");
-
-    run_system_test(
-        22,
-        Path::new("nes-test-roms/sprite_hit_tests_2005.10.05/04.flip.nes"),
-        hashes,
-        commands,
-    );
-}
-
-fn run_system_test(
-    frames: u32,
-    file_name: &Path,
-    hashes: HashMap<u32, &'static str>,
-    commands: HashMap<u32, &'static str>,
-) {
-
-    let cart = ::cart::Cart::read(file_name).expect("Failed to read ROM File");
-    let settings = Settings {
-        jit: true,
-        ..Default::default()
-    };
-    let mut builder = ::EmulatorBuilder::new(cart, settings);
-    builder.io = Box::new(test_io::TestIO::new(commands));
-    builder.screen = Box::new(hash_screen::HashVerifier::new(hashes));
-    builder.screen = Box::new(hash_screen::HashPrinter::new(builder.screen));
-
-    let mut emulator = builder.build();
-
-    for _ in 0..frames {
-        assert!(!emulator.halted());
-        emulator.run_frame();
-    }
-}
+mod hash_screen;
+mod test_io;
+mod bench;
+
+use Settings;
+use memory::MemSegment;
+use std::collections::HashMap;
+use std::path::Path;
+
+#[test]
+fn verify_completes_nestest() {
+    let mut hashes: HashMap<u32, &'static str> = HashMap::new();
+    let mut commands: HashMap<u32, &'static str> = HashMap::new();
+
+    // Run the main tests
+    commands.insert(10, "....T...|........");
+    hashes.insert(35, "2bfe5ffe2fae65fa730c04735a3b25115c5fb65e");
+
+    // Switch to the unofficial opcode tests and run them
+    commands.insert(40, ".....S..|........");
+    commands.insert(45, "....T...|........");
+    hashes.insert(65, "0b6895e6ff0e8be76e805a067be6ebec89e7d6ad");
+
+    run_system_test(
+        "verify_completes_nestest",
+        70,
+        Path::new("nes-test-roms/other/nestest.nes"),
+        hashes,
+        commands,
+    );
+}
+
+#[test]
+fn verify_completes_nestest_with_run_ahead() {
+    let mut hashes: HashMap<u32, &'static str> = HashMap::new();
+    let mut commands: HashMap<u32, &'static str> = HashMap::new();
+
+    // Same as above - the screens being compared are static, so showing
+    // the frame after the real one shouldn't change the hashes.
+    commands.insert(10, "....T...|........");
+    hashes.insert(35, "2bfe5ffe2fae65fa730c04735a3b25115c5fb65e");
+
+    commands.insert(40, ".....S..|........");
+    commands.insert(45, "....T...|........");
+    hashes.insert(65, "0b6895e6ff0e8be76e805a067be6ebec89e7d6ad");
+
+    let settings = Settings {
+        jit: true,
+        run_ahead: 1,
+        ..Default::default()
+    };
+    run_system_test_with_settings(
+        "verify_completes_nestest_with_run_ahead",
+        70,
+        Path::new("nes-test-roms/other/nestest.nes"),
+        hashes,
+        commands,
+        settings,
+    );
+}
+
+#[test]
+fn blargg_apu_test_len_ctr() {
+    let mut hashes: HashMap<u32, &'static str> = HashMap::new();
+    let commands: HashMap<u32, &'static str> = HashMap::new();
+
+    hashes.insert(18, "ea9ac1696a5cec416f0a9f34c052815ca59850d5");
+
+    run_system_test(
+        "blargg_apu_test_len_ctr",
+        19,
+        Path::new("nes-test-roms/apu_test/rom_singles/1-len_ctr.nes"),
+        hashes,
+        commands,
+    );
+}
+
+#[test]
+fn blargg_apu_test_len_table() {
+    let mut hashes: HashMap<u32, &'static str> = HashMap::new();
+    let commands: HashMap<u32, &'static str> = HashMap::new();
+
+    hashes.insert(13, "90a61bd003c5794713aa5f207b9b70c8862d892b");
+
+    run_system_test(
+        "blargg_apu_test_len_table",
+        14,
+        Path::new("nes-test-roms/apu_test/rom_singles/2-len_table.nes"),
+        hashes,
+        commands,
+    );
+}
+
+#[test]
+fn blargg_apu_test_irq_flag() {
+    let mut hashes: HashMap<u32, &'static str> = HashMap::new();
+    let commands: HashMap<u32, &'static str> = HashMap::new();
+
+    hashes.insert(18, "09e4ad012c8fddfd8e3b4cc6d1b395c5062768c2");
+
+    run_system_test(
+        "blargg_apu_test_irq_flag",
+        19,
+        Path::new("nes-test-roms/apu_test/rom_singles/3-irq_flag.nes"),
+        hashes,
+        commands,
+    );
+}
+
+#[test]
+fn blargg_ppu_test_palette_ram() {
+    let mut hashes: HashMap<u32, &'static str> = HashMap::new();
+    let commands: HashMap<u32, &'static str> = HashMap::new();
+
+    hashes.insert(18, "cb15f68f631c1d409beefb775bcff990286096fb");
+
+    run_system_test(
+        "blargg_ppu_test_palette_ram",
+        19,
+        Path::new("nes-test-roms/blargg_ppu_tests_2005.09.15b/palette_ram.nes"),
+        hashes,
+        commands,
+    );
+}
+
+#[test]
+fn blargg_ppu_test_sprite_ram() {
+    let mut hashes: HashMap<u32, &'static str> = HashMap::new();
+    let commands: HashMap<u32, &'static str> = HashMap::new();
+
+    hashes.insert(18, "cb15f68f631c1d409beefb775bcff990286096fb");
+
+    run_system_test(
+        "blargg_ppu_test_sprite_ram",
+        19,
+        Path::new("nes-test-roms/blargg_ppu_tests_2005.09.15b/sprite_ram.nes"),
+        hashes,
+        commands,
+    );
+}
+
+#[test]
+fn blargg_ppu_test_vram_access() {
+    let mut hashes: HashMap<u32, &'static str> = HashMap::new();
+    let commands: HashMap<u32, &'static str> = HashMap::new();
+
+    hashes.insert(18, "cb15f68f631c1d409beefb775bcff990286096fb");
+
+    run_system_test(
+        "blargg_ppu_test_vram_access",
+        19,
+        Path::new("nes-test-roms/blargg_ppu_tests_2005.09.15b/vram_access.nes"),
+        hashes,
+        commands,
+    );
+}
+
+#[test]
+fn oam_read() {
+    let mut hashes: HashMap<u32, &'static str> = HashMap::new();
+    let commands: HashMap<u32, &'static str> = HashMap::new();
+
+    hashes.insert(27, "cc2447362cceb400803a18c2e4b5d5d4e4aa2ea7");
+
+    run_system_test(
+        "oam_read",
+        28,
+        Path::new("nes-test-roms/oam_read/oam_read.nes"),
+        hashes,
+        commands,
+    );
+}
+
+#[test]
+fn sprite_hit_basics() {
+    let mut hashes: HashMap<u32, &'static str> = HashMap::new();
+    let commands: HashMap<u32, &'static str> = HashMap::new();
+
+    hashes.insert(33, "1437c48bb22dd3be0d37449171d2120e13877326");
+
+    run_system_test(
+        "sprite_hit_basics",
+        33,
+        Path::new("nes-test-roms/sprite_hit_tests_2005.10.05/01.basics.nes"),
+        hashes,
+        commands,
+    );
+}
+
+#[test]
+fn sprite_hit_alignment() {
+    let mut hashes: HashMap<u32, &'static str> = HashMap::new();
+    let commands: HashMap<u32, &'static str> = HashMap::new();
+
+    hashes.insert(31, "33815f5682dda683d1a9fe7495f6358c0e741a9d");
+
+    run_system_test(
+        "sprite_hit_alignment",
+        32,
+        Path::new("nes-test-roms/sprite_hit_tests_2005.10.05/02.alignment.nes"),
+        hashes,
+        commands,
+    );
+}
+
+#[test]
+fn sprite_hit_corners() {
+    let mut hashes: HashMap<u32, &'static str> = HashMap::new();
+    let commands: HashMap<u32, &'static str> = HashMap::new();
+
+    hashes.insert(21, "760203cab0bc4df16bda48438f67a91e8a152fb9");
+
+    run_system_test(
+        "sprite_hit_corners",
+        22,
+        Path::new("nes-test-roms/sprite_hit_tests_2005.10.05/03.corners.nes"),
+        hashes,
+        commands,
+    );
+}
+
+#[test]
+fn sprite_hit_flip() {
+    let mut hashes: HashMap<u32, &'static str> = HashMap::new();
+    let commands: HashMap<u32, &'static str> = HashMap::new();
+
+    hashes.insert(21, "e16e43e5efdeacfd999a8ea031fa5058ec202f96");
+
+    run_system_test(
+        "sprite_hit_flip",
+        22,
+        Path::new("nes-test-roms/sprite_hit_tests_2005.10.05/04.flip.nes"),
+        hashes,
+        commands,
+    );
+}
+
+fn jit_settings() -> Settings {
+    Settings {
+        jit: true,
+        ..Default::default()
+    }
+}
+
+fn dot_accurate_settings() -> Settings {
+    Settings {
+        jit: true,
+        dot_accurate_ppu: true,
+        ..Default::default()
+    }
+}
+
+// The sprite hit tests should give the same results with the dot-accurate
+// renderer.
+#[test]
+fn sprite_hit_basics_dot_accurate() {
+    let mut hashes: HashMap<u32, &'static str> = HashMap::new();
+    let commands: HashMap<u32, &'static str> = HashMap::new();
+
+    hashes.insert(33, "1437c48bb22dd3be0d37449171d2120e13877326");
+
+    run_system_test_with_settings(
+        "sprite_hit_basics_dot_accurate",
+        33,
+        Path::new("nes-test-roms/sprite_hit_tests_2005.10.05/01.basics.nes"),
+        hashes,
+        commands,
+        dot_accurate_settings(),
+    );
+}
+
+#[test]
+fn sprite_hit_alignment_dot_accurate() {
+    let mut hashes: HashMap<u32, &'static str> = HashMap::new();
+    let commands: HashMap<u32, &'static str> = HashMap::new();
+
+    hashes.insert(31, "33815f5682dda683d1a9fe7495f6358c0e741a9d");
+
+    run_system_test_with_settings(
+        "sprite_hit_alignment_dot_accurate",
+        32,
+        Path::new("nes-test-roms/sprite_hit_tests_2005.10.05/02.alignment.nes"),
+        hashes,
+        commands,
+        dot_accurate_settings(),
+    );
+}
+
+#[test]
+fn sprite_hit_corners_dot_accurate() {
+    let mut hashes: HashMap<u32, &'static str> = HashMap::new();
+    let commands: HashMap<u32, &'static str> = HashMap::new();
+
+    hashes.insert(21, "760203cab0bc4df16bda48438f67a91e8a152fb9");
+
+    run_system_test_with_settings(
+        "sprite_hit_corners_dot_accurate",
+        22,
+        Path::new("nes-test-roms/sprite_hit_tests_2005.10.05/03.corners.nes"),
+        hashes,
+        commands,
+        dot_accurate_settings(),
+    );
+}
+
+#[test]
+fn sprite_hit_flip_dot_accurate() {
+    let mut hashes: HashMap<u32, &'static str> = HashMap::new();
+    let commands: HashMap<u32, &'static str> = HashMap::new();
+
+    hashes.insert(21, "e16e43e5efdeacfd999a8ea031fa5058ec202f96");
+
+    run_system_test_with_settings(
+        "sprite_hit_flip_dot_accurate",
+        22,
+        Path::new("nes-test-roms/sprite_hit_tests_2005.10.05/04.flip.nes"),
+        hashes,
+        commands,
+        dot_accurate_settings(),
+    );
+}
+
+#[test]
+fn ppu_vbl_nmi_vbl_basics() {
+    run_blargg_test(
+        200,
+        Path::new("nes-test-roms/ppu_vbl_nmi/rom_singles/01-vbl_basics.nes"),
+        dot_accurate_settings(),
+    );
+}
+
+#[test]
+fn ppu_vbl_nmi_vbl_set_time() {
+    run_blargg_test(
+        400,
+        Path::new("nes-test-roms/ppu_vbl_nmi/rom_singles/02-vbl_set_time.nes"),
+        dot_accurate_settings(),
+    );
+}
+
+#[test]
+fn ppu_vbl_nmi_vbl_clear_time() {
+    run_blargg_test(
+        400,
+        Path::new("nes-test-roms/ppu_vbl_nmi/rom_singles/03-vbl_clear_time.nes"),
+        dot_accurate_settings(),
+    );
+}
+
+#[test]
+fn ppu_vbl_nmi_nmi_control() {
+    run_blargg_test(
+        400,
+        Path::new("nes-test-roms/ppu_vbl_nmi/rom_singles/04-nmi_control.nes"),
+        dot_accurate_settings(),
+    );
+}
+
+#[test]
+fn ppu_vbl_nmi_nmi_timing() {
+    run_blargg_test(
+        400,
+        Path::new("nes-test-roms/ppu_vbl_nmi/rom_singles/05-nmi_timing.nes"),
+        dot_accurate_settings(),
+    );
+}
+
+#[test]
+fn ppu_vbl_nmi_suppression() {
+    run_blargg_test(
+        400,
+        Path::new("nes-test-roms/ppu_vbl_nmi/rom_singles/06-suppression.nes"),
+        dot_accurate_settings(),
+    );
+}
+
+#[test]
+fn ppu_vbl_nmi_nmi_on_timing() {
+    run_blargg_test(
+        400,
+        Path::new("nes-test-roms/ppu_vbl_nmi/rom_singles/07-nmi_on_timing.nes"),
+        dot_accurate_settings(),
+    );
+}
+
+#[test]
+fn ppu_vbl_nmi_nmi_off_timing() {
+    run_blargg_test(
+        400,
+        Path::new("nes-test-roms/ppu_vbl_nmi/rom_singles/08-nmi_off_timing.nes"),
+        dot_accurate_settings(),
+    );
+}
+
+#[test]
+fn ppu_vbl_nmi_even_odd_frames() {
+    run_blargg_test(
+        400,
+        Path::new("nes-test-roms/ppu_vbl_nmi/rom_singles/09-even_odd_frames.nes"),
+        dot_accurate_settings(),
+    );
+}
+
+#[test]
+fn ppu_vbl_nmi_even_odd_timing() {
+    run_blargg_test(
+        400,
+        Path::new("nes-test-roms/ppu_vbl_nmi/rom_singles/10-even_odd_timing.nes"),
+        dot_accurate_settings(),
+    );
+}
+
+#[test]
+fn ppu_open_bus() {
+    run_blargg_test(
+        600,
+        Path::new("nes-test-roms/ppu_open_bus/ppu_open_bus.nes"),
+        jit_settings(),
+    );
+}
+
+#[test]
+fn ppu_read_buffer() {
+    run_blargg_test(
+        2000,
+        Path::new("nes-test-roms/ppu_read_buffer/test_ppu_read_buffer.nes"),
+        jit_settings(),
+    );
+}
+
+#[test]
+fn blargg_apu_test_jitter() {
+    run_blargg_test(
+        300,
+        Path::new("nes-test-roms/apu_test/rom_singles/4-jitter.nes"),
+        jit_settings(),
+    );
+}
+
+#[test]
+fn blargg_apu_test_len_timing() {
+    run_blargg_test(
+        600,
+        Path::new("nes-test-roms/apu_test/rom_singles/5-len_timing.nes"),
+        jit_settings(),
+    );
+}
+
+#[test]
+fn blargg_apu_test_irq_flag_timing() {
+    run_blargg_test(
+        300,
+        Path::new("nes-test-roms/apu_test/rom_singles/6-irq_flag_timing.nes"),
+        jit_settings(),
+    );
+}
+
+#[test]
+fn blargg_apu_test_dmc_basics() {
+    run_blargg_test(
+        600,
+        Path::new("nes-test-roms/apu_test/rom_singles/7-dmc_basics.nes"),
+        jit_settings(),
+    );
+}
+
+#[test]
+fn blargg_apu_test_dmc_rates() {
+    run_blargg_test(
+        600,
+        Path::new("nes-test-roms/apu_test/rom_singles/8-dmc_rates.nes"),
+        jit_settings(),
+    );
+}
+
+#[test]
+fn apu_reset_4015_cleared() {
+    run_blargg_test(
+        600,
+        Path::new("nes-test-roms/apu_reset/4015_cleared.nes"),
+        jit_settings(),
+    );
+}
+
+#[test]
+fn apu_reset_4017_timing() {
+    run_blargg_test(
+        600,
+        Path::new("nes-test-roms/apu_reset/4017_timing.nes"),
+        jit_settings(),
+    );
+}
+
+#[test]
+fn apu_reset_4017_written() {
+    run_blargg_test(
+        600,
+        Path::new("nes-test-roms/apu_reset/4017_written.nes"),
+        jit_settings(),
+    );
+}
+
+#[test]
+fn apu_reset_irq_flag_cleared() {
+    run_blargg_test(
+        600,
+        Path::new("nes-test-roms/apu_reset/irq_flag_cleared.nes"),
+        jit_settings(),
+    );
+}
+
+#[test]
+fn apu_reset_len_ctrs_enabled() {
+    run_blargg_test(
+        600,
+        Path::new("nes-test-roms/apu_reset/len_ctrs_enabled.nes"),
+        jit_settings(),
+    );
+}
+
+#[test]
+fn apu_reset_works_immediately() {
+    run_blargg_test(
+        600,
+        Path::new("nes-test-roms/apu_reset/works_immediately.nes"),
+        jit_settings(),
+    );
+}
+
+fn run_system_test(
+    name: &str,
+    frames: u32,
+    file_name: &Path,
+    hashes: HashMap<u32, &'static str>,
+    commands: HashMap<u32, &'static str>,
+) {
+    let settings = Settings {
+        jit: true,
+        ..Default::default()
+    };
+    run_system_test_with_settings(name, frames, file_name, hashes, commands, settings);
+}
+
+/// Runs a ROM, checking the frames given in `hashes`. Golden and output
+/// images are kept under the test's `name`, as several tests may run the
+/// same ROM with different settings.
+fn run_system_test_with_settings(
+    name: &str,
+    frames: u32,
+    file_name: &Path,
+    hashes: HashMap<u32, &'static str>,
+    commands: HashMap<u32, &'static str>,
+    settings: Settings,
+) {
+    let cart = ::cart::Cart::read(file_name).expect("Failed to read ROM File");
+    let mut builder = ::EmulatorBuilder::new(cart, settings);
+    builder.io = Box::new(test_io::TestIO::new(commands));
+    builder.screen = Box::new(hash_screen::FrameVerifier::new(name, hashes));
+    builder.screen = Box::new(hash_screen::HashPrinter::new(builder.screen));
+
+    let mut emulator = builder.build();
+
+    for _ in 0..frames {
+        assert!(!emulator.halted());
+        emulator.run_frame();
+    }
+}
+
+/// Frames to wait between a test ROM asking for a reset and pressing it.
+const BLARGG_RESET_DELAY: u32 = 10;
+
+/// Runs one of blargg's test ROMs which report their result in PRG RAM: $6000
+/// holds the status ($80 while running, 0 on success) once $6001-$6003 hold
+/// the signature, and $6004 onwards holds the text of the result. A status
+/// of $81 asks for the reset button to be pressed.
+fn run_blargg_test(max_frames: u32, file_name: &Path, settings: Settings) {
+    let cart = ::cart::Cart::read(file_name).expect("Failed to read ROM File");
+    let mut emulator = ::EmulatorBuilder::new(cart, settings).build();
+
+    let mut status = None;
+    let mut reset_at = None;
+    for frame in 0..max_frames {
+        assert!(!emulator.halted());
+        emulator.run_frame();
+
+        if reset_at == Some(frame) {
+            reset_at = None;
+            emulator.reset();
+            continue;
+        }
+
+        let signature = [
+            emulator.cpu.read(0x6001),
+            emulator.cpu.read(0x6002),
+            emulator.cpu.read(0x6003),
+        ];
+        let current = emulator.cpu.read(0x6000);
+        if signature != [0xDE, 0xB0, 0x61] {
+            continue;
+        }
+        if current < 0x80 {
+            status = Some(current);
+            break;
+        }
+        if current == 0x81 && reset_at.is_none() {
+            // The ROM wants the button held for a moment before release.
+            reset_at = Some(frame + BLARGG_RESET_DELAY);
+        }
+    }
+
+    let mut text = String::new();
+    let mut addr = 0x6004;
+    loop {
+        let byte = emulator.cpu.read(addr);
+        if byte == 0 || addr == 0x7FFF {
+            break;
+        }
+        text.push(byte as char);
+        addr += 1;
+    }
+    assert_eq!(Some(0), status, "{}", text);
+}